@@ -11,7 +11,10 @@ use axum::{
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
-use service::{cache_service::Driver, system_login_log_server, system_user_service};
+use service::{
+    cache_service::Driver, log_writer_service, security_event_service::SecurityEvent,
+    system_login_log_server, system_user_service,
+};
 use std::net::SocketAddr;
 use utils::password::Password;
 
@@ -32,7 +35,7 @@ async fn login_by_account(
     Json(params): Json<LoginByAccountRequest>,
 ) -> Result<impl IntoResponse> {
     let captcha_cache_type = service::cache_service::CacheType::SystemAuthLoginCaptcha;
-    let mut cache = state.cache.lock().await;
+    let cache = &state.cache;
     let cache_info = cache
         .first(captcha_cache_type.clone(), &params.key, None)
         .await?
@@ -40,17 +43,24 @@ async fn login_by_account(
 
     let captcha_code = cache_info.value::<String>()?;
     if captcha_code.to_lowercase().ne(&params.code.to_lowercase()) {
+        login_failed(&state, addr, &user_agent, &params.username, "captcha");
         return Err(ErrorCode::Captche);
     }
     cache.pull(captcha_cache_type, &params.key).await?;
 
-    let user = system_user_service::find_user_by_username(&state.db, &params.username)
-        .await?
-        .ok_or(ErrorCode::InputUserAndPwd)?;
+    let user = system_user_service::find_user_by_username(&state.db, &params.username).await?;
+    let user = match user {
+        Some(user) => user,
+        None => {
+            login_failed(&state, addr, &user_agent, &params.username, "unknown user");
+            return Err(ErrorCode::InputUserAndPwd);
+        }
+    };
     let verify_result =
         Password::verify_password(user.password(), user.salt(), params.password.as_bytes())?;
 
     if !verify_result {
+        login_failed(&state, addr, &user_agent, &params.username, "password");
         return Err(ErrorCode::InputUserAndPwd);
     }
 
@@ -86,22 +96,44 @@ async fn login_after(
 ) -> Result<()> {
     let ip_address = addr.to_string();
     system_user_service::set_last_login(&state.db, user_id, &ip_address).await?;
-    system_login_log_server::create(
-        &state.db,
-        user_id,
+    state.events.emit(SecurityEvent::login(
+        *user_id,
         &ip_address,
-        system_login_log_server::CreateParams {
-            r#type: Some(login_type.into()),
-            ip_address_name: None,
-            browser_agent: match user_agent.to_str() {
-                Ok(x) => Some(x.to_owned()),
-                Err(_) => None,
+        user_agent.to_str().unwrap_or_default(),
+    ));
+    state
+        .log_writer
+        .write(log_writer_service::LogEntry::Login {
+            user_id: *user_id,
+            ip_address,
+            params: system_login_log_server::CreateParams {
+                r#type: Some(login_type.into()),
+                ip_address_name: None,
+                browser_agent: match user_agent.to_str() {
+                    Ok(x) => Some(x.to_owned()),
+                    Err(_) => None,
+                },
             },
-        },
-    )
-    .await?;
+        })
+        .await;
     Ok(())
 }
+/// report the failed login to the security event sinks
+fn login_failed(
+    state: &AppState,
+    addr: SocketAddr,
+    user_agent: &HeaderValue,
+    username: &str,
+    reason: &str,
+) {
+    state.events.emit(SecurityEvent::login_failed(
+        username,
+        &addr.to_string(),
+        user_agent.to_str().unwrap_or_default(),
+        reason,
+    ));
+}
+
 /// auth for mobile
 async fn login_by_mobile(
     State(state): State<AppState>,
@@ -110,11 +142,15 @@ async fn login_by_mobile(
     Json(params): Json<LoginByMobileRequest>,
 ) -> Result<impl IntoResponse> {
     // todo
-    let user = system_user_service::find_user_by_phone(&state.db, &params.mobile)
-        .await?
-        .ok_or(ErrorCode::InputUserAndPwd)?;
+    let user = match system_user_service::find_user_by_phone(&state.db, &params.mobile).await? {
+        Some(user) => user,
+        None => {
+            login_failed(&state, addr, &user_agent, &params.mobile, "unknown mobile");
+            return Err(ErrorCode::InputUserAndPwd);
+        }
+    };
     let token_cache_type = service::cache_service::CacheType::SystemAuthJwt;
-    let mut cache = state.cache.lock().await;
+    let cache = &state.cache;
     let token = generate_token(Claims::build(user.id()), "secret");
     cache
         .put(token_cache_type, &token, user.id(), Some(24 * 3600), None)
@@ -144,7 +180,7 @@ async fn login_by_qrcode(
 ) -> Result<impl IntoResponse> {
     // todo
     let token_cache_type = service::cache_service::CacheType::SystemAuthJwt;
-    let mut cache = state.cache.lock().await;
+    let cache = &state.cache;
     let token = generate_token(Claims::build(&1), "secret");
     cache
         .put(token_cache_type, &token, 1, Some(24 * 3600), None)
@@ -178,8 +214,6 @@ async fn get_captcha(State(state): State<AppState>) -> Result<impl IntoResponse>
     let key = utils::datetime::now_timestamp(None).to_string();
     state
         .cache
-        .lock()
-        .await
         .put(
             service::cache_service::CacheType::SystemAuthLoginCaptcha,
             &key,
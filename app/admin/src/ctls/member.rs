@@ -1,3 +1,4 @@
+use super::ApiRouter;
 use crate::{
     error::{ErrorCode, Result},
     state::AppState,
@@ -6,22 +7,20 @@ use axum::{
     body::Body,
     extract::{self, Path, State},
     response::IntoResponse,
-    routing::{delete, get, post, put},
-    Json, Router,
+    Json,
 };
 use axum_extra::extract::Query;
 use serde::Deserialize;
-use service::member_service;
+use service::{member_service, member_team_service};
 use utils::paginate::PaginateParams;
 
-pub fn routers<S>(state: crate::state::AppState) -> axum::Router<S> {
-    Router::new()
-        .route("/member", get(index))
-        .route("/member/:id", get(info))
-        .route("/member", post(create))
-        .route("/member/:id", put(update))
-        .route("/member/:id", delete(del))
-        .with_state(state)
+pub fn routers() -> ApiRouter {
+    ApiRouter::new()
+        .get("/member", index, "列表")
+        .get("/member/:id", info, "详情")
+        .post("/member", create, "新增")
+        .put("/member/:id", update, "更新")
+        .delete("/member/:id", del, "删除")
 }
 
 /// member list
@@ -52,12 +51,21 @@ async fn create(
     {
         return Err(ErrorCode::EmailExsist);
     }
+    let invite_code = params.invite_code.clone().filter(|x| !x.trim().is_empty());
+    if let Some(invite_code) = &invite_code {
+        if let Some(refusal) =
+            member_team_service::check_invite(&state.db, invite_code, None).await?
+        {
+            return Err(refusal.into());
+        }
+    }
     let unique_code = member_service::generate_code(&state.db, 8).await?;
     member_service::create(
         &state.db,
         &unique_code,
         &params.email.clone(),
-        params.into(),
+        params.try_into()?,
+        invite_code.as_deref(),
     )
     .await?;
     Ok(Body::empty())
@@ -75,7 +83,7 @@ async fn update(
     {
         return Err(ErrorCode::EmailExsist);
     }
-    member_service::update(&state.db, id, params.into()).await?;
+    member_service::update(&state.db, id, params.try_into()?).await?;
     Ok(Body::empty())
 }
 
@@ -115,50 +123,62 @@ struct CreateRequest {
     password: Option<String>,
     #[serde(default)]
     sex: Option<i32>,
-    #[serde(default)]
-    balance: Option<bigdecimal::BigDecimal>,
-    #[serde(default)]
-    integral: Option<i32>,
     remark: Option<String>,
     #[serde(default)]
     status: Option<i32>,
     #[serde(default)]
     is_promoter: Option<i32>,
+    /// unique code of the inviter, only used on create
+    invite_code: Option<String>,
 }
 
-impl From<CreateRequest> for member_service::CreateParams {
-    fn from(value: CreateRequest) -> Self {
-        Self {
+impl TryFrom<CreateRequest> for member_service::CreateParams {
+    type Error = ErrorCode;
+
+    fn try_from(value: CreateRequest) -> Result<Self> {
+        let (password, salt) = hash_password(value.password)?;
+        Ok(Self {
             mobile: value.mobile,
             nickname: value.nickname,
             avatar: value.avatar,
-            password: value.password,
-            salt: None,
+            password,
+            salt,
             sex: value.sex,
-            balance: value.balance,
-            integral: value.integral,
             remark: value.remark,
             status: value.status,
             is_promoter: value.is_promoter,
-        }
+        })
     }
 }
 
-impl From<CreateRequest> for member_service::UpdateParams {
-    fn from(value: CreateRequest) -> Self {
-        Self {
+impl TryFrom<CreateRequest> for member_service::UpdateParams {
+    type Error = ErrorCode;
+
+    fn try_from(value: CreateRequest) -> Result<Self> {
+        let (password, salt) = hash_password(value.password)?;
+        Ok(Self {
             email: Some(value.email),
             mobile: value.mobile,
             nickname: value.nickname,
             avatar: value.avatar,
-            password: value.password,
-            salt: None,
+            password,
+            salt,
             sex: value.sex,
-            balance: value.balance,
-            integral: value.integral,
             remark: value.remark,
             status: value.status,
             is_promoter: value.is_promoter,
-        }
+        })
     }
 }
+
+/// hash the password when one is given, an empty one keeps the current password
+fn hash_password(password: Option<String>) -> Result<(Option<String>, Option<String>)> {
+    Ok(match password.filter(|x| !x.is_empty()) {
+        Some(password) => {
+            let (password, salt) =
+                utils::password::Password::generate_hash_salt(password.as_bytes())?;
+            (Some(password), Some(salt))
+        }
+        None => (None, None),
+    })
+}
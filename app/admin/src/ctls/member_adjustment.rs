@@ -0,0 +1,187 @@
+use super::{ApiRouter, Claims, IdempotencyKey};
+use crate::{
+    error::{ErrorCode, Result},
+    state::AppState,
+};
+use axum::{
+    body::Body,
+    extract::{self, Path, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+use axum_extra::extract::Query;
+use bigdecimal::BigDecimal;
+use serde::Deserialize;
+use service::{
+    member_adjustment_service::{self, AdjustmentStatus},
+    member_service,
+};
+use utils::paginate::PaginateParams;
+
+pub fn routers() -> ApiRouter {
+    ApiRouter::new()
+        .get("/member_adjustment", index, "列表")
+        .get("/member_adjustment/:id", info, "详情")
+        .post("/member_adjustment", create, "调整")
+        .put("/member_adjustment/:id/approve", approve, "审核通过")
+        .put("/member_adjustment/:id/reject", reject, "驳回")
+}
+
+/// balance and integral adjustment list
+async fn index(
+    State(state): State<AppState>,
+    Query(params): Query<SearchRequest>,
+) -> Result<impl IntoResponse> {
+    let data = member_adjustment_service::paginate(&state.db, &params.into()).await?;
+    Ok(Json(data))
+}
+
+/// adjustment detail
+async fn info(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<i32>,
+) -> Result<impl IntoResponse> {
+    Ok(Json(member_adjustment_service::info(&state.db, id).await?))
+}
+
+/// adjust a member`s balance or integral, large adjustments wait for approval;
+/// a request repeated with its `Idempotency-Key` returns the first adjustment
+async fn create(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    IdempotencyKey(key): IdempotencyKey,
+    Json(params): Json<CreateRequest>,
+) -> Result<impl IntoResponse> {
+    params.check()?;
+    member_service::info(&state.db, params.member_id).await?;
+    let needs_approval = state
+        .adjust_approval
+        .needs_approval(&params.balance, params.integral);
+    let member_id = params.member_id;
+    let remark = params.remark.trim().to_owned();
+    let mut params = member_adjustment_service::CreateParams::from(params);
+    params.idempotency_key = Some(key);
+    let data = member_adjustment_service::create(
+        &state.db,
+        member_id,
+        claims.user_id,
+        &remark,
+        params,
+        needs_approval,
+    )
+    .await?;
+    Ok(Json(data))
+}
+
+/// approve and execute a pending adjustment, by another admin than its creator
+async fn approve(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(params): Json<ReviewRequest>,
+) -> Result<impl IntoResponse> {
+    review(
+        state,
+        id,
+        claims.user_id,
+        AdjustmentStatus::Executed,
+        params,
+    )
+    .await
+}
+
+/// reject a pending adjustment
+async fn reject(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(params): Json<ReviewRequest>,
+) -> Result<impl IntoResponse> {
+    review(
+        state,
+        id,
+        claims.user_id,
+        AdjustmentStatus::Rejected,
+        params,
+    )
+    .await
+}
+
+async fn review(
+    state: AppState,
+    id: i32,
+    user_id: i32,
+    status: AdjustmentStatus,
+    params: ReviewRequest,
+) -> Result<Body> {
+    let data = member_adjustment_service::info(&state.db, id).await?;
+    if data.status() != AdjustmentStatus::Pending {
+        return Err(ErrorCode::AdjustNotPending);
+    }
+    if data.created_by() == user_id {
+        return Err(ErrorCode::AdjustSelfReview);
+    }
+    member_adjustment_service::review(&state.db, id, user_id, status, &params.remark)
+        .await?
+        .ok_or(ErrorCode::AdjustNotPending)?;
+    Ok(Body::empty())
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchRequest {
+    member_id: Option<i32>,
+    status: Option<AdjustmentStatus>,
+    #[serde(flatten)]
+    paginate: PaginateParams,
+}
+impl From<SearchRequest> for member_adjustment_service::SearchParams {
+    fn from(value: SearchRequest) -> Self {
+        Self::new(value.member_id, value.status, value.paginate)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateRequest {
+    member_id: i32,
+    /// negative to deduct
+    #[serde(default)]
+    balance: BigDecimal,
+    /// negative to deduct
+    #[serde(default)]
+    integral: i32,
+    #[serde(default)]
+    order_id: String,
+    remark: String,
+}
+
+impl CreateRequest {
+    fn check(&self) -> Result<()> {
+        if self.remark.trim().is_empty() {
+            return Err(ErrorCode::AdjustRemark);
+        }
+        let zero = BigDecimal::default();
+        if (self.balance == zero && self.integral == 0)
+            || self.balance.with_scale(2) != self.balance
+        {
+            return Err(ErrorCode::AdjustAmount);
+        }
+        Ok(())
+    }
+}
+
+impl From<CreateRequest> for member_adjustment_service::CreateParams {
+    fn from(value: CreateRequest) -> Self {
+        Self {
+            balance: Some(value.balance),
+            integral: Some(value.integral),
+            order_id: Some(value.order_id.trim().to_owned()),
+            idempotency_key: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ReviewRequest {
+    #[serde(default)]
+    remark: String,
+}
@@ -1,20 +1,19 @@
+use super::ApiRouter;
 use crate::{error::Result, state::AppState};
 use axum::{
     extract::{self, State},
     response::IntoResponse,
-    routing::get,
-    Json, Router,
+    Json,
 };
 use axum_extra::extract::Query;
 use serde::Deserialize;
 use service::member_bill_service;
 use utils::paginate::PaginateParams;
 
-pub fn routers<S>(state: crate::state::AppState) -> axum::Router<S> {
-    Router::new()
-        .route("/member_bill", get(index))
-        .route("/member_bill/:id", get(info))
-        .with_state(state)
+pub fn routers() -> ApiRouter {
+    ApiRouter::new()
+        .get("/member_bill", index, "列表")
+        .get("/member_bill/:id", info, "详情")
 }
 
 /// member bill list
@@ -38,6 +37,7 @@ async fn info(
 struct SearchRequest {
     r#type: Option<member_bill_service::BillType>,
     pm: Option<member_bill_service::BillPm>,
+    source: Option<member_bill_service::BillSource>,
     keyword: Option<String>,
     date: Option<String>,
     #[serde(flatten)]
@@ -48,6 +48,7 @@ impl From<SearchRequest> for member_bill_service::SearchParams {
         Self::new(
             value.r#type,
             value.pm,
+            value.source,
             value.keyword,
             value.date,
             value.paginate,
@@ -0,0 +1,225 @@
+use super::ApiRouter;
+use crate::{
+    error::{ErrorCode, Result},
+    state::AppState,
+};
+use axum::{
+    body::Body,
+    extract::{self, Path, State},
+    response::IntoResponse,
+    Json,
+};
+use axum_extra::extract::Query;
+use bigdecimal::BigDecimal;
+use serde::Deserialize;
+use service::{
+    member_bill_service::BillType,
+    member_commission_service::{self, CommissionMode},
+};
+use utils::paginate::PaginateParams;
+
+pub fn routers() -> ApiRouter {
+    ApiRouter::new()
+        .get("/member_commission_rule", rule_index, "列表")
+        .get("/member_commission_rule/:id", rule_info, "详情")
+        .post("/member_commission_rule", rule_create, "新增")
+        .put("/member_commission_rule/:id", rule_update, "更新")
+        .delete("/member_commission_rule/:id", rule_del, "删除")
+        .get("/member_commission", index, "列表")
+        .get("/member_commission/report", report, "结算报表")
+}
+
+/// commission rule list
+async fn rule_index(
+    State(state): State<AppState>,
+    Query(params): Query<RuleSearchRequest>,
+) -> Result<impl IntoResponse> {
+    let data = member_commission_service::rule_paginate(&state.db, &params.into()).await?;
+    Ok(Json(data))
+}
+
+/// commission rule detail
+async fn rule_info(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<i32>,
+) -> Result<impl IntoResponse> {
+    Ok(Json(
+        member_commission_service::rule_info(&state.db, id).await?,
+    ))
+}
+
+/// create commission rule
+async fn rule_create(
+    State(state): State<AppState>,
+    Json(params): Json<RuleCreateRequest>,
+) -> Result<impl IntoResponse> {
+    params.check()?;
+    member_commission_service::create_rule(&state.db, &params.name.clone(), params.into()).await?;
+    Ok(Body::empty())
+}
+
+/// update commission rule
+async fn rule_update(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+    Json(params): Json<RuleCreateRequest>,
+) -> Result<impl IntoResponse> {
+    params.check()?;
+    member_commission_service::rule_info(&state.db, id).await?;
+    member_commission_service::update_rule(&state.db, id, params.into()).await?;
+    Ok(Body::empty())
+}
+
+/// delete commission rule
+async fn rule_del(Path(id): Path<i32>, State(state): State<AppState>) -> Result<impl IntoResponse> {
+    member_commission_service::rule_info(&state.db, id).await?;
+    member_commission_service::delete_rule(&state.db, id).await?;
+    Ok(Body::empty())
+}
+
+/// paid commission list
+async fn index(
+    State(state): State<AppState>,
+    Query(params): Query<SearchRequest>,
+) -> Result<impl IntoResponse> {
+    let data = member_commission_service::paginate(&state.db, &params.into()).await?;
+    Ok(Json(data))
+}
+
+/// commissions of every member within the dates
+async fn report(
+    State(state): State<AppState>,
+    Query(params): Query<ReportRequest>,
+) -> Result<impl IntoResponse> {
+    let data = member_commission_service::report(
+        &state.db,
+        params.start_date,
+        params.end_date,
+        &params.paginate,
+    )
+    .await?;
+    Ok(Json(data))
+}
+
+#[derive(Debug, Deserialize)]
+struct RuleSearchRequest {
+    keyword: Option<String>,
+    level: Option<i32>,
+    status: Option<i32>,
+    #[serde(flatten)]
+    paginate: PaginateParams,
+}
+impl From<RuleSearchRequest> for member_commission_service::RuleSearchParams {
+    fn from(value: RuleSearchRequest) -> Self {
+        Self::new(value.keyword, value.level, value.status, value.paginate)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchRequest {
+    member_id: Option<i32>,
+    source_member_id: Option<i32>,
+    rule_id: Option<i32>,
+    #[serde(flatten)]
+    paginate: PaginateParams,
+}
+impl From<SearchRequest> for member_commission_service::SearchParams {
+    fn from(value: SearchRequest) -> Self {
+        Self::new(
+            value.member_id,
+            value.source_member_id,
+            value.rule_id,
+            value.paginate,
+        )
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ReportRequest {
+    /// %Y-%m-%d
+    start_date: Option<String>,
+    /// %Y-%m-%d
+    end_date: Option<String>,
+    #[serde(flatten)]
+    paginate: PaginateParams,
+}
+
+#[derive(Debug, Deserialize)]
+struct RuleCreateRequest {
+    name: String,
+    /// bills paying the commission, never commission bills
+    bill_types: Vec<BillType>,
+    /// level of the paid upline, 1 is the inviter
+    level: i32,
+    mode: CommissionMode,
+    /// percent or fixed amount
+    value: BigDecimal,
+    /// most paid per bill, 0 for no cap
+    #[serde(default)]
+    cap: BigDecimal,
+    #[serde(default = "default_promoter_only")]
+    promoter_only: bool,
+    remark: Option<String>,
+    #[serde(default)]
+    status: i32,
+}
+
+fn default_promoter_only() -> bool {
+    true
+}
+
+impl RuleCreateRequest {
+    fn check(&self) -> Result<()> {
+        let zero = BigDecimal::default();
+        if self.level < 1
+            || self.value <= zero
+            || self.cap < zero
+            || self.bill_types.is_empty()
+            || self.bill_types.contains(&BillType::Commission)
+            || self.bill_types.contains(&BillType::Withdraw)
+        {
+            return Err(ErrorCode::CommissionRule);
+        }
+        Ok(())
+    }
+
+    fn get_bill_types(&self) -> String {
+        let bill_types = self
+            .bill_types
+            .iter()
+            .map(|x| x.clone().into())
+            .collect::<Vec<i32>>();
+        serde_json::to_string(&bill_types).unwrap_or_default()
+    }
+}
+
+impl From<RuleCreateRequest> for member_commission_service::RuleCreateParams {
+    fn from(value: RuleCreateRequest) -> Self {
+        Self {
+            bill_types: Some(value.get_bill_types()),
+            level: Some(value.level),
+            mode: Some(value.mode.into()),
+            value: Some(value.value),
+            cap: Some(value.cap),
+            promoter_only: Some(value.promoter_only),
+            remark: value.remark,
+            status: Some(value.status),
+        }
+    }
+}
+
+impl From<RuleCreateRequest> for member_commission_service::RuleUpdateParams {
+    fn from(value: RuleCreateRequest) -> Self {
+        Self {
+            name: Some(value.name.clone()),
+            bill_types: Some(value.get_bill_types()),
+            level: Some(value.level),
+            mode: Some(value.mode.into()),
+            value: Some(value.value),
+            cap: Some(value.cap),
+            promoter_only: Some(value.promoter_only),
+            remark: value.remark,
+            status: Some(value.status),
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use super::ApiRouter;
+use crate::{error::Result, state::AppState};
+use axum::{
+    extract::{self, State},
+    response::IntoResponse,
+    Json,
+};
+use axum_extra::extract::Query;
+use serde::Deserialize;
+use service::member_recharge_service::{self, RechargeStatus};
+use utils::paginate::PaginateParams;
+
+pub fn routers() -> ApiRouter {
+    ApiRouter::new().get("/member_recharge", index, "列表").get(
+        "/member_recharge/:id",
+        info,
+        "详情",
+    )
+}
+
+/// member recharge list
+async fn index(
+    State(state): State<AppState>,
+    Query(params): Query<SearchRequest>,
+) -> Result<impl IntoResponse> {
+    let data = member_recharge_service::paginate(&state.db, &params.into()).await?;
+    Ok(Json(data))
+}
+
+/// member recharge detail
+async fn info(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<i32>,
+) -> Result<impl IntoResponse> {
+    Ok(Json(member_recharge_service::info(&state.db, id).await?))
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchRequest {
+    member_id: Option<i32>,
+    order_no: Option<String>,
+    status: Option<RechargeStatus>,
+    #[serde(flatten)]
+    paginate: PaginateParams,
+}
+impl From<SearchRequest> for member_recharge_service::SearchParams {
+    fn from(value: SearchRequest) -> Self {
+        Self::new(
+            value.member_id,
+            value.order_no,
+            value.status,
+            value.paginate,
+        )
+    }
+}
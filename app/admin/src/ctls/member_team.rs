@@ -1,20 +1,26 @@
-use crate::{error::Result, state::AppState};
+use super::ApiRouter;
+use crate::{
+    error::{ErrorCode, Result},
+    state::AppState,
+};
 use axum::{
     extract::{self, State},
     response::IntoResponse,
-    routing::get,
-    Json, Router,
+    Json,
 };
 use axum_extra::extract::Query;
 use serde::Deserialize;
 use service::member_team_service;
-use utils::paginate::PaginateParams;
+use utils::{datetime::parse_date, paginate::PaginateParams};
 
-pub fn routers<S>(state: crate::state::AppState) -> axum::Router<S> {
-    Router::new()
-        .route("/member_team", get(index))
-        .route("/member_team/:id", get(info))
-        .with_state(state)
+pub fn routers() -> ApiRouter {
+    ApiRouter::new()
+        .get("/member_team", index, "列表")
+        .get("/member_team/:id", info, "详情")
+        .get("/member_team/downline/:member_id", downline, "下级团队")
+        .get("/member_team/upline/:member_id", upline, "上级链")
+        .get("/member_team/levels/:member_id", levels, "层级人数")
+        .get("/member_team/stats/:member_id", stats, "团队统计")
 }
 
 /// member team list
@@ -34,6 +40,77 @@ async fn info(
     Ok(Json(member_team_service::info(&state.db, id).await?))
 }
 
+/// downline tree of the member
+async fn downline(
+    State(state): State<AppState>,
+    extract::Path(member_id): extract::Path<i32>,
+    Query(params): Query<DownlineRequest>,
+) -> Result<impl IntoResponse> {
+    let data = member_team_service::downline(
+        &state.db,
+        member_id,
+        params.depth.unwrap_or(1),
+        params.page.unwrap_or(1),
+    )
+    .await?;
+    Ok(Json(data))
+}
+
+/// upline chain of the member
+async fn upline(
+    State(state): State<AppState>,
+    extract::Path(member_id): extract::Path<i32>,
+) -> Result<impl IntoResponse> {
+    Ok(Json(
+        member_team_service::upline(&state.db, member_id).await?,
+    ))
+}
+
+/// members on every level of the team
+async fn levels(
+    State(state): State<AppState>,
+    extract::Path(member_id): extract::Path<i32>,
+) -> Result<impl IntoResponse> {
+    Ok(Json(
+        member_team_service::level_counts(&state.db, member_id).await?,
+    ))
+}
+
+/// team statistics of the member
+async fn stats(
+    State(state): State<AppState>,
+    extract::Path(member_id): extract::Path<i32>,
+    Query(params): Query<StatsRequest>,
+) -> Result<impl IntoResponse> {
+    let start_date = params
+        .start_date
+        .map(|x| parse_date(&x).ok_or(ErrorCode::DateFormat))
+        .transpose()?;
+    let end_date = params
+        .end_date
+        .map(|x| parse_date(&x).ok_or(ErrorCode::DateFormat))
+        .transpose()?;
+    Ok(Json(
+        member_team_service::stats(&state.db, member_id, start_date, end_date).await?,
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+struct DownlineRequest {
+    /// levels loaded at once, 1 to 3
+    depth: Option<i32>,
+    /// page of the first level, from 1
+    page: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StatsRequest {
+    /// %Y-%m-%d
+    start_date: Option<String>,
+    /// %Y-%m-%d
+    end_date: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 struct SearchRequest {
     keyword: Option<String>,
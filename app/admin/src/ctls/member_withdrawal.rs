@@ -0,0 +1,127 @@
+use super::{ApiRouter, Claims};
+use crate::{
+    error::{ErrorCode, Result},
+    state::AppState,
+};
+use axum::{
+    body::Body,
+    extract::{self, Path, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+use axum_extra::extract::Query;
+use serde::Deserialize;
+use service::member_withdrawal_service::{self, WithdrawalStatus};
+use utils::paginate::PaginateParams;
+
+pub fn routers() -> ApiRouter {
+    ApiRouter::new()
+        .get("/member_withdrawal", index, "列表")
+        .get("/member_withdrawal/:id", info, "详情")
+        .put("/member_withdrawal/:id/approve", approve, "审核通过")
+        .put("/member_withdrawal/:id/payout", payout, "打款")
+        .put("/member_withdrawal/:id/reject", reject, "驳回")
+        .put("/member_withdrawal/:id/refund", refund, "退款")
+}
+
+/// member withdrawal list
+async fn index(
+    State(state): State<AppState>,
+    Query(params): Query<SearchRequest>,
+) -> Result<impl IntoResponse> {
+    let data = member_withdrawal_service::paginate(&state.db, &params.into()).await?;
+    Ok(Json(data))
+}
+
+/// member withdrawal detail
+async fn info(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<i32>,
+) -> Result<impl IntoResponse> {
+    Ok(Json(member_withdrawal_service::info(&state.db, id).await?))
+}
+
+/// approve a pending withdrawal and pay it out
+async fn approve(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(params): Json<ReviewRequest>,
+) -> Result<impl IntoResponse> {
+    member_withdrawal_service::approve(
+        &state.db,
+        state.payment.as_ref(),
+        id,
+        claims.user_id,
+        &params.remark,
+    )
+    .await?
+    .ok_or(ErrorCode::WithdrawalStatus)?;
+    Ok(Body::empty())
+}
+
+/// pay out an approved withdrawal again, after its payout failed
+async fn payout(Path(id): Path<i32>, State(state): State<AppState>) -> Result<impl IntoResponse> {
+    member_withdrawal_service::payout(&state.db, state.payment.as_ref(), id)
+        .await?
+        .ok_or(ErrorCode::WithdrawalStatus)?;
+    Ok(Body::empty())
+}
+
+/// reject a pending withdrawal, refunding its amount
+async fn reject(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(params): Json<ReviewRequest>,
+) -> Result<impl IntoResponse> {
+    member_withdrawal_service::reject(&state.db, id, claims.user_id, &params.remark)
+        .await?
+        .ok_or(ErrorCode::WithdrawalStatus)?;
+    Ok(Body::empty())
+}
+
+/// refund an approved withdrawal whose payout failed, once the gateway
+/// confirms it paid nothing
+async fn refund(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(params): Json<ReviewRequest>,
+) -> Result<impl IntoResponse> {
+    member_withdrawal_service::refund(
+        &state.db,
+        state.payment.as_ref(),
+        id,
+        claims.user_id,
+        &params.remark,
+    )
+    .await?
+    .ok_or(ErrorCode::WithdrawalStatus)?;
+    Ok(Body::empty())
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchRequest {
+    member_id: Option<i32>,
+    order_no: Option<String>,
+    status: Option<WithdrawalStatus>,
+    #[serde(flatten)]
+    paginate: PaginateParams,
+}
+impl From<SearchRequest> for member_withdrawal_service::SearchParams {
+    fn from(value: SearchRequest) -> Self {
+        Self::new(
+            value.member_id,
+            value.order_no,
+            value.status,
+            value.paginate,
+        )
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ReviewRequest {
+    #[serde(default)]
+    remark: String,
+}
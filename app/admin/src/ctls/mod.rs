@@ -1,12 +1,20 @@
 mod auth;
 mod member;
+mod member_adjustment;
 mod member_bill;
+mod member_commission;
+mod member_recharge;
 mod member_team;
+mod member_withdrawal;
 mod sys_action_log;
+mod sys_audit;
 mod sys_dept;
 mod sys_dict;
 mod sys_dict_data;
+mod sys_grant;
 mod sys_login_log;
+mod sys_mail_outbox;
+mod sys_mail_template;
 mod sys_menu;
 mod sys_role;
 mod sys_user;
@@ -26,6 +34,121 @@ impl Claims {
     }
 }
 
+/// `Idempotency-Key` request header, a request repeated with the key
+/// returns the result of the first one
+pub struct IdempotencyKey(pub Option<String>);
+#[axum::async_trait]
+impl<S> axum::extract::FromRequestParts<S> for IdempotencyKey
+where
+    S: Send + Sync,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(
+            parts
+                .headers
+                .get("Idempotency-Key")
+                .and_then(|x| x.to_str().ok())
+                .map(|x| x.trim().to_owned())
+                .filter(|x| !x.is_empty()),
+        ))
+    }
+}
+
+/// registered api route, collected alongside the axum router
+pub struct ApiRouter<S = crate::state::AppState> {
+    router: axum::Router<S>,
+    routes: Vec<service::system_menu_service::ApiRoute>,
+}
+
+impl<S> ApiRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    pub fn new() -> Self {
+        Self {
+            router: axum::Router::new(),
+            routes: vec![],
+        }
+    }
+
+    pub fn get<H, T>(self, path: &str, handler: H, title: &str) -> Self
+    where
+        H: axum::handler::Handler<T, S>,
+        T: 'static,
+    {
+        self.route("GET", path, axum::routing::get(handler), title)
+    }
+
+    pub fn post<H, T>(self, path: &str, handler: H, title: &str) -> Self
+    where
+        H: axum::handler::Handler<T, S>,
+        T: 'static,
+    {
+        self.route("POST", path, axum::routing::post(handler), title)
+    }
+
+    pub fn put<H, T>(self, path: &str, handler: H, title: &str) -> Self
+    where
+        H: axum::handler::Handler<T, S>,
+        T: 'static,
+    {
+        self.route("PUT", path, axum::routing::put(handler), title)
+    }
+
+    pub fn delete<H, T>(self, path: &str, handler: H, title: &str) -> Self
+    where
+        H: axum::handler::Handler<T, S>,
+        T: 'static,
+    {
+        self.route("DELETE", path, axum::routing::delete(handler), title)
+    }
+
+    pub fn merge(mut self, other: ApiRouter<S>) -> Self {
+        self.router = self.router.merge(other.router);
+        self.routes.extend(other.routes);
+        self
+    }
+
+    /// registered routes
+    pub fn routes(&self) -> &[service::system_menu_service::ApiRoute] {
+        &self.routes
+    }
+
+    pub fn into_router(self) -> axum::Router<S> {
+        self.router
+    }
+
+    fn route(
+        mut self,
+        method: &str,
+        path: &str,
+        method_router: axum::routing::MethodRouter<S>,
+        title: &str,
+    ) -> Self {
+        self.router = self.router.route(path, method_router);
+        self.routes.push(service::system_menu_service::ApiRoute {
+            method: method.to_owned(),
+            path: path.to_owned(),
+            title: title.to_owned(),
+        });
+        self
+    }
+}
+
+impl<S> Default for ApiRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// router mod
 pub mod router {
     use super::*;
@@ -39,20 +162,62 @@ pub mod router {
             .merge(auths(state))
     }
 
+    /// routes which need api permission, used to sync api menus
+    pub fn api_routers() -> ApiRouter {
+        ApiRouter::new()
+            .merge(sys_user::routers())
+            .merge(sys_role::routers())
+            .merge(sys_menu::routers())
+            .merge(sys_dept::routers())
+            .merge(sys_dict::routers())
+            .merge(sys_dict_data::routers())
+            .merge(sys_grant::routers())
+            .merge(sys_login_log::routers())
+            .merge(sys_action_log::routers())
+            .merge(sys_audit::routers())
+            .merge(sys_mail_template::routers())
+            .merge(sys_mail_outbox::routers())
+            .merge(member::routers())
+            .merge(member_team::routers())
+            .merge(member_bill::routers())
+            .merge(member_commission::routers())
+            .merge(member_adjustment::routers())
+            .merge(member_recharge::routers())
+            .merge(member_withdrawal::routers())
+    }
+
+    /// report api routes missing from menus and api menus without route
+    pub async fn check(db: &service::Database, create: bool) -> service::Result<()> {
+        let routes = api_routers();
+        let diff = service::system_menu_service::diff_api_routes(db, routes.routes()).await?;
+        for route in diff.missing.iter() {
+            tracing::warn!(
+                "api route without permission menu: {} {} ({})",
+                route.method,
+                route.path,
+                route.title
+            );
+        }
+        for menu in diff.orphaned.iter() {
+            tracing::warn!(
+                "api permission menu without route: #{} {} {} ({})",
+                menu.id,
+                menu.api_method,
+                menu.api_url,
+                menu.title
+            );
+        }
+        if create && !diff.missing.is_empty() {
+            let created = service::system_menu_service::create_api_menus(db, diff.missing).await?;
+            tracing::info!("created {} api permission menus", created.len());
+        }
+        Ok(())
+    }
+
     /// need auth`routers
     fn auths(state: AppState) -> Router {
-        Router::new()
-            .merge(sys_user::routers(state.clone()))
-            .merge(sys_role::routers(state.clone()))
-            .merge(sys_menu::routers(state.clone()))
-            .merge(sys_dept::routers(state.clone()))
-            .merge(sys_dict::routers(state.clone()))
-            .merge(sys_dict_data::routers(state.clone()))
-            .merge(sys_login_log::routers(state.clone()))
-            .merge(sys_action_log::routers(state.clone()))
-            .merge(member::routers(state.clone()))
-            .merge(member_team::routers(state.clone()))
-            .merge(member_bill::routers(state.clone()))
+        api_routers()
+            .into_router()
             .layer(middleware::from_fn_with_state(
                 state.clone(),
                 middlewares::access_matched_path,
@@ -75,9 +240,10 @@ mod middlewares {
     use crate::{error::ErrorCode, state::AppState};
     use axum::{
         async_trait,
+        body::{Body, Bytes},
         extract::{
-            rejection::MatchedPathRejection, ConnectInfo, FromRequestParts, MatchedPath, Request,
-            State,
+            rejection::MatchedPathRejection, ConnectInfo, FromRequestParts, MatchedPath,
+            RawPathParams, Request, State,
         },
         http::{
             header::{AUTHORIZATION, USER_AGENT},
@@ -88,6 +254,11 @@ mod middlewares {
         response::{IntoResponse, Response},
         Extension, RequestExt,
     };
+    use futures_util::{stream, StreamExt};
+    use std::{
+        sync::{Arc, Mutex},
+        time::Instant,
+    };
 
     /// get request`User-Agent
     pub struct ExtractUserAgent(pub HeaderValue);
@@ -119,8 +290,14 @@ mod middlewares {
         let headers = req.headers();
         match parse_token(state, headers).await {
             Ok(claims) => {
+                let user_id = claims.user_id;
                 req.extensions_mut().insert(claims);
-                Ok(next.run(req).await)
+                Ok(service::system_audit_service::with_actor(
+                    service::system_audit_service::ActorType::Admin,
+                    user_id,
+                    next.run(req),
+                )
+                .await)
             }
             Err(err) => Ok(err.into_response()),
         }
@@ -147,7 +324,7 @@ mod middlewares {
             })
             .ok_or(ErrorCode::Unauthorized)?;
         let token_cache_type = service::cache_service::CacheType::SystemAuthJwt;
-        let cache = state.cache.lock().await;
+        let cache = &state.cache;
         let claims = super::decode_token(token, "secret")?;
         let jwt_item = cache
             .get(token_cache_type, token, None)
@@ -160,7 +337,8 @@ mod middlewares {
         Ok(claims)
     }
 
-    /// logined user`s permissions check
+    /// logined user`s permissions check, the permitted request is recorded
+    /// into the action log once the handler has responded
     pub async fn access_matched_path(
         Extension(claims): Extension<super::Claims>,
         ExtractUserAgent(user_agent): ExtractUserAgent,
@@ -171,51 +349,134 @@ mod middlewares {
     ) -> Result<Response, StatusCode> {
         let matched_path: Result<MatchedPath, MatchedPathRejection> =
             req.extract_parts::<MatchedPath>().await;
-        Ok(match matched_path {
-            Ok(path) => {
-                let request_method = req.method().as_str();
-                match service::system_user_service::check_user_permission(
-                    &state.db,
-                    claims.user_id,
-                    request_method,
-                    path.as_str(),
-                )
-                .await
-                {
-                    Ok(true) => {
-                        if let Ok(Some(menu_info)) =
-                            service::system_menu_service::get_menu_id_by_api_request(
-                                &state.db,
-                                request_method,
-                                path.as_str(),
-                            )
-                            .await
-                        {
-                            service::system_action_log_service::create(
-                                &state.db,
-                                claims.user_id,
-                                menu_info.0,
-                                &addr.to_string(),
-                                service::system_action_log_service::CreateParams {
-                                    menu_names: Some(menu_info.1),
-                                    ip_address_name: None,
-                                    browser_agent: match user_agent.to_str() {
-                                        Ok(x) => Some(x.to_owned()),
-                                        Err(_) => None,
-                                    },
-                                },
-                            )
-                            .await
-                            .unwrap();
-                        }
-
-                        next.run(req).await
-                    }
-                    _ => ErrorCode::Permissions.into_response(),
+        let path = match matched_path {
+            Ok(path) => path,
+            Err(_) => return Ok(ErrorCode::Permissions.into_response()),
+        };
+        let request_method = req.method().as_str().to_owned();
+        match service::system_user_service::check_user_permission(
+            &state.db,
+            claims.user_id,
+            &request_method,
+            path.as_str(),
+        )
+        .await
+        {
+            Ok(true) => {}
+            _ => return Ok(ErrorCode::Permissions.into_response()),
+        }
+        let menu_info = match service::system_menu_service::get_menu_id_by_api_request(
+            &state.db,
+            &request_method,
+            path.as_str(),
+        )
+        .await
+        {
+            Ok(Some(menu_info)) => menu_info,
+            _ => return Ok(next.run(req).await),
+        };
+
+        let started_at = Instant::now();
+        let config = &state.action_log;
+        let path_params = match req.extract_parts::<RawPathParams>().await {
+            Ok(params) => serde_json::Value::Object(
+                params
+                    .iter()
+                    .map(|(key, value)| (key.to_owned(), serde_json::Value::from(value)))
+                    .collect(),
+            )
+            .to_string(),
+            Err(_) => String::new(),
+        };
+        let uri_path = req.uri().path().to_owned();
+        let query = config.redact_query(req.uri().query().unwrap_or_default());
+        let (parts, body) = req.into_parts();
+        let (body, request_copy) = tee(body, config.max_length);
+        let response = next.run(Request::from_parts(parts, body)).await;
+        let request_body = config.redact_body(&request_copy.lock().unwrap());
+
+        let status = response.status();
+        let (response, error_message) = match status.is_client_error() || status.is_server_error() {
+            true => {
+                let (parts, body) = response.into_parts();
+                let (head, body) = peek(body, config.max_length).await;
+                let error_message = config.truncate(String::from_utf8_lossy(&head).into_owned());
+                (Response::from_parts(parts, body), error_message)
+            }
+            false => (response, String::new()),
+        };
+
+        let user_agent = user_agent.to_str().unwrap_or_default().to_owned();
+        state
+            .events
+            .emit(service::security_event_service::SecurityEvent::action(
+                claims.user_id,
+                &addr.to_string(),
+                &user_agent,
+                &request_method,
+                &uri_path,
+                status.as_u16() as i32,
+                &menu_info.1,
+            ));
+        state
+            .log_writer
+            .write(service::log_writer_service::LogEntry::Action {
+                user_id: claims.user_id,
+                menu_id: menu_info.0,
+                ip_address: addr.to_string(),
+                params: service::system_action_log_service::CreateParams {
+                    menu_names: Some(menu_info.1),
+                    ip_address_name: None,
+                    browser_agent: Some(user_agent),
+                    method: Some(request_method),
+                    path: Some(uri_path),
+                    path_params: Some(path_params),
+                    query: Some(query),
+                    request_body: Some(request_body),
+                    status: Some(status.as_u16() as i32),
+                    error_message: Some(error_message),
+                    duration: Some(started_at.elapsed().as_millis() as i32),
+                },
+            })
+            .await;
+        Ok(response)
+    }
+
+    /// forward the body unchanged, keeping a copy of its first `limit` bytes
+    /// as the handler reads it
+    fn tee(body: Body, limit: usize) -> (Body, Arc<Mutex<Vec<u8>>>) {
+        let copy = Arc::new(Mutex::new(Vec::new()));
+        let sink = copy.clone();
+        let stream = body.into_data_stream().inspect(move |chunk| {
+            if let Ok(chunk) = chunk {
+                let mut sink = sink.lock().unwrap();
+                let take = limit.saturating_sub(sink.len()).min(chunk.len());
+                sink.extend_from_slice(&chunk[..take]);
+            }
+        });
+        (Body::from_stream(stream), copy)
+    }
+
+    /// read the first `limit` bytes of the body, the returned body still
+    /// yields all of it
+    async fn peek(body: Body, limit: usize) -> (Vec<u8>, Body) {
+        let mut rest = body.into_data_stream();
+        let (mut head, mut read) = (Vec::new(), Vec::<Result<Bytes, axum::Error>>::new());
+        while head.len() < limit {
+            match rest.next().await {
+                Some(Ok(chunk)) => {
+                    let take = (limit - head.len()).min(chunk.len());
+                    head.extend_from_slice(&chunk[..take]);
+                    read.push(Ok(chunk));
+                }
+                Some(Err(err)) => {
+                    read.push(Err(err));
+                    break;
                 }
+                None => break,
             }
-            Err(_) => ErrorCode::Permissions.into_response(),
-        })
+        }
+        (head, Body::from_stream(stream::iter(read).chain(rest)))
     }
 }
 
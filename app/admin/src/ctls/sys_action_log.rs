@@ -1,28 +1,35 @@
-use crate::{error::Result, state::AppState};
+use super::{ApiRouter, Claims};
+use crate::{
+    error::Result,
+    export::{self, ExportFormat, ExportParams},
+    state::AppState,
+};
 use axum::{
     extract::{self, State},
-    response::IntoResponse,
-    routing::get,
-    Json, Router,
+    response::{IntoResponse, Response},
+    Extension, Json,
 };
 use axum_extra::extract::Query;
 use serde::Deserialize;
-use service::system_action_log_service;
+use service::{system_action_log_service, system_user_service};
+use std::sync::Arc;
 use utils::paginate::PaginateParams;
 
-pub fn routers<S>(state: crate::state::AppState) -> axum::Router<S> {
-    Router::new()
-        .route("/action_log", get(index))
-        .route("/action_log/:id", get(info))
-        .with_state(state)
+pub fn routers() -> ApiRouter {
+    ApiRouter::new()
+        .get("/action_log", index, "列表")
+        .get("/action_log/export", export, "导出")
+        .get("/action_log/:id", info, "详情")
 }
 
 /// action log list
 async fn index(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Query(params): Query<SearchRequest>,
 ) -> Result<impl IntoResponse> {
-    let data = system_action_log_service::paginate(&state.db, &params.into()).await?;
+    let scope = system_user_service::get_data_scope(&state.db, claims.user_id).await?;
+    let data = system_action_log_service::paginate(&state.db, &params.into(), &scope).await?;
     Ok(Json(data))
 }
 
@@ -34,6 +41,31 @@ async fn info(
     Ok(Json(system_action_log_service::info(&state.db, id).await?))
 }
 
+/// action log export, as csv or xlsx
+async fn export(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(params): Query<ExportRequest>,
+) -> Result<Response> {
+    let scope = Arc::new(system_user_service::get_data_scope(&state.db, claims.user_id).await?);
+    let (search, export_params) = params.into();
+    let search = Arc::new(search);
+    export::export(
+        "action_log",
+        system_action_log_service::EXPORT_COLUMNS,
+        export_params,
+        move |before_id, take| {
+            let state = state.clone();
+            let search = search.clone();
+            let scope = scope.clone();
+            async move {
+                system_action_log_service::export(&state.db, &search, &scope, before_id, take).await
+            }
+        },
+    )
+    .await
+}
+
 #[derive(Debug, Deserialize)]
 struct SearchRequest {
     user_id: Option<i32>,
@@ -54,3 +86,33 @@ impl From<SearchRequest> for system_action_log_service::SearchParams {
         )
     }
 }
+
+#[derive(Debug, Deserialize)]
+struct ExportRequest {
+    user_id: Option<i32>,
+    menu_id: Option<i32>,
+    keyword: Option<String>,
+    date: Option<String>,
+    #[serde(default)]
+    format: ExportFormat,
+    columns: Option<String>,
+    limit: Option<i64>,
+}
+impl From<ExportRequest> for (system_action_log_service::SearchParams, ExportParams) {
+    fn from(value: ExportRequest) -> Self {
+        (
+            system_action_log_service::SearchParams::new(
+                value.user_id,
+                value.menu_id,
+                value.keyword,
+                value.date,
+                PaginateParams::new(1, export::MAX_ROWS),
+            ),
+            ExportParams {
+                format: value.format,
+                columns: value.columns,
+                limit: value.limit,
+            },
+        )
+    }
+}
@@ -0,0 +1,57 @@
+use super::ApiRouter;
+use crate::{error::Result, state::AppState};
+use axum::{
+    extract::{self, State},
+    response::IntoResponse,
+    Json,
+};
+use axum_extra::extract::Query;
+use serde::Deserialize;
+use service::system_audit_service::{self, ActorType, AuditAction};
+use utils::paginate::PaginateParams;
+
+pub fn routers() -> ApiRouter {
+    ApiRouter::new()
+        .get("/audit", index, "列表")
+        .get("/audit/:id", info, "详情")
+}
+
+/// audit list
+async fn index(
+    State(state): State<AppState>,
+    Query(params): Query<SearchRequest>,
+) -> Result<impl IntoResponse> {
+    let data = system_audit_service::paginate(&state.db, &params.into()).await?;
+    Ok(Json(data))
+}
+
+/// audit detail
+async fn info(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<i32>,
+) -> Result<impl IntoResponse> {
+    Ok(Json(system_audit_service::info(&state.db, id).await?))
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchRequest {
+    entity: Option<String>,
+    entity_id: Option<i32>,
+    actor_type: Option<ActorType>,
+    user_id: Option<i32>,
+    action: Option<AuditAction>,
+    #[serde(flatten)]
+    paginate: PaginateParams,
+}
+impl From<SearchRequest> for system_audit_service::SearchParams {
+    fn from(value: SearchRequest) -> Self {
+        Self::new(
+            value.entity,
+            value.entity_id,
+            value.actor_type,
+            value.user_id,
+            value.action,
+            value.paginate,
+        )
+    }
+}
@@ -1,24 +1,22 @@
-use super::Claims;
+use super::{ApiRouter, Claims};
 use crate::{error::Result, state::AppState};
 use axum::{
     body::Body,
     extract::{Path, State},
     response::IntoResponse,
-    routing::{delete, get, post, put},
-    Extension, Json, Router,
+    Extension, Json,
 };
 use axum_extra::extract::Query;
 use serde::Deserialize;
 use service::system_dept_service;
 
-pub fn routers<S>(state: crate::state::AppState) -> axum::Router<S> {
-    Router::new()
-        .route("/dept", get(index))
-        .route("/dept/:id", get(info))
-        .route("/dept", post(create))
-        .route("/dept/:id", put(update))
-        .route("/dept/:id", delete(del))
-        .with_state(state)
+pub fn routers() -> ApiRouter {
+    ApiRouter::new()
+        .get("/dept", index, "列表")
+        .get("/dept/:id", info, "详情")
+        .post("/dept", create, "新增")
+        .put("/dept/:id", update, "更新")
+        .delete("/dept/:id", del, "删除")
 }
 /// get tree dept
 async fn index(
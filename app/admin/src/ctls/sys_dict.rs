@@ -1,3 +1,4 @@
+use super::ApiRouter;
 use crate::{
     error::{ErrorCode, Result},
     state::AppState,
@@ -6,23 +7,21 @@ use axum::{
     body::Body,
     extract::{self, Path, State},
     response::IntoResponse,
-    routing::{delete, get, post, put},
-    Json, Router,
+    Json,
 };
 use axum_extra::extract::Query;
 use serde::Deserialize;
 use service::system_dict_service;
 use utils::paginate::PaginateParams;
 
-pub fn routers<S>(state: crate::state::AppState) -> axum::Router<S> {
-    Router::new()
-        .route("/dict", get(index))
-        .route("/dict/all", get(all))
-        .route("/dict/:id", get(info))
-        .route("/dict", post(create))
-        .route("/dict/:id", put(update))
-        .route("/dict/:id", delete(del))
-        .with_state(state)
+pub fn routers() -> ApiRouter {
+    ApiRouter::new()
+        .get("/dict", index, "列表")
+        .get("/dict/all", all, "全部")
+        .get("/dict/:id", info, "详情")
+        .post("/dict", create, "新增")
+        .put("/dict/:id", update, "更新")
+        .delete("/dict/:id", del, "删除")
 }
 
 /// get all dict data
@@ -1,3 +1,4 @@
+use super::ApiRouter;
 use crate::{
     error::{ErrorCode, Result},
     state::AppState,
@@ -6,23 +7,22 @@ use axum::{
     body::Body,
     extract::{self, Path, State},
     response::IntoResponse,
-    routing::{delete, get, post, put},
-    Json, Router,
+    Json,
 };
 use axum_extra::extract::Query;
 use serde::Deserialize;
 use service::system_dict_data_service;
 use utils::paginate::PaginateParams;
 
-pub fn routers<S>(state: crate::state::AppState) -> axum::Router<S> {
-    Router::new()
-        .route("/dict_data", get(index))
-        .route("/dict_data/:id", get(info))
-        .route("/dict_data", post(create))
-        .route("/dict_data/:id", put(update))
-        .route("/dict_data/:id", delete(del))
-        .route("/dict_data", delete(batch_del))
-        .with_state(state)
+pub fn routers() -> ApiRouter {
+    ApiRouter::new()
+        .get("/dict_data", index, "列表")
+        .get("/dict_data/:id", info, "详情")
+        .get("/dict_data/dict/:dict_id", by_dict, "字典数据")
+        .post("/dict_data", create, "新增")
+        .put("/dict_data/:id", update, "更新")
+        .delete("/dict_data/:id", del, "删除")
+        .delete("/dict_data", batch_del, "批量删除")
 }
 
 /// dict data list
@@ -42,6 +42,16 @@ async fn info(
     Ok(Json(system_dict_data_service::info(&state.db, id).await?))
 }
 
+/// data of a dict
+async fn by_dict(
+    State(state): State<AppState>,
+    extract::Path(dict_id): extract::Path<i32>,
+) -> Result<impl IntoResponse> {
+    Ok(Json(
+        system_dict_data_service::get_by_dict(&state.db, dict_id).await?,
+    ))
+}
+
 /// create dict data
 async fn create(
     State(state): State<AppState>,
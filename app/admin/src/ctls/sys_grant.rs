@@ -0,0 +1,131 @@
+use super::{ApiRouter, Claims};
+use crate::{
+    error::{ErrorCode, Result},
+    state::AppState,
+};
+use axum::{
+    body::Body,
+    extract::{self, Path, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+use axum_extra::extract::Query;
+use serde::Deserialize;
+use service::{system_grant_service, system_menu_service};
+use utils::{datetime::parse_datetime, paginate::PaginateParams};
+
+pub fn routers() -> ApiRouter {
+    ApiRouter::new()
+        .get("/grant", index, "列表")
+        .get("/grant/:id", info, "详情")
+        .post("/grant", create, "新增")
+        .put("/grant/:id/revoke", revoke, "撤销")
+}
+
+/// grant list
+async fn index(
+    State(state): State<AppState>,
+    Query(params): Query<SearchRequest>,
+) -> Result<impl IntoResponse> {
+    let data = system_grant_service::paginate(&state.db, &params.into()).await?;
+    Ok(Json(data))
+}
+
+/// grant detail
+async fn info(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<i32>,
+) -> Result<impl IntoResponse> {
+    Ok(Json(system_grant_service::info(&state.db, id).await?))
+}
+
+/// create grant, only with menus of the operator
+async fn create(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(params): Json<CreateRequest>,
+) -> Result<impl IntoResponse> {
+    let user_id = params.user_id.unwrap_or_default();
+    let role_id = params.role_id.unwrap_or_default();
+    if (user_id > 0) == (role_id > 0) {
+        return Err(ErrorCode::GrantTarget);
+    }
+    let (start_at, end_at) = match (
+        parse_datetime(&params.start_at),
+        parse_datetime(&params.end_at),
+    ) {
+        (Some(start_at), Some(end_at)) if start_at < end_at => (start_at, end_at),
+        _ => return Err(ErrorCode::GrantTime),
+    };
+    let menu_ids = system_menu_service::get_user_menus_by_menu_ids(
+        &state.db,
+        claims.user_id,
+        params.menu_ids.clone(),
+    )
+    .await?
+    .into_iter()
+    .map(|x| x.id)
+    .collect::<Vec<i32>>();
+    if menu_ids.is_empty() {
+        return Err(ErrorCode::Permissions);
+    }
+    system_grant_service::create(
+        &state.db,
+        claims.user_id,
+        start_at,
+        end_at,
+        params.into(),
+        menu_ids,
+    )
+    .await?;
+    Ok(Body::empty())
+}
+
+/// revoke grant
+async fn revoke(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<impl IntoResponse> {
+    system_grant_service::revoke(&state.db, id, claims.user_id)
+        .await?
+        .ok_or(ErrorCode::GrantNotActive)?;
+    Ok(Body::empty())
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchRequest {
+    user_id: Option<i32>,
+    role_id: Option<i32>,
+    status: Option<system_grant_service::GrantStatus>,
+    #[serde(flatten)]
+    paginate: PaginateParams,
+}
+impl From<SearchRequest> for system_grant_service::SearchParams {
+    fn from(value: SearchRequest) -> Self {
+        Self::new(value.user_id, value.role_id, value.status, value.paginate)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateRequest {
+    user_id: Option<i32>,
+    role_id: Option<i32>,
+    menu_ids: Vec<i32>,
+    /// %Y-%m-%d %H:%M:%S
+    start_at: String,
+    /// %Y-%m-%d %H:%M:%S
+    end_at: String,
+    #[serde(default)]
+    reason: String,
+}
+
+impl From<CreateRequest> for system_grant_service::CreateParams {
+    fn from(value: CreateRequest) -> Self {
+        Self {
+            user_id: value.user_id,
+            role_id: value.role_id,
+            reason: Some(value.reason),
+        }
+    }
+}
@@ -1,28 +1,35 @@
-use crate::{error::Result, state::AppState};
+use super::{ApiRouter, Claims};
+use crate::{
+    error::Result,
+    export::{self, ExportFormat, ExportParams},
+    state::AppState,
+};
 use axum::{
     extract::{self, State},
-    response::IntoResponse,
-    routing::get,
-    Json, Router,
+    response::{IntoResponse, Response},
+    Extension, Json,
 };
 use axum_extra::extract::Query;
 use serde::Deserialize;
-use service::system_login_log_server;
+use service::{system_login_log_server, system_user_service};
+use std::sync::Arc;
 use utils::paginate::PaginateParams;
 
-pub fn routers<S>(state: crate::state::AppState) -> axum::Router<S> {
-    Router::new()
-        .route("/login_log", get(index))
-        .route("/login_log/:id", get(info))
-        .with_state(state)
+pub fn routers() -> ApiRouter {
+    ApiRouter::new()
+        .get("/login_log", index, "列表")
+        .get("/login_log/export", export, "导出")
+        .get("/login_log/:id", info, "详情")
 }
 
 /// login_log list
 async fn index(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Query(params): Query<SearchRequest>,
 ) -> Result<impl IntoResponse> {
-    let data = system_login_log_server::paginate(&state.db, &params.into()).await?;
+    let scope = system_user_service::get_data_scope(&state.db, claims.user_id).await?;
+    let data = system_login_log_server::paginate(&state.db, &params.into(), &scope).await?;
     Ok(Json(data))
 }
 
@@ -34,6 +41,31 @@ async fn info(
     Ok(Json(system_login_log_server::info(&state.db, id).await?))
 }
 
+/// login log export, as csv or xlsx
+async fn export(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(params): Query<ExportRequest>,
+) -> Result<Response> {
+    let scope = Arc::new(system_user_service::get_data_scope(&state.db, claims.user_id).await?);
+    let (search, export_params) = params.into();
+    let search = Arc::new(search);
+    export::export(
+        "login_log",
+        system_login_log_server::EXPORT_COLUMNS,
+        export_params,
+        move |before_id, take| {
+            let state = state.clone();
+            let search = search.clone();
+            let scope = scope.clone();
+            async move {
+                system_login_log_server::export(&state.db, &search, &scope, before_id, take).await
+            }
+        },
+    )
+    .await
+}
+
 #[derive(Debug, Deserialize)]
 struct SearchRequest {
     user_id: Option<i32>,
@@ -47,3 +79,31 @@ impl From<SearchRequest> for system_login_log_server::SearchParams {
         Self::new(value.user_id, value.keyword, value.date, value.paginate)
     }
 }
+
+#[derive(Debug, Deserialize)]
+struct ExportRequest {
+    user_id: Option<i32>,
+    keyword: Option<String>,
+    date: Option<String>,
+    #[serde(default)]
+    format: ExportFormat,
+    columns: Option<String>,
+    limit: Option<i64>,
+}
+impl From<ExportRequest> for (system_login_log_server::SearchParams, ExportParams) {
+    fn from(value: ExportRequest) -> Self {
+        (
+            system_login_log_server::SearchParams::new(
+                value.user_id,
+                value.keyword,
+                value.date,
+                PaginateParams::new(1, export::MAX_ROWS),
+            ),
+            ExportParams {
+                format: value.format,
+                columns: value.columns,
+                limit: value.limit,
+            },
+        )
+    }
+}
@@ -0,0 +1,71 @@
+use super::ApiRouter;
+use crate::{
+    error::{ErrorCode, Result},
+    state::AppState,
+};
+use axum::{
+    body::Body,
+    extract::{self, Path, State},
+    response::IntoResponse,
+    Json,
+};
+use axum_extra::extract::Query;
+use serde::Deserialize;
+use service::system_mail_outbox_service::{self, MailStatus};
+use utils::paginate::PaginateParams;
+
+pub fn routers() -> ApiRouter {
+    ApiRouter::new()
+        .get("/mail_outbox", index, "列表")
+        .get("/mail_outbox/:id", info, "详情")
+        .put("/mail_outbox/:id/retry", retry, "重试")
+}
+
+/// outbox mail list
+async fn index(
+    State(state): State<AppState>,
+    Query(params): Query<SearchRequest>,
+) -> Result<impl IntoResponse> {
+    let data = system_mail_outbox_service::paginate(&state.db, &params.into()).await?;
+    Ok(Json(data))
+}
+
+/// outbox mail detail
+async fn info(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<i32>,
+) -> Result<impl IntoResponse> {
+    Ok(Json(system_mail_outbox_service::info(&state.db, id).await?))
+}
+
+/// send a failed mail again
+async fn retry(Path(id): Path<i32>, State(state): State<AppState>) -> Result<impl IntoResponse> {
+    let info = system_mail_outbox_service::info(&state.db, id).await?;
+    if info.status() != MailStatus::Failed {
+        return Err(ErrorCode::MailNotFailed);
+    }
+    if info.sensitive() {
+        return Err(ErrorCode::MailSensitive);
+    }
+    system_mail_outbox_service::retry(&state.db, id).await?;
+    Ok(Body::empty())
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchRequest {
+    keyword: Option<String>,
+    template_sign: Option<String>,
+    status: Option<MailStatus>,
+    #[serde(flatten)]
+    paginate: PaginateParams,
+}
+impl From<SearchRequest> for system_mail_outbox_service::SearchParams {
+    fn from(value: SearchRequest) -> Self {
+        Self::new(
+            value.keyword,
+            value.template_sign,
+            value.status,
+            value.paginate,
+        )
+    }
+}
@@ -0,0 +1,165 @@
+use super::ApiRouter;
+use crate::{
+    error::{ErrorCode, Result},
+    state::AppState,
+};
+use axum::{
+    body::Body,
+    extract::{self, Path, State},
+    response::IntoResponse,
+    Json,
+};
+use axum_extra::extract::Query;
+use serde::Deserialize;
+use service::system_mail_template_service;
+use utils::paginate::PaginateParams;
+
+pub fn routers() -> ApiRouter {
+    ApiRouter::new()
+        .get("/mail_template", index, "列表")
+        .get("/mail_template/:id", info, "详情")
+        .post("/mail_template", create, "新增")
+        .put("/mail_template/:id", update, "更新")
+        .delete("/mail_template/:id", del, "删除")
+        .post("/mail_template/preview", preview, "预览")
+}
+
+/// mail template list
+async fn index(
+    State(state): State<AppState>,
+    Query(params): Query<SearchRequest>,
+) -> Result<impl IntoResponse> {
+    let data = system_mail_template_service::paginate(&state.db, &params.into()).await?;
+    Ok(Json(data))
+}
+
+/// mail template detail
+async fn info(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<i32>,
+) -> Result<impl IntoResponse> {
+    Ok(Json(
+        system_mail_template_service::info(&state.db, id).await?,
+    ))
+}
+
+/// create mail template
+async fn create(
+    State(state): State<AppState>,
+    Json(params): Json<CreateRequest>,
+) -> Result<impl IntoResponse> {
+    if system_mail_template_service::get_by_sign(&state.db, &params.sign, None)
+        .await?
+        .is_some()
+    {
+        return Err(ErrorCode::MailTemplateSignExsist);
+    }
+    system_mail_template_service::validate(&params.subject, &params.body)?;
+    system_mail_template_service::create(
+        &state.db,
+        &params.name.clone(),
+        &params.sign.clone(),
+        &params.subject.clone(),
+        &params.body.clone(),
+        params.into(),
+    )
+    .await?;
+    Ok(Body::empty())
+}
+
+/// update mail template
+async fn update(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+    Json(params): Json<CreateRequest>,
+) -> Result<impl IntoResponse> {
+    if system_mail_template_service::get_by_sign(&state.db, &params.sign, Some(id))
+        .await?
+        .is_some()
+    {
+        return Err(ErrorCode::MailTemplateSignExsist);
+    }
+    system_mail_template_service::validate(&params.subject, &params.body)?;
+    system_mail_template_service::update(&state.db, id, params.into()).await?;
+    Ok(Body::empty())
+}
+
+/// delete mail template
+async fn del(Path(id): Path<i32>, State(state): State<AppState>) -> Result<impl IntoResponse> {
+    system_mail_template_service::delete(&state.db, id).await?;
+    Ok(Body::empty())
+}
+
+/// render the template source with the variables, before it is saved
+async fn preview(Json(params): Json<PreviewRequest>) -> Result<impl IntoResponse> {
+    Ok(Json(system_mail_template_service::preview(
+        &params.subject,
+        &params.body,
+        params.is_html,
+        params.variables,
+    )?))
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchRequest {
+    keyword: Option<String>,
+    status: Option<i32>,
+    #[serde(flatten)]
+    paginate: PaginateParams,
+}
+impl From<SearchRequest> for system_mail_template_service::SearchParams {
+    fn from(value: SearchRequest) -> Self {
+        Self::new(value.keyword, value.status, value.paginate)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateRequest {
+    name: String,
+    sign: String,
+    subject: String,
+    body: String,
+    #[serde(default)]
+    is_html: bool,
+    /// sample values of the variables, as a JSON object
+    variables: Option<serde_json::Value>,
+    remark: Option<String>,
+    #[serde(default)]
+    status: i32,
+}
+
+impl From<CreateRequest> for system_mail_template_service::CreateParams {
+    fn from(value: CreateRequest) -> Self {
+        Self {
+            is_html: Some(value.is_html),
+            variables: value.variables.map(|x| x.to_string()),
+            remark: value.remark,
+            status: Some(value.status),
+        }
+    }
+}
+
+impl From<CreateRequest> for system_mail_template_service::UpdateParams {
+    fn from(value: CreateRequest) -> Self {
+        Self {
+            name: Some(value.name),
+            sign: Some(value.sign),
+            subject: Some(value.subject),
+            body: Some(value.body),
+            is_html: Some(value.is_html),
+            variables: value.variables.map(|x| x.to_string()),
+            remark: value.remark,
+            status: Some(value.status),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PreviewRequest {
+    subject: String,
+    body: String,
+    #[serde(default)]
+    is_html: bool,
+    #[serde(default)]
+    variables: serde_json::Map<String, serde_json::Value>,
+}
@@ -1,24 +1,22 @@
-use super::Claims;
+use super::{ApiRouter, Claims};
 use crate::{error::Result, state::AppState};
 use axum::{
     body::Body,
     extract::{Path, State},
     response::IntoResponse,
-    routing::{delete, get, post, put},
-    Extension, Json, Router,
+    Extension, Json,
 };
 use axum_extra::extract::Query;
 use serde::Deserialize;
 use service::system_menu_service;
 
-pub fn routers<S>(state: crate::state::AppState) -> axum::Router<S> {
-    Router::new()
-        .route("/menu", get(index))
-        .route("/menu/:id", get(info))
-        .route("/menu", post(create))
-        .route("/menu/:id", put(update))
-        .route("/menu/:id", delete(del))
-        .with_state(state)
+pub fn routers() -> ApiRouter {
+    ApiRouter::new()
+        .get("/menu", index, "列表")
+        .get("/menu/:id", info, "详情")
+        .post("/menu", create, "新增")
+        .put("/menu/:id", update, "更新")
+        .delete("/menu/:id", del, "删除")
 }
 
 /// get tree menu
@@ -95,6 +93,7 @@ struct CreateRequest {
     btn_auth: String,
     api_url: String,
     api_method: String,
+    is_deny: Option<i32>,
     is_hide: Option<i32>,
     is_keep_alive: Option<i32>,
     is_affix: Option<i32>,
@@ -116,6 +115,7 @@ impl From<CreateRequest> for system_menu_service::CreateParams {
             btn_auth: Some(value.btn_auth),
             api_url: Some(value.api_url),
             api_method: Some(value.api_method),
+            is_deny: value.is_deny,
             is_hide: value.is_hide,
             is_keep_alive: value.is_keep_alive,
             is_affix: value.is_affix,
@@ -140,6 +140,7 @@ impl From<CreateRequest> for system_menu_service::UpdateParams {
             btn_auth: Some(value.btn_auth),
             api_url: Some(value.api_url),
             api_method: Some(value.api_method),
+            is_deny: value.is_deny,
             is_hide: value.is_hide,
             is_keep_alive: value.is_keep_alive,
             is_affix: value.is_affix,
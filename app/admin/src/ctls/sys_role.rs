@@ -1,4 +1,4 @@
-use super::Claims;
+use super::{ApiRouter, Claims};
 use crate::{
     error::{ErrorCode, Result},
     state::AppState,
@@ -7,23 +7,22 @@ use axum::{
     body::Body,
     extract::{self, Path, State},
     response::IntoResponse,
-    routing::{delete, get, post, put},
-    Extension, Json, Router,
+    Extension, Json,
 };
 use axum_extra::extract::Query;
 use serde::Deserialize;
-use service::{system_menu_service, system_role_service};
+use service::{system_menu_service, system_permission_service, system_role_service};
 use utils::paginate::PaginateParams;
 
-pub fn routers<S>(state: crate::state::AppState) -> axum::Router<S> {
-    Router::new()
-        .route("/role", get(index))
-        .route("/role/all", get(all))
-        .route("/role/:id", get(info))
-        .route("/role", post(create))
-        .route("/role/:id", put(update))
-        .route("/role/:id", delete(del))
-        .with_state(state)
+pub fn routers() -> ApiRouter {
+    ApiRouter::new()
+        .get("/role", index, "列表")
+        .get("/role/all", all, "全部")
+        .get("/role/:id", info, "详情")
+        .post("/role", create, "新增")
+        .put("/role/:id", update, "更新")
+        .delete("/role/:id", del, "删除")
+        .post("/role/:id/clone", clone, "复制")
 }
 
 /// get all role
@@ -61,18 +60,36 @@ async fn create(
     {
         return Err(ErrorCode::RoleSignExsist);
     }
+    if let Some(parent_id) = params.parent_id {
+        if parent_id > 0 {
+            check_role(&state, claims.user_id, parent_id).await?;
+        }
+    }
+    // a role created without a data scope sees everything
+    check_scope(
+        &state,
+        claims.user_id,
+        params
+            .data_scope
+            .as_ref()
+            .unwrap_or(&system_role_service::DataScopeType::All),
+        params.dept_ids.as_deref().unwrap_or_default(),
+    )
+    .await?;
     let user_menus = system_menu_service::get_user_menus_by_menu_ids(
         &state.db,
         claims.user_id,
         params.menu_ids.clone().unwrap_or_default(),
     )
     .await?;
+    let dept_ids = params.dept_ids.clone().unwrap_or_default();
     system_role_service::create(
         &state.db,
         &params.name.clone(),
         &params.sign.clone(),
         params.into(),
         user_menus,
+        dept_ids,
     )
     .await?;
     Ok(Body::empty())
@@ -85,15 +102,31 @@ async fn update(
     Extension(claims): Extension<Claims>,
     Json(params): Json<CreateRequest>,
 ) -> Result<impl IntoResponse> {
-    match system_role_service::get_by_sign(&state.db, &params.sign, Some(id)).await? {
-        Some(_) => {
-            return Err(ErrorCode::RoleSignExsist);
-        }
-        None => {
-            let info = system_role_service::info(&state.db, id).await?;
-            if info.sign().eq(&state.db.config().get_admin_role_sign()) {
-                return Err(ErrorCode::NotChangeAdmin);
+    if system_role_service::get_by_sign(&state.db, &params.sign, Some(id))
+        .await?
+        .is_some()
+    {
+        return Err(ErrorCode::RoleSignExsist);
+    }
+    let info = system_role_service::info(&state.db, id).await?;
+    if info.sign().eq(&state.db.config().get_admin_role_sign()) {
+        return Err(ErrorCode::NotChangeAdmin);
+    }
+    check_role(&state, claims.user_id, id).await?;
+    // a field left out keeps the role`s current value
+    check_scope(
+        &state,
+        claims.user_id,
+        params.data_scope.as_ref().unwrap_or(info.data_scope()),
+        params.dept_ids.as_deref().unwrap_or(info.dept_ids()),
+    )
+    .await?;
+    if let Some(parent_id) = params.parent_id {
+        if parent_id > 0 {
+            if system_role_service::is_parent_cycle(&state.db, id, parent_id).await? {
+                return Err(ErrorCode::RoleParentCycle);
             }
+            check_role(&state, claims.user_id, parent_id).await?;
         }
     }
     let user_menus = system_menu_service::get_user_menus_by_menu_ids(
@@ -102,20 +135,70 @@ async fn update(
         params.menu_ids.clone().unwrap_or_default(),
     )
     .await?;
-    system_role_service::update(&state.db, id, params.into(), user_menus).await?;
+    let dept_ids = params.dept_ids.clone();
+    system_role_service::update(&state.db, id, params.into(), user_menus, dept_ids).await?;
     Ok(Body::empty())
 }
 
 /// delete role
-async fn del(Path(id): Path<i32>, State(state): State<AppState>) -> Result<impl IntoResponse> {
+async fn del(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<impl IntoResponse> {
     let info = system_role_service::info(&state.db, id).await?;
     if info.sign().eq(&state.db.config().get_admin_role_sign()) {
         return Err(ErrorCode::NotDeleteData);
     }
+    check_role(&state, claims.user_id, id).await?;
     system_role_service::delete(&state.db, id).await?;
     Ok(Body::empty())
 }
 
+/// clone role with its menus
+async fn clone(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(params): Json<CloneRequest>,
+) -> Result<impl IntoResponse> {
+    check_role(&state, claims.user_id, id).await?;
+    if system_role_service::get_by_sign(&state.db, &params.sign, None)
+        .await?
+        .is_some()
+    {
+        return Err(ErrorCode::RoleSignExsist);
+    }
+    system_role_service::clone_role(&state.db, id, &params.name, &params.sign).await?;
+    Ok(Body::empty())
+}
+
+/// a role the operator changes, deletes, inherits or copies must not exceed
+/// the operator
+async fn check_role(state: &AppState, operator_id: i32, role_id: i32) -> Result<()> {
+    match system_permission_service::check_assign_roles(&state.db, operator_id, None, &[role_id])
+        .await?
+    {
+        Some(err) => Err(err.into()),
+        None => Ok(()),
+    }
+}
+
+/// the data scope a role is given must stay within the operator`s own
+async fn check_scope(
+    state: &AppState,
+    operator_id: i32,
+    data_scope: &system_role_service::DataScopeType,
+    dept_ids: &[i32],
+) -> Result<()> {
+    match system_permission_service::check_role_scope(&state.db, operator_id, data_scope, dept_ids)
+        .await?
+    {
+        Some(err) => Err(err.into()),
+        None => Ok(()),
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct SearchRequest {
     keyword: Option<String>,
@@ -128,8 +211,15 @@ impl From<SearchRequest> for system_role_service::SearchParams {
         Self::new(value.keyword, value.status, value.paginate)
     }
 }
+#[derive(Debug, Deserialize)]
+struct CloneRequest {
+    name: String,
+    sign: String,
+}
+
 #[derive(Debug, Deserialize)]
 struct CreateRequest {
+    parent_id: Option<i32>,
     name: String,
     sign: String,
     #[serde(default)]
@@ -139,14 +229,18 @@ struct CreateRequest {
     #[serde(default)]
     status: i32,
     menu_ids: Option<Vec<i32>>,
+    data_scope: Option<system_role_service::DataScopeType>,
+    dept_ids: Option<Vec<i32>>,
 }
 
 impl From<CreateRequest> for system_role_service::CreateParams {
     fn from(value: CreateRequest) -> Self {
         Self {
+            parent_id: value.parent_id,
             sort: Some(value.sort),
             describe: Some(value.describe),
             status: Some(value.status),
+            data_scope: value.data_scope.map(|x| x.into()),
         }
     }
 }
@@ -154,11 +248,13 @@ impl From<CreateRequest> for system_role_service::CreateParams {
 impl From<CreateRequest> for system_role_service::UpdateParams {
     fn from(value: CreateRequest) -> Self {
         Self {
+            parent_id: value.parent_id,
             name: Some(value.name),
             sign: Some(value.sign),
             sort: Some(value.sort),
             describe: Some(value.describe),
             status: Some(value.status),
+            data_scope: value.data_scope.map(|x| x.into()),
         }
     }
 }
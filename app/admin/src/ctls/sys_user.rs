@@ -1,4 +1,4 @@
-use super::Claims;
+use super::{ApiRouter, Claims};
 use crate::{
     error::{ErrorCode, Result},
     state::AppState,
@@ -7,47 +7,71 @@ use axum::{
     body::Body,
     extract::{Path, State},
     response::IntoResponse,
-    routing::{delete, get, post, put},
-    Extension, Json, Router,
+    Extension, Json,
 };
 use axum_extra::extract::Query;
 use serde::{Deserialize, Serialize};
-use service::{system_menu_service, system_user_service};
+use service::{system_menu_service, system_permission_service, system_user_service};
 use utils::{paginate::PaginateParams, password::Password};
 
-pub fn routers<S>(state: crate::state::AppState) -> axum::Router<S> {
-    Router::new()
-        .route("/user", get(index))
-        .route("/user/:id", get(info))
-        .route("/user", post(create))
-        .route("/user/:id", put(update))
-        .route("/user/:id", delete(del))
-        .route("/user/update_password", put(update_password))
-        .route("/user/get_menu", get(get_menu))
-        .route("/user/get_user_permission", get(get_user_permission))
-        .with_state(state)
+pub fn routers() -> ApiRouter {
+    ApiRouter::new()
+        .get("/user", index, "列表")
+        .get("/user/:id", info, "详情")
+        .post("/user", create, "新增")
+        .put("/user/:id", update, "更新")
+        .delete("/user/:id", del, "删除")
+        .put("/user/update_password", update_password, "修改密码")
+        .get("/user/get_menu", get_menu, "获取当前用户菜单")
+        .get(
+            "/user/get_user_permission",
+            get_user_permission,
+            "获取当前用户权限",
+        )
 }
 /// user list
 async fn index(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Query(params): Query<SearchRequest>,
 ) -> Result<impl IntoResponse> {
+    let scope = system_user_service::get_data_scope(&state.db, claims.user_id).await?;
     Ok(Json(
-        system_user_service::paginate(&state.db, params.into()).await?,
+        system_user_service::paginate(&state.db, params.into(), &scope).await?,
     ))
 }
 
-/// user`detail
-async fn info(State(state): State<AppState>, Path(id): Path<i32>) -> Result<impl IntoResponse> {
-    Ok(Json(system_user_service::info(&state.db, id).await?))
+/// user`detail, users outside the operator`s data scope are not found like
+/// in the list
+async fn info(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse> {
+    let info = system_user_service::info(&state.db, id).await?;
+    let scope = system_user_service::get_data_scope(&state.db, claims.user_id).await?;
+    if !scope.contains(*info.id(), *info.dept_id()) {
+        return Err(service::ServiceError::DataNotFound.into());
+    }
+    Ok(Json(info))
 }
 
 /// add user
 async fn create(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Json(params): Json<CreateRequest>,
 ) -> Result<impl IntoResponse> {
-    system_user_service::create(&state.db, &params.username.clone(), params.into()).await?;
+    let role_ids = params.get_role_ids();
+    check_assignment(
+        &state,
+        claims.user_id,
+        role_ids.as_deref().unwrap_or_default(),
+        params.dept_id,
+    )
+    .await?;
+    system_user_service::create(&state.db, &params.username.clone(), params.into(), role_ids)
+        .await?;
     Ok(Body::empty())
 }
 
@@ -55,19 +79,43 @@ async fn create(
 async fn update(
     State(state): State<AppState>,
     Path(id): Path<i32>,
+    Extension(claims): Extension<Claims>,
     Json(params): Json<CreateRequest>,
 ) -> Result<impl IntoResponse> {
+    if let Some(err) =
+        system_permission_service::check_manage_user(&state.db, claims.user_id, id).await?
+    {
+        return Err(err.into());
+    }
+    let role_ids = params.get_role_ids();
+    check_assignment(
+        &state,
+        claims.user_id,
+        role_ids.as_deref().unwrap_or_default(),
+        params.dept_id,
+    )
+    .await?;
     system_user_service::update(
         &state.db,
         id,
         Into::<system_user_service::UpdateParams>::into(params).to_params(),
+        role_ids,
     )
     .await?;
     Ok(Body::empty())
 }
 
 /// delete user by user`id
-async fn del(State(state): State<AppState>, Path(id): Path<i32>) -> Result<impl IntoResponse> {
+async fn del(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Extension(claims): Extension<Claims>,
+) -> Result<impl IntoResponse> {
+    if let Some(err) =
+        system_permission_service::check_manage_user(&state.db, claims.user_id, id).await?
+    {
+        return Err(err.into());
+    }
     system_user_service::delete(&state.db, id).await?;
     Ok(Body::empty())
 }
@@ -92,6 +140,7 @@ async fn update_password(
         &state.db,
         claims.user_id,
         Into::<system_user_service::UpdatePasswordParams>::into(params).to_params(),
+        None,
     )
     .await?;
     Ok(Body::empty())
@@ -128,7 +177,7 @@ async fn get_user_permission(
     let info = system_user_service::get_current_user_info(&state.db, claims.user_id).await?;
     let btn_auths = system_menu_service::filter_menu_types(
         Some(vec![system_menu_service::MenuType::BtnAuth]),
-        system_menu_service::get_menu_by_role(&state.db, info.role().clone()).await?,
+        system_menu_service::get_menu_by_roles(&state.db, info.roles().clone()).await?,
     )
     .into_iter()
     .map(|x| x.btn_auth)
@@ -157,11 +206,33 @@ impl From<SearchRequest> for system_user_service::SearchParams {
     }
 }
 
+/// roles and dept given to a user must stay within the operator`s own
+async fn check_assignment(
+    state: &AppState,
+    operator_id: i32,
+    role_ids: &[i32],
+    dept_id: Option<i32>,
+) -> Result<()> {
+    if let Some(err) =
+        system_permission_service::check_assign_roles(&state.db, operator_id, dept_id, role_ids)
+            .await?
+    {
+        return Err(err.into());
+    }
+    if let Some(err) =
+        system_permission_service::check_assign_dept(&state.db, operator_id, dept_id).await?
+    {
+        return Err(err.into());
+    }
+    Ok(())
+}
+
 #[derive(Debug, Deserialize)]
 struct CreateRequest {
     username: String,
     nickname: String,
     role_id: Option<i32>,
+    role_ids: Option<Vec<i32>>,
     dept_id: Option<i32>,
     phone: Option<String>,
     email: Option<String>,
@@ -172,6 +243,17 @@ struct CreateRequest {
     status: i32,
 }
 
+impl CreateRequest {
+    /// `role_ids`, falling back to the single `role_id`; `None` when neither
+    /// is given, which keeps the roles unchanged
+    fn get_role_ids(&self) -> Option<Vec<i32>> {
+        match &self.role_ids {
+            Some(role_ids) => Some(role_ids.clone()),
+            None => self.role_id.map(|x| vec![x]),
+        }
+    }
+}
+
 impl From<CreateRequest> for system_user_service::CreateParams {
     fn from(value: CreateRequest) -> Self {
         let mut data = Self {
@@ -47,15 +47,90 @@ pub enum ErrorCode {
     /// Email exsist
     #[attr(status_code = StatusCode::BAD_REQUEST, message = "Email exsist")]
     EmailExsist,
+    /// Invite code error
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Invite code error")]
+    InviteCode,
+    /// Inviter not promoter
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Inviter can not promote")]
+    InviterNotPromoter,
+    /// Member already joined a team
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Member already joined a team")]
+    MemberTeamJoined,
+    /// Inviter in the member`s own team
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Inviter is in the member team")]
+    MemberTeamCycle,
     /// Role Sign exsist
     #[attr(status_code = StatusCode::BAD_REQUEST, message = "Role Sign exsist")]
     RoleSignExsist,
+    /// Role parent cycle
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Role parent can not be itself or its child")]
+    RoleParentCycle,
     /// Dict Sign exsist
     #[attr(status_code = StatusCode::BAD_REQUEST, message = "Dict Sign exsist")]
     DictSignExsist,
     /// Dict Data Lable exsist
     #[attr(status_code = StatusCode::BAD_REQUEST, message = "Dict Data Lable exsist")]
     DictDataLableExsist,
+    /// Commission rule error
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Commission rule needs a level from 1, a positive value and bill types other than commission and withdraw")]
+    CommissionRule,
+    /// Adjustment remark empty
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Adjustment needs a remark")]
+    AdjustRemark,
+    /// Adjustment amount error
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Adjustment needs a balance with at most 2 decimals or an integral")]
+    AdjustAmount,
+    /// Adjustment reviewed by its creator
+    #[attr(status_code = StatusCode::FORBIDDEN, message = "Adjustment must be reviewed by another admin")]
+    AdjustSelfReview,
+    /// Adjustment not pending
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Adjustment is not pending")]
+    AdjustNotPending,
+    /// Withdrawal status error
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Withdrawal status does not allow it")]
+    WithdrawalStatus,
+    /// Mail Template Sign exsist
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Mail Template Sign exsist")]
+    MailTemplateSignExsist,
+    /// Mail template not rendered
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Mail template error")]
+    MailTemplate(String),
+    /// Only failed mails can be retried
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Only failed mails can be retried")]
+    MailNotFailed,
+    /// Sensitive mails are not retried
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Mails with a code are dropped once failed, send a new code instead")]
+    MailSensitive,
+    /// Date error
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Date must be %Y-%m-%d")]
+    DateFormat,
+    /// Grant target error
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Grant to either a user or a role")]
+    GrantTarget,
+    /// Grant time error
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Grant start time must be before end time")]
+    GrantTime,
+    /// Grant not active
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Grant was revoked or has expired")]
+    GrantNotActive,
+    /// Role escalation
+    #[attr(status_code = StatusCode::FORBIDDEN, message = "Role has permissions beyond your own")]
+    RoleEscalation,
+    /// Role data scope escalation
+    #[attr(status_code = StatusCode::FORBIDDEN, message = "Role data scope is beyond your own")]
+    RoleScopeEscalation,
+    /// Dept out of data scope
+    #[attr(status_code = StatusCode::FORBIDDEN, message = "Dept is out of your data scope")]
+    DeptOutOfScope,
+    /// User above operator
+    #[attr(status_code = StatusCode::FORBIDDEN, message = "User is above you")]
+    UserAboveOperator,
+    /// Insufficient balance or integral
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Insufficient balance or integral")]
+    InsufficientFunds,
+    /// Idempotency key used by another request
+    #[attr(status_code = StatusCode::CONFLICT, message = "Idempotency key was used by another request")]
+    IdempotencyConflict,
     #[attr(status_code = StatusCode::BAD_REQUEST, message = "json error")]
     SerdeJson(serde_json::Error),
 }
@@ -69,13 +144,43 @@ impl From<service::ServiceError> for ErrorCode {
                 format!("RelationNotFetchedError: {}", err)
             }
             service::ServiceError::SerializeJson(err) => err.to_string(),
+            service::ServiceError::Io(err) => format!("IoError: {}", err),
             service::ServiceError::DataNotFound => "DataNotExsist".to_owned(),
             service::ServiceError::CacheNotFound => "CacheNotExsist".to_owned(),
+            service::ServiceError::CacheDriver(err) => format!("CacheDriverError: {}", err),
+            service::ServiceError::Template(err) => return Self::MailTemplate(err),
+            service::ServiceError::Mail(err) => format!("MailError: {}", err),
+            service::ServiceError::InsufficientFunds => return Self::InsufficientFunds,
+            service::ServiceError::IdempotencyConflict => return Self::IdempotencyConflict,
+            service::ServiceError::Payment(err) => format!("PaymentError: {}", err),
+            service::ServiceError::InviteRefused(refusal) => return refusal.into(),
         };
         Self::InternalServerString(err_string)
     }
 }
 
+impl From<service::system_permission_service::Escalation> for ErrorCode {
+    fn from(value: service::system_permission_service::Escalation) -> Self {
+        match value {
+            service::system_permission_service::Escalation::Role(_) => Self::RoleEscalation,
+            service::system_permission_service::Escalation::Dept(_) => Self::DeptOutOfScope,
+            service::system_permission_service::Escalation::Scope => Self::RoleScopeEscalation,
+            service::system_permission_service::Escalation::User(_) => Self::UserAboveOperator,
+        }
+    }
+}
+
+impl From<service::member_team_service::InviteRefusal> for ErrorCode {
+    fn from(value: service::member_team_service::InviteRefusal) -> Self {
+        match value {
+            service::member_team_service::InviteRefusal::NotFound => Self::InviteCode,
+            service::member_team_service::InviteRefusal::NotPromoter => Self::InviterNotPromoter,
+            service::member_team_service::InviteRefusal::Joined => Self::MemberTeamJoined,
+            service::member_team_service::InviteRefusal::Cycle => Self::MemberTeamCycle,
+        }
+    }
+}
+
 impl From<utils::password::ErrorType> for ErrorCode {
     fn from(value: utils::password::ErrorType) -> Self {
         let msg = match value {
@@ -86,6 +191,12 @@ impl From<utils::password::ErrorType> for ErrorCode {
     }
 }
 
+impl From<utils::export::XlsxError> for ErrorCode {
+    fn from(value: utils::export::XlsxError) -> Self {
+        Self::InternalServerString(format!("ExportError:{}", value))
+    }
+}
+
 impl From<axum::http::StatusCode> for ErrorCode {
     fn from(value: axum::http::StatusCode) -> Self {
         Self::InternalServerString(value.to_string())
@@ -101,6 +212,7 @@ impl IntoResponse for ErrorCode {
     fn into_response(self) -> axum::response::Response {
         let response = match self {
             Self::InternalServerString(ref err_str) => Some(err_str).map(|x| x.as_str()),
+            Self::MailTemplate(ref err_str) => Some(err_str).map(|x| x.as_str()),
             _ => self.get_message(),
         }
         .map(|x| x.to_string());
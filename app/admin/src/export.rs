@@ -0,0 +1,137 @@
+use crate::error::Result;
+use axum::{
+    body::Body,
+    http::header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    response::{IntoResponse, Response},
+};
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use utils::{
+    datetime::now_time,
+    export::{to_values, Exporter},
+};
+
+/// most rows of one export
+pub const MAX_ROWS: i64 = 50_000;
+/// rows read at once
+const BATCH_SIZE: i64 = 500;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Xlsx,
+}
+
+/// format, columns and row limit of an export request
+#[derive(Debug)]
+pub struct ExportParams {
+    pub format: ExportFormat,
+    /// comma separated column keys, all columns when empty
+    pub columns: Option<String>,
+    pub limit: Option<i64>,
+}
+
+impl ExportParams {
+    fn get_limit(&self) -> i64 {
+        self.limit.unwrap_or(MAX_ROWS).clamp(1, MAX_ROWS)
+    }
+}
+
+/// export the rows read by `fetch(before_id, take)`, newest first; CSV is
+/// streamed batch by batch, XLSX is built once every row is read
+pub async fn export<T, F, Fut>(
+    name: &str,
+    columns: &[(&'static str, &'static str)],
+    params: ExportParams,
+    fetch: F,
+) -> Result<Response>
+where
+    T: Serialize + Send,
+    F: Fn(Option<i32>, i64) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = service::Result<Vec<T>>> + Send + 'static,
+{
+    let exporter = Exporter::new(columns, params.columns.as_deref());
+    let limit = params.get_limit();
+    let filename = format!("{}-{}", name, now_time().format("%Y%m%d%H%M%S"));
+    match params.format {
+        ExportFormat::Csv => {
+            let header = exporter.csv_header();
+            let rows = stream::unfold(
+                (fetch, exporter, None, limit),
+                |(fetch, exporter, before_id, remaining)| async move {
+                    if remaining <= 0 {
+                        return None;
+                    }
+                    let take = remaining.min(BATCH_SIZE);
+                    let values = match fetch(before_id, take).await {
+                        Ok(rows) => to_values(&rows).map_err(|x| x.to_string()),
+                        Err(err) => Err(format!("{:?}", err)),
+                    };
+                    let values = match values {
+                        Ok(values) if values.is_empty() => return None,
+                        Ok(values) => values,
+                        Err(err) => {
+                            tracing::error!("csv export stopped: {}", err);
+                            let err = std::io::Error::other(err);
+                            return Some((Err(err), (fetch, exporter, before_id, 0)));
+                        }
+                    };
+                    let remaining = match (values.len() as i64) < take {
+                        true => 0,
+                        false => remaining - take,
+                    };
+                    let before_id = values
+                        .last()
+                        .and_then(|x| x["id"].as_i64())
+                        .map(|x| x as i32);
+                    let chunk = exporter.csv_rows(&values);
+                    Some((Ok(chunk), (fetch, exporter, before_id, remaining)))
+                },
+            );
+            let body = stream::iter([Ok::<String, std::io::Error>(header)]).chain(rows);
+            Ok((
+                [
+                    (CONTENT_TYPE, "text/csv; charset=utf-8".to_owned()),
+                    (
+                        CONTENT_DISPOSITION,
+                        format!("attachment; filename=\"{}.csv\"", filename),
+                    ),
+                ],
+                Body::from_stream(body),
+            )
+                .into_response())
+        }
+        ExportFormat::Xlsx => {
+            let mut values = vec![];
+            let mut before_id = None;
+            while (values.len() as i64) < limit {
+                let take = (limit - values.len() as i64).min(BATCH_SIZE);
+                let rows = to_values(&fetch(before_id, take).await?)?;
+                let count = rows.len() as i64;
+                before_id = rows.last().and_then(|x| x["id"].as_i64()).map(|x| x as i32);
+                values.extend(rows);
+                if count < take {
+                    break;
+                }
+            }
+            Ok((
+                [
+                    (
+                        CONTENT_TYPE,
+                        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+                            .to_owned(),
+                    ),
+                    (
+                        CONTENT_DISPOSITION,
+                        format!("attachment; filename=\"{}.xlsx\"", filename),
+                    ),
+                ],
+                exporter.xlsx(&values)?,
+            )
+                .into_response())
+        }
+    }
+}
@@ -0,0 +1,7 @@
+/// controllers
+pub mod ctls;
+/// error and result
+pub mod error;
+/// csv and xlsx export
+pub mod export;
+pub mod state;
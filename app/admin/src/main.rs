@@ -1,11 +1,9 @@
+use admin::{
+    ctls,
+    error::{ErrorCode, Result},
+    state,
+};
 use axum::{extract::MatchedPath, http::Request};
-use error::{ErrorCode, Result};
-
-/// controllers
-mod ctls;
-/// error and result
-mod error;
-mod state;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -15,9 +13,47 @@ async fn main() -> Result<()> {
     ));
     utils::logger::init(env_filter);
     let prisma_client = service::Database::new(service::DatabaseConfig::default()).await?;
-    let state = state::State::build(prisma_client);
+    ctls::router::check(&prisma_client, false).await?;
+    let (log_writer, log_worker) = service::log_writer_service::LogWriter::new(Default::default());
+    let (events, event_worker) = service::security_event_service::EventDispatcher::new(
+        service::security_event_service::EventSinkConfig::from_env(),
+    );
+    let state = state::State::build(prisma_client, log_writer, events)?;
+    let event_task = tokio::spawn(event_worker.run());
+    let log_task = {
+        let state = state.clone();
+        tokio::spawn(async move { log_worker.run(&state.db).await })
+    };
+    // the purge lock lives in the database, every admin instance sees it
+    let retention_locks =
+        service::cache_service::Cache::new(service::cache_service::CacheDriverDatabase::new(
+            service::Database::new(service::DatabaseConfig::default()).await?,
+        ));
+    let retention_task = {
+        let state = state.clone();
+        tokio::spawn(async move {
+            service::log_retention_service::schedule(
+                &state.db,
+                &retention_locks,
+                service::log_retention_service::RetentionConfig::from_env(),
+                retention_interval(),
+            )
+            .await
+        })
+    };
+    let withdrawal_task = {
+        let state = state.clone();
+        tokio::spawn(async move {
+            service::member_withdrawal_service::run(
+                &state.db,
+                state.payment.as_ref(),
+                withdrawal_recover_interval(),
+            )
+            .await
+        })
+    };
 
-    let app = ctls::router::init(state).await.layer(
+    let app = ctls::router::init(state.clone()).await.layer(
         tower_http::trace::TraceLayer::new_for_http().make_span_with(|request: &Request<_>| {
             let matched_path = request
                 .extensions()
@@ -42,7 +78,72 @@ async fn main() -> Result<()> {
         listener,
         app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
     )
+    .with_graceful_shutdown(shutdown_signal())
     .await
     .map_err(|_| ErrorCode::ServerSteup)?;
+
+    retention_task.abort();
+    withdrawal_task.abort();
+    // write the queued logs and events before leaving
+    state.log_writer.close();
+    state.events.close();
+    let _ = log_task.await;
+    let _ = event_task.await;
+    let metrics = state.log_writer.metrics();
+    tracing::info!(
+        "log writer stopped, accepted: {}, pending: {}, written: {}, dropped: {}, failed: {}",
+        metrics.accepted(),
+        metrics.pending(),
+        metrics.written(),
+        metrics.dropped(),
+        metrics.failed()
+    );
     Ok(())
 }
+
+/// `RETENTION_INTERVAL_HOURS` between log purges, a day by default
+fn retention_interval() -> std::time::Duration {
+    let hours = std::env::var("RETENTION_INTERVAL_HOURS")
+        .ok()
+        .and_then(|x| x.trim().parse::<u64>().ok())
+        .filter(|x| *x > 0)
+        .unwrap_or(24);
+    std::time::Duration::from_secs(hours * 3600)
+}
+
+/// `MEMBER_WITHDRAWAL_RECOVER_INTERVAL_SECS` between settling the payouts
+/// whose result was lost, 60 seconds by default
+fn withdrawal_recover_interval() -> std::time::Duration {
+    let secs = std::env::var("MEMBER_WITHDRAWAL_RECOVER_INTERVAL_SECS")
+        .ok()
+        .and_then(|x| x.trim().parse::<u64>().ok())
+        .filter(|x| *x > 0)
+        .unwrap_or(60);
+    std::time::Duration::from_secs(secs)
+}
+
+/// ctrl-c, or the SIGTERM sent by docker, systemd or kubernetes
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                tracing::error!("SIGTERM not handled: {}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    tracing::info!("Service is shutting down");
+}
@@ -1,21 +1,50 @@
-use service::{cache_service, Database};
+use service::{
+    cache_service, log_writer_service, member_adjustment_service, payment_service,
+    security_event_service, system_action_log_service, Database,
+};
 use std::sync::Arc;
-use tokio::sync::Mutex;
 
 pub type AppState = Arc<State>;
 
 pub struct State {
     pub db: Database,
-    pub cache: Mutex<cache_service::Cache<cache_service::CacheDriverMemory>>,
+    pub cache: cache_service::Cache<cache_service::CacheDriverMemory>,
+    pub action_log: system_action_log_service::RecordConfig,
+    pub log_writer: log_writer_service::LogWriter,
+    pub events: security_event_service::EventDispatcher,
+    pub adjust_approval: member_adjustment_service::ApprovalConfig,
+    /// pays the withdrawals out
+    pub payment: Arc<dyn payment_service::PaymentGateway>,
 }
 
 impl State {
-    pub fn build(db: Database) -> AppState {
-        Arc::new(Self {
+    pub fn build(
+        db: Database,
+        log_writer: log_writer_service::LogWriter,
+        events: security_event_service::EventDispatcher,
+    ) -> service::Result<AppState> {
+        Ok(Arc::new(Self {
             db,
-            cache: Mutex::new(cache_service::Cache::new(
-                cache_service::CacheDriverMemory::default(),
-            )),
-        })
+            cache: cache_service::Cache::new(cache_service::CacheDriverMemory::default()),
+            action_log: action_log_config(),
+            log_writer,
+            events,
+            adjust_approval: member_adjustment_service::ApprovalConfig::from_env(),
+            payment: payment_service::gateway_from_env()?,
+        }))
     }
 }
+
+/// `ACTION_LOG_REDACT_FIELDS`, comma separated, adds fields to redact
+fn action_log_config() -> system_action_log_service::RecordConfig {
+    let mut config = system_action_log_service::RecordConfig::default();
+    if let Ok(fields) = std::env::var("ACTION_LOG_REDACT_FIELDS") {
+        config.redact_fields.extend(
+            fields
+                .split(',')
+                .map(|x| x.trim().to_owned())
+                .filter(|x| !x.is_empty()),
+        );
+    }
+    config
+}
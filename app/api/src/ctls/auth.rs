@@ -0,0 +1,290 @@
+use crate::{
+    error::{ErrorCode, Result},
+    state::AppState,
+};
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, State},
+    response::IntoResponse,
+    routing::post,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use service::{
+    cache_service::{CacheType, Driver},
+    member_service, member_team_service, system_mail_outbox_service,
+};
+use std::net::SocketAddr;
+use utils::password::Password;
+
+/// seconds an email code stays valid
+const CODE_TTL: i64 = 10 * 60;
+/// seconds before another code can be sent to the same email
+const CODE_RESEND: i64 = 60;
+/// wrong tries before the code is dropped
+const CODE_TRIES: i64 = 5;
+/// seconds the password logins of an email are counted
+const LOGIN_WINDOW: i64 = 15 * 60;
+/// password logins of an email within the window
+const LOGIN_TRIES: i64 = 10;
+
+pub fn routers<S>(state: AppState) -> Router<S> {
+    Router::new()
+        .route("/auth/send_code", post(send_code))
+        .route("/auth/register", post(register))
+        .route("/auth/login_by_password", post(login_by_password))
+        .route("/auth/login_by_code", post(login_by_code))
+        .with_state(state)
+}
+
+/// send a code to the email, for registering or logging in
+async fn send_code(
+    State(state): State<AppState>,
+    Json(params): Json<SendCodeRequest>,
+) -> Result<impl IntoResponse> {
+    let email = normalize_email(&params.email);
+    let sent = state
+        .cache
+        .incr_with_ttl(&format!("member_code_send:{}", email), CODE_RESEND)
+        .await?;
+    if sent > 1 {
+        return Err(ErrorCode::EmailCodeFrequent);
+    }
+    // the reply never tells whether the email belongs to a member
+    let member = member_service::get_by_email(&state.db, &email, None).await?;
+    match (params.scene, member.is_some()) {
+        (CodeScene::Register, true) | (CodeScene::Login, false) => return Ok(Body::empty()),
+        _ => {}
+    }
+    let code = (0..6).map(|_| fastrand::digit(10)).collect::<String>();
+    state
+        .cache
+        .put(
+            params.scene.cache_type(),
+            &email,
+            code.clone(),
+            Some(CODE_TTL),
+            None,
+        )
+        .await?;
+    system_mail_outbox_service::queue_sensitive(
+        &state.db,
+        &email,
+        params.scene.template_sign(),
+        serde_json::json!({ "code": code, "minutes": CODE_TTL / 60 }),
+    )
+    .await
+    .map_err(|err| {
+        tracing::error!("email code to {} not queued: {:?}", email, err);
+        ErrorCode::EmailSend
+    })?;
+    Ok(Body::empty())
+}
+
+/// register with an email code
+async fn register(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(params): Json<RegisterRequest>,
+) -> Result<impl IntoResponse> {
+    let email = normalize_email(&params.email);
+    if params.password.is_empty() {
+        return Err(ErrorCode::InputPasswordNotEmpty);
+    }
+    let invite_code = params.invite_code.clone().filter(|x| !x.trim().is_empty());
+    if let Some(invite_code) = &invite_code {
+        if let Some(refusal) =
+            member_team_service::check_invite(&state.db, invite_code, None).await?
+        {
+            return Err(refusal.into());
+        }
+    }
+    verify_code(&state, CodeScene::Register, &email, &params.code).await?;
+    // registered since the code was sent, replied as a wrong code
+    if member_service::get_by_email(&state.db, &email, None)
+        .await?
+        .is_some()
+    {
+        return Err(ErrorCode::EmailCode);
+    }
+    let (password, salt) = Password::generate_hash_salt(params.password.as_bytes())?;
+    let unique_code = member_service::generate_code(&state.db, 8).await?;
+    let member = member_service::create(
+        &state.db,
+        &unique_code,
+        &email,
+        member_service::CreateParams {
+            mobile: None,
+            nickname: params.nickname,
+            avatar: None,
+            password: Some(password),
+            salt: Some(salt),
+            sex: None,
+            remark: None,
+            status: None,
+            is_promoter: None,
+        },
+        invite_code.as_deref(),
+    )
+    .await?;
+    login_after(&state, addr, member).await
+}
+
+/// login by email and password, limited to `LOGIN_TRIES` within
+/// `LOGIN_WINDOW` per email
+async fn login_by_password(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(params): Json<LoginByPasswordRequest>,
+) -> Result<impl IntoResponse> {
+    let email = normalize_email(&params.email);
+    let tries = state
+        .cache
+        .incr_with_ttl(&format!("member_login_password:{}", email), LOGIN_WINDOW)
+        .await?;
+    if tries > LOGIN_TRIES {
+        return Err(ErrorCode::LoginFrequent);
+    }
+    let member = member_service::get_by_email(&state.db, &email, None)
+        .await?
+        .ok_or(ErrorCode::InputEmailAndPwd)?;
+    // members created without a password can only login by code
+    if member.password().is_empty()
+        || !Password::verify_password(member.password(), member.salt(), params.password.as_bytes())?
+    {
+        return Err(ErrorCode::InputEmailAndPwd);
+    }
+    login_after(&state, addr, member).await
+}
+
+/// login by email code
+async fn login_by_code(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(params): Json<LoginByCodeRequest>,
+) -> Result<impl IntoResponse> {
+    let email = normalize_email(&params.email);
+    verify_code(&state, CodeScene::Login, &email, &params.code).await?;
+    let member = member_service::get_by_email(&state.db, &email, None)
+        .await?
+        .ok_or(ErrorCode::InputEmailAndPwd)?;
+    login_after(&state, addr, member).await
+}
+
+/// issue the member token
+async fn login_after(
+    state: &AppState,
+    addr: SocketAddr,
+    member: member_service::Info,
+) -> Result<Json<LoginReponse>> {
+    // 1.正常
+    if *member.status() != 1 {
+        return Err(ErrorCode::MemberDisabled);
+    }
+    let member =
+        member_service::set_last_login(&state.db, member.id(), &addr.ip().to_string()).await?;
+    let token = super::issue_token(state, *member.id()).await?;
+    Ok(Json(LoginReponse { token, member }))
+}
+
+/// check the code and use it up, the code is dropped after too many wrong tries;
+/// the try is counted before the compare, concurrent guesses can not exceed it
+async fn verify_code(state: &AppState, scene: CodeScene, email: &str, code: &str) -> Result<()> {
+    let cache_type = scene.cache_type();
+    let info = state
+        .cache
+        .first(cache_type.clone(), email, None)
+        .await?
+        .ok_or(ErrorCode::EmailCode)?;
+    let expected = info.value::<String>()?;
+    if !info.is_valid() {
+        return Err(ErrorCode::EmailCode);
+    }
+    let tries = state
+        .cache
+        .incr_with_ttl(
+            &format!("member_code_verify:{}:{}", scene.name(), email),
+            CODE_TTL,
+        )
+        .await?;
+    if tries > CODE_TRIES || expected != code.trim() {
+        if tries >= CODE_TRIES {
+            state.cache.forget(cache_type, email).await?;
+        }
+        return Err(ErrorCode::EmailCode);
+    }
+    state.cache.pull(cache_type, email).await?;
+    Ok(())
+}
+
+fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum CodeScene {
+    Register,
+    Login,
+}
+
+impl CodeScene {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Register => "register",
+            Self::Login => "login",
+        }
+    }
+
+    fn cache_type(&self) -> CacheType {
+        match self {
+            Self::Register => CacheType::MemberAuthRegisterEmail,
+            Self::Login => CacheType::MemberAuthLoginEmail,
+        }
+    }
+
+    /// mail template of the code
+    fn template_sign(&self) -> &'static str {
+        match self {
+            Self::Register => "member_register_code",
+            Self::Login => "member_login_code",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SendCodeRequest {
+    email: String,
+    scene: CodeScene,
+}
+
+#[derive(Debug, Deserialize)]
+struct RegisterRequest {
+    email: String,
+    /// email code
+    code: String,
+    password: String,
+    nickname: Option<String>,
+    /// unique code of the inviter
+    invite_code: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginByPasswordRequest {
+    email: String,
+    password: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginByCodeRequest {
+    email: String,
+    /// email code
+    code: String,
+}
+
+#[derive(Debug, Serialize)]
+struct LoginReponse {
+    /// member`token
+    token: String,
+    member: member_service::Info,
+}
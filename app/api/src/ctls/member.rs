@@ -0,0 +1,120 @@
+use super::Claims;
+use crate::{
+    error::{ErrorCode, Result},
+    state::AppState,
+};
+use axum::{
+    body::Body,
+    extract::State,
+    http::HeaderMap,
+    response::IntoResponse,
+    routing::{get, post, put},
+    Extension, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use service::{cache_service::CacheType, member_service};
+use utils::password::Password;
+
+pub fn routers() -> Router<AppState> {
+    Router::new()
+        .route("/member/profile", get(profile).put(update_profile))
+        .route("/member/password", put(update_password))
+        .route("/member/logout", post(logout))
+}
+
+/// current member
+async fn profile(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<impl IntoResponse> {
+    Ok(Json(
+        member_service::info(&state.db, claims.member_id).await?,
+    ))
+}
+
+/// update current member`profile
+async fn update_profile(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(params): Json<UpdateProfileRequest>,
+) -> Result<impl IntoResponse> {
+    Ok(Json(
+        member_service::update_profile(&state.db, claims.member_id, params.into()).await?,
+    ))
+}
+
+/// change password, the old one is required; every token issued so far is
+/// revoked and a new one replied
+async fn update_password(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(params): Json<UpdatePasswordRequest>,
+) -> Result<impl IntoResponse> {
+    let info = member_service::info(&state.db, claims.member_id).await?;
+    if !info.password().is_empty()
+        && !Password::verify_password(info.password(), info.salt(), params.old_password.as_bytes())?
+    {
+        return Err(ErrorCode::InputOldPassword);
+    }
+    if params.new_password.is_empty() {
+        return Err(ErrorCode::InputPasswordNotEmpty);
+    }
+    if params.new_password.ne(&params.confirm_password) {
+        return Err(ErrorCode::InputComfirmPasswordDifferentForInputPassword);
+    }
+    let (password, salt) = Password::generate_hash_salt(params.new_password.as_bytes())?;
+    member_service::update_password(
+        &state.db,
+        claims.member_id,
+        member_service::PasswordParams {
+            password: Some(password),
+            salt: Some(salt),
+        },
+    )
+    .await?;
+    super::revoke_tokens(&state, claims.member_id).await?;
+    let token = super::issue_token(&state, claims.member_id).await?;
+    Ok(Json(TokenResponse { token }))
+}
+
+/// drop the current token
+async fn logout(State(state): State<AppState>, headers: HeaderMap) -> Result<impl IntoResponse> {
+    if let Some(token) = super::get_bearer_token(&headers) {
+        state.cache.forget(CacheType::MemberAuthJwt, token).await?;
+    }
+    Ok(Body::empty())
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateProfileRequest {
+    mobile: Option<String>,
+    nickname: Option<String>,
+    avatar: Option<String>,
+    sex: Option<i32>,
+}
+
+impl From<UpdateProfileRequest> for member_service::ProfileParams {
+    fn from(value: UpdateProfileRequest) -> Self {
+        Self {
+            mobile: value.mobile,
+            nickname: value.nickname,
+            avatar: value.avatar,
+            sex: value.sex,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdatePasswordRequest {
+    /// empty for members without a password yet
+    #[serde(default)]
+    old_password: String,
+    new_password: String,
+    confirm_password: String,
+}
+
+#[derive(Debug, Serialize)]
+struct TokenResponse {
+    /// member`token, replacing the revoked ones
+    token: String,
+}
@@ -0,0 +1,205 @@
+mod auth;
+mod member;
+mod payment;
+mod wallet;
+
+use crate::{error::Result, state::AppState};
+use service::cache_service::{CacheType, Driver};
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Claims {
+    member_id: i32,
+    exp: i64,
+    /// milliseconds, tokens issued before a password change are refused
+    #[serde(default)]
+    issued_at: i64,
+}
+
+impl Claims {
+    pub fn build(member_id: &i32) -> Self {
+        Self {
+            member_id: *member_id,
+            exp: utils::datetime::now_timestamp(Some(TOKEN_TTL)),
+            issued_at: utils::datetime::now_time().timestamp_millis(),
+        }
+    }
+}
+
+/// seconds a member token stays valid
+const TOKEN_TTL: i64 = 24 * 3600;
+
+/// sign a token for the member and keep it until logout
+async fn issue_token(state: &AppState, member_id: i32) -> Result<String> {
+    let token = generate_token(&Claims::build(&member_id), &state.jwt_secret);
+    state
+        .cache
+        .put(
+            CacheType::MemberAuthJwt,
+            &token,
+            member_id,
+            Some(TOKEN_TTL),
+            None,
+        )
+        .await?;
+    Ok(token)
+}
+
+/// cache key of the time the member`s tokens issued before are refused
+fn revoked_key(member_id: i32) -> String {
+    format!("revoked:{}", member_id)
+}
+
+/// refuse every token of the member issued so far
+async fn revoke_tokens(state: &AppState, member_id: i32) -> Result<()> {
+    state
+        .cache
+        .put(
+            CacheType::MemberAuthJwt,
+            &revoked_key(member_id),
+            utils::datetime::now_time().timestamp_millis(),
+            Some(TOKEN_TTL),
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+/// router mod
+pub mod router {
+    use super::*;
+    use crate::state::AppState;
+    use axum::{middleware, Router};
+
+    /// routers init
+    pub async fn init(state: AppState) -> Router {
+        Router::new()
+            .merge(no_auths(state.clone()))
+            .merge(auths(state))
+    }
+
+    /// need auth`routers
+    fn auths(state: AppState) -> Router {
+        Router::new()
+            .merge(member::routers())
+            .merge(wallet::routers())
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                middlewares::token_check,
+            ))
+            .with_state(state)
+    }
+
+    /// not need auth`routers
+    fn no_auths(state: AppState) -> Router {
+        Router::new()
+            .merge(auth::routers(state.clone()))
+            .merge(payment::routers(state))
+    }
+}
+
+/// middleware mod
+mod middlewares {
+    use crate::{error::ErrorCode, state::AppState};
+    use axum::{
+        extract::{Request, State},
+        http::{HeaderMap, HeaderValue, StatusCode},
+        middleware::Next,
+        response::{IntoResponse, Response},
+    };
+    use service::{
+        cache_service::{CacheType, Driver},
+        member_service, system_audit_service,
+    };
+
+    /// jwt`token check middleware
+    pub async fn token_check(
+        State(state): State<AppState>,
+        mut req: Request,
+        next: Next,
+    ) -> Result<Response, StatusCode> {
+        let headers = req.headers();
+        match parse_token(state, headers).await {
+            Ok(claims) => {
+                let member_id = claims.member_id;
+                req.extensions_mut().insert(claims);
+                Ok(system_audit_service::with_actor(
+                    system_audit_service::ActorType::Member,
+                    member_id,
+                    next.run(req),
+                )
+                .await)
+            }
+            Err(err) => Ok(err.into_response()),
+        }
+    }
+
+    /// parse jwt token, only tokens of active members which are not logged out
+    /// or revoked pass
+    async fn parse_token(
+        state: AppState,
+        headers: &HeaderMap<HeaderValue>,
+    ) -> crate::error::Result<super::Claims> {
+        let token = super::get_bearer_token(headers).ok_or(ErrorCode::Unauthorized)?;
+        let claims = super::decode_token(token, &state.jwt_secret)?;
+        let jwt_item = state
+            .cache
+            .get(CacheType::MemberAuthJwt, token, None)
+            .await
+            .map_err(|_| ErrorCode::Unauthorized)?;
+        if !jwt_item.is_valid() {
+            return Err(ErrorCode::Unauthorized);
+        }
+        if let Some(revoked) = state
+            .cache
+            .first(
+                CacheType::MemberAuthJwt,
+                &super::revoked_key(claims.member_id),
+                None,
+            )
+            .await?
+        {
+            if !revoked.is_expired() && claims.issued_at < revoked.value::<i64>()? {
+                return Err(ErrorCode::Unauthorized);
+            }
+        }
+        // disabled or deleted since the token was issued
+        let member = member_service::get(&state.db, claims.member_id)
+            .await?
+            .ok_or(ErrorCode::Unauthorized)?;
+        // 1.正常
+        if *member.status() != 1 {
+            return Err(ErrorCode::MemberDisabled);
+        }
+        Ok(claims)
+    }
+}
+
+/// token of the `Authorization: Bearer` header
+fn get_bearer_token(headers: &axum::http::HeaderMap) -> Option<&str> {
+    headers
+        .get(axum::http::header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+/// encode jwt`token
+fn generate_token(claims: &Claims, secret: &str) -> String {
+    jsonwebtoken::encode(
+        &jsonwebtoken::Header::default(),
+        claims,
+        &jsonwebtoken::EncodingKey::from_secret(secret.as_bytes()),
+    )
+    .unwrap()
+}
+
+/// decode jwt`token
+fn decode_token(token: &str, secret: &str) -> crate::error::Result<Claims> {
+    jsonwebtoken::decode::<Claims>(
+        token,
+        &jsonwebtoken::DecodingKey::from_secret(secret.as_bytes()),
+        &jsonwebtoken::Validation::default(),
+    )
+    .map(|x| x.claims)
+    .map_err(|_| crate::error::ErrorCode::Unauthorized)
+}
@@ -0,0 +1,56 @@
+use crate::{
+    error::{ErrorCode, Result},
+    state::AppState,
+};
+use axum::{
+    extract::{Path, Query, State},
+    response::IntoResponse,
+    routing::get,
+    Form, Router,
+};
+use service::{member_recharge_service, ServiceError};
+use std::collections::BTreeMap;
+
+pub fn routers<S>(state: AppState) -> Router<S> {
+    Router::new()
+        .route("/payment/notify/:gateway", get(notify).post(notify_form))
+        .with_state(state)
+}
+
+/// gateway callback with query params, the mock gateway pays this way
+async fn notify(
+    State(state): State<AppState>,
+    Path(gateway): Path<String>,
+    Query(params): Query<BTreeMap<String, String>>,
+) -> Result<impl IntoResponse> {
+    handle(state, &gateway, params).await
+}
+
+/// gateway callback with a form body
+async fn notify_form(
+    State(state): State<AppState>,
+    Path(gateway): Path<String>,
+    Form(params): Form<BTreeMap<String, String>>,
+) -> Result<impl IntoResponse> {
+    handle(state, &gateway, params).await
+}
+
+/// credit the recharge of a verified callback, answering `success` so the
+/// gateway stops calling back
+async fn handle(
+    state: AppState,
+    gateway: &str,
+    params: BTreeMap<String, String>,
+) -> Result<&'static str> {
+    if gateway != state.payment.name() {
+        return Err(ErrorCode::PaymentNotify);
+    }
+    match member_recharge_service::notify(&state.db, state.payment.as_ref(), &params).await {
+        Ok(_) => Ok("success"),
+        Err(ServiceError::Payment(err)) => {
+            tracing::warn!("payment callback rejected: {}", err);
+            Err(ErrorCode::PaymentNotify)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
@@ -0,0 +1,145 @@
+use super::Claims;
+use crate::{
+    error::{ErrorCode, Result},
+    state::AppState,
+};
+use axum::{
+    extract::{Path, Query, State},
+    response::IntoResponse,
+    routing::get,
+    Extension, Json, Router,
+};
+use bigdecimal::BigDecimal;
+use serde::Deserialize;
+use service::{
+    member_recharge_service::{self, RechargeStatus},
+    member_withdrawal_service::{self, WithdrawalStatus},
+};
+use utils::paginate::PaginateParams;
+
+pub fn routers() -> Router<AppState> {
+    Router::new()
+        .route("/member/recharge", get(recharges).post(recharge))
+        .route("/member/recharge/:order_no", get(recharge_info))
+        .route("/member/withdrawal", get(withdrawals).post(withdraw))
+}
+
+/// create a recharge, the member pays it at its `pay_url`
+async fn recharge(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(params): Json<RechargeRequest>,
+) -> Result<impl IntoResponse> {
+    check_amount(&params.amount)?;
+    Ok(Json(
+        member_recharge_service::create(
+            &state.db,
+            state.payment.as_ref(),
+            claims.member_id,
+            params.amount,
+            state.recharge_ttl,
+        )
+        .await?,
+    ))
+}
+
+/// current member`s recharges
+async fn recharges(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(params): Query<RechargeSearchRequest>,
+) -> Result<impl IntoResponse> {
+    let params = member_recharge_service::SearchParams::new(
+        Some(claims.member_id),
+        None,
+        params.status,
+        params.paginate,
+    );
+    Ok(Json(
+        member_recharge_service::paginate(&state.db, &params).await?,
+    ))
+}
+
+/// current member`s recharge, to follow its payment
+async fn recharge_info(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(order_no): Path<String>,
+) -> Result<impl IntoResponse> {
+    Ok(Json(
+        member_recharge_service::get_by_order_no(&state.db, claims.member_id, &order_no).await?,
+    ))
+}
+
+/// apply for a withdrawal, debited from the balance until it is rejected
+async fn withdraw(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(params): Json<WithdrawRequest>,
+) -> Result<impl IntoResponse> {
+    check_amount(&params.amount)?;
+    if params.account.trim().is_empty() || params.account_name.trim().is_empty() {
+        return Err(ErrorCode::WithdrawalAccount);
+    }
+    Ok(Json(
+        member_withdrawal_service::apply(
+            &state.db,
+            claims.member_id,
+            params.amount,
+            params.account.trim(),
+            params.account_name.trim(),
+        )
+        .await?,
+    ))
+}
+
+/// current member`s withdrawals
+async fn withdrawals(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(params): Query<WithdrawalSearchRequest>,
+) -> Result<impl IntoResponse> {
+    let params = member_withdrawal_service::SearchParams::new(
+        Some(claims.member_id),
+        None,
+        params.status,
+        params.paginate,
+    );
+    Ok(Json(
+        member_withdrawal_service::paginate(&state.db, &params).await?,
+    ))
+}
+
+/// positive, in cents at most
+fn check_amount(amount: &BigDecimal) -> Result<()> {
+    if amount <= &BigDecimal::default() || amount.with_scale(2) != *amount {
+        return Err(ErrorCode::Amount);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct RechargeRequest {
+    amount: BigDecimal,
+}
+
+#[derive(Debug, Deserialize)]
+struct RechargeSearchRequest {
+    status: Option<RechargeStatus>,
+    #[serde(flatten)]
+    paginate: PaginateParams,
+}
+
+#[derive(Debug, Deserialize)]
+struct WithdrawRequest {
+    amount: BigDecimal,
+    account: String,
+    account_name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct WithdrawalSearchRequest {
+    status: Option<WithdrawalStatus>,
+    #[serde(flatten)]
+    paginate: PaginateParams,
+}
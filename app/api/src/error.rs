@@ -0,0 +1,137 @@
+use axum::{http::StatusCode, response::IntoResponse};
+use custom_attrs::CustomAttrs;
+
+pub type Result<T> = std::result::Result<T, ErrorCode>;
+
+/// Error Code Mapping
+#[derive(Debug, CustomAttrs)]
+#[attr(pub status_code: StatusCode)]
+#[attr(pub message: Option<&str>)]
+pub enum ErrorCode {
+    /// Internal Server error
+    #[attr(status_code = StatusCode::INTERNAL_SERVER_ERROR,message = "Internal Server error")]
+    InternalServerString(String),
+    /// Token valid
+    #[attr(status_code = StatusCode::UNAUTHORIZED,message = "Token valid")]
+    Unauthorized,
+    /// Server startup error
+    #[attr(status_code = StatusCode::INTERNAL_SERVER_ERROR, message = "Server startup error")]
+    ServerSteup,
+    /// Email code error
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Email code error")]
+    EmailCode,
+    /// Email code sent too often
+    #[attr(status_code = StatusCode::TOO_MANY_REQUESTS, message = "Email code sent too often")]
+    EmailCodeFrequent,
+    /// Email code not sent
+    #[attr(status_code = StatusCode::INTERNAL_SERVER_ERROR, message = "Email code not sent")]
+    EmailSend,
+    /// Password logins too often
+    #[attr(status_code = StatusCode::TOO_MANY_REQUESTS, message = "Too many login attempts, try again later")]
+    LoginFrequent,
+    /// Input email or password error
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Input email or password error")]
+    InputEmailAndPwd,
+    /// Member disabled
+    #[attr(status_code = StatusCode::FORBIDDEN, message = "Member disabled")]
+    MemberDisabled,
+    /// Invite code error
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Invite code error")]
+    InviteCode,
+    /// Inviter not promoter
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Inviter can not promote")]
+    InviterNotPromoter,
+    /// Member already joined a team
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Member already joined a team")]
+    MemberTeamJoined,
+    /// Inviter in the member`s own team
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Inviter is in the member team")]
+    MemberTeamCycle,
+    /// input old Password Error
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Input old Password error")]
+    InputOldPassword,
+    /// input Password not empty Error
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Input Password not empty")]
+    InputPasswordNotEmpty,
+    /// Input comfirm password is different for input password
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Input comfirm password is different for input password")]
+    InputComfirmPasswordDifferentForInputPassword,
+    /// Amount error
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Amount must be positive with at most 2 decimals")]
+    Amount,
+    /// Withdrawal account empty
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Withdrawal needs an account and its name")]
+    WithdrawalAccount,
+    /// Payment callback rejected
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Payment callback rejected")]
+    PaymentNotify,
+    /// Insufficient balance or integral
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "Insufficient balance or integral")]
+    InsufficientFunds,
+    /// Idempotency key used by another request
+    #[attr(status_code = StatusCode::CONFLICT, message = "Idempotency key was used by another request")]
+    IdempotencyConflict,
+    #[attr(status_code = StatusCode::BAD_REQUEST, message = "json error")]
+    SerdeJson(serde_json::Error),
+}
+
+impl From<service::ServiceError> for ErrorCode {
+    fn from(value: service::ServiceError) -> Self {
+        let err_string = match value {
+            service::ServiceError::BuildClient(err) => format!("BuildDataClient: {}", err),
+            service::ServiceError::QueryError(err) => format!("QueryError: {}", err),
+            service::ServiceError::RelationNotFetchedError(err) => {
+                format!("RelationNotFetchedError: {}", err)
+            }
+            service::ServiceError::SerializeJson(err) => err.to_string(),
+            service::ServiceError::Io(err) => format!("IoError: {}", err),
+            service::ServiceError::DataNotFound => "DataNotExsist".to_owned(),
+            service::ServiceError::CacheNotFound => "CacheNotExsist".to_owned(),
+            service::ServiceError::CacheDriver(err) => format!("CacheDriverError: {}", err),
+            service::ServiceError::Template(err) => format!("TemplateError: {}", err),
+            service::ServiceError::Mail(err) => format!("MailError: {}", err),
+            service::ServiceError::InsufficientFunds => return Self::InsufficientFunds,
+            service::ServiceError::IdempotencyConflict => return Self::IdempotencyConflict,
+            service::ServiceError::Payment(err) => format!("PaymentError: {}", err),
+            service::ServiceError::InviteRefused(refusal) => return refusal.into(),
+        };
+        Self::InternalServerString(err_string)
+    }
+}
+
+impl From<service::member_team_service::InviteRefusal> for ErrorCode {
+    fn from(value: service::member_team_service::InviteRefusal) -> Self {
+        match value {
+            service::member_team_service::InviteRefusal::NotFound => Self::InviteCode,
+            service::member_team_service::InviteRefusal::NotPromoter => Self::InviterNotPromoter,
+            service::member_team_service::InviteRefusal::Joined => Self::MemberTeamJoined,
+            service::member_team_service::InviteRefusal::Cycle => Self::MemberTeamCycle,
+        }
+    }
+}
+
+impl From<utils::password::ErrorType> for ErrorCode {
+    fn from(value: utils::password::ErrorType) -> Self {
+        let msg = match value {
+            utils::password::ErrorType::Argon2(e) => e.to_string(),
+            utils::password::ErrorType::Hash(e) => e.to_string(),
+        };
+        Self::InternalServerString(format!("GeneratePasswordError:{}", msg))
+    }
+}
+
+impl From<serde_json::Error> for ErrorCode {
+    fn from(value: serde_json::Error) -> Self {
+        Self::SerdeJson(value)
+    }
+}
+impl IntoResponse for ErrorCode {
+    fn into_response(self) -> axum::response::Response {
+        let response = match self {
+            Self::InternalServerString(ref err_str) => Some(err_str).map(|x| x.as_str()),
+            _ => self.get_message(),
+        }
+        .map(|x| x.to_string());
+        (self.get_status_code(), response.unwrap_or_default()).into_response()
+    }
+}
@@ -1,44 +1,82 @@
-use lettre::{
-    message::header::ContentType, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
-    AsyncTransport, Message, Tokio1Executor,
-};
+use axum::{extract::MatchedPath, http::Request};
+use error::{ErrorCode, Result};
+
+/// controllers
+mod ctls;
+/// error and result
+mod error;
+mod state;
 
 #[tokio::main]
-async fn main() {
+async fn main() -> Result<()> {
     let env_filter = Some(format!(
         "{}=DEBUG,tower_http=debug,axum::rejection=trace",
         env!("CARGO_PKG_NAME")
     ));
     utils::logger::init(env_filter);
-    let smtp_host = "smtp.163.com";
-    let smtp_port = 25;
-    let smtp_user = "";
-    let smtp_password = "";
-    let from_user_name = "NoBody";
-    let from = format!("{from_user_name} <{smtp_user}>").parse().unwrap();
-    let to_user = "";
-    let to_user_name = "Hei";
-    let to = format!("{to_user_name} <{to_user}>").parse().unwrap();
+    let prisma_client = service::Database::new(service::DatabaseConfig::default()).await?;
+    let mailer = service::mailer_service::Mailer::from_env()?;
+    let state = state::State::build(prisma_client)?;
+    let outbox_task = {
+        let state = state.clone();
+        tokio::spawn(async move {
+            service::system_mail_outbox_service::run(&state.db, mailer, outbox_interval()).await
+        })
+    };
+    let recharge_task = {
+        let state = state.clone();
+        tokio::spawn(async move {
+            service::member_recharge_service::run(&state.db, recharge_expire_interval()).await
+        })
+    };
+
+    let app = ctls::router::init(state).await.layer(
+        tower_http::trace::TraceLayer::new_for_http().make_span_with(|request: &Request<_>| {
+            let matched_path = request
+                .extensions()
+                .get::<MatchedPath>()
+                .map(MatchedPath::as_str);
 
-    let email = Message::builder()
-        .from(from)
-        .to(to)
-        .subject("Happy new async year")
-        .header(ContentType::TEXT_PLAIN)
-        .body(String::from("Be happy with async!"))
+            tracing::info_span!(
+                "http_request",
+                method = ?request.method(),
+                matched_path,
+                query = request.uri().query().unwrap_or_default()
+            )
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:3001")
+        .await
         .unwrap();
+    tracing::info!("Service is running on {}", listener.local_addr().unwrap());
 
-    let creds = Credentials::new(smtp_user.to_owned(), smtp_password.to_owned());
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+    )
+    .await
+    .map_err(|_| ErrorCode::ServerSteup)?;
+    outbox_task.abort();
+    recharge_task.abort();
+    Ok(())
+}
 
-    let mailer: AsyncSmtpTransport<Tokio1Executor> =
-        AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(smtp_host)
-            .unwrap()
-            .credentials(creds)
-            .port(smtp_port)
-            .build();
+/// `MAIL_OUTBOX_INTERVAL_SECS` between outbox dispatches, 5 seconds by default
+fn outbox_interval() -> std::time::Duration {
+    let secs = std::env::var("MAIL_OUTBOX_INTERVAL_SECS")
+        .ok()
+        .and_then(|x| x.trim().parse::<u64>().ok())
+        .filter(|x| *x > 0)
+        .unwrap_or(5);
+    std::time::Duration::from_secs(secs)
+}
 
-    match mailer.send(email).await {
-        Ok(_) => println!("Email sent successfully!"),
-        Err(e) => panic!("Could not send email: {e:?}"),
-    }
+/// `MEMBER_RECHARGE_EXPIRE_INTERVAL_SECS` between closing expired recharges, 60 seconds by default
+fn recharge_expire_interval() -> std::time::Duration {
+    let secs = std::env::var("MEMBER_RECHARGE_EXPIRE_INTERVAL_SECS")
+        .ok()
+        .and_then(|x| x.trim().parse::<u64>().ok())
+        .filter(|x| *x > 0)
+        .unwrap_or(60);
+    std::time::Duration::from_secs(secs)
 }
@@ -0,0 +1,35 @@
+use crate::error::{ErrorCode, Result};
+use service::{cache_service, member_recharge_service, payment_service, Database};
+use std::sync::Arc;
+
+pub type AppState = Arc<State>;
+
+pub struct State {
+    pub db: Database,
+    pub cache: cache_service::Cache<cache_service::CacheDriverMemory>,
+    /// signs member tokens, never the admin secret
+    pub jwt_secret: String,
+    /// pays the recharges and verifies their callbacks
+    pub payment: Arc<dyn payment_service::PaymentGateway>,
+    /// time a recharge waits for its payment
+    pub recharge_ttl: std::time::Duration,
+}
+
+impl State {
+    /// fails without `MEMBER_JWT_SECRET` or a payment gateway
+    pub fn build(db: Database) -> Result<AppState> {
+        let jwt_secret = std::env::var("MEMBER_JWT_SECRET")
+            .ok()
+            .filter(|x| !x.trim().is_empty())
+            .ok_or_else(|| {
+                ErrorCode::InternalServerString("MEMBER_JWT_SECRET is required".to_owned())
+            })?;
+        Ok(Arc::new(Self {
+            db,
+            cache: cache_service::Cache::new(cache_service::CacheDriverMemory::default()),
+            jwt_secret,
+            payment: payment_service::gateway_from_env()?,
+            recharge_ttl: member_recharge_service::ttl_from_env(),
+        }))
+    }
+}
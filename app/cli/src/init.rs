@@ -1,4 +1,4 @@
-use service::{system_menu_service, system_user_service};
+use service::{system_mail_template_service, system_menu_service, system_user_service};
 use utils::password::Password;
 
 #[derive(Debug, clap::Args)]
@@ -32,6 +32,7 @@ pub async fn exec(params: &CliInitParams) -> service::Result<()> {
                 expire_time: None,
                 status: Some(1),
             },
+            None,
         )
         .await?;
     }
@@ -43,5 +44,10 @@ pub async fn exec(params: &CliInitParams) -> service::Result<()> {
         crate::menu::import().await?;
     }
     tracing::info!("Menu Import finish..");
+
+    system_mail_template_service::seed_defaults(&db).await?;
+    tracing::info!("Mail Template finish..");
+
+    crate::user_role::migrate().await?;
     Ok(())
 }
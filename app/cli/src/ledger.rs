@@ -0,0 +1,28 @@
+use service::member_service;
+
+#[derive(Debug, clap::Args)]
+pub struct CliReconcileParams {
+    /// Set the differing members to the amounts recomputed from their bills
+    #[arg(long)]
+    pub fix: bool,
+}
+
+pub async fn reconcile(params: &CliReconcileParams) -> service::Result<()> {
+    let db = service::Database::new(service::DatabaseConfig::default()).await?;
+    let result = member_service::reconcile(&db, params.fix).await?;
+    for item in &result {
+        tracing::warn!(
+            "member {}: balance {} by bills {}, integral {} by bills {}",
+            item.member_id,
+            item.balance,
+            item.bill_balance,
+            item.integral,
+            item.bill_integral
+        );
+    }
+    match params.fix {
+        true => tracing::info!("Ledger reconcile finish, {} members fixed..", result.len()),
+        false => tracing::info!("Ledger reconcile finish, {} members differ..", result.len()),
+    }
+    Ok(())
+}
@@ -1,6 +1,10 @@
 use clap::Parser;
 mod init;
+mod ledger;
 mod menu;
+mod retention;
+mod route;
+mod user_role;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -11,17 +15,30 @@ enum Cli {
     MenuExport,
     /// Menu Import
     MenuImport,
+    /// Copy users` legacy role_id into user roles
+    UserRoleMigrate,
+    /// Purge logs and caches past retention, archiving them first
+    LogPurge(retention::CliRetentionParams),
+    /// Recompute member balances and integrals from their bills
+    LedgerReconcile(ledger::CliReconcileParams),
+    /// Diff registered admin api routes with api permission menus
+    RouteCheck(route::CliRouteParams),
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let env_filter = Some(format!("{}=INFO", env!("CARGO_PKG_NAME")));
+    // the route check reports through the admin crate
+    let env_filter = Some(format!("{}=INFO,admin=INFO", env!("CARGO_PKG_NAME")));
     utils::logger::init(env_filter);
     let cli = Cli::parse();
     let result = match &cli {
         Cli::Init(params) => init::exec(params).await,
         Cli::MenuExport => menu::export().await,
         Cli::MenuImport => menu::import().await,
+        Cli::UserRoleMigrate => user_role::migrate().await,
+        Cli::LogPurge(params) => retention::exec(params).await,
+        Cli::LedgerReconcile(params) => ledger::reconcile(params).await,
+        Cli::RouteCheck(params) => route::check(params).await,
     };
     if let Err(e) = result {
         tracing::error!("{:#?}", e);
@@ -0,0 +1,35 @@
+use service::log_retention_service::{self, RetentionConfig};
+
+#[derive(Debug, clap::Args)]
+pub struct CliRetentionParams {
+    /// Only count the rows past retention
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+pub async fn exec(params: &CliRetentionParams) -> service::Result<()> {
+    let db = service::Database::new(service::DatabaseConfig::default()).await?;
+    let config = RetentionConfig::from_env();
+    if params.dry_run {
+        for report in log_retention_service::dry_run(&db, &config).await? {
+            tracing::info!(
+                "{}: {} rows past retention",
+                report.table.name(),
+                report.rows
+            );
+        }
+        return Ok(());
+    }
+    for report in log_retention_service::purge(&db, &config).await? {
+        match report.archive {
+            Some(archive) => tracing::info!(
+                "{}: {} rows purged, archived to {}",
+                report.table.name(),
+                report.rows,
+                archive.display()
+            ),
+            None => tracing::info!("{}: {} rows purged", report.table.name(), report.rows),
+        }
+    }
+    Ok(())
+}
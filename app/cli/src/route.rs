@@ -0,0 +1,11 @@
+#[derive(Debug, clap::Args)]
+pub struct CliRouteParams {
+    /// Create the missing api permission menus
+    #[arg(long)]
+    pub create: bool,
+}
+
+pub async fn check(params: &CliRouteParams) -> service::Result<()> {
+    let db = service::Database::new(service::DatabaseConfig::default()).await?;
+    admin::ctls::router::check(&db, params.create).await
+}
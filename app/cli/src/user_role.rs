@@ -0,0 +1,8 @@
+use service::system_user_role_service;
+
+pub async fn migrate() -> service::Result<()> {
+    let db = service::Database::new(service::DatabaseConfig::default()).await?;
+    let count = system_user_role_service::migrate_role_id(&db).await?;
+    tracing::info!("User Role migrate finish, {} users migrated..", count);
+    Ok(())
+}
@@ -1,7 +1,20 @@
-use crate::{generate_prisma::system_cache, Database, Result};
-use serde::Serialize;
+use crate::{
+    generate_prisma::{system_cache, system_cache_hit, system_cache_lock},
+    Database, Result,
+};
+use prisma_client_rust::prisma_errors::query_engine::UniqueKeyViolation;
+use serde::{de::DeserializeOwned, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
-use utils::datetime::{now_time, now_timestamp};
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::Mutex;
+use utils::datetime::{now_time, now_timestamp, offset_from_timestamp};
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize_repr, Deserialize_repr)]
 #[repr(i32)]
@@ -12,6 +25,9 @@ pub enum CacheType {
     SystemAuthLoginQrCode = 3,
     MemberAuthRegisterEmail = 4,
     MemberAuthLoginEmail = 5,
+    SystemRoleMenus = 6,
+    SystemDict = 7,
+    MemberAuthJwt = 8,
 }
 impl From<i32> for CacheType {
     fn from(value: i32) -> Self {
@@ -22,6 +38,9 @@ impl From<i32> for CacheType {
             3 => Self::MemberAuthRegisterEmail,
             4 => Self::SystemAuthLoginQrCode,
             5 => Self::MemberAuthLoginEmail,
+            6 => Self::SystemRoleMenus,
+            7 => Self::SystemDict,
+            8 => Self::MemberAuthJwt,
             _ => Self::SystemAuthJwt,
         }
     }
@@ -35,6 +54,9 @@ impl From<CacheType> for i32 {
             CacheType::MemberAuthRegisterEmail => 3,
             CacheType::SystemAuthLoginQrCode => 4,
             CacheType::MemberAuthLoginEmail => 5,
+            CacheType::SystemRoleMenus => 6,
+            CacheType::SystemDict => 7,
+            CacheType::MemberAuthJwt => 8,
         }
     }
 }
@@ -43,7 +65,7 @@ impl From<CacheType> for i32 {
 pub trait Driver {
     /// Storing Items In The Cache
     async fn put<T: Serialize + std::marker::Send + std::marker::Sync>(
-        &mut self,
+        &self,
         r#type: CacheType,
         key: &str,
         value: T,
@@ -60,20 +82,33 @@ pub trait Driver {
     ) -> Result<Option<Info>>;
 
     /// Retrieve & Delete
-    async fn pull(&mut self, r#type: CacheType, key: &str) -> Result<Info>;
+    async fn pull(&self, r#type: CacheType, key: &str) -> Result<Info>;
     /// clear the entire cache
-    async fn flush(&mut self, r#type: Option<CacheType>) -> Result<i64>;
+    async fn flush(&self, r#type: Option<CacheType>) -> Result<i64>;
+
+    /// Acquire an exclusive lock for `ttl` seconds, returns the fencing token
+    async fn acquire_lock(&self, key: &str, ttl: i64) -> Result<Option<i64>>;
+    /// Release the lock if it is still held with `token`
+    async fn release_lock(&self, key: &str, token: i64) -> Result<bool>;
+    /// Count a hit, returns the hits within the last `ttl` seconds
+    async fn incr_with_ttl(&self, key: &str, ttl: i64) -> Result<i64>;
 }
 pub enum CacheDriverType {
     Memory,
     Database(Database),
-    // Redis,
+    #[cfg(feature = "redis")]
+    Redis(String),
     // Memcached,
     // DynamoDB,
     // File,
 }
 
-pub struct Cache<D: Driver>(D);
+/// in-flight `remember` computations, keyed by cache type and key
+type Flights = Mutex<HashMap<String, Arc<Mutex<()>>>>;
+
+/// the third field counts removals, a `remember` that saw one while computing
+/// drops what it stored
+pub struct Cache<D: Driver>(Arc<D>, Flights, AtomicU64);
 
 #[allow(dead_code)]
 impl<D> Cache<D>
@@ -81,7 +116,11 @@ where
     D: Driver,
 {
     pub fn new(driver: D) -> Self {
-        Self(driver)
+        Self(
+            Arc::new(driver),
+            Mutex::new(HashMap::new()),
+            AtomicU64::new(0),
+        )
     }
 }
 
@@ -91,7 +130,7 @@ where
     D: Driver + std::marker::Sync + std::marker::Send,
 {
     async fn put<T>(
-        &mut self,
+        &self,
         r#type: CacheType,
         key: &str,
         value: T,
@@ -115,13 +154,27 @@ where
         self.0.first(r#type, key, default).await
     }
 
-    async fn pull(&mut self, r#type: CacheType, key: &str) -> Result<Info> {
+    async fn pull(&self, r#type: CacheType, key: &str) -> Result<Info> {
+        self.2.fetch_add(1, Ordering::SeqCst);
         self.0.pull(r#type, key).await
     }
 
-    async fn flush(&mut self, r#type: Option<CacheType>) -> Result<i64> {
+    async fn flush(&self, r#type: Option<CacheType>) -> Result<i64> {
+        self.2.fetch_add(1, Ordering::SeqCst);
         self.0.flush(r#type).await
     }
+
+    async fn acquire_lock(&self, key: &str, ttl: i64) -> Result<Option<i64>> {
+        self.0.acquire_lock(key, ttl).await
+    }
+
+    async fn release_lock(&self, key: &str, token: i64) -> Result<bool> {
+        self.0.release_lock(key, token).await
+    }
+
+    async fn incr_with_ttl(&self, key: &str, ttl: i64) -> Result<i64> {
+        self.0.incr_with_ttl(key, ttl).await
+    }
 }
 
 #[allow(dead_code)]
@@ -139,7 +192,7 @@ where
             .ok_or(super::ServiceError::CacheNotFound)
     }
     pub async fn add<T>(
-        &mut self,
+        &self,
         r#type: CacheType,
         key: &str,
         value: T,
@@ -159,7 +212,7 @@ where
     }
     /// Storing Items Forever
     pub async fn forever<T: Serialize + std::marker::Send + std::marker::Sync>(
-        &mut self,
+        &self,
         r#type: CacheType,
         key: &str,
         value: T,
@@ -168,9 +221,20 @@ where
         self.0.put(r#type, key, value, None, attach).await
     }
 
+    /// Removing Items From The Cache
+    pub async fn forget(&self, r#type: CacheType, key: &str) -> Result<bool> {
+        // counted before the check, a `remember` storing meanwhile sees it
+        self.2.fetch_add(1, Ordering::SeqCst);
+        if !self.has(&r#type, key).await? {
+            return Ok(false);
+        }
+        self.0.pull(r#type, key).await?;
+        Ok(true)
+    }
+
     /// increment value
     pub async fn increment(
-        &mut self,
+        &self,
         r#type: CacheType,
         key: &str,
         number: Option<f64>,
@@ -191,7 +255,7 @@ where
 
     /// decrement value
     pub async fn decrement(
-        &mut self,
+        &self,
         r#type: CacheType,
         key: &str,
         number: Option<f64>,
@@ -210,56 +274,202 @@ where
             .await
     }
 
-    /// remember
-    pub async fn remember<F>(
-        &mut self,
+    /// Retrieve the cached value, or compute and store it.
+    ///
+    /// Concurrent misses on the same key wait for the first caller
+    /// instead of all running `r#fn`. A value computed while something was
+    /// forgotten is returned but not kept, it may predate the change.
+    pub async fn remember<T, F, Fut>(
+        &self,
         r#type: CacheType,
         key: &str,
         valid_time_length: Option<i64>,
         attach: Option<String>,
         r#fn: F,
-    ) -> Result<Info>
+    ) -> Result<T>
     where
-        F: FnOnce() -> Result<Info> + std::marker::Send,
+        T: Serialize + DeserializeOwned + std::marker::Send + std::marker::Sync,
+        F: FnOnce() -> Fut + std::marker::Send,
+        Fut: Future<Output = Result<T>> + std::marker::Send,
     {
-        if !self.has(&r#type, key).await? {
-            let info = r#fn()?;
-            return self
-                .0
-                .put(r#type, key, info, valid_time_length, attach)
-                .await;
+        if let Some(value) = self.fresh_value(&r#type, key).await? {
+            return Ok(value);
         }
-        self.get(r#type, key, None).await
+        let flight_key = format!("{}:{}", i32::from(r#type.clone()), key);
+        let flight = self
+            .1
+            .lock()
+            .await
+            .entry(flight_key.clone())
+            .or_default()
+            .clone();
+        let guard = flight.lock().await;
+        let generation = self.2.load(Ordering::SeqCst);
+        let result = match self.fresh_value(&r#type, key).await {
+            Ok(Some(value)) => Ok(value),
+            Ok(None) => match r#fn().await {
+                Ok(value) => {
+                    self.put_unless_forgotten(
+                        r#type,
+                        key,
+                        value,
+                        valid_time_length,
+                        attach,
+                        generation,
+                    )
+                    .await
+                }
+                Err(err) => Err(err),
+            },
+            Err(err) => Err(err),
+        };
+        drop(guard);
+        let mut flights = self.1.lock().await;
+        if Arc::strong_count(&flight) <= 2 {
+            flights.remove(&flight_key);
+        }
+        result
     }
 
     /// remember_forever
-    pub async fn remember_forever<F>(
-        &mut self,
+    pub async fn remember_forever<T, F, Fut>(
+        &self,
         r#type: CacheType,
         key: &str,
         attach: Option<String>,
         r#fn: F,
-    ) -> Result<Info>
+    ) -> Result<T>
     where
-        F: Fn() -> Result<Info> + std::marker::Send,
+        T: Serialize + DeserializeOwned + std::marker::Send + std::marker::Sync,
+        F: FnOnce() -> Fut + std::marker::Send,
+        Fut: Future<Output = Result<T>> + std::marker::Send,
     {
-        if !self.has(&r#type, key).await? {
-            let info = r#fn()?;
-            return self.0.put(r#type, key, info, None, attach).await;
+        self.remember(r#type, key, None, attach, r#fn).await
+    }
+
+    /// store the value, then drop it again when something was forgotten
+    /// since `generation`; checked after the put, a removal landing between
+    /// the check and the put can not be missed
+    async fn put_unless_forgotten<T>(
+        &self,
+        r#type: CacheType,
+        key: &str,
+        value: T,
+        valid_time_length: Option<i64>,
+        attach: Option<String>,
+        generation: u64,
+    ) -> Result<T>
+    where
+        T: Serialize + std::marker::Send + std::marker::Sync,
+    {
+        self.0
+            .put(r#type.clone(), key, &value, valid_time_length, attach)
+            .await?;
+        if self.2.load(Ordering::SeqCst) != generation {
+            match self.0.pull(r#type, key).await {
+                Ok(_) | Err(super::ServiceError::CacheNotFound) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(value)
+    }
+
+    async fn fresh_value<T: DeserializeOwned>(
+        &self,
+        r#type: &CacheType,
+        key: &str,
+    ) -> Result<Option<T>> {
+        match self.0.first(r#type.clone(), key, None).await? {
+            Some(info) if !info.is_expired() => Ok(Some(info.value::<T>()?)),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl<D> Cache<D>
+where
+    D: Driver + std::marker::Send + std::marker::Sync + 'static,
+{
+    /// Acquire an exclusive lock for `ttl` seconds
+    ///
+    /// Returns `None` while someone else holds it.
+    pub async fn lock(&self, key: &str, ttl: i64) -> Result<Option<Lock<D>>> {
+        Ok(self.0.acquire_lock(key, ttl).await?.map(|token| Lock {
+            driver: self.0.clone(),
+            key: key.to_owned(),
+            token,
+            released: false,
+        }))
+    }
+}
+
+/// Lock acquired through [`Cache::lock`]
+///
+/// Released with [`Lock::release`] or when dropped, otherwise it expires
+/// after its ttl, so a crashed holder never blocks the key forever.
+pub struct Lock<D>
+where
+    D: Driver + std::marker::Send + std::marker::Sync + 'static,
+{
+    driver: Arc<D>,
+    key: String,
+    token: i64,
+    released: bool,
+}
+
+impl<D> Lock<D>
+where
+    D: Driver + std::marker::Send + std::marker::Sync + 'static,
+{
+    /// fencing token, grows every time the key is acquired
+    pub fn token(&self) -> i64 {
+        self.token
+    }
+
+    pub async fn release(mut self) -> Result<bool> {
+        self.released = true;
+        self.driver.release_lock(&self.key, self.token).await
+    }
+}
+
+impl<D> Drop for Lock<D>
+where
+    D: Driver + std::marker::Send + std::marker::Sync + 'static,
+{
+    /// release in the background, without a runtime the lock just expires
+    fn drop(&mut self) {
+        if self.released {
+            return;
+        }
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            let (driver, key, token) = (
+                self.driver.clone(),
+                std::mem::take(&mut self.key),
+                self.token,
+            );
+            handle.spawn(async move {
+                if let Err(err) = driver.release_lock(&key, token).await {
+                    tracing::warn!("lock {} not released: {:?}", key, err);
+                }
+            });
         }
-        self.get(r#type, key, None).await
     }
 }
 
 #[derive(Default)]
 pub struct CacheDriverMemory {
-    data: Vec<Info>,
+    data: std::sync::RwLock<Vec<Info>>,
+    /// key => (fencing token, expire timestamp)
+    locks: std::sync::Mutex<HashMap<String, (i64, i64)>>,
+    fence: std::sync::atomic::AtomicI64,
+    /// key => (window in milliseconds, hit timestamps in milliseconds)
+    hits: std::sync::Mutex<HashMap<String, (i64, Vec<i64>)>>,
 }
 
 #[async_trait::async_trait]
 impl Driver for CacheDriverMemory {
     async fn put<T: Serialize + std::marker::Send + std::marker::Sync>(
-        &mut self,
+        &self,
         r#type: CacheType,
         key: &str,
         value: T,
@@ -269,12 +479,14 @@ impl Driver for CacheDriverMemory {
         let info = Info {
             r#type,
             key: key.to_owned(),
-            value: serde_json::to_string(&value).unwrap(),
+            value: serde_json::to_string(&value)?,
             valid_time_length,
             attach,
             create_time: now_timestamp(None),
         };
-        self.data.push(info.clone());
+        let mut data = self.data.write().unwrap();
+        data.retain(|x| !(x.r#type.eq(&info.r#type) && x.key.eq(key)));
+        data.push(info.clone());
         Ok(info)
     }
 
@@ -286,75 +498,107 @@ impl Driver for CacheDriverMemory {
     ) -> Result<Option<Info>> {
         let info = self
             .data
-            .clone()
-            .into_iter()
-            .find(|x| x.r#type.eq(&r#type) && x.key.eq(key));
+            .read()
+            .unwrap()
+            .iter()
+            .find(|x| x.r#type.eq(&r#type) && x.key.eq(key))
+            .cloned();
         if info.is_none() {
             return Ok(default);
         }
         Ok(info)
     }
-    async fn pull(&mut self, r#type: CacheType, key: &str) -> Result<Info> {
+    async fn pull(&self, r#type: CacheType, key: &str) -> Result<Info> {
         let info = self
             .first(r#type.clone(), key, None)
             .await?
             .ok_or(super::ServiceError::CacheNotFound)?;
-        self.data = self
-            .data
-            .clone()
-            .into_iter()
-            .filter(|x| x.r#type.ne(&r#type) && x.key.ne(key))
-            .collect::<Vec<Info>>();
+        self.data
+            .write()
+            .unwrap()
+            .retain(|x| !(x.r#type.eq(&r#type) && x.key.eq(key)));
         Ok(info)
     }
 
-    async fn flush(&mut self, r#type: Option<CacheType>) -> Result<i64> {
-        if let Some(cache_type) = r#type {
-            let count = self
-                .data
-                .clone()
-                .into_iter()
-                .filter(|x| x.r#type.eq(&cache_type))
-                .count() as i64;
-            self.data = self
-                .data
-                .clone()
-                .into_iter()
-                .filter(|x| x.r#type.ne(&cache_type))
-                .collect::<Vec<Info>>();
-            return Ok(count);
+    async fn flush(&self, r#type: Option<CacheType>) -> Result<i64> {
+        let mut data = self.data.write().unwrap();
+        let count = data.len();
+        match r#type {
+            Some(cache_type) => data.retain(|x| x.r#type.ne(&cache_type)),
+            None => data.clear(),
         }
-        let count = self.data.len() as i64;
-        self.data = vec![];
-        Ok(count)
+        Ok((count - data.len()) as i64)
+    }
+
+    async fn acquire_lock(&self, key: &str, ttl: i64) -> Result<Option<i64>> {
+        let now = now_timestamp(None);
+        let mut locks = self.locks.lock().unwrap();
+        locks.retain(|_, (_, expire)| *expire > now);
+        if locks.contains_key(key) {
+            return Ok(None);
+        }
+        let token = self.fence.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+        locks.insert(key.to_owned(), (token, now + ttl));
+        Ok(Some(token))
+    }
+
+    async fn release_lock(&self, key: &str, token: i64) -> Result<bool> {
+        let mut locks = self.locks.lock().unwrap();
+        if locks.get(key).is_some_and(|(x, _)| *x == token) {
+            locks.remove(key);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    async fn incr_with_ttl(&self, key: &str, ttl: i64) -> Result<i64> {
+        let now = now_time().timestamp_millis();
+        let mut hits = self.hits.lock().unwrap();
+        // drop the keys whose window has passed since their last hit
+        hits.retain(|_, (window, x)| x.last().is_some_and(|last| *last > now - *window));
+        let (window, key_hits) = hits.entry(key.to_owned()).or_default();
+        *window = ttl * 1000;
+        key_hits.retain(|x| *x > now - ttl * 1000);
+        key_hits.push(now);
+        Ok(key_hits.len() as i64)
     }
 }
 
 pub struct CacheDriverDatabase(Database);
 
+impl CacheDriverDatabase {
+    pub fn new(db: Database) -> Self {
+        Self(db)
+    }
+}
+
 #[async_trait::async_trait]
 impl Driver for CacheDriverDatabase {
     async fn put<T: Serialize + std::marker::Send + std::marker::Sync>(
-        &mut self,
+        &self,
         r#type: CacheType,
         key: &str,
         value: T,
         valid_time_length: Option<i64>,
         attach: Option<String>,
     ) -> Result<Info> {
+        let value = serde_json::to_string(&value)?;
+        let data = vec![
+            system_cache::attach::set(attach.unwrap_or_default()),
+            system_cache::valid_time_length::set(valid_time_length.map(|x| x as i32)),
+            system_cache::created_at::set(now_time()),
+            system_cache::deleted_at::set(None),
+        ];
+        let mut update_data = data.clone();
+        update_data.push(system_cache::value::set(value.clone()));
         Ok(self
             .0
             .client
             .system_cache()
-            .create_unchecked(
-                key.to_owned(),
-                r#type.into(),
-                serde_json::to_string(&value)?,
-                CreateParams {
-                    attach,
-                    valid_time_length: valid_time_length.map(|x| Some(x as i32)),
-                }
-                .to_params(),
+            .upsert(
+                system_cache::key_type(key.to_owned(), r#type.clone().into()),
+                system_cache::create(key.to_owned(), r#type.into(), value, data),
+                update_data,
             )
             .exec()
             .await?
@@ -384,7 +628,7 @@ impl Driver for CacheDriverDatabase {
         }
         Ok(info)
     }
-    async fn pull(&mut self, r#type: CacheType, key: &str) -> Result<Info> {
+    async fn pull(&self, r#type: CacheType, key: &str) -> Result<Info> {
         Ok(self
             .0
             .client
@@ -398,7 +642,7 @@ impl Driver for CacheDriverDatabase {
             .into())
     }
 
-    async fn flush(&mut self, r#type: Option<CacheType>) -> Result<i64> {
+    async fn flush(&self, r#type: Option<CacheType>) -> Result<i64> {
         let mut params = vec![];
         if let Some(cache_type) = r#type {
             params.push(system_cache::r#type::equals(cache_type.into()))
@@ -414,9 +658,306 @@ impl Driver for CacheDriverDatabase {
             .exec()
             .await?)
     }
+
+    async fn acquire_lock(&self, key: &str, ttl: i64) -> Result<Option<i64>> {
+        let client = &self.0.client;
+        let expired_at = offset_from_timestamp(now_timestamp(Some(ttl)));
+        // conditional update, only one instance can take over an expired lock
+        let acquired = client
+            .system_cache_lock()
+            .update_many(
+                vec![
+                    system_cache_lock::key::equals(key.to_owned()),
+                    system_cache_lock::expired_at::lte(now_time()),
+                ],
+                vec![
+                    system_cache_lock::token::increment(1),
+                    system_cache_lock::expired_at::set(expired_at),
+                ],
+            )
+            .exec()
+            .await?;
+        if acquired == 0 {
+            let exists = client
+                .system_cache_lock()
+                .find_unique(system_cache_lock::key::equals(key.to_owned()))
+                .exec()
+                .await?;
+            if exists.is_some() {
+                return Ok(None);
+            }
+            return match client
+                .system_cache_lock()
+                .create(key.to_owned(), expired_at, vec![])
+                .exec()
+                .await
+            {
+                Ok(lock) => Ok(Some(lock.token as i64)),
+                Err(err) if err.is_prisma_error::<UniqueKeyViolation>() => Ok(None),
+                Err(err) => Err(err.into()),
+            };
+        }
+        Ok(client
+            .system_cache_lock()
+            .find_unique(system_cache_lock::key::equals(key.to_owned()))
+            .exec()
+            .await?
+            .map(|x| x.token as i64))
+    }
+
+    async fn release_lock(&self, key: &str, token: i64) -> Result<bool> {
+        let released = self
+            .0
+            .client
+            .system_cache_lock()
+            .update_many(
+                vec![
+                    system_cache_lock::key::equals(key.to_owned()),
+                    system_cache_lock::token::equals(token as i32),
+                ],
+                vec![system_cache_lock::expired_at::set(now_time())],
+            )
+            .exec()
+            .await?;
+        Ok(released > 0)
+    }
+
+    async fn incr_with_ttl(&self, key: &str, ttl: i64) -> Result<i64> {
+        let client = &self.0.client;
+        let window_start = offset_from_timestamp(now_timestamp(Some(-ttl)));
+        client
+            .system_cache_hit()
+            .delete_many(vec![
+                system_cache_hit::key::equals(key.to_owned()),
+                system_cache_hit::created_at::lte(window_start),
+            ])
+            .exec()
+            .await?;
+        client
+            .system_cache_hit()
+            .create(key.to_owned(), vec![])
+            .exec()
+            .await?;
+        Ok(client
+            .system_cache_hit()
+            .count(vec![
+                system_cache_hit::key::equals(key.to_owned()),
+                system_cache_hit::created_at::gt(window_start),
+            ])
+            .exec()
+            .await?)
+    }
 }
 
-#[derive(Debug, Serialize, Clone)]
+/// key of the row holding the shared version of a cache type
+const SHARED_VERSION_KEY: &str = "version";
+
+/// version of a cache type kept in the database, shared by every instance;
+/// process local entries keyed by it are left behind once any instance calls
+/// [`bump_shared_version`]
+pub async fn shared_version(db: &Database, r#type: CacheType) -> Result<String> {
+    Ok(db
+        .client
+        .system_cache()
+        .find_unique(system_cache::key_type(
+            SHARED_VERSION_KEY.to_owned(),
+            r#type.into(),
+        ))
+        .exec()
+        .await?
+        .map(|x| x.value)
+        .unwrap_or_default())
+}
+
+/// move the shared version of a cache type on, invalidating it everywhere
+pub async fn bump_shared_version(db: &Database, r#type: CacheType) -> Result<()> {
+    let version = format!("{}-{}", now_time().timestamp_millis(), fastrand::u32(..));
+    db.client
+        .system_cache()
+        .upsert(
+            system_cache::key_type(SHARED_VERSION_KEY.to_owned(), r#type.clone().into()),
+            system_cache::create(
+                SHARED_VERSION_KEY.to_owned(),
+                r#type.into(),
+                version.clone(),
+                vec![],
+            ),
+            vec![
+                system_cache::value::set(version),
+                system_cache::deleted_at::set(None),
+            ],
+        )
+        .exec()
+        .await?;
+    Ok(())
+}
+
+/// redis cache driver, keys are namespaced by `prefix`
+#[cfg(feature = "redis")]
+#[derive(Clone)]
+pub struct CacheDriverRedis {
+    conn: redis::aio::ConnectionManager,
+    prefix: String,
+}
+
+#[cfg(feature = "redis")]
+impl CacheDriverRedis {
+    pub async fn new(url: &str, prefix: &str) -> Result<Self> {
+        let client = redis::Client::open(url)?;
+        Ok(Self {
+            conn: redis::aio::ConnectionManager::new(client).await?,
+            prefix: prefix.to_owned(),
+        })
+    }
+
+    fn item_key(&self, r#type: &CacheType, key: &str) -> String {
+        format!("{}:{}:{}", self.prefix, i32::from(r#type.clone()), key)
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait::async_trait]
+impl Driver for CacheDriverRedis {
+    async fn put<T: Serialize + std::marker::Send + std::marker::Sync>(
+        &self,
+        r#type: CacheType,
+        key: &str,
+        value: T,
+        valid_time_length: Option<i64>,
+        attach: Option<String>,
+    ) -> Result<Info> {
+        let info = Info {
+            key: key.to_owned(),
+            value: serde_json::to_string(&value)?,
+            r#type,
+            attach,
+            valid_time_length,
+            create_time: now_timestamp(None),
+        };
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(self.item_key(&info.r#type, key))
+            .arg(serde_json::to_string(&info)?);
+        if let Some(seconds) = valid_time_length {
+            cmd.arg("EX").arg(seconds);
+        }
+        cmd.query_async::<_, ()>(&mut self.conn.clone()).await?;
+        Ok(info)
+    }
+
+    async fn first(
+        &self,
+        r#type: CacheType,
+        key: &str,
+        default: Option<Info>,
+    ) -> Result<Option<Info>> {
+        let value: Option<String> = redis::cmd("GET")
+            .arg(self.item_key(&r#type, key))
+            .query_async(&mut self.conn.clone())
+            .await?;
+        Ok(match value {
+            Some(x) => Some(serde_json::from_str(&x)?),
+            None => default,
+        })
+    }
+
+    async fn pull(&self, r#type: CacheType, key: &str) -> Result<Info> {
+        let info = self
+            .first(r#type.clone(), key, None)
+            .await?
+            .ok_or(super::ServiceError::CacheNotFound)?;
+        redis::cmd("DEL")
+            .arg(self.item_key(&r#type, key))
+            .query_async::<_, ()>(&mut self.conn.clone())
+            .await?;
+        Ok(info)
+    }
+
+    async fn flush(&self, r#type: Option<CacheType>) -> Result<i64> {
+        let pattern = match r#type {
+            Some(cache_type) => format!("{}:{}:*", self.prefix, i32::from(cache_type)),
+            None => format!("{}:*", self.prefix),
+        };
+        let mut conn = self.conn.clone();
+        let (mut cursor, mut count) = (0u64, 0i64);
+        loop {
+            let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(100)
+                .query_async(&mut conn)
+                .await?;
+            if !keys.is_empty() {
+                count += redis::cmd("DEL")
+                    .arg(&keys)
+                    .query_async::<_, i64>(&mut conn)
+                    .await?;
+            }
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        Ok(count)
+    }
+
+    async fn acquire_lock(&self, key: &str, ttl: i64) -> Result<Option<i64>> {
+        let mut conn = self.conn.clone();
+        let token: i64 = redis::cmd("INCR")
+            .arg(format!("{}:fence:{}", self.prefix, key))
+            .query_async(&mut conn)
+            .await?;
+        let acquired: Option<String> = redis::cmd("SET")
+            .arg(format!("{}:lock:{}", self.prefix, key))
+            .arg(token)
+            .arg("NX")
+            .arg("EX")
+            .arg(ttl)
+            .query_async(&mut conn)
+            .await?;
+        Ok(acquired.map(|_| token))
+    }
+
+    async fn release_lock(&self, key: &str, token: i64) -> Result<bool> {
+        let released: i64 = redis::Script::new(
+            r#"if redis.call("GET", KEYS[1]) == ARGV[1] then return redis.call("DEL", KEYS[1]) else return 0 end"#,
+        )
+        .key(format!("{}:lock:{}", self.prefix, key))
+        .arg(token)
+        .invoke_async(&mut self.conn.clone())
+        .await?;
+        Ok(released > 0)
+    }
+
+    async fn incr_with_ttl(&self, key: &str, ttl: i64) -> Result<i64> {
+        let counter_key = format!("{}:counter:{}", self.prefix, key);
+        let now = now_time().timestamp_millis();
+        let (count,): (i64,) = redis::pipe()
+            .atomic()
+            .cmd("ZREMRANGEBYSCORE")
+            .arg(&counter_key)
+            .arg(0)
+            .arg(now - ttl * 1000)
+            .ignore()
+            .cmd("ZADD")
+            .arg(&counter_key)
+            .arg(now)
+            .arg(format!("{}-{}", now, fastrand::u32(..)))
+            .ignore()
+            .cmd("ZCARD")
+            .arg(&counter_key)
+            .cmd("EXPIRE")
+            .arg(&counter_key)
+            .arg(ttl)
+            .ignore()
+            .query_async(&mut self.conn.clone())
+            .await?;
+        Ok(count)
+    }
+}
+
+#[derive(Debug, Serialize, serde::Deserialize, Clone)]
 pub struct Info {
     key: String,
     r#type: CacheType,
@@ -434,6 +975,12 @@ impl Info {
         false
     }
 
+    /// whether the item had a validity period and it has passed
+    pub fn is_expired(&self) -> bool {
+        self.valid_time_length
+            .is_some_and(|x| self.create_time + x <= now_timestamp(None))
+    }
+
     pub fn get_valid_timestamp(self) -> Option<i64> {
         self.valid_time_length.map(|x| self.create_time + x)
     }
@@ -471,3 +1018,148 @@ system_cache::partial_unchecked!(UpdateParams {
     attach
     valid_time_length
 });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// the lock and counter contract every driver has to keep
+    async fn check_driver<D>(cache: Cache<D>, prefix: &str)
+    where
+        D: Driver + std::marker::Send + std::marker::Sync + 'static,
+    {
+        let key = format!("{}:lock", prefix);
+        let lock = cache.lock(&key, 10).await.unwrap().unwrap();
+        // exclusive while held
+        assert!(cache.lock(&key, 10).await.unwrap().is_none());
+        let first = lock.token();
+        assert!(lock.release().await.unwrap());
+        // a stale token releases nothing
+        assert!(!cache.0.release_lock(&key, first).await.unwrap());
+
+        // fencing tokens grow with every acquisition
+        let lock = cache.lock(&key, 10).await.unwrap().unwrap();
+        assert!(lock.token() > first);
+        let second = lock.token();
+        // dropping releases in the background
+        drop(lock);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let lock = cache.lock(&key, 1).await.unwrap().unwrap();
+        assert!(lock.token() > second);
+        let third = lock.token();
+        std::mem::forget(lock);
+        // an unreleased lock expires after its ttl
+        tokio::time::sleep(Duration::from_millis(2100)).await;
+        let lock = cache.lock(&key, 10).await.unwrap().unwrap();
+        assert!(lock.token() > third);
+        assert!(lock.release().await.unwrap());
+
+        // hits are counted within the window only
+        let key = format!("{}:hits", prefix);
+        assert_eq!(cache.0.incr_with_ttl(&key, 1).await.unwrap(), 1);
+        assert_eq!(cache.0.incr_with_ttl(&key, 1).await.unwrap(), 2);
+        assert_eq!(cache.0.incr_with_ttl(&key, 1).await.unwrap(), 3);
+        tokio::time::sleep(Duration::from_millis(2100)).await;
+        assert_eq!(cache.0.incr_with_ttl(&key, 1).await.unwrap(), 1);
+    }
+
+    fn unique_prefix() -> String {
+        format!("test:{}", fastrand::u32(..))
+    }
+
+    #[tokio::test]
+    async fn memory_driver() {
+        check_driver(Cache::new(CacheDriverMemory::default()), &unique_prefix()).await;
+    }
+
+    #[tokio::test]
+    async fn memory_driver_evicts_expired() {
+        let driver = CacheDriverMemory::default();
+        driver.acquire_lock("expired", 0).await.unwrap();
+        driver.incr_with_ttl("expired", 0).await.unwrap();
+        driver.acquire_lock("held", 10).await.unwrap();
+        driver.incr_with_ttl("held", 10).await.unwrap();
+        let locks = driver
+            .locks
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        let hits = driver
+            .hits
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(locks, vec!["held".to_owned()]);
+        assert_eq!(hits, vec!["held".to_owned()]);
+    }
+
+    #[tokio::test]
+    async fn remember_drops_forgotten_value() {
+        let cache = Cache::new(CacheDriverMemory::default());
+        let key = unique_prefix();
+        let value: i32 = cache
+            .remember(CacheType::SystemDict, &key, Some(60), None, || async {
+                // a change lands while the value is computed
+                cache.forget(CacheType::SystemDict, &key).await.unwrap();
+                Ok(1)
+            })
+            .await
+            .unwrap();
+        assert_eq!(value, 1);
+        assert!(!cache.has(&CacheType::SystemDict, &key).await.unwrap());
+        let value: i32 = cache
+            .remember(CacheType::SystemDict, &key, Some(60), None, || async {
+                Ok(2)
+            })
+            .await
+            .unwrap();
+        assert_eq!(value, 2);
+        assert!(cache.has(&CacheType::SystemDict, &key).await.unwrap());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database"]
+    async fn shared_version_moves_on() {
+        let db = Database::new(crate::DatabaseConfig::default())
+            .await
+            .unwrap();
+        bump_shared_version(&db, CacheType::SystemRoleMenus)
+            .await
+            .unwrap();
+        let version = shared_version(&db, CacheType::SystemRoleMenus)
+            .await
+            .unwrap();
+        bump_shared_version(&db, CacheType::SystemRoleMenus)
+            .await
+            .unwrap();
+        assert_ne!(
+            shared_version(&db, CacheType::SystemRoleMenus)
+                .await
+                .unwrap(),
+            version
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database"]
+    async fn database_driver() {
+        let db = Database::new(crate::DatabaseConfig::default())
+            .await
+            .unwrap();
+        check_driver(Cache::new(CacheDriverDatabase::new(db)), &unique_prefix()).await;
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore = "needs a redis server at REDIS_URL"]
+    async fn redis_driver() {
+        let url = std::env::var("REDIS_URL").unwrap_or("redis://127.0.0.1/".to_owned());
+        let driver = CacheDriverRedis::new(&url, &unique_prefix()).await.unwrap();
+        check_driver(Cache::new(driver), "test").await;
+    }
+}
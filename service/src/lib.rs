@@ -2,17 +2,32 @@
 mod generate_prisma;
 
 pub mod cache_service;
+pub mod log_retention_service;
+pub mod log_writer_service;
+pub mod mailer_service;
+pub mod member_adjustment_service;
 pub mod member_bill_service;
+pub mod member_commission_service;
+pub mod member_recharge_service;
 pub mod member_service;
 pub mod member_team_service;
+pub mod member_withdrawal_service;
+pub mod payment_service;
+pub mod security_event_service;
 pub mod system_action_log_service;
+pub mod system_audit_service;
 pub mod system_dept_service;
 pub mod system_dict_data_service;
 pub mod system_dict_service;
+pub mod system_grant_service;
 pub mod system_login_log_server;
+pub mod system_mail_outbox_service;
+pub mod system_mail_template_service;
 pub mod system_menu_service;
+pub mod system_permission_service;
 pub mod system_role_menu_service;
 pub mod system_role_service;
+pub mod system_user_role_service;
 pub mod system_user_service;
 
 use generate_prisma as prisma;
@@ -25,7 +40,18 @@ pub enum ServiceError {
     RelationNotFetchedError(String),
     DataNotFound,
     SerializeJson(serde_json::Error),
+    Io(std::io::Error),
     CacheNotFound,
+    CacheDriver(String),
+    Template(String),
+    Mail(String),
+    /// the member's balance or integral is below the debit
+    InsufficientFunds,
+    /// the idempotency key was used by another request
+    IdempotencyConflict,
+    Payment(String),
+    /// the invite was refused, the member did not join
+    InviteRefused(member_team_service::InviteRefusal),
 }
 
 impl From<prisma_client_rust::NewClientError> for ServiceError {
@@ -50,6 +76,22 @@ impl From<serde_json::Error> for ServiceError {
         Self::SerializeJson(value)
     }
 }
+impl From<std::io::Error> for ServiceError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+impl From<minijinja::Error> for ServiceError {
+    fn from(value: minijinja::Error) -> Self {
+        Self::Template(value.to_string())
+    }
+}
+#[cfg(feature = "redis")]
+impl From<redis::RedisError> for ServiceError {
+    fn from(value: redis::RedisError) -> Self {
+        Self::CacheDriver(value.to_string())
+    }
+}
 #[derive(Debug, serde::Serialize)]
 pub struct DataPower<T: serde::Serialize> {
     _can_edit: bool,
@@ -85,6 +127,8 @@ impl DatabaseConfig {
 pub struct Database {
     config: DatabaseConfig,
     client: prisma::PrismaClient,
+    cache: cache_service::Cache<cache_service::CacheDriverMemory>,
+    permissions: system_permission_service::PermissionIndex,
 }
 
 impl Database {
@@ -98,10 +142,25 @@ impl Database {
             }
             None => prisma::PrismaClient::_builder().build().await?,
         };
-        Ok(Self { config, client })
+        Ok(Self {
+            config,
+            client,
+            cache: cache_service::Cache::new(cache_service::CacheDriverMemory::default()),
+            permissions: system_permission_service::PermissionIndex::default(),
+        })
     }
 
     pub fn config(&self) -> DatabaseConfig {
         self.config.clone()
     }
+
+    /// process local cache for hot query results
+    pub fn cache(&self) -> &cache_service::Cache<cache_service::CacheDriverMemory> {
+        &self.cache
+    }
+
+    /// compiled api permissions
+    pub fn permissions(&self) -> &system_permission_service::PermissionIndex {
+        &self.permissions
+    }
 }
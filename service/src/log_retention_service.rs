@@ -0,0 +1,411 @@
+use crate::{
+    cache_service::{Cache, Driver},
+    prisma::{system_action_log, system_cache, system_login_log, SortOrder},
+    Database, Result,
+};
+use flate2::{write::GzEncoder, Compression};
+use prisma_client_rust::{
+    and,
+    chrono::{DateTime, Duration, FixedOffset},
+    or, raw, PrismaValue,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+use utils::datetime::now_time;
+
+/// lock key of the scheduled purge
+const PURGE_LOCK: &str = "log_retention_purge";
+
+/// table with a retention policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum RetentionTable {
+    LoginLog,
+    ActionLog,
+    Cache,
+}
+
+impl RetentionTable {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::LoginLog => "system_login_logs",
+            Self::ActionLog => "system_action_logs",
+            Self::Cache => "system_caches",
+        }
+    }
+
+    fn env_key(&self) -> &'static str {
+        match self {
+            Self::LoginLog => "RETENTION_LOGIN_LOG_DAYS",
+            Self::ActionLog => "RETENTION_ACTION_LOG_DAYS",
+            Self::Cache => "RETENTION_CACHE_DAYS",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RetentionPolicy {
+    pub table: RetentionTable,
+    /// rows older than the days are purged, caches once deleted or expired for the days
+    pub keep_days: i64,
+    /// write the rows to the archive dir before deleting them
+    pub archive: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct RetentionConfig {
+    pub policies: Vec<RetentionPolicy>,
+    /// dir of the `<table>-<time>.jsonl.gz` archives
+    pub archive_dir: PathBuf,
+    /// most rows read and deleted at once
+    pub batch_size: i64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            policies: vec![
+                RetentionPolicy {
+                    table: RetentionTable::LoginLog,
+                    keep_days: 180,
+                    archive: true,
+                },
+                RetentionPolicy {
+                    table: RetentionTable::ActionLog,
+                    keep_days: 90,
+                    archive: true,
+                },
+                RetentionPolicy {
+                    table: RetentionTable::Cache,
+                    keep_days: 7,
+                    archive: false,
+                },
+            ],
+            archive_dir: PathBuf::from("data").join("archive"),
+            batch_size: 1000,
+        }
+    }
+}
+
+impl RetentionConfig {
+    /// defaults overridden by `RETENTION_<TABLE>_DAYS` (`0` disables the table),
+    /// `RETENTION_ARCHIVE_DIR` and `RETENTION_ARCHIVE` (`false` disables archives)
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        let archive = std::env::var("RETENTION_ARCHIVE").map(|x| x != "false");
+        for policy in config.policies.iter_mut() {
+            if let Some(days) = std::env::var(policy.table.env_key())
+                .ok()
+                .and_then(|x| x.trim().parse::<i64>().ok())
+            {
+                policy.keep_days = days;
+            }
+            if let Ok(archive) = archive {
+                policy.archive = policy.archive && archive;
+            }
+        }
+        config.policies.retain(|x| x.keep_days > 0);
+        if let Ok(dir) = std::env::var("RETENTION_ARCHIVE_DIR") {
+            config.archive_dir = PathBuf::from(dir);
+        }
+        config
+    }
+}
+
+/// rows of a table past its retention
+#[derive(Debug, Clone, Serialize)]
+pub struct RetentionReport {
+    pub table: RetentionTable,
+    /// rows matching the policy, purged unless it was a dry run
+    pub rows: i64,
+    pub archive: Option<PathBuf>,
+}
+
+/// count the rows past retention without touching them
+pub async fn dry_run(db: &Database, config: &RetentionConfig) -> Result<Vec<RetentionReport>> {
+    let mut reports = vec![];
+    for policy in config.policies.iter() {
+        let cutoff = get_cutoff(policy);
+        reports.push(RetentionReport {
+            table: policy.table,
+            rows: count(db, policy.table, cutoff).await?,
+            archive: None,
+        });
+    }
+    Ok(reports)
+}
+
+/// archive then delete the rows past retention, a batch is only deleted
+/// once it is flushed to the archive
+pub async fn purge(db: &Database, config: &RetentionConfig) -> Result<Vec<RetentionReport>> {
+    let mut reports = vec![];
+    for policy in config.policies.iter() {
+        let cutoff = get_cutoff(policy);
+        let mut rows = 0;
+        let mut after_id = 0;
+        let mut archive: Option<Archive> = None;
+        while let Some((last_id, batch)) =
+            fetch(db, policy.table, cutoff, after_id, config.batch_size).await?
+        {
+            after_id = last_id;
+            if batch.is_empty() {
+                continue;
+            }
+            if policy.archive {
+                if archive.is_none() {
+                    archive = Some(Archive::create(&config.archive_dir, policy.table)?);
+                }
+                if let Some(archive) = archive.as_mut() {
+                    archive.write(&batch)?;
+                }
+            }
+            let ids = batch.iter().map(|x| x.0).collect::<Vec<i32>>();
+            rows += delete(db, policy.table, ids).await?;
+        }
+        let archive = match archive {
+            Some(archive) => Some(archive.finish()?),
+            None => None,
+        };
+        if rows > 0 {
+            tracing::info!("purged {} rows of {}", rows, policy.table.name());
+        }
+        reports.push(RetentionReport {
+            table: policy.table,
+            rows,
+            archive,
+        });
+    }
+    Ok(reports)
+}
+
+/// purge on every interval, the first run waits one interval; the purge runs
+/// under a lock of `locks`, so give it a cache shared by all the instances
+pub async fn schedule<D>(
+    db: &Database,
+    locks: &Cache<D>,
+    config: RetentionConfig,
+    interval: std::time::Duration,
+) where
+    D: Driver + std::marker::Send + std::marker::Sync + 'static,
+{
+    let ttl = interval.as_secs().max(60) as i64;
+    let mut interval = tokio::time::interval(interval);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let lock = match locks.lock(PURGE_LOCK, ttl).await {
+            Ok(Some(lock)) => lock,
+            Ok(None) => {
+                tracing::info!("log retention purge left to another instance");
+                continue;
+            }
+            Err(err) => {
+                tracing::error!("log retention lock failed: {:?}", err);
+                continue;
+            }
+        };
+        if let Err(err) = purge(db, &config).await {
+            tracing::error!("log retention purge failed: {:?}", err);
+        }
+        if let Err(err) = lock.release().await {
+            tracing::warn!("log retention lock not released: {:?}", err);
+        }
+    }
+}
+
+fn get_cutoff(policy: &RetentionPolicy) -> DateTime<FixedOffset> {
+    now_time() - Duration::days(policy.keep_days)
+}
+
+/// next rows past retention after the id, with the last scanned id,
+/// `None` once the table is scanned
+async fn fetch(
+    db: &Database,
+    table: RetentionTable,
+    cutoff: DateTime<FixedOffset>,
+    after_id: i32,
+    take: i64,
+) -> Result<Option<(i32, Vec<(i32, serde_json::Value)>)>> {
+    let rows = match table {
+        RetentionTable::LoginLog => db
+            .client
+            .system_login_log()
+            .find_many(vec![
+                system_login_log::id::gt(after_id),
+                system_login_log::created_at::lt(cutoff),
+            ])
+            .order_by(system_login_log::id::order(SortOrder::Asc))
+            .take(take)
+            .exec()
+            .await?
+            .into_iter()
+            .map(|x| Ok((x.id, serde_json::to_value(&x)?)))
+            .collect::<Result<Vec<(i32, serde_json::Value)>>>()?,
+        RetentionTable::ActionLog => db
+            .client
+            .system_action_log()
+            .find_many(vec![
+                system_action_log::id::gt(after_id),
+                system_action_log::created_at::lt(cutoff),
+            ])
+            .order_by(system_action_log::id::order(SortOrder::Asc))
+            .take(take)
+            .exec()
+            .await?
+            .into_iter()
+            .map(|x| Ok((x.id, serde_json::to_value(&x)?)))
+            .collect::<Result<Vec<(i32, serde_json::Value)>>>()?,
+        RetentionTable::Cache => {
+            let data = db
+                .client
+                .system_cache()
+                .find_many(vec![
+                    system_cache::id::gt(after_id),
+                    or![
+                        system_cache::deleted_at::lt(cutoff),
+                        and![
+                            system_cache::deleted_at::equals(None),
+                            system_cache::valid_time_length::not(None),
+                            system_cache::created_at::lt(cutoff),
+                        ],
+                    ],
+                ])
+                .order_by(system_cache::id::order(SortOrder::Asc))
+                .take(take)
+                .exec()
+                .await?;
+            let last_id = match data.last() {
+                Some(last) => last.id,
+                None => return Ok(None),
+            };
+            // live caches keep their rows until expired for the days
+            let rows = data
+                .into_iter()
+                .filter(|x| {
+                    x.deleted_at.is_some()
+                        || x.valid_time_length.is_some_and(|length| {
+                            x.created_at + Duration::seconds(length as i64) <= cutoff
+                        })
+                })
+                .map(|x| Ok((x.id, serde_json::to_value(&x)?)))
+                .collect::<Result<Vec<(i32, serde_json::Value)>>>()?;
+            return Ok(Some((last_id, rows)));
+        }
+    };
+    Ok(rows.last().map(|x| x.0).map(|last_id| (last_id, rows)))
+}
+
+/// rows past retention, counted in the database
+async fn count(db: &Database, table: RetentionTable, cutoff: DateTime<FixedOffset>) -> Result<i64> {
+    Ok(match table {
+        RetentionTable::LoginLog => {
+            db.client
+                .system_login_log()
+                .count(vec![system_login_log::created_at::lt(cutoff)])
+                .exec()
+                .await?
+        }
+        RetentionTable::ActionLog => {
+            db.client
+                .system_action_log()
+                .count(vec![system_action_log::created_at::lt(cutoff)])
+                .exec()
+                .await?
+        }
+        RetentionTable::Cache => {
+            let deleted = db
+                .client
+                .system_cache()
+                .count(vec![system_cache::deleted_at::lt(cutoff)])
+                .exec()
+                .await?;
+            // live caches count once expired for the days; the expiry is the
+            // creation plus the length, sqlite keeps the dates as milliseconds
+            let expired = db
+                .client
+                ._query_raw::<RowCount>(raw!(
+                    "SELECT COUNT(*) AS count FROM system_caches \
+                     WHERE deleted_at IS NULL AND valid_time_length IS NOT NULL \
+                     AND created_at + valid_time_length * 1000 <= {}",
+                    PrismaValue::Int(cutoff.timestamp_millis())
+                ))
+                .exec()
+                .await?
+                .pop()
+                .map(|x| x.count)
+                .unwrap_or_default();
+            deleted + expired
+        }
+    })
+}
+
+async fn delete(db: &Database, table: RetentionTable, ids: Vec<i32>) -> Result<i64> {
+    Ok(match table {
+        RetentionTable::LoginLog => {
+            db.client
+                .system_login_log()
+                .delete_many(vec![system_login_log::id::in_vec(ids)])
+                .exec()
+                .await?
+        }
+        RetentionTable::ActionLog => {
+            db.client
+                .system_action_log()
+                .delete_many(vec![system_action_log::id::in_vec(ids)])
+                .exec()
+                .await?
+        }
+        RetentionTable::Cache => {
+            db.client
+                .system_cache()
+                .delete_many(vec![system_cache::id::in_vec(ids)])
+                .exec()
+                .await?
+        }
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct RowCount {
+    count: i64,
+}
+
+/// gzip compressed JSON Lines file
+struct Archive {
+    path: PathBuf,
+    writer: GzEncoder<BufWriter<fs::File>>,
+}
+
+impl Archive {
+    fn create(dir: &Path, table: RetentionTable) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!(
+            "{}-{}.jsonl.gz",
+            table.name(),
+            now_time().format("%Y%m%d%H%M%S")
+        ));
+        let file = fs::File::create(&path)?;
+        Ok(Self {
+            path,
+            writer: GzEncoder::new(BufWriter::new(file), Compression::default()),
+        })
+    }
+
+    /// rows are flushed to the file before they may be deleted
+    fn write(&mut self, rows: &[(i32, serde_json::Value)]) -> Result<()> {
+        for (_, row) in rows {
+            serde_json::to_writer(&mut self.writer, row)?;
+            self.writer.write_all(b"\n")?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn finish(self) -> Result<PathBuf> {
+        self.writer.finish()?.flush()?;
+        Ok(self.path)
+    }
+}
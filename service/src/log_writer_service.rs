@@ -0,0 +1,282 @@
+use crate::{
+    prisma::{system_action_log, system_login_log},
+    system_action_log_service, system_login_log_server, Database, Result,
+};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::{mpsc, Notify};
+
+/// log row waiting to be written
+pub enum LogEntry {
+    Action {
+        user_id: i32,
+        menu_id: i32,
+        ip_address: String,
+        params: system_action_log_service::CreateParams,
+    },
+    Login {
+        user_id: i32,
+        ip_address: String,
+        params: system_login_log_server::CreateParams,
+    },
+}
+
+/// what to do with an entry while the queue is full
+#[derive(Debug, Clone)]
+pub enum OverflowPolicy {
+    /// drop the entry at once
+    Drop,
+    /// wait for room up to the duration, then drop the entry
+    Wait(Duration),
+}
+
+#[derive(Debug, Clone)]
+pub struct LogWriterConfig {
+    /// queued entries before the overflow policy applies
+    pub capacity: usize,
+    /// most entries inserted at once
+    pub batch_size: usize,
+    /// longest time an entry stays queued
+    pub flush_interval: Duration,
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for LogWriterConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 10_000,
+            batch_size: 200,
+            flush_interval: Duration::from_secs(1),
+            overflow: OverflowPolicy::Wait(Duration::from_millis(50)),
+        }
+    }
+}
+
+/// counters of the log writer
+#[derive(Debug, Default)]
+pub struct LogWriterMetrics {
+    accepted: AtomicU64,
+    written: AtomicU64,
+    dropped: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl LogWriterMetrics {
+    /// entries queued since the start
+    pub fn accepted(&self) -> u64 {
+        self.accepted.load(Ordering::Relaxed)
+    }
+    /// entries queued and neither written nor failed yet
+    pub fn pending(&self) -> u64 {
+        self.accepted()
+            .saturating_sub(self.written())
+            .saturating_sub(self.failed())
+    }
+    pub fn written(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+    pub fn failed(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+}
+
+/// sending side, kept in the app state
+#[derive(Clone)]
+pub struct LogWriter {
+    sender: mpsc::Sender<LogEntry>,
+    overflow: OverflowPolicy,
+    metrics: Arc<LogWriterMetrics>,
+    shutdown: Arc<Notify>,
+}
+
+/// receiving side, run it on a task with [`LogWorker::run`]
+pub struct LogWorker {
+    receiver: mpsc::Receiver<LogEntry>,
+    config: LogWriterConfig,
+    metrics: Arc<LogWriterMetrics>,
+    shutdown: Arc<Notify>,
+}
+
+impl LogWriter {
+    pub fn new(config: LogWriterConfig) -> (Self, LogWorker) {
+        let (sender, receiver) = mpsc::channel(config.capacity.max(1));
+        let metrics = Arc::new(LogWriterMetrics::default());
+        let shutdown = Arc::new(Notify::new());
+        (
+            Self {
+                sender,
+                overflow: config.overflow.clone(),
+                metrics: metrics.clone(),
+                shutdown: shutdown.clone(),
+            },
+            LogWorker {
+                receiver,
+                config,
+                metrics,
+                shutdown,
+            },
+        )
+    }
+
+    /// queue the entry, never fails the caller: an entry which can not be queued is counted as dropped
+    pub async fn write(&self, entry: LogEntry) {
+        let result = match self.overflow {
+            OverflowPolicy::Drop => self.sender.try_send(entry).map_err(|_| ()),
+            OverflowPolicy::Wait(timeout) => self
+                .sender
+                .send_timeout(entry, timeout)
+                .await
+                .map_err(|_| ()),
+        };
+        match result {
+            Ok(_) => self.metrics.accepted.fetch_add(1, Ordering::Relaxed),
+            Err(_) => {
+                let dropped = self.metrics.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                if dropped.is_power_of_two() {
+                    tracing::warn!("log queue is full, {} entries dropped so far", dropped);
+                }
+                dropped
+            }
+        };
+    }
+
+    pub fn metrics(&self) -> &LogWriterMetrics {
+        &self.metrics
+    }
+
+    /// ask the worker to flush what is queued and stop
+    pub fn close(&self) {
+        self.shutdown.notify_one();
+    }
+}
+
+impl LogWorker {
+    /// insert queued entries by batches until [`LogWriter::close`] is called
+    pub async fn run(mut self, db: &Database) {
+        let mut batch: Vec<LogEntry> = Vec::with_capacity(self.config.batch_size);
+        let mut interval = tokio::time::interval(self.config.flush_interval);
+        loop {
+            tokio::select! {
+                entry = self.receiver.recv() => match entry {
+                    Some(entry) => {
+                        batch.push(entry);
+                        if batch.len() >= self.config.batch_size {
+                            self.flush(db, &mut batch).await;
+                        }
+                    }
+                    None => break,
+                },
+                _ = interval.tick() => self.flush(db, &mut batch).await,
+                _ = self.shutdown.notified() => break,
+            }
+        }
+        self.receiver.close();
+        while let Ok(entry) = self.receiver.try_recv() {
+            batch.push(entry);
+            if batch.len() >= self.config.batch_size {
+                self.flush(db, &mut batch).await;
+            }
+        }
+        self.flush(db, &mut batch).await;
+    }
+
+    async fn flush(&self, db: &Database, batch: &mut Vec<LogEntry>) {
+        if batch.is_empty() {
+            return;
+        }
+        let (actions, logins) = split(std::mem::take(batch));
+        // each kind on its own, a failed insert does not lose the other
+        let total = actions.len() as u64;
+        if total > 0 {
+            let result = insert_actions(db, actions).await;
+            self.count("action", total, result);
+        }
+        let total = logins.len() as u64;
+        if total > 0 {
+            let result = insert_logins(db, logins).await;
+            self.count("login", total, result);
+        }
+    }
+
+    fn count(&self, kind: &str, total: u64, result: Result<i64>) {
+        match result {
+            Ok(_) => self.metrics.written.fetch_add(total, Ordering::Relaxed),
+            Err(err) => {
+                tracing::error!(
+                    "{} log batch of {} entries not written: {:?}",
+                    kind,
+                    total,
+                    err
+                );
+                self.metrics.failed.fetch_add(total, Ordering::Relaxed)
+            }
+        };
+    }
+}
+
+fn split(
+    entries: Vec<LogEntry>,
+) -> (
+    Vec<system_action_log::CreateUnchecked>,
+    Vec<system_login_log::CreateUnchecked>,
+) {
+    let mut actions = vec![];
+    let mut logins = vec![];
+    for entry in entries {
+        match entry {
+            LogEntry::Action {
+                user_id,
+                menu_id,
+                ip_address,
+                params,
+            } => actions.push(system_action_log::create_unchecked(
+                user_id,
+                menu_id,
+                ip_address,
+                params.to_params(),
+            )),
+            LogEntry::Login {
+                user_id,
+                ip_address,
+                params,
+            } => logins.push(system_login_log::create_unchecked(
+                user_id,
+                ip_address,
+                params.to_params(),
+            )),
+        }
+    }
+    (actions, logins)
+}
+
+async fn insert_actions(
+    db: &Database,
+    actions: Vec<system_action_log::CreateUnchecked>,
+) -> Result<i64> {
+    Ok(db
+        .client
+        .system_action_log()
+        .create_many(actions)
+        .exec()
+        .await?)
+}
+
+async fn insert_logins(
+    db: &Database,
+    logins: Vec<system_login_log::CreateUnchecked>,
+) -> Result<i64> {
+    Ok(db
+        .client
+        .system_login_log()
+        .create_many(logins)
+        .exec()
+        .await?)
+}
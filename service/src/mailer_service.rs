@@ -0,0 +1,287 @@
+use crate::{Result, ServiceError};
+use lettre::{
+    message::header::ContentType, transport::smtp::authentication::Credentials, AsyncFileTransport,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use std::path::PathBuf;
+
+/// how the smtp connection is secured
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpTls {
+    /// plain connection, for a local smtp sink
+    None,
+    StartTls,
+    /// implicit tls, usually port 465
+    Tls,
+}
+
+#[derive(Debug, Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+    pub tls: SmtpTls,
+}
+
+/// where the mails go
+#[derive(Debug, Clone)]
+pub enum MailTransportConfig {
+    Smtp(SmtpConfig),
+    /// write every mail as a `.eml` file into the dir
+    File(PathBuf),
+    /// only log the recipients and subjects, never the bodies
+    Log,
+}
+
+#[derive(Debug, Clone)]
+pub struct MailerConfig {
+    pub transport: MailTransportConfig,
+    pub from: String,
+}
+
+impl MailerConfig {
+    /// `MAIL_TRANSPORT` is `smtp`, `file` or `log`, smtp when `SMTP_HOST` is set
+    /// and log, with a warning, otherwise; smtp reads `SMTP_HOST`, `SMTP_PORT`, `SMTP_USERNAME`,
+    /// `SMTP_PASSWORD` and `SMTP_TLS` (`none`, `starttls` or `tls`), file writes
+    /// into `MAIL_FILE_DIR`; the sender is `SMTP_FROM`
+    pub fn from_env() -> Self {
+        let host = std::env::var("SMTP_HOST").ok();
+        let username = std::env::var("SMTP_USERNAME").unwrap_or_default();
+        let from = std::env::var("SMTP_FROM").unwrap_or_else(|_| match username.is_empty() {
+            true => "NoBody <nobody@localhost>".to_owned(),
+            false => format!("NoBody <{}>", username),
+        });
+        let kind = std::env::var("MAIL_TRANSPORT")
+            .map(|x| x.trim().to_lowercase())
+            .unwrap_or_else(|_| match host.is_some() {
+                true => "smtp".to_owned(),
+                false => {
+                    tracing::warn!("neither MAIL_TRANSPORT nor SMTP_HOST set, mails are not sent");
+                    "log".to_owned()
+                }
+            });
+        let transport = match kind.as_str() {
+            "smtp" => {
+                let tls = match std::env::var("SMTP_TLS")
+                    .unwrap_or_default()
+                    .to_lowercase()
+                    .as_str()
+                {
+                    "none" => SmtpTls::None,
+                    "tls" => SmtpTls::Tls,
+                    _ => SmtpTls::StartTls,
+                };
+                let default_port = match tls {
+                    SmtpTls::None => 25,
+                    SmtpTls::StartTls => 587,
+                    SmtpTls::Tls => 465,
+                };
+                MailTransportConfig::Smtp(SmtpConfig {
+                    host: host.unwrap_or_else(|| "localhost".to_owned()),
+                    port: std::env::var("SMTP_PORT")
+                        .ok()
+                        .and_then(|x| x.trim().parse::<u16>().ok())
+                        .unwrap_or(default_port),
+                    username,
+                    password: std::env::var("SMTP_PASSWORD").unwrap_or_default(),
+                    tls,
+                })
+            }
+            "file" => MailTransportConfig::File(
+                std::env::var("MAIL_FILE_DIR")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|_| PathBuf::from("data").join("mail")),
+            ),
+            "log" => MailTransportConfig::Log,
+            other => {
+                tracing::warn!("unknown MAIL_TRANSPORT {}, mails are not sent", other);
+                MailTransportConfig::Log
+            }
+        };
+        Self { transport, from }
+    }
+}
+
+enum Transport {
+    Smtp(AsyncSmtpTransport<Tokio1Executor>),
+    File(AsyncFileTransport<Tokio1Executor>),
+    Log,
+}
+
+/// mail content ready to send
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+    pub is_html: bool,
+}
+
+pub struct Mailer {
+    transport: Transport,
+    from: String,
+}
+
+impl Mailer {
+    pub fn new(config: MailerConfig) -> Result<Self> {
+        let transport = match config.transport {
+            MailTransportConfig::Smtp(smtp) => {
+                let builder = match smtp.tls {
+                    SmtpTls::None => {
+                        AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&smtp.host)
+                    }
+                    SmtpTls::StartTls => {
+                        AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp.host)
+                            .map_err(|x| ServiceError::Mail(x.to_string()))?
+                    }
+                    SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp.host)
+                        .map_err(|x| ServiceError::Mail(x.to_string()))?,
+                };
+                let builder = builder.port(smtp.port);
+                let builder = match smtp.username.is_empty() {
+                    true => builder,
+                    false => builder.credentials(Credentials::new(smtp.username, smtp.password)),
+                };
+                Transport::Smtp(builder.build())
+            }
+            MailTransportConfig::File(dir) => {
+                std::fs::create_dir_all(&dir)?;
+                Transport::File(AsyncFileTransport::<Tokio1Executor>::new(dir))
+            }
+            MailTransportConfig::Log => Transport::Log,
+        };
+        Ok(Self {
+            transport,
+            from: config.from,
+        })
+    }
+
+    pub fn from_env() -> Result<Self> {
+        Self::new(MailerConfig::from_env())
+    }
+
+    pub async fn send(&self, mail: &Mail) -> Result<()> {
+        let email = Message::builder()
+            .from(
+                self.from
+                    .parse()
+                    .map_err(|x| ServiceError::Mail(format!("from: {:?}", x)))?,
+            )
+            .to(mail
+                .to
+                .parse()
+                .map_err(|x| ServiceError::Mail(format!("to: {:?}", x)))?)
+            .subject(mail.subject.clone())
+            .header(match mail.is_html {
+                true => ContentType::TEXT_HTML,
+                false => ContentType::TEXT_PLAIN,
+            })
+            .body(mail.body.clone())
+            .map_err(|x| ServiceError::Mail(x.to_string()))?;
+        match &self.transport {
+            Transport::Smtp(transport) => {
+                transport
+                    .send(email)
+                    .await
+                    .map_err(|x| ServiceError::Mail(x.to_string()))?;
+            }
+            Transport::File(transport) => {
+                let id = transport
+                    .send(email)
+                    .await
+                    .map_err(|x| ServiceError::Mail(x.to_string()))?;
+                tracing::info!("mail to {} written as {}.eml", mail.to, id);
+            }
+            Transport::Log => {
+                // the body may carry a code or a link, keep it out of the logs
+                tracing::info!(
+                    "mail to {} ({}) not sent, {} bytes",
+                    mail.to,
+                    mail.subject,
+                    mail.body.len()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    /// accept one smtp session and return what came after DATA
+    async fn smtp_sink(listener: TcpListener) -> String {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        writer.write_all(b"220 sink ESMTP\r\n").await.unwrap();
+        let mut data = String::new();
+        let mut in_data = false;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await.unwrap() == 0 {
+                break;
+            }
+            if in_data {
+                if line == ".\r\n" {
+                    writer.write_all(b"250 queued\r\n").await.unwrap();
+                    break;
+                } else {
+                    data.push_str(&line);
+                }
+                continue;
+            }
+            let command = line.trim_end().to_uppercase();
+            let reply: &[u8] = match command.starts_with("DATA") {
+                true => {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                }
+                false => b"250 ok\r\n",
+            };
+            writer.write_all(reply).await.unwrap();
+        }
+        data
+    }
+
+    #[tokio::test]
+    async fn send_through_smtp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let sink = tokio::spawn(smtp_sink(listener));
+        let mailer = Mailer::new(MailerConfig {
+            transport: MailTransportConfig::Smtp(SmtpConfig {
+                host: "127.0.0.1".to_owned(),
+                port,
+                username: String::new(),
+                password: String::new(),
+                tls: SmtpTls::None,
+            }),
+            from: "NoBody <nobody@localhost>".to_owned(),
+        })
+        .unwrap();
+        mailer
+            .send(&Mail {
+                to: "member@example.com".to_owned(),
+                subject: "Login code".to_owned(),
+                body: "Your code is 123456".to_owned(),
+                is_html: false,
+            })
+            .await
+            .unwrap();
+
+        let data = tokio::time::timeout(std::time::Duration::from_secs(5), sink)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(data.contains("To: member@example.com"), "{}", data);
+        assert!(data.contains("Subject: Login code"), "{}", data);
+        assert!(data.contains("Your code is 123456"), "{}", data);
+    }
+}
@@ -0,0 +1,504 @@
+use crate::{
+    member_bill_service::{BillMeta, BillPm, BillSource, BillType},
+    member_service,
+    prisma::{member_adjustment, PrismaClient, SortOrder},
+    system_audit_service::{self, AuditAction},
+    Database, Result, ServiceError,
+};
+use prisma_client_rust::bigdecimal::BigDecimal;
+use serde::Serialize;
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use utils::{
+    datetime::{now_time, to_local_string},
+    paginate::{PaginateParams, PaginateResult},
+};
+
+/// adjustments beyond the thresholds wait for a second admin to approve them
+#[derive(Debug, Clone, Default)]
+pub struct ApprovalConfig {
+    /// absolute balance, `None` never needs approval
+    pub balance: Option<BigDecimal>,
+    /// absolute integral, `None` never needs approval
+    pub integral: Option<i32>,
+}
+
+impl ApprovalConfig {
+    /// `MEMBER_ADJUST_APPROVAL_BALANCE` and `MEMBER_ADJUST_APPROVAL_INTEGRAL`,
+    /// approval is off while unset
+    pub fn from_env() -> Self {
+        Self {
+            balance: std::env::var("MEMBER_ADJUST_APPROVAL_BALANCE")
+                .ok()
+                .and_then(|x| x.trim().parse::<BigDecimal>().ok()),
+            integral: std::env::var("MEMBER_ADJUST_APPROVAL_INTEGRAL")
+                .ok()
+                .and_then(|x| x.trim().parse::<i32>().ok()),
+        }
+    }
+
+    pub fn needs_approval(&self, balance: &BigDecimal, integral: i32) -> bool {
+        self.balance.as_ref().is_some_and(|x| &balance.abs() > x)
+            || self.integral.is_some_and(|x| integral.abs() > x)
+    }
+}
+
+/// record the adjustment, it is executed at once unless it needs approval;
+/// an adjustment with a recorded idempotency key returns the recorded one.
+/// Executed at once it is recorded in the transaction posting it, a failed
+/// posting records nothing
+pub async fn create(
+    db: &Database,
+    member_id: i32,
+    created_by: i32,
+    remark: &str,
+    params: CreateParams,
+    needs_approval: bool,
+) -> Result<Info> {
+    let key = params.idempotency_key.clone().flatten();
+    let balance = params.balance.clone().unwrap_or_default();
+    let integral = params.integral.unwrap_or_default();
+    if let Some(data) = created(db, member_id, &balance, integral, key.as_deref()).await? {
+        return Ok(data);
+    }
+    let result = db
+        .client
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            let data = client
+                .member_adjustment()
+                .create_unchecked(member_id, remark.to_owned(), created_by, params.to_params())
+                .exec()
+                .await?;
+            system_audit_service::record(
+                &client,
+                "member_adjustment",
+                data.id,
+                AuditAction::Create,
+                None,
+                Some(&data),
+            )
+            .await?;
+            if !needs_approval {
+                review_with(
+                    &client,
+                    data.id,
+                    0,
+                    AdjustmentStatus::Executed,
+                    String::new(),
+                )
+                .await?;
+            }
+            Ok(data.id)
+        })
+        .await;
+    match result {
+        Ok(id) => info(db, id).await,
+        // a concurrent request with the same key was recorded first
+        Err(err) => match created(db, member_id, &balance, integral, key.as_deref()).await? {
+            Some(data) => Ok(data),
+            None => Err(err),
+        },
+    }
+}
+
+/// adjustment recorded with the idempotency key, for the same member and
+/// amounts only
+async fn created(
+    db: &Database,
+    member_id: i32,
+    balance: &BigDecimal,
+    integral: i32,
+    key: Option<&str>,
+) -> Result<Option<Info>> {
+    let key = match key {
+        Some(key) => key,
+        None => return Ok(None),
+    };
+    match db
+        .client
+        .member_adjustment()
+        .find_first(vec![member_adjustment::idempotency_key::equals(Some(
+            key.to_owned(),
+        ))])
+        .exec()
+        .await?
+    {
+        Some(data)
+            if data.member_id != member_id
+                || &data.balance != balance
+                || data.integral != integral =>
+        {
+            Err(ServiceError::IdempotencyConflict)
+        }
+        data => Ok(data.map(|x| x.into())),
+    }
+}
+
+/// approve and execute, or reject a pending adjustment; `None` when it is no
+/// longer pending. The claim, its audit and the postings share one
+/// transaction, a failed posting leaves the adjustment pending with nothing
+/// posted
+pub async fn review(
+    db: &Database,
+    id: i32,
+    reviewed_by: i32,
+    status: AdjustmentStatus,
+    review_remark: &str,
+) -> Result<Option<Info>> {
+    let review_remark = review_remark.to_owned();
+    let reviewed = db
+        .client
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            review_with(&client, id, reviewed_by, status, review_remark).await
+        })
+        .await?;
+    if !reviewed {
+        return Ok(None);
+    }
+    Ok(Some(info(db, id).await?))
+}
+
+/// `review` within the caller`s transaction, `false` when the adjustment is
+/// no longer pending
+async fn review_with(
+    client: &PrismaClient,
+    id: i32,
+    reviewed_by: i32,
+    status: AdjustmentStatus,
+    review_remark: String,
+) -> Result<bool> {
+    let before = client
+        .member_adjustment()
+        .find_unique(member_adjustment::id::equals(id))
+        .exec()
+        .await?
+        .ok_or(ServiceError::DataNotFound)?;
+    // claim the adjustment, another admin may review it at the same time
+    let claimed = client
+        .member_adjustment()
+        .update_many(
+            vec![
+                member_adjustment::id::equals(id),
+                member_adjustment::status::equals(AdjustmentStatus::Pending.into()),
+            ],
+            vec![
+                member_adjustment::status::set(status.into()),
+                member_adjustment::reviewed_by::set(reviewed_by),
+                member_adjustment::reviewed_at::set(Some(now_time())),
+                member_adjustment::review_remark::set(review_remark),
+            ],
+        )
+        .exec()
+        .await?;
+    if claimed == 0 {
+        return Ok(false);
+    }
+    let data = client
+        .member_adjustment()
+        .find_unique(member_adjustment::id::equals(id))
+        .exec()
+        .await?
+        .ok_or(ServiceError::DataNotFound)?;
+    system_audit_service::record(
+        client,
+        "member_adjustment",
+        data.id,
+        AuditAction::Update,
+        Some(&before),
+        Some(&data),
+    )
+    .await?;
+    if status == AdjustmentStatus::Executed {
+        execute(client, &data).await?;
+    }
+    Ok(true)
+}
+
+/// credit the positive parts and debit the negative parts within the
+/// reviewing transaction, billed as made by the admin who started the
+/// adjustment
+async fn execute(client: &PrismaClient, data: &member_adjustment::Data) -> Result<()> {
+    let zero = BigDecimal::default();
+    let meta = |posting: &str| BillMeta {
+        source: BillSource::Admin,
+        operator_id: data.created_by,
+        order_id: data.order_id.clone(),
+        remark: data.remark.clone(),
+        idempotency_key: Some(format!("member_adjustment:{}:{}", data.id, posting)),
+    };
+    let balance = (data.balance != zero).then(|| data.balance.clone());
+    let credit_balance = balance.clone().filter(|x| x > &zero);
+    let credit_integral = Some(data.integral).filter(|x| *x > 0);
+    if credit_balance.is_some() || credit_integral.is_some() {
+        member_service::post_with(
+            client,
+            data.member_id,
+            BillPm::Increment,
+            BillType::Adjust,
+            credit_balance,
+            credit_integral,
+            &meta("credit"),
+        )
+        .await?;
+    }
+    let debit_balance = balance.filter(|x| x < &zero).map(|x| -x);
+    let debit_integral = Some(-data.integral).filter(|x| *x > 0);
+    if debit_balance.is_some() || debit_integral.is_some() {
+        member_service::post_with(
+            client,
+            data.member_id,
+            BillPm::Decrement,
+            BillType::Adjust,
+            debit_balance,
+            debit_integral,
+            &meta("debit"),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+pub async fn info(db: &Database, id: i32) -> Result<Info> {
+    Ok(db
+        .client
+        .member_adjustment()
+        .find_unique(member_adjustment::id::equals(id))
+        .exec()
+        .await?
+        .ok_or(ServiceError::DataNotFound)?
+        .into())
+}
+
+pub async fn paginate(db: &Database, params: &SearchParams) -> Result<PaginateResult<Vec<Info>>> {
+    let (data, total) = db
+        .client
+        ._batch((
+            db.client
+                .member_adjustment()
+                .find_many(params.to_params())
+                .skip(params.paginate.get_skip())
+                .take(params.paginate.get_limit())
+                .order_by(member_adjustment::id::order(SortOrder::Desc)),
+            db.client.member_adjustment().count(params.to_params()),
+        ))
+        .await?;
+    Ok(PaginateResult {
+        total,
+        data: data.into_iter().map(|x| x.into()).collect::<Vec<Info>>(),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize_repr, Deserialize_repr)]
+#[repr(i32)]
+pub enum AdjustmentStatus {
+    /// 1.待审核
+    Pending = 1,
+    /// 2.已执行
+    Executed = 2,
+    /// 3.已驳回
+    Rejected = 3,
+}
+
+impl From<i32> for AdjustmentStatus {
+    fn from(value: i32) -> Self {
+        match value {
+            1 => Self::Pending,
+            2 => Self::Executed,
+            3 => Self::Rejected,
+            _ => Self::Pending,
+        }
+    }
+}
+
+impl From<AdjustmentStatus> for i32 {
+    fn from(value: AdjustmentStatus) -> Self {
+        match value {
+            AdjustmentStatus::Pending => 1,
+            AdjustmentStatus::Executed => 2,
+            AdjustmentStatus::Rejected => 3,
+        }
+    }
+}
+
+pub struct SearchParams {
+    member_id: Option<i32>,
+    status: Option<AdjustmentStatus>,
+    paginate: PaginateParams,
+}
+
+impl SearchParams {
+    fn to_params(&self) -> Vec<member_adjustment::WhereParam> {
+        let mut params = vec![];
+        if let Some(member_id) = self.member_id {
+            params.push(member_adjustment::member_id::equals(member_id));
+        }
+        if let Some(status) = self.status {
+            params.push(member_adjustment::status::equals(status.into()));
+        }
+        params
+    }
+
+    pub fn new(
+        member_id: Option<i32>,
+        status: Option<AdjustmentStatus>,
+        paginate: PaginateParams,
+    ) -> Self {
+        Self {
+            member_id,
+            status,
+            paginate,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Info {
+    id: i32,
+    member_id: i32,
+    balance: BigDecimal,
+    integral: i32,
+    order_id: String,
+    remark: String,
+    idempotency_key: Option<String>,
+    status: AdjustmentStatus,
+    created_by: i32,
+    reviewed_by: i32,
+    reviewed_at: Option<String>,
+    review_remark: String,
+    created_at: String,
+}
+
+impl Info {
+    pub fn status(&self) -> AdjustmentStatus {
+        self.status
+    }
+
+    pub fn created_by(&self) -> i32 {
+        self.created_by
+    }
+}
+
+impl From<member_adjustment::Data> for Info {
+    fn from(value: member_adjustment::Data) -> Self {
+        Self {
+            id: value.id,
+            member_id: value.member_id,
+            balance: value.balance,
+            integral: value.integral,
+            order_id: value.order_id,
+            remark: value.remark,
+            idempotency_key: value.idempotency_key,
+            status: value.status.into(),
+            created_by: value.created_by,
+            reviewed_by: value.reviewed_by,
+            reviewed_at: value.reviewed_at.map(to_local_string),
+            review_remark: value.review_remark,
+            created_at: to_local_string(value.created_at),
+        }
+    }
+}
+
+member_adjustment::partial_unchecked!(CreateParams {
+    balance
+    integral
+    order_id
+    idempotency_key
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prisma::member;
+
+    async fn seed_member(db: &Database) -> i32 {
+        let code = format!("test{}", fastrand::u32(..));
+        db.client
+            .member()
+            .create(code.clone(), format!("{}@example.com", code), vec![])
+            .exec()
+            .await
+            .unwrap()
+            .id
+    }
+
+    async fn balance(db: &Database, member_id: i32) -> BigDecimal {
+        db.client
+            .member()
+            .find_unique(member::id::equals(member_id))
+            .exec()
+            .await
+            .unwrap()
+            .unwrap()
+            .balance
+    }
+
+    fn params(balance: i32, key: &str) -> CreateParams {
+        CreateParams {
+            balance: Some(BigDecimal::from(balance)),
+            integral: None,
+            order_id: None,
+            idempotency_key: Some(Some(key.to_owned())),
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database"]
+    async fn idempotent_create() {
+        let db = Database::new(crate::DatabaseConfig::default())
+            .await
+            .unwrap();
+        let member_id = seed_member(&db).await;
+        let key = format!("test{}", fastrand::u32(..));
+        let first = create(&db, member_id, 1, "", params(10, &key), false)
+            .await
+            .unwrap();
+        assert_eq!(first.status, AdjustmentStatus::Executed);
+        // the same request again is the recorded adjustment, executed once
+        let second = create(&db, member_id, 1, "", params(10, &key), false)
+            .await
+            .unwrap();
+        assert_eq!(second.id, first.id);
+        assert_eq!(balance(&db, member_id).await, BigDecimal::from(10));
+        // the key of another amount is refused
+        assert!(matches!(
+            create(&db, member_id, 1, "", params(20, &key), false).await,
+            Err(ServiceError::IdempotencyConflict)
+        ));
+        assert_eq!(balance(&db, member_id).await, BigDecimal::from(10));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database"]
+    async fn insufficient_funds_records_nothing() {
+        let db = Database::new(crate::DatabaseConfig::default())
+            .await
+            .unwrap();
+        let member_id = seed_member(&db).await;
+        let key = format!("test{}", fastrand::u32(..));
+        assert!(matches!(
+            create(&db, member_id, 1, "", params(-10, &key), false).await,
+            Err(ServiceError::InsufficientFunds)
+        ));
+        assert!(
+            created(&db, member_id, &BigDecimal::from(-10), 0, Some(&key))
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(balance(&db, member_id).await, BigDecimal::default());
+        // waiting for approval posts nothing yet
+        let pending = create(&db, member_id, 1, "", params(-10, &key), true)
+            .await
+            .unwrap();
+        assert_eq!(pending.status, AdjustmentStatus::Pending);
+        assert!(matches!(
+            review(&db, pending.id, 2, AdjustmentStatus::Executed, "").await,
+            Err(ServiceError::InsufficientFunds)
+        ));
+        assert_eq!(
+            info(&db, pending.id).await.unwrap().status,
+            AdjustmentStatus::Pending
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use crate::{
     member_service,
     prisma::{member, member_bill, SortOrder},
+    system_audit_service::{self, AuditAction},
     Database, Result, ServiceError,
 };
 use prisma_client_rust::{or, prisma_models::parse_datetime};
@@ -19,9 +20,24 @@ pub async fn create(
 ) -> Result<Info> {
     Ok(db
         .client
-        .member_bill()
-        .create_unchecked(user_id, r#type.into(), params.to_params())
-        .exec()
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            let data = client
+                .member_bill()
+                .create_unchecked(user_id, r#type.into(), params.to_params())
+                .exec()
+                .await?;
+            system_audit_service::record(
+                &client,
+                "member_bill",
+                data.id,
+                AuditAction::Create,
+                None,
+                Some(&data),
+            )
+            .await?;
+            Ok(data)
+        })
         .await?
         .into())
 }
@@ -57,6 +73,7 @@ pub async fn paginate(db: &Database, params: &SearchParams) -> Result<PaginateRe
 pub struct SearchParams {
     r#type: Option<BillType>,
     pm: Option<BillPm>,
+    source: Option<BillSource>,
     keyword: Option<String>,
     date: Option<String>,
     paginate: PaginateParams,
@@ -70,6 +87,9 @@ impl SearchParams {
         if let Some(pm) = &self.pm {
             params.push(member_bill::pm::equals(pm.clone().into()));
         }
+        if let Some(source) = self.source {
+            params.push(member_bill::source::equals(source.into()));
+        }
         if let Some(keyword) = &self.keyword {
             let user_search = vec![or!(
                 member::unique_code::contains(keyword.to_string()),
@@ -90,6 +110,7 @@ impl SearchParams {
     pub fn new(
         r#type: Option<BillType>,
         pm: Option<BillPm>,
+        source: Option<BillSource>,
         keyword: Option<String>,
         date: Option<String>,
         paginate: PaginateParams,
@@ -97,6 +118,7 @@ impl SearchParams {
         Self {
             r#type,
             pm,
+            source,
             keyword,
             date,
             paginate,
@@ -111,9 +133,38 @@ pub struct Info {
     r#type: BillType,
     pm: BillPm,
     number: prisma_client_rust::bigdecimal::BigDecimal,
+    /// balance after the bill, the integral for integral bills
+    balance_after: prisma_client_rust::bigdecimal::BigDecimal,
+    source: BillSource,
+    operator_id: i32,
+    order_id: String,
+    remark: String,
+    idempotency_key: Option<String>,
     created_at: String,
 }
 
+impl Info {
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn user_id(&self) -> i32 {
+        self.user_id
+    }
+
+    pub fn bill_type(&self) -> BillType {
+        self.r#type.clone()
+    }
+
+    pub fn pm(&self) -> BillPm {
+        self.pm.clone()
+    }
+
+    pub fn number(&self) -> &prisma_client_rust::bigdecimal::BigDecimal {
+        &self.number
+    }
+}
+
 impl From<member_bill::Data> for Info {
     fn from(value: member_bill::Data) -> Self {
         Self {
@@ -126,6 +177,12 @@ impl From<member_bill::Data> for Info {
             r#type: value.r#type.into(),
             pm: value.pm.into(),
             number: value.number,
+            balance_after: value.balance_after,
+            source: value.source.into(),
+            operator_id: value.operator_id,
+            order_id: value.order_id,
+            remark: value.remark,
+            idempotency_key: value.idempotency_key,
             created_at: to_local_string(value.created_at),
         }
     }
@@ -135,12 +192,24 @@ impl From<member_bill::Data> for Info {
 pub enum BillType {
     Balance = 1,
     Integral = 2,
+    /// balance credited as commission
+    Commission = 3,
+    /// balance adjusted by an admin
+    Adjust = 4,
+    /// balance recharged through a payment gateway
+    Recharge = 5,
+    /// balance withdrawn, or refunded by a rejected withdrawal
+    Withdraw = 6,
 }
 impl From<i32> for BillType {
     fn from(value: i32) -> Self {
         match value {
             1 => Self::Balance,
             2 => Self::Integral,
+            3 => Self::Commission,
+            4 => Self::Adjust,
+            5 => Self::Recharge,
+            6 => Self::Withdraw,
             _ => Self::Balance,
         }
     }
@@ -150,6 +219,10 @@ impl From<BillType> for i32 {
         match value {
             BillType::Balance => 1,
             BillType::Integral => 2,
+            BillType::Commission => 3,
+            BillType::Adjust => 4,
+            BillType::Recharge => 5,
+            BillType::Withdraw => 6,
         }
     }
 }
@@ -176,7 +249,77 @@ impl From<BillPm> for i32 {
         }
     }
 }
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize_repr, Deserialize_repr)]
+#[repr(i32)]
+pub enum BillSource {
+    /// 0.系统
+    #[default]
+    System = 0,
+    /// 1.后台
+    Admin = 1,
+    /// 2.会员
+    Member = 2,
+}
+impl From<i32> for BillSource {
+    fn from(value: i32) -> Self {
+        match value {
+            0 => Self::System,
+            1 => Self::Admin,
+            2 => Self::Member,
+            _ => Self::System,
+        }
+    }
+}
+impl From<BillSource> for i32 {
+    fn from(value: BillSource) -> Self {
+        match value {
+            BillSource::System => 0,
+            BillSource::Admin => 1,
+            BillSource::Member => 2,
+        }
+    }
+}
+
+/// who made the bills and why
+#[derive(Debug, Clone, Default)]
+pub struct BillMeta {
+    pub source: BillSource,
+    /// admin making the bills, 0 otherwise
+    pub operator_id: i32,
+    /// related order
+    pub order_id: String,
+    pub remark: String,
+    /// posting again with the same key returns the bills of the first posting
+    pub idempotency_key: Option<String>,
+}
+
+impl BillMeta {
+    pub fn to_create_params(
+        &self,
+        pm: BillPm,
+        number: prisma_client_rust::bigdecimal::BigDecimal,
+        balance_after: prisma_client_rust::bigdecimal::BigDecimal,
+    ) -> CreateParams {
+        CreateParams {
+            pm: Some(pm.into()),
+            number: Some(number),
+            balance_after: Some(balance_after),
+            source: Some(self.source.into()),
+            operator_id: Some(self.operator_id),
+            order_id: Some(self.order_id.clone()),
+            remark: Some(self.remark.clone()),
+            idempotency_key: Some(self.idempotency_key.clone()),
+        }
+    }
+}
+
 member_bill::partial_unchecked!(CreateParams {
     pm
     number
+    balance_after
+    source
+    operator_id
+    order_id
+    remark
+    idempotency_key
 });
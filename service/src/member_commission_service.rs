@@ -0,0 +1,697 @@
+use crate::{
+    member_bill_service::{self, BillMeta, BillPm, BillType},
+    member_service,
+    prisma::{
+        member, member_commission, member_commission_rule, member_team, PrismaClient, SortOrder,
+    },
+    system_audit_service::{self, AuditAction},
+    Database, Result, ServiceError,
+};
+use prisma_client_rust::{bigdecimal::BigDecimal, chrono::Duration, raw, PrismaValue};
+use serde::{Deserialize, Serialize};
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use std::str::FromStr;
+use utils::{
+    datetime::{now_time, parse_string, to_local_string},
+    paginate::{PaginateParams, PaginateResult},
+};
+
+pub async fn create_rule(db: &Database, name: &str, params: RuleCreateParams) -> Result<RuleInfo> {
+    Ok(db
+        .client
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            let data = client
+                .member_commission_rule()
+                .create_unchecked(name.to_owned(), params.to_params())
+                .exec()
+                .await?;
+            system_audit_service::record(
+                &client,
+                "member_commission_rule",
+                data.id,
+                AuditAction::Create,
+                None,
+                Some(&data),
+            )
+            .await?;
+            Ok(data)
+        })
+        .await?
+        .into())
+}
+
+pub async fn update_rule(db: &Database, id: i32, params: RuleUpdateParams) -> Result<RuleInfo> {
+    set_rule(db, id, AuditAction::Update, params.to_params()).await
+}
+
+pub async fn delete_rule(db: &Database, id: i32) -> Result<RuleInfo> {
+    set_rule(
+        db,
+        id,
+        AuditAction::Delete,
+        vec![member_commission_rule::deleted_at::set(Some(now_time()))],
+    )
+    .await
+}
+
+/// update the rule and audit it in one transaction
+async fn set_rule(
+    db: &Database,
+    id: i32,
+    action: AuditAction,
+    params: Vec<member_commission_rule::UncheckedSetParam>,
+) -> Result<RuleInfo> {
+    Ok(db
+        .client
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            let before = client
+                .member_commission_rule()
+                .find_unique(member_commission_rule::id::equals(id))
+                .exec()
+                .await?;
+            let data = client
+                .member_commission_rule()
+                .update_unchecked(member_commission_rule::id::equals(id), params)
+                .exec()
+                .await?;
+            system_audit_service::record(
+                &client,
+                "member_commission_rule",
+                data.id,
+                action,
+                before.as_ref(),
+                Some(&data),
+            )
+            .await?;
+            Ok(data)
+        })
+        .await?
+        .into())
+}
+
+pub async fn rule_info(db: &Database, id: i32) -> Result<RuleInfo> {
+    Ok(db
+        .client
+        .member_commission_rule()
+        .find_first(vec![
+            member_commission_rule::id::equals(id),
+            member_commission_rule::deleted_at::equals(None),
+        ])
+        .exec()
+        .await?
+        .ok_or(ServiceError::DataNotFound)?
+        .into())
+}
+
+pub async fn rule_paginate(
+    db: &Database,
+    params: &RuleSearchParams,
+) -> Result<PaginateResult<Vec<RuleInfo>>> {
+    let (data, total) = db
+        .client
+        ._batch((
+            db.client
+                .member_commission_rule()
+                .find_many(params.to_params())
+                .skip(params.paginate.get_skip())
+                .take(params.paginate.get_limit())
+                .order_by(member_commission_rule::level::order(SortOrder::Asc)),
+            db.client.member_commission_rule().count(params.to_params()),
+        ))
+        .await?;
+    Ok(PaginateResult {
+        total,
+        data: data
+            .into_iter()
+            .map(|x| x.into())
+            .collect::<Vec<RuleInfo>>(),
+    })
+}
+
+/// pay the uplines of the bill`s member by the enabled rules of the bill type,
+/// a rule pays each upline once per bill; commission bills never pay again,
+/// nor do withdrawal refunds. Runs within the transaction posting the bill,
+/// the commissions are recorded and credited with it or not at all
+#[async_recursion::async_recursion]
+pub(crate) async fn distribute(
+    client: &PrismaClient,
+    bill: &member_bill_service::Info,
+) -> Result<Vec<Info>> {
+    if [BillType::Commission, BillType::Withdraw].contains(&bill.bill_type())
+        || bill.pm() != BillPm::Increment
+    {
+        return Ok(vec![]);
+    }
+    let bill_type: i32 = bill.bill_type().into();
+    let rules = client
+        .member_commission_rule()
+        .find_many(vec![
+            member_commission_rule::status::equals(1),
+            member_commission_rule::deleted_at::equals(None),
+        ])
+        .exec()
+        .await?
+        .into_iter()
+        .filter(|x| {
+            serde_json::from_str::<Vec<i32>>(&x.bill_types)
+                .unwrap_or_default()
+                .contains(&bill_type)
+        })
+        .collect::<Vec<member_commission_rule::Data>>();
+    if rules.is_empty() {
+        return Ok(vec![]);
+    }
+    let uplines = client
+        .member_team()
+        .find_many(vec![
+            member_team::member_id::equals(bill.user_id()),
+            member_team::level::in_vec(rules.iter().map(|x| x.level).collect()),
+            member_team::deleted_at::equals(None),
+            member_team::owner::is(vec![
+                // 1.正常
+                member::status::equals(1),
+                member::deleted_at::equals(None),
+            ]),
+        ])
+        .with(member_team::owner::fetch())
+        .exec()
+        .await?;
+    let mut result = vec![];
+    for rule in rules.iter() {
+        for upline in uplines.iter().filter(|x| x.level == rule.level) {
+            let is_promoter = upline.owner().map(|x| x.is_promoter == 1).unwrap_or(false);
+            if rule.promoter_only && !is_promoter {
+                continue;
+            }
+            let amount = get_amount(rule, bill.number());
+            if amount <= BigDecimal::default() {
+                continue;
+            }
+            let data = client
+                .member_commission()
+                .create_unchecked(
+                    rule.id,
+                    bill.id(),
+                    bill.user_id(),
+                    upline.owner_uid,
+                    rule.level,
+                    bill.number().clone(),
+                    amount.clone(),
+                    vec![],
+                )
+                .exec()
+                .await?;
+            member_service::post_with(
+                client,
+                upline.owner_uid,
+                BillPm::Increment,
+                BillType::Commission,
+                Some(amount),
+                None,
+                &BillMeta {
+                    order_id: bill.id().to_string(),
+                    remark: rule.name.clone(),
+                    idempotency_key: Some(format!(
+                        "commission:{}:{}:{}",
+                        rule.id,
+                        bill.id(),
+                        upline.owner_uid
+                    )),
+                    ..Default::default()
+                },
+            )
+            .await?;
+            result.push(data.into());
+        }
+    }
+    Ok(result)
+}
+
+/// percent of the base or the fixed amount, cut to cents and capped
+fn get_amount(rule: &member_commission_rule::Data, base: &BigDecimal) -> BigDecimal {
+    let amount = match CommissionMode::from(rule.mode) {
+        CommissionMode::Percent => base * &rule.value / BigDecimal::from(100),
+        CommissionMode::Fixed => rule.value.clone(),
+    }
+    .with_scale(2);
+    match rule.cap > BigDecimal::default() && amount > rule.cap {
+        true => rule.cap.clone(),
+        false => amount,
+    }
+}
+
+pub async fn paginate(db: &Database, params: &SearchParams) -> Result<PaginateResult<Vec<Info>>> {
+    let (data, total) = db
+        .client
+        ._batch((
+            db.client
+                .member_commission()
+                .find_many(params.to_params())
+                .skip(params.paginate.get_skip())
+                .take(params.paginate.get_limit())
+                .order_by(member_commission::id::order(SortOrder::Desc)),
+            db.client.member_commission().count(params.to_params()),
+        ))
+        .await?;
+    Ok(PaginateResult {
+        total,
+        data: data.into_iter().map(|x| x.into()).collect::<Vec<Info>>(),
+    })
+}
+
+/// commissions of every member between the `%Y-%m-%d` dates, both included,
+/// the most paid first
+pub async fn report(
+    db: &Database,
+    start_date: Option<String>,
+    end_date: Option<String>,
+    paginate: &PaginateParams,
+) -> Result<Report> {
+    let mut params = vec![];
+    if let Some(start_date) = start_date {
+        params.push(member_commission::created_at::gte(parse_string(start_date)));
+    }
+    if let Some(end_date) = end_date {
+        params.push(member_commission::created_at::lt(
+            parse_string(end_date) + Duration::days(1),
+        ));
+    }
+    // ids grow with the time, the period is read as an id range
+    let (first, last) = db
+        .client
+        ._batch((
+            db.client
+                .member_commission()
+                .find_first(params.clone())
+                .order_by(member_commission::id::order(SortOrder::Asc)),
+            db.client
+                .member_commission()
+                .find_first(params)
+                .order_by(member_commission::id::order(SortOrder::Desc)),
+        ))
+        .await?;
+    let (first_id, last_id) = match (first, last) {
+        (Some(first), Some(last)) => (first.id, last.id),
+        _ => {
+            return Ok(Report {
+                members: 0,
+                commissions: 0,
+                amount: BigDecimal::default(),
+                data: PaginateResult {
+                    total: 0,
+                    data: vec![],
+                },
+            })
+        }
+    };
+    // sums are read as text, sqlite may return integers or floats for decimals
+    let total = db
+        .client
+        ._query_raw::<ReportTotal>(raw!(
+            "SELECT COUNT(DISTINCT member_id) AS members, COUNT(*) AS commissions, \
+             CAST(COALESCE(SUM(amount), 0) AS TEXT) AS amount \
+             FROM member_commissions WHERE id BETWEEN {} AND {}",
+            PrismaValue::Int(first_id.into()),
+            PrismaValue::Int(last_id.into())
+        ))
+        .exec()
+        .await?
+        .pop()
+        .unwrap_or_default();
+    let data = db
+        .client
+        ._query_raw::<ReportRow>(raw!(
+            "SELECT member_id, COUNT(*) AS commissions, \
+             CAST(SUM(amount) AS TEXT) AS amount \
+             FROM member_commissions WHERE id BETWEEN {} AND {} \
+             GROUP BY member_id ORDER BY SUM(amount) DESC, member_id ASC \
+             LIMIT {} OFFSET {}",
+            PrismaValue::Int(first_id.into()),
+            PrismaValue::Int(last_id.into()),
+            PrismaValue::Int(paginate.get_limit().try_into().unwrap_or_default()),
+            PrismaValue::Int(paginate.get_skip().try_into().unwrap_or_default())
+        ))
+        .exec()
+        .await?;
+    let members = db
+        .client
+        .member()
+        .find_many(vec![member::id::in_vec(
+            data.iter().map(|x| x.member_id).collect(),
+        )])
+        .exec()
+        .await?;
+    Ok(Report {
+        members: total.members,
+        commissions: total.commissions,
+        amount: BigDecimal::from_str(&total.amount).unwrap_or_default(),
+        data: PaginateResult {
+            total: total.members,
+            data: data
+                .into_iter()
+                .map(|x| ReportItem {
+                    member_id: x.member_id,
+                    member: members
+                        .iter()
+                        .find(|m| m.id == x.member_id)
+                        .map(|m| m.clone().into()),
+                    commissions: x.commissions,
+                    amount: BigDecimal::from_str(&x.amount).unwrap_or_default(),
+                })
+                .collect::<Vec<ReportItem>>(),
+        },
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize_repr, Deserialize_repr)]
+#[repr(i32)]
+pub enum CommissionMode {
+    /// 1.按比例
+    Percent = 1,
+    /// 2.固定金额
+    Fixed = 2,
+}
+
+impl From<i32> for CommissionMode {
+    fn from(value: i32) -> Self {
+        match value {
+            1 => Self::Percent,
+            2 => Self::Fixed,
+            _ => Self::Percent,
+        }
+    }
+}
+
+impl From<CommissionMode> for i32 {
+    fn from(value: CommissionMode) -> Self {
+        match value {
+            CommissionMode::Percent => 1,
+            CommissionMode::Fixed => 2,
+        }
+    }
+}
+
+pub struct RuleSearchParams {
+    keyword: Option<String>,
+    level: Option<i32>,
+    status: Option<i32>,
+    paginate: PaginateParams,
+}
+
+impl RuleSearchParams {
+    fn to_params(&self) -> Vec<member_commission_rule::WhereParam> {
+        let mut params = vec![member_commission_rule::deleted_at::equals(None)];
+        if let Some(keyword) = &self.keyword {
+            params.push(member_commission_rule::name::contains(keyword.to_string()));
+        }
+        if let Some(level) = self.level {
+            params.push(member_commission_rule::level::equals(level));
+        }
+        if let Some(status) = self.status {
+            params.push(member_commission_rule::status::equals(status));
+        }
+        params
+    }
+
+    pub fn new(
+        keyword: Option<String>,
+        level: Option<i32>,
+        status: Option<i32>,
+        paginate: PaginateParams,
+    ) -> Self {
+        Self {
+            keyword,
+            level,
+            status,
+            paginate,
+        }
+    }
+}
+
+pub struct SearchParams {
+    member_id: Option<i32>,
+    source_member_id: Option<i32>,
+    rule_id: Option<i32>,
+    paginate: PaginateParams,
+}
+
+impl SearchParams {
+    fn to_params(&self) -> Vec<member_commission::WhereParam> {
+        let mut params = vec![];
+        if let Some(member_id) = self.member_id {
+            params.push(member_commission::member_id::equals(member_id));
+        }
+        if let Some(source_member_id) = self.source_member_id {
+            params.push(member_commission::source_member_id::equals(
+                source_member_id,
+            ));
+        }
+        if let Some(rule_id) = self.rule_id {
+            params.push(member_commission::rule_id::equals(rule_id));
+        }
+        params
+    }
+
+    pub fn new(
+        member_id: Option<i32>,
+        source_member_id: Option<i32>,
+        rule_id: Option<i32>,
+        paginate: PaginateParams,
+    ) -> Self {
+        Self {
+            member_id,
+            source_member_id,
+            rule_id,
+            paginate,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RuleInfo {
+    id: i32,
+    name: String,
+    bill_types: Vec<BillType>,
+    level: i32,
+    mode: CommissionMode,
+    value: BigDecimal,
+    cap: BigDecimal,
+    promoter_only: bool,
+    remark: String,
+    status: i32,
+    created_at: String,
+}
+
+impl From<member_commission_rule::Data> for RuleInfo {
+    fn from(value: member_commission_rule::Data) -> Self {
+        Self {
+            id: value.id,
+            name: value.name,
+            bill_types: serde_json::from_str::<Vec<i32>>(&value.bill_types)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|x| x.into())
+                .collect::<Vec<BillType>>(),
+            level: value.level,
+            mode: value.mode.into(),
+            value: value.value,
+            cap: value.cap,
+            promoter_only: value.promoter_only,
+            remark: value.remark,
+            status: value.status,
+            created_at: to_local_string(value.created_at),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Info {
+    id: i32,
+    rule_id: i32,
+    source_bill_id: i32,
+    source_member_id: i32,
+    member_id: i32,
+    level: i32,
+    base: BigDecimal,
+    amount: BigDecimal,
+    created_at: String,
+}
+
+impl From<member_commission::Data> for Info {
+    fn from(value: member_commission::Data) -> Self {
+        Self {
+            id: value.id,
+            rule_id: value.rule_id,
+            source_bill_id: value.source_bill_id,
+            source_member_id: value.source_member_id,
+            member_id: value.member_id,
+            level: value.level,
+            base: value.base,
+            amount: value.amount,
+            created_at: to_local_string(value.created_at),
+        }
+    }
+}
+
+/// commissions paid within a period
+#[derive(Debug, Serialize)]
+pub struct Report {
+    /// members paid
+    members: i64,
+    commissions: i64,
+    amount: BigDecimal,
+    data: PaginateResult<Vec<ReportItem>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReportItem {
+    member_id: i32,
+    member: Option<member_service::Info>,
+    commissions: i64,
+    amount: BigDecimal,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ReportTotal {
+    members: i64,
+    commissions: i64,
+    amount: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReportRow {
+    member_id: i32,
+    commissions: i64,
+    amount: String,
+}
+
+member_commission_rule::partial_unchecked!(RuleCreateParams {
+    bill_types
+    level
+    mode
+    value
+    cap
+    promoter_only
+    remark
+    status
+});
+
+member_commission_rule::partial_unchecked!(RuleUpdateParams {
+    name
+    bill_types
+    level
+    mode
+    value
+    cap
+    promoter_only
+    remark
+    status
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn seed_member(db: &Database) -> i32 {
+        let code = format!("test{}", fastrand::u32(..));
+        db.client
+            .member()
+            .create(code.clone(), format!("{}@example.com", code), vec![])
+            .exec()
+            .await
+            .unwrap()
+            .id
+    }
+
+    async fn balance(db: &Database, member_id: i32) -> BigDecimal {
+        db.client
+            .member()
+            .find_unique(member::id::equals(member_id))
+            .exec()
+            .await
+            .unwrap()
+            .unwrap()
+            .balance
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database"]
+    async fn commission_bills_pay_nothing() {
+        let db = Database::new(crate::DatabaseConfig::default())
+            .await
+            .unwrap();
+        // top invited upline, upline invited member
+        let (top, upline, member_id) = (
+            seed_member(&db).await,
+            seed_member(&db).await,
+            seed_member(&db).await,
+        );
+        db.client
+            .member_team()
+            .create_many(vec![
+                member_team::create_unchecked(top, top, upline, vec![member_team::level::set(1)]),
+                member_team::create_unchecked(
+                    upline,
+                    upline,
+                    member_id,
+                    vec![member_team::level::set(1)],
+                ),
+                member_team::create_unchecked(
+                    top,
+                    upline,
+                    member_id,
+                    vec![member_team::level::set(2)],
+                ),
+            ])
+            .exec()
+            .await
+            .unwrap();
+        // a rule paying the direct upline for recharges, and for commissions
+        let rule = db
+            .client
+            .member_commission_rule()
+            .create(
+                format!("test{}", fastrand::u32(..)),
+                vec![
+                    member_commission_rule::bill_types::set(
+                        serde_json::json!([
+                            i32::from(BillType::Recharge),
+                            i32::from(BillType::Commission)
+                        ])
+                        .to_string(),
+                    ),
+                    member_commission_rule::mode::set(CommissionMode::Fixed.into()),
+                    member_commission_rule::value::set(BigDecimal::from(1)),
+                    member_commission_rule::promoter_only::set(false),
+                ],
+            )
+            .exec()
+            .await
+            .unwrap();
+        let result = member_service::increment(
+            &db,
+            member_id,
+            BillType::Recharge,
+            Some(BigDecimal::from(10)),
+            None,
+            BillMeta::default(),
+        )
+        .await;
+        let (upline_balance, top_balance) = (balance(&db, upline).await, balance(&db, top).await);
+        db.client
+            .member_commission_rule()
+            .update(
+                member_commission_rule::id::equals(rule.id),
+                vec![member_commission_rule::deleted_at::set(Some(now_time()))],
+            )
+            .exec()
+            .await
+            .unwrap();
+        result.unwrap();
+        assert_eq!(upline_balance, BigDecimal::from(1));
+        // the upline`s commission bill pays the top nothing
+        assert_eq!(top_balance, BigDecimal::default());
+    }
+}
@@ -0,0 +1,312 @@
+use crate::{
+    member_bill_service::{BillMeta, BillPm, BillSource, BillType},
+    member_service,
+    payment_service::{self, PaymentGateway, PaymentOrder},
+    prisma::{member_recharge, SortOrder},
+    Database, Result, ServiceError,
+};
+use prisma_client_rust::{bigdecimal::BigDecimal, chrono::Duration};
+use serde::Serialize;
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use std::collections::BTreeMap;
+use utils::{
+    datetime::{now_time, to_local_string},
+    paginate::{PaginateParams, PaginateResult},
+};
+
+/// seconds a recharge waits for its payment, `MEMBER_RECHARGE_TTL_SECS`,
+/// 30 minutes by default
+pub fn ttl_from_env() -> std::time::Duration {
+    let secs = std::env::var("MEMBER_RECHARGE_TTL_SECS")
+        .ok()
+        .and_then(|x| x.trim().parse::<u64>().ok())
+        .filter(|x| *x > 0)
+        .unwrap_or(30 * 60);
+    std::time::Duration::from_secs(secs)
+}
+
+/// create the recharge and start its payment at the gateway
+pub async fn create(
+    db: &Database,
+    gateway: &dyn PaymentGateway,
+    member_id: i32,
+    amount: BigDecimal,
+    ttl: std::time::Duration,
+) -> Result<Info> {
+    let data = db
+        .client
+        .member_recharge()
+        .create_unchecked(
+            payment_service::generate_order_no("R"),
+            member_id,
+            amount,
+            gateway.name().to_owned(),
+            now_time() + Duration::seconds(ttl.as_secs() as i64),
+            vec![],
+        )
+        .exec()
+        .await?;
+    let pay_url = gateway
+        .pay(&PaymentOrder {
+            order_no: data.order_no.clone(),
+            amount: data.amount.clone(),
+            subject: format!("recharge {}", data.order_no),
+        })
+        .await?;
+    Ok(db
+        .client
+        .member_recharge()
+        .update(
+            member_recharge::id::equals(data.id),
+            vec![member_recharge::pay_url::set(pay_url)],
+        )
+        .exec()
+        .await?
+        .into())
+}
+
+/// verify the gateway callback and credit the paid recharge, marked paid in
+/// the same transaction; repeated callbacks credit once, and a payment
+/// arriving after the recharge expired is still credited
+pub async fn notify(
+    db: &Database,
+    gateway: &dyn PaymentGateway,
+    params: &BTreeMap<String, String>,
+) -> Result<Info> {
+    let notice = gateway.verify(params)?;
+    let data = db
+        .client
+        .member_recharge()
+        .find_unique(member_recharge::order_no::equals(notice.order_no.clone()))
+        .exec()
+        .await?
+        .ok_or(ServiceError::DataNotFound)?;
+    if data.gateway != gateway.name() || data.amount != notice.amount {
+        return Err(ServiceError::Payment(format!(
+            "recharge {} does not match the payment",
+            data.order_no
+        )));
+    }
+    if data.status == i32::from(RechargeStatus::Paid) {
+        return Ok(data.into());
+    }
+    let (id, remark) = (data.id, format!("{} {}", gateway.name(), notice.trade_no));
+    db.client
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            // claim the recharge, a concurrent callback credits nothing
+            let claimed = client
+                .member_recharge()
+                .update_many(
+                    vec![
+                        member_recharge::id::equals(data.id),
+                        member_recharge::status::not(RechargeStatus::Paid.into()),
+                    ],
+                    vec![
+                        member_recharge::status::set(RechargeStatus::Paid.into()),
+                        member_recharge::trade_no::set(notice.trade_no),
+                        member_recharge::paid_at::set(Some(now_time())),
+                    ],
+                )
+                .exec()
+                .await?;
+            if claimed == 0 {
+                return Ok(());
+            }
+            member_service::post_with(
+                &client,
+                data.member_id,
+                BillPm::Increment,
+                BillType::Recharge,
+                Some(data.amount.clone()),
+                None,
+                &BillMeta {
+                    source: BillSource::Member,
+                    order_id: data.order_no.clone(),
+                    remark,
+                    idempotency_key: Some(format!("member_recharge:{}", data.order_no)),
+                    ..Default::default()
+                },
+            )
+            .await?;
+            Ok(())
+        })
+        .await?;
+    info(db, id).await
+}
+
+/// close the pending recharges past their expiry, returns the number closed
+pub async fn expire(db: &Database) -> Result<i64> {
+    Ok(db
+        .client
+        .member_recharge()
+        .update_many(
+            vec![
+                member_recharge::status::equals(RechargeStatus::Pending.into()),
+                member_recharge::expired_at::lt(now_time()),
+            ],
+            vec![member_recharge::status::set(RechargeStatus::Expired.into())],
+        )
+        .exec()
+        .await?)
+}
+
+/// expire on every interval
+pub async fn run(db: &Database, interval: std::time::Duration) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        match expire(db).await {
+            Ok(0) => {}
+            Ok(count) => tracing::info!("{} member recharges expired", count),
+            Err(err) => tracing::error!("member recharge expire failed: {:?}", err),
+        }
+    }
+}
+
+pub async fn info(db: &Database, id: i32) -> Result<Info> {
+    Ok(db
+        .client
+        .member_recharge()
+        .find_unique(member_recharge::id::equals(id))
+        .exec()
+        .await?
+        .ok_or(ServiceError::DataNotFound)?
+        .into())
+}
+
+/// recharge of the order no, only when it belongs to the member
+pub async fn get_by_order_no(db: &Database, member_id: i32, order_no: &str) -> Result<Info> {
+    Ok(db
+        .client
+        .member_recharge()
+        .find_first(vec![
+            member_recharge::order_no::equals(order_no.to_owned()),
+            member_recharge::member_id::equals(member_id),
+        ])
+        .exec()
+        .await?
+        .ok_or(ServiceError::DataNotFound)?
+        .into())
+}
+
+pub async fn paginate(db: &Database, params: &SearchParams) -> Result<PaginateResult<Vec<Info>>> {
+    let (data, total) = db
+        .client
+        ._batch((
+            db.client
+                .member_recharge()
+                .find_many(params.to_params())
+                .skip(params.paginate.get_skip())
+                .take(params.paginate.get_limit())
+                .order_by(member_recharge::id::order(SortOrder::Desc)),
+            db.client.member_recharge().count(params.to_params()),
+        ))
+        .await?;
+    Ok(PaginateResult {
+        total,
+        data: data.into_iter().map(|x| x.into()).collect::<Vec<Info>>(),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize_repr, Deserialize_repr)]
+#[repr(i32)]
+pub enum RechargeStatus {
+    /// 1.待支付
+    Pending = 1,
+    /// 2.已支付
+    Paid = 2,
+    /// 3.已过期
+    Expired = 3,
+}
+
+impl From<i32> for RechargeStatus {
+    fn from(value: i32) -> Self {
+        match value {
+            1 => Self::Pending,
+            2 => Self::Paid,
+            3 => Self::Expired,
+            _ => Self::Pending,
+        }
+    }
+}
+
+impl From<RechargeStatus> for i32 {
+    fn from(value: RechargeStatus) -> Self {
+        match value {
+            RechargeStatus::Pending => 1,
+            RechargeStatus::Paid => 2,
+            RechargeStatus::Expired => 3,
+        }
+    }
+}
+
+pub struct SearchParams {
+    member_id: Option<i32>,
+    order_no: Option<String>,
+    status: Option<RechargeStatus>,
+    paginate: PaginateParams,
+}
+
+impl SearchParams {
+    fn to_params(&self) -> Vec<member_recharge::WhereParam> {
+        let mut params = vec![];
+        if let Some(member_id) = self.member_id {
+            params.push(member_recharge::member_id::equals(member_id));
+        }
+        if let Some(order_no) = &self.order_no {
+            params.push(member_recharge::order_no::contains(order_no.to_string()));
+        }
+        if let Some(status) = self.status {
+            params.push(member_recharge::status::equals(status.into()));
+        }
+        params
+    }
+
+    pub fn new(
+        member_id: Option<i32>,
+        order_no: Option<String>,
+        status: Option<RechargeStatus>,
+        paginate: PaginateParams,
+    ) -> Self {
+        Self {
+            member_id,
+            order_no,
+            status,
+            paginate,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Info {
+    id: i32,
+    order_no: String,
+    member_id: i32,
+    amount: BigDecimal,
+    gateway: String,
+    trade_no: String,
+    pay_url: String,
+    status: RechargeStatus,
+    paid_at: Option<String>,
+    expired_at: String,
+    created_at: String,
+}
+
+impl From<member_recharge::Data> for Info {
+    fn from(value: member_recharge::Data) -> Self {
+        Self {
+            id: value.id,
+            order_no: value.order_no,
+            member_id: value.member_id,
+            amount: value.amount,
+            gateway: value.gateway,
+            trade_no: value.trade_no,
+            pay_url: value.pay_url,
+            status: value.status.into(),
+            paid_at: value.paid_at.map(to_local_string),
+            expired_at: to_local_string(value.expired_at),
+            created_at: to_local_string(value.created_at),
+        }
+    }
+}
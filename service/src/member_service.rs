@@ -1,62 +1,135 @@
 use crate::{
-    member_bill_service,
-    prisma::{member, SortOrder},
+    member_bill_service, member_commission_service, member_team_service,
+    prisma::{member, member_bill, PrismaClient, SortOrder},
+    system_audit_service::{self, AuditAction},
     Database, Result, ServiceError,
 };
 use getset::Getters;
-use prisma_client_rust::{bigdecimal::BigDecimal, or};
-use serde::Serialize;
-use std::ops::{Add, Sub};
+use prisma_client_rust::{bigdecimal::BigDecimal, or, raw, PrismaValue};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, str::FromStr};
 use utils::{
     datetime::{now_time, to_local_string},
     paginate::{PaginateParams, PaginateResult},
 };
 
+/// create the member, joining the team of the inviter in the same
+/// transaction; a refused invite is an `InviteRefused` error and nothing is
+/// created
 pub async fn create(
     db: &Database,
     unique_code: &str,
     email: &str,
     params: CreateParams,
+    invite_code: Option<&str>,
 ) -> Result<Info> {
     Ok(db
         .client
-        .member()
-        .create_unchecked(unique_code.to_owned(), email.to_owned(), params.to_params())
-        .exec()
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            let data = client
+                .member()
+                .create_unchecked(unique_code.to_owned(), email.to_owned(), params.to_params())
+                .exec()
+                .await?;
+            system_audit_service::record(
+                &client,
+                "member",
+                data.id,
+                AuditAction::Create,
+                None,
+                Some(&data),
+            )
+            .await?;
+            if let Some(invite_code) = invite_code {
+                member_team_service::join_with(&client, invite_code, data.id).await?;
+            }
+            Ok(data)
+        })
         .await?
         .into())
 }
 
 pub async fn update(db: &Database, id: i32, params: UpdateParams) -> Result<Info> {
+    set(db, id, AuditAction::Update, params.to_params()).await
+}
+
+/// fields a member may change on its own profile
+pub async fn update_profile(db: &Database, id: i32, params: ProfileParams) -> Result<Info> {
+    set(db, id, AuditAction::Update, params.to_params()).await
+}
+
+pub async fn update_password(db: &Database, id: i32, params: PasswordParams) -> Result<Info> {
+    set(db, id, AuditAction::Update, params.to_params()).await
+}
+
+pub async fn delete(db: &Database, id: i32) -> Result<Info> {
+    set(
+        db,
+        id,
+        AuditAction::Delete,
+        vec![member::deleted_at::set(Some(now_time()))],
+    )
+    .await
+}
+
+/// update the member and audit it in one transaction
+async fn set(
+    db: &Database,
+    id: i32,
+    action: AuditAction,
+    params: Vec<member::UncheckedSetParam>,
+) -> Result<Info> {
     Ok(db
         .client
-        .member()
-        .update_unchecked(member::id::equals(id), params.to_params())
-        .exec()
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            let before = client
+                .member()
+                .find_unique(member::id::equals(id))
+                .exec()
+                .await?;
+            let data = client
+                .member()
+                .update_unchecked(member::id::equals(id), params)
+                .exec()
+                .await?;
+            system_audit_service::record(
+                &client,
+                "member",
+                data.id,
+                action,
+                before.as_ref(),
+                Some(&data),
+            )
+            .await?;
+            Ok(data)
+        })
         .await?
         .into())
 }
-pub async fn delete(db: &Database, id: i32) -> Result<Info> {
+pub async fn info(db: &Database, id: i32) -> Result<Info> {
     Ok(db
         .client
         .member()
-        .update(
-            member::id::equals(id),
-            vec![member::deleted_at::set(Some(now_time()))],
-        )
+        .find_unique(member::id::equals(id))
         .exec()
         .await?
+        .ok_or(ServiceError::DataNotFound)?
         .into())
 }
-pub async fn info(db: &Database, id: i32) -> Result<Info> {
+/// member of the id, unless deleted
+pub async fn get(db: &Database, id: i32) -> Result<Option<Info>> {
     Ok(db
         .client
         .member()
-        .find_unique(member::id::equals(id))
+        .find_first(vec![
+            member::id::equals(id),
+            member::deleted_at::equals(None),
+        ])
         .exec()
         .await?
-        .ok_or(ServiceError::DataNotFound)?
-        .into())
+        .map(|x| x.into()))
 }
 pub async fn get_by_unique_code(
     db: &Database,
@@ -130,119 +203,361 @@ pub async fn paginate(db: &Database, params: &SearchParams) -> Result<PaginateRe
     })
 }
 
+/// credit the member, the balance is billed as `balance_type` and pays the
+/// uplines their commissions in the same transaction
 pub async fn increment(
     db: &Database,
     user_id: i32,
+    balance_type: member_bill_service::BillType,
     balance: Option<BigDecimal>,
     integral: Option<i32>,
-) -> Result<Info> {
-    let result = db
-        .client
-        ._transaction()
-        .run::<ServiceError, _, _, _>(|_| async move {
-            let user = info(db, user_id).await?;
-            let mut params = UpdateParams {
-                email: None,
-                mobile: None,
-                nickname: None,
-                avatar: None,
-                password: None,
-                salt: None,
-                sex: None,
-                balance: None,
-                integral: None,
-                remark: None,
-                status: None,
-                is_promoter: None,
-            };
-            if let Some(balance_num) = balance {
-                params.balance = Some(user.balance.add(balance_num.clone()));
-                member_bill_service::create(
-                    db,
-                    user_id,
-                    member_bill_service::BillType::Balance,
-                    member_bill_service::CreateParams {
-                        pm: Some(member_bill_service::BillPm::Increment.into()),
-                        number: Some(balance_num),
-                    },
-                )
-                .await?;
-            }
-            if let Some(integral_num) = integral {
-                params.integral = Some(user.integral + integral_num);
-                member_bill_service::create(
-                    db,
-                    user_id,
-                    member_bill_service::BillType::Integral,
-                    member_bill_service::CreateParams {
-                        pm: Some(member_bill_service::BillPm::Increment.into()),
-                        number: Some(integral_num.try_into().unwrap()),
-                    },
-                )
-                .await?;
-            }
-            let result = update(db, user_id, params).await?;
-            Ok(result)
-        })
-        .await?;
-    Ok(result)
+    meta: member_bill_service::BillMeta,
+) -> Result<Posting> {
+    post(
+        db,
+        user_id,
+        member_bill_service::BillPm::Increment,
+        balance_type,
+        balance,
+        integral,
+        meta,
+    )
+    .await
 }
 
+/// debit the member, the balance is billed as `balance_type`; nothing is
+/// debited and `ServiceError::InsufficientFunds` is returned when the balance
+/// or the integral is short
 pub async fn decrement(
     db: &Database,
     user_id: i32,
+    balance_type: member_bill_service::BillType,
     balance: Option<BigDecimal>,
     integral: Option<i32>,
-) -> Result<Info> {
+    meta: member_bill_service::BillMeta,
+) -> Result<Posting> {
+    post(
+        db,
+        user_id,
+        member_bill_service::BillPm::Decrement,
+        balance_type,
+        balance,
+        integral,
+        meta,
+    )
+    .await
+}
+
+/// post in a transaction of its own; a posting with a posted idempotency key
+/// returns the bills posted first, flagged as replayed
+async fn post(
+    db: &Database,
+    user_id: i32,
+    pm: member_bill_service::BillPm,
+    balance_type: member_bill_service::BillType,
+    balance: Option<BigDecimal>,
+    integral: Option<i32>,
+    meta: member_bill_service::BillMeta,
+) -> Result<Posting> {
+    let key = meta.idempotency_key.clone();
+    let (posted_type, posted_balance) = (balance_type.clone(), balance.clone());
+    if let Some(bills) = posted(
+        db,
+        user_id,
+        &pm,
+        &posted_type,
+        posted_balance.as_ref(),
+        integral,
+        key.as_deref(),
+    )
+    .await?
+    {
+        return Ok(Posting {
+            member: info(db, user_id).await?,
+            bills,
+            replayed: true,
+        });
+    }
+    let posting_pm = pm.clone();
     let result = db
         .client
         ._transaction()
-        .run::<ServiceError, _, _, _>(|_| async move {
-            let user = info(db, user_id).await?;
-            let mut params = UpdateParams {
-                email: None,
-                mobile: None,
-                nickname: None,
-                avatar: None,
-                password: None,
-                salt: None,
-                sex: None,
-                balance: None,
-                integral: None,
-                remark: None,
-                status: None,
-                is_promoter: None,
-            };
-            if let Some(balance_num) = balance {
-                params.balance = Some(user.balance.sub(balance_num.clone()));
-                member_bill_service::create(
-                    db,
+        .run::<ServiceError, _, _, _>(|client| async move {
+            post_with(
+                &client,
+                user_id,
+                posting_pm,
+                balance_type,
+                balance,
+                integral,
+                &meta,
+            )
+            .await
+        })
+        .await;
+    let (bills, replayed) = match result {
+        Ok(bills) => (bills, false),
+        // a concurrent posting with the same key took the unique index first
+        Err(err) => match posted(
+            db,
+            user_id,
+            &pm,
+            &posted_type,
+            posted_balance.as_ref(),
+            integral,
+            key.as_deref(),
+        )
+        .await?
+        {
+            Some(bills) => (bills, true),
+            None => return Err(err),
+        },
+    };
+    Ok(Posting {
+        member: info(db, user_id).await?,
+        bills,
+        replayed,
+    })
+}
+
+/// post within the caller`s transaction: change the balance and integral in
+/// place, debits only when enough is left, and bill every change with the
+/// amount left after it; credited balance bills pay the uplines their
+/// commissions, so a failure anywhere rolls the whole posting back
+pub(crate) async fn post_with(
+    client: &PrismaClient,
+    user_id: i32,
+    pm: member_bill_service::BillPm,
+    balance_type: member_bill_service::BillType,
+    balance: Option<BigDecimal>,
+    integral: Option<i32>,
+    meta: &member_bill_service::BillMeta,
+) -> Result<Vec<member_bill_service::Info>> {
+    let mut bills = vec![];
+    if let Some(number) = balance {
+        let mut params = vec![member::id::equals(user_id)];
+        let change = match pm {
+            member_bill_service::BillPm::Increment => member::balance::increment(number.clone()),
+            member_bill_service::BillPm::Decrement => {
+                params.push(member::balance::gte(number.clone()));
+                member::balance::decrement(number.clone())
+            }
+        };
+        let user = change_member(client, user_id, params, change).await?;
+        bills.push(
+            client
+                .member_bill()
+                .create_unchecked(
                     user_id,
-                    member_bill_service::BillType::Integral,
-                    member_bill_service::CreateParams {
-                        pm: Some(member_bill_service::BillPm::Decrement.into()),
-                        number: Some(balance_num),
-                    },
+                    balance_type.into(),
+                    meta.to_create_params(pm.clone(), number, user.balance)
+                        .to_params(),
                 )
-                .await?;
+                .exec()
+                .await?,
+        );
+    }
+    if let Some(number) = integral {
+        let mut params = vec![member::id::equals(user_id)];
+        let change = match pm {
+            member_bill_service::BillPm::Increment => member::integral::increment(number),
+            member_bill_service::BillPm::Decrement => {
+                params.push(member::integral::gte(number));
+                member::integral::decrement(number)
             }
-            if let Some(integral_num) = integral {
-                params.integral = Some(user.integral - integral_num);
-                member_bill_service::create(
-                    db,
+        };
+        let user = change_member(client, user_id, params, change).await?;
+        bills.push(
+            client
+                .member_bill()
+                .create_unchecked(
                     user_id,
-                    member_bill_service::BillType::Integral,
-                    member_bill_service::CreateParams {
-                        pm: Some(member_bill_service::BillPm::Decrement.into()),
-                        number: Some(integral_num.try_into().unwrap()),
-                    },
+                    member_bill_service::BillType::Integral.into(),
+                    meta.to_create_params(pm.clone(), number.into(), user.integral.into())
+                        .to_params(),
                 )
-                .await?;
-            }
-            let result = update(db, user_id, params).await?;
-            Ok(result)
-        })
+                .exec()
+                .await?,
+        );
+    }
+    for bill in &bills {
+        system_audit_service::record(
+            client,
+            "member_bill",
+            bill.id,
+            AuditAction::Create,
+            None,
+            Some(bill),
+        )
+        .await?;
+    }
+    let bills = bills
+        .into_iter()
+        .map(|x| x.into())
+        .collect::<Vec<member_bill_service::Info>>();
+    for bill in bills
+        .iter()
+        .filter(|x| x.bill_type() != member_bill_service::BillType::Integral)
+    {
+        member_commission_service::distribute(client, bill).await?;
+    }
+    Ok(bills)
+}
+
+/// bills already posted with the idempotency key; the key may only be
+/// reused for the same posting, the same member, direction, bill type and
+/// amounts, anything else is an `IdempotencyConflict`
+async fn posted(
+    db: &Database,
+    user_id: i32,
+    pm: &member_bill_service::BillPm,
+    balance_type: &member_bill_service::BillType,
+    balance: Option<&BigDecimal>,
+    integral: Option<i32>,
+    key: Option<&str>,
+) -> Result<Option<Vec<member_bill_service::Info>>> {
+    let key = match key {
+        Some(key) => key,
+        None => return Ok(None),
+    };
+    let bills = db
+        .client
+        .member_bill()
+        .find_many(vec![member_bill::idempotency_key::equals(Some(
+            key.to_owned(),
+        ))])
+        .order_by(member_bill::id::order(SortOrder::Asc))
+        .exec()
+        .await?;
+    if bills.is_empty() {
+        return Ok(None);
+    }
+    // the bills the posting would make, as (type, number)
+    let mut expected = vec![];
+    if let Some(balance) = balance {
+        expected.push((i32::from(balance_type.clone()), balance.clone()));
+    }
+    if let Some(integral) = integral {
+        expected.push((
+            member_bill_service::BillType::Integral.into(),
+            BigDecimal::from(integral),
+        ));
+    }
+    let pm: i32 = pm.clone().into();
+    let same = bills.len() == expected.len()
+        && bills
+            .iter()
+            .zip(expected.iter())
+            .all(|(bill, (r#type, number))| {
+                bill.member_id == user_id
+                    && bill.pm == pm
+                    && bill.r#type == *r#type
+                    && &bill.number == number
+            });
+    if !same {
+        return Err(ServiceError::IdempotencyConflict);
+    }
+    Ok(Some(bills.into_iter().map(|x| x.into()).collect()))
+}
+
+/// update the member when it matches `params`, a member which exists but
+/// does not match has too little left
+async fn change_member(
+    client: &PrismaClient,
+    user_id: i32,
+    params: Vec<member::WhereParam>,
+    change: member::SetParam,
+) -> Result<member::Data> {
+    let count = client
+        .member()
+        .update_many(params, vec![change])
+        .exec()
         .await?;
+    let user = client
+        .member()
+        .find_unique(member::id::equals(user_id))
+        .exec()
+        .await?
+        .ok_or(ServiceError::DataNotFound)?;
+    if count == 0 {
+        return Err(ServiceError::InsufficientFunds);
+    }
+    Ok(user)
+}
+
+/// recompute every member's balance and integral from the bills, listing
+/// the members which differ; `fix` sets them to the recomputed amounts
+///
+/// run it while no bills are being made, the bills of the running
+/// transactions are not counted yet
+pub async fn reconcile(db: &Database, fix: bool) -> Result<Vec<Reconciliation>> {
+    let sums = db
+        .client
+        ._query_raw::<BillSum>(raw!(
+            "SELECT member_id, \
+             CAST(ROUND(COALESCE(SUM(CASE WHEN type <> {} THEN \
+             CASE WHEN pm = {} THEN number ELSE -number END END), 0), 2) AS TEXT) AS balance, \
+             CAST(COALESCE(SUM(CASE WHEN type = {} THEN \
+             CASE WHEN pm = {} THEN number ELSE -number END END), 0) AS INTEGER) AS integral \
+             FROM member_bills WHERE deleted_at IS NULL GROUP BY member_id",
+            PrismaValue::Int(i32::from(member_bill_service::BillType::Integral).into()),
+            PrismaValue::Int(i32::from(member_bill_service::BillPm::Increment).into()),
+            PrismaValue::Int(i32::from(member_bill_service::BillType::Integral).into()),
+            PrismaValue::Int(i32::from(member_bill_service::BillPm::Increment).into())
+        ))
+        .exec()
+        .await?
+        .into_iter()
+        .map(|x| (x.member_id, x))
+        .collect::<HashMap<i32, BillSum>>();
+    let mut result = vec![];
+    for user in db.client.member().find_many(vec![]).exec().await? {
+        let (balance, integral) = match sums.get(&user.id) {
+            Some(sum) => (
+                BigDecimal::from_str(&sum.balance).unwrap_or_default(),
+                sum.integral as i32,
+            ),
+            None => (BigDecimal::default(), 0),
+        };
+        if balance.with_scale(2) == user.balance.with_scale(2) && integral == user.integral {
+            continue;
+        }
+        if fix {
+            let (before, balance) = (&user, balance.clone());
+            db.client
+                ._transaction()
+                .run::<ServiceError, _, _, _>(|client| async move {
+                    let data = client
+                        .member()
+                        .update(
+                            member::id::equals(before.id),
+                            vec![
+                                member::balance::set(balance),
+                                member::integral::set(integral),
+                            ],
+                        )
+                        .exec()
+                        .await?;
+                    system_audit_service::record(
+                        &client,
+                        "member",
+                        data.id,
+                        AuditAction::Update,
+                        Some(before),
+                        Some(&data),
+                    )
+                    .await
+                })
+                .await?;
+        }
+        result.push(Reconciliation {
+            member_id: user.id,
+            balance: user.balance,
+            bill_balance: balance,
+            integral: user.integral,
+            bill_integral: integral,
+        });
+    }
     Ok(result)
 }
 
@@ -319,7 +634,7 @@ impl SearchParams {
         }
     }
 }
-#[derive(Debug, Serialize, Getters)]
+#[derive(Debug, Clone, Serialize, Getters)]
 pub struct Info {
     #[getset(get = "pub")]
     id: i32,
@@ -381,6 +696,34 @@ impl From<member::Data> for Info {
         }
     }
 }
+/// the member after a posting and the bills posted
+#[derive(Debug, Serialize)]
+pub struct Posting {
+    pub member: Info,
+    pub bills: Vec<member_bill_service::Info>,
+    /// the idempotency key was posted before, nothing changed this time
+    pub replayed: bool,
+}
+
+/// a member whose amounts differ from its bills
+#[derive(Debug, Serialize)]
+pub struct Reconciliation {
+    pub member_id: i32,
+    pub balance: BigDecimal,
+    /// balance summed from the bills
+    pub bill_balance: BigDecimal,
+    pub integral: i32,
+    /// integral summed from the bills
+    pub bill_integral: i32,
+}
+
+#[derive(Debug, Deserialize)]
+struct BillSum {
+    member_id: i32,
+    balance: String,
+    integral: i64,
+}
+
 member::partial_unchecked!(CreateParams {
     mobile
     nickname
@@ -388,8 +731,6 @@ member::partial_unchecked!(CreateParams {
     password
     salt
     sex
-    balance
-    integral
     remark
     status
     is_promoter
@@ -402,9 +743,17 @@ member::partial_unchecked!(UpdateParams {
     password
     salt
     sex
-    balance
-    integral
     remark
     status
     is_promoter
 });
+member::partial_unchecked!(ProfileParams {
+    mobile
+    nickname
+    avatar
+    sex
+});
+member::partial_unchecked!(PasswordParams {
+    password
+    salt
+});
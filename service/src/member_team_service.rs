@@ -1,15 +1,29 @@
 use crate::{
     member_service,
-    prisma::{member, member_team, SortOrder},
+    prisma::{member, member_team, PrismaClient, SortOrder},
+    system_audit_service::{self, AuditAction},
     Database, Result, ServiceError,
 };
-use prisma_client_rust::{or, prisma_models::parse_datetime};
-use serde::Serialize;
+use prisma_client_rust::{
+    bigdecimal::BigDecimal,
+    chrono::{DateTime, Duration, FixedOffset},
+    or,
+    prisma_models::parse_datetime,
+    raw, PrismaValue, Raw,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, str::FromStr};
 use utils::{
     datetime::to_local_string,
     paginate::{PaginateParams, PaginateResult},
+    tree::{vec_to_tree_into, Tree, TreeInfo},
 };
 
+/// deepest downline levels loaded at once
+const MAX_DEPTH: i32 = 3;
+/// most members loaded on a downline level at once
+const LEVEL_NODES: i64 = 500;
+
 pub async fn create(
     db: &Database,
     owner_uid: i32,
@@ -19,12 +33,185 @@ pub async fn create(
 ) -> Result<Info> {
     Ok(db
         .client
-        .member_team()
-        .create_unchecked(owner_uid, parent_uid, uid, params.to_params())
-        .exec()
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            let data = client
+                .member_team()
+                .create_unchecked(owner_uid, parent_uid, uid, params.to_params())
+                .exec()
+                .await?;
+            system_audit_service::record(
+                &client,
+                "member_team",
+                data.id,
+                AuditAction::Create,
+                None,
+                Some(&data),
+            )
+            .await?;
+            Ok(data)
+        })
         .await?
         .into())
 }
+
+/// why an invite is refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InviteRefusal {
+    /// no active member has the invite code
+    NotFound,
+    /// the inviter has no promotion right
+    NotPromoter,
+    /// the member already joined a team
+    Joined,
+    /// the inviter is the member itself or in its team
+    Cycle,
+}
+
+/// the inviter of the code must be an active promoter, and a member who
+/// already has a team may not join its own team
+pub async fn check_invite(
+    db: &Database,
+    invite_code: &str,
+    member_id: Option<i32>,
+) -> Result<Option<InviteRefusal>> {
+    Ok(get_inviter(&db.client, invite_code, member_id).await?.err())
+}
+
+/// join the member to the team of the inviter, the member is added to the
+/// team of every ancestor of the inviter and so is its own team; the check
+/// and the rows share one transaction
+pub async fn join(
+    db: &Database,
+    invite_code: &str,
+    member_id: i32,
+) -> Result<Option<InviteRefusal>> {
+    let invite_code = invite_code.to_owned();
+    let joined = db
+        .client
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            join_with(&client, &invite_code, member_id).await
+        })
+        .await;
+    match joined {
+        Ok(()) => Ok(None),
+        Err(ServiceError::InviteRefused(refusal)) => Ok(Some(refusal)),
+        Err(err) => Err(err),
+    }
+}
+
+/// `join` within the caller`s transaction, a refused invite is an
+/// `InviteRefused` error so the transaction rolls back
+pub(crate) async fn join_with(
+    client: &PrismaClient,
+    invite_code: &str,
+    member_id: i32,
+) -> Result<()> {
+    let inviter_id = get_inviter(client, invite_code, Some(member_id))
+        .await?
+        .map_err(ServiceError::InviteRefused)?;
+    // teams the member joins, with the level of the member in them
+    let mut owners = vec![(inviter_id, 1)];
+    owners.extend(
+        client
+            .member_team()
+            .find_many(vec![
+                member_team::member_id::equals(inviter_id),
+                member_team::deleted_at::equals(None),
+            ])
+            .exec()
+            .await?
+            .into_iter()
+            .map(|x| (x.owner_uid, x.level + 1)),
+    );
+    // the member and its own team, with their parent and level below the member
+    let mut members = vec![(member_id, inviter_id, 0)];
+    members.extend(
+        client
+            .member_team()
+            .find_many(vec![
+                member_team::owner_uid::equals(member_id),
+                member_team::deleted_at::equals(None),
+            ])
+            .exec()
+            .await?
+            .into_iter()
+            .map(|x| (x.member_id, x.parent_uid, x.level)),
+    );
+    let rows = owners
+        .iter()
+        .flat_map(|(owner_uid, owner_level)| {
+            members.iter().map(|(uid, parent_uid, level)| {
+                member_team::create_unchecked(
+                    *owner_uid,
+                    *parent_uid,
+                    *uid,
+                    vec![member_team::level::set(owner_level + level)],
+                )
+            })
+        })
+        .collect::<Vec<member_team::CreateUnchecked>>();
+    client.member_team().create_many(rows).exec().await?;
+    system_audit_service::record(
+        client,
+        "member",
+        member_id,
+        AuditAction::Update,
+        Some(&serde_json::json!({ "inviter_id": null })),
+        Some(&serde_json::json!({ "inviter_id": inviter_id })),
+    )
+    .await
+}
+
+async fn get_inviter(
+    client: &PrismaClient,
+    invite_code: &str,
+    member_id: Option<i32>,
+) -> Result<std::result::Result<i32, InviteRefusal>> {
+    let inviter = match client
+        .member()
+        .find_first(vec![
+            member::unique_code::equals(invite_code.trim().to_owned()),
+            member::deleted_at::equals(None),
+        ])
+        .exec()
+        .await?
+    {
+        // 1.正常
+        Some(inviter) if inviter.status == 1 => inviter,
+        _ => return Ok(Err(InviteRefusal::NotFound)),
+    };
+    if inviter.is_promoter != 1 {
+        return Ok(Err(InviteRefusal::NotPromoter));
+    }
+    let member_id = match member_id {
+        Some(member_id) => member_id,
+        None => return Ok(Ok(inviter.id)),
+    };
+    if inviter.id == member_id {
+        return Ok(Err(InviteRefusal::Cycle));
+    }
+    let (joined, below) = client
+        ._batch((
+            client.member_team().count(vec![
+                member_team::member_id::equals(member_id),
+                member_team::deleted_at::equals(None),
+            ]),
+            client.member_team().count(vec![
+                member_team::owner_uid::equals(member_id),
+                member_team::member_id::equals(inviter.id),
+                member_team::deleted_at::equals(None),
+            ]),
+        ))
+        .await?;
+    Ok(match (joined > 0, below > 0) {
+        (true, _) => Err(InviteRefusal::Joined),
+        (false, true) => Err(InviteRefusal::Cycle),
+        (false, false) => Ok(inviter.id),
+    })
+}
+
 pub async fn info(db: &Database, id: i32) -> Result<Info> {
     Ok(db
         .client
@@ -57,6 +244,184 @@ pub async fn paginate(db: &Database, params: &SearchParams) -> Result<PaginateRe
     })
 }
 
+/// downline of the member as a tree, `depth` levels deep; the first level is
+/// paged by `page`, the levels below are loaded under the nodes above them, at
+/// most `LEVEL_NODES` a level, and `has_more` tells a level was cut; a node with
+/// a team but fewer children loads the rest by asking for its own downline
+pub async fn downline(
+    db: &Database,
+    member_id: i32,
+    depth: i32,
+    page: i64,
+) -> Result<DownlinePage> {
+    let depth = depth.clamp(1, MAX_DEPTH);
+    let mut data = vec![];
+    let mut parents = vec![member_id];
+    let mut has_more = false;
+    for level in 1..=depth {
+        let skip = match level {
+            1 => (page.max(1) - 1) * LEVEL_NODES,
+            _ => 0,
+        };
+        let mut nodes = db
+            .client
+            .member_team()
+            .find_many(vec![
+                member_team::owner_uid::equals(member_id),
+                member_team::level::equals(level),
+                member_team::parent_uid::in_vec(parents),
+                member_team::deleted_at::equals(None),
+            ])
+            .with(member_team::member::fetch())
+            .order_by(member_team::id::order(SortOrder::Asc))
+            .skip(skip)
+            .take(LEVEL_NODES + 1)
+            .exec()
+            .await?;
+        if nodes.len() as i64 > LEVEL_NODES {
+            nodes.truncate(LEVEL_NODES as usize);
+            has_more = true;
+        }
+        if nodes.is_empty() {
+            break;
+        }
+        parents = nodes.iter().map(|x| x.member_id).collect();
+        data.extend(nodes);
+    }
+    // only the loaded nodes are counted; the ids are integers read above, put
+    // in the sql as they are so a full tree stays within the bind limit
+    let team_sizes = match data.is_empty() {
+        true => HashMap::new(),
+        false => db
+            .client
+            ._query_raw::<TeamSize>(Raw::new(
+                &format!(
+                    "SELECT owner_uid, COUNT(*) AS members FROM member_teams \
+                     WHERE deleted_at IS NULL AND owner_uid IN ({}) GROUP BY owner_uid",
+                    data.iter()
+                        .map(|x| x.member_id.to_string())
+                        .collect::<Vec<String>>()
+                        .join(",")
+                ),
+                vec![],
+            ))
+            .exec()
+            .await?
+            .into_iter()
+            .map(|x| (x.owner_uid, x.members))
+            .collect::<HashMap<i32, i64>>(),
+    };
+    let infos = data
+        .into_iter()
+        .map(|x| DownlineInfo {
+            member_id: x.member_id,
+            parent_uid: x.parent_uid,
+            level: x.level,
+            team_size: team_sizes.get(&x.member_id).copied().unwrap_or_default(),
+            member: x.member().ok().map(|x| x.clone().into()),
+            joined_at: to_local_string(x.created_at),
+        })
+        .collect::<Vec<DownlineInfo>>();
+    Ok(DownlinePage {
+        data: vec_to_tree_into::<Downline, DownlineInfo>(&member_id, &infos),
+        has_more,
+    })
+}
+
+/// teams the member is in, from the inviter up
+pub async fn upline(db: &Database, member_id: i32) -> Result<Vec<Info>> {
+    Ok(db
+        .client
+        .member_team()
+        .find_many(vec![
+            member_team::member_id::equals(member_id),
+            member_team::deleted_at::equals(None),
+        ])
+        .with(member_team::owner::fetch())
+        .order_by(member_team::level::order(SortOrder::Asc))
+        .exec()
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect::<Vec<Info>>())
+}
+
+/// members on every level of the team
+pub async fn level_counts(db: &Database, member_id: i32) -> Result<Vec<LevelCount>> {
+    Ok(db
+        .client
+        ._query_raw::<LevelCount>(raw!(
+            "SELECT level, COUNT(*) AS members FROM member_teams \
+             WHERE owner_uid = {} AND deleted_at IS NULL \
+             GROUP BY level ORDER BY level",
+            PrismaValue::Int(member_id.into())
+        ))
+        .exec()
+        .await?)
+}
+
+/// team size, balance and integral of the team, with the members who joined
+/// between the dates, both included
+pub async fn stats(
+    db: &Database,
+    member_id: i32,
+    start_date: Option<DateTime<FixedOffset>>,
+    end_date: Option<DateTime<FixedOffset>>,
+) -> Result<TeamStats> {
+    let mut joined_params = vec![
+        member_team::owner_uid::equals(member_id),
+        member_team::deleted_at::equals(None),
+    ];
+    if let Some(start_date) = start_date {
+        joined_params.push(member_team::created_at::gte(start_date));
+    }
+    if let Some(end_date) = end_date {
+        joined_params.push(member_team::created_at::lt(end_date + Duration::days(1)));
+    }
+    let (members, direct_members, new_members) = db
+        .client
+        ._batch((
+            db.client.member_team().count(vec![
+                member_team::owner_uid::equals(member_id),
+                member_team::deleted_at::equals(None),
+            ]),
+            db.client.member_team().count(vec![
+                member_team::owner_uid::equals(member_id),
+                member_team::level::equals(1),
+                member_team::deleted_at::equals(None),
+            ]),
+            db.client.member_team().count(joined_params),
+        ))
+        .await?;
+    // sums are read as text, sqlite may return integers or floats for decimals
+    let sum = db
+        .client
+        ._query_raw::<TeamSum>(raw!(
+            "SELECT CAST(COALESCE(SUM(m.balance), 0) AS TEXT) AS balance, \
+             CAST(COALESCE(SUM(m.integral), 0) AS TEXT) AS integral \
+             FROM member_teams t INNER JOIN members m ON m.id = t.member_id \
+             WHERE t.owner_uid = {} AND t.deleted_at IS NULL AND m.deleted_at IS NULL",
+            PrismaValue::Int(member_id.into())
+        ))
+        .exec()
+        .await?
+        .pop();
+    let (balance, integral) = match sum {
+        Some(sum) => (
+            BigDecimal::from_str(&sum.balance).unwrap_or_default(),
+            sum.integral.parse::<i64>().unwrap_or_default(),
+        ),
+        None => (BigDecimal::default(), 0),
+    };
+    Ok(TeamStats {
+        members,
+        direct_members,
+        new_members,
+        balance,
+        integral,
+    })
+}
+
 pub struct SearchParams {
     keyword: Option<String>,
     date: Option<String>,
@@ -132,3 +497,88 @@ impl From<member_team::Data> for Info {
     }
 }
 member_team::partial_unchecked!(CreateParams { level });
+
+/// member in a downline
+#[derive(Debug, Clone, Serialize)]
+pub struct DownlineInfo {
+    member_id: i32,
+    parent_uid: i32,
+    /// level below the member whose downline is loaded
+    level: i32,
+    /// members in its own team, more to load when it has no children
+    team_size: i64,
+    member: Option<member_service::Info>,
+    joined_at: String,
+}
+
+impl TreeInfo for DownlineInfo {
+    fn get_parent_id(&self) -> i32 {
+        self.parent_uid
+    }
+
+    fn get_id(&self) -> i32 {
+        self.member_id
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Downline {
+    #[serde(flatten)]
+    info: DownlineInfo,
+    children: Vec<Downline>,
+}
+
+impl From<DownlineInfo> for Downline {
+    fn from(value: DownlineInfo) -> Self {
+        Self {
+            info: value,
+            children: vec![],
+        }
+    }
+}
+
+impl Tree<Downline> for Downline {
+    fn set_child(&mut self, data: Vec<Downline>) {
+        self.children = data;
+    }
+}
+
+/// one page of a downline
+#[derive(Debug, Serialize)]
+pub struct DownlinePage {
+    data: Vec<Downline>,
+    /// a level had more members than loaded
+    has_more: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LevelCount {
+    level: i32,
+    members: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TeamStats {
+    /// members in the team
+    members: i64,
+    /// members invited by the member itself
+    direct_members: i64,
+    /// members who joined within the dates
+    new_members: i64,
+    /// balance of all members in the team
+    balance: BigDecimal,
+    /// integral of all members in the team
+    integral: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct TeamSize {
+    owner_uid: i32,
+    members: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct TeamSum {
+    balance: String,
+    integral: String,
+}
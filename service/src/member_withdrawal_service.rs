@@ -0,0 +1,667 @@
+use crate::{
+    member_bill_service::{BillMeta, BillPm, BillSource, BillType},
+    member_service,
+    payment_service::{self, PaymentGateway, PayoutOrder, PayoutState},
+    prisma::{member_withdrawal, PrismaClient, SortOrder},
+    system_audit_service::{self, AuditAction},
+    Database, Result, ServiceError,
+};
+use prisma_client_rust::{bigdecimal::BigDecimal, chrono::Duration};
+use serde::Serialize;
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use utils::{
+    datetime::{now_time, to_local_string},
+    paginate::{PaginateParams, PaginateResult},
+};
+
+/// payouts left paying for the minutes lost their result, the gateway is asked
+const PAYING_TIMEOUT: i64 = 10;
+
+/// apply for a withdrawal, the amount is debited from the balance in the
+/// transaction recording it; nothing is recorded when the balance is short
+pub async fn apply(
+    db: &Database,
+    member_id: i32,
+    amount: BigDecimal,
+    account: &str,
+    account_name: &str,
+) -> Result<Info> {
+    let (account, account_name) = (account.to_owned(), account_name.to_owned());
+    Ok(db
+        .client
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            let data = client
+                .member_withdrawal()
+                .create_unchecked(
+                    payment_service::generate_order_no("W"),
+                    member_id,
+                    amount,
+                    account,
+                    account_name,
+                    vec![],
+                )
+                .exec()
+                .await?;
+            system_audit_service::record(
+                &client,
+                "member_withdrawal",
+                data.id,
+                AuditAction::Create,
+                None,
+                Some(&data),
+            )
+            .await?;
+            member_service::post_with(
+                &client,
+                member_id,
+                BillPm::Decrement,
+                BillType::Withdraw,
+                Some(data.amount.clone()),
+                None,
+                &BillMeta {
+                    source: BillSource::Member,
+                    order_id: data.order_no.clone(),
+                    idempotency_key: Some(format!("member_withdrawal:{}", data.order_no)),
+                    ..Default::default()
+                },
+            )
+            .await?;
+            Ok(data)
+        })
+        .await?
+        .into())
+}
+
+/// approve a pending withdrawal and pay it out; `None` when it is no longer
+/// pending
+pub async fn approve(
+    db: &Database,
+    gateway: &dyn PaymentGateway,
+    id: i32,
+    reviewed_by: i32,
+    review_remark: &str,
+) -> Result<Option<Info>> {
+    let approved = transition(
+        db,
+        id,
+        &[WithdrawalStatus::Pending],
+        vec![
+            member_withdrawal::status::set(WithdrawalStatus::Approved.into()),
+            member_withdrawal::reviewed_by::set(reviewed_by),
+            member_withdrawal::reviewed_at::set(Some(now_time())),
+            member_withdrawal::review_remark::set(review_remark.to_owned()),
+        ],
+    )
+    .await?;
+    if approved.is_none() {
+        return Ok(None);
+    }
+    payout(db, gateway, id).await
+}
+
+/// pay an approved withdrawal out at the gateway; `None` when it is not
+/// waiting for the payout, a failed payout waits again with its error
+pub async fn payout(db: &Database, gateway: &dyn PaymentGateway, id: i32) -> Result<Option<Info>> {
+    // claim the payout, paying twice must never happen
+    let data = match transition(
+        db,
+        id,
+        &[WithdrawalStatus::Approved],
+        vec![
+            member_withdrawal::status::set(WithdrawalStatus::Paying.into()),
+            member_withdrawal::gateway::set(gateway.name().to_owned()),
+        ],
+    )
+    .await?
+    {
+        Some(data) => data,
+        None => return Ok(None),
+    };
+    let result = gateway
+        .payout(&PayoutOrder {
+            order_no: data.order_no.clone(),
+            amount: data.amount.clone(),
+            account: data.account.clone(),
+            account_name: data.account_name.clone(),
+        })
+        .await;
+    let params = match &result {
+        Ok(trade_no) => vec![
+            member_withdrawal::status::set(WithdrawalStatus::Paid.into()),
+            member_withdrawal::trade_no::set(trade_no.clone()),
+            member_withdrawal::last_error::set(String::new()),
+            member_withdrawal::paid_at::set(Some(now_time())),
+        ],
+        Err(err) => {
+            let err = match err {
+                ServiceError::Payment(err) => err.clone(),
+                err => format!("{:?}", err),
+            };
+            tracing::warn!("withdrawal {} not paid out: {}", data.order_no, err);
+            vec![
+                member_withdrawal::status::set(WithdrawalStatus::Approved.into()),
+                member_withdrawal::last_error::set(err),
+            ]
+        }
+    };
+    let data = update(db, &data, params).await?;
+    result?;
+    Ok(Some(data.into()))
+}
+
+/// reject a pending withdrawal and refund its amount in the same
+/// transaction; `None` when it is no longer pending. An approved withdrawal
+/// may have reached the gateway, it is only refunded through `refund`
+pub async fn reject(
+    db: &Database,
+    id: i32,
+    reviewed_by: i32,
+    review_remark: &str,
+) -> Result<Option<Info>> {
+    close_refunded(
+        db,
+        id,
+        WithdrawalStatus::Pending,
+        reviewed_by,
+        review_remark,
+    )
+    .await
+}
+
+/// refund an approved withdrawal whose payout failed, once the gateway
+/// confirms it paid nothing; `None` when it has no failed payout, or the
+/// gateway paid or still pays it. A payout found paid is recorded so
+pub async fn refund(
+    db: &Database,
+    gateway: &dyn PaymentGateway,
+    id: i32,
+    reviewed_by: i32,
+    review_remark: &str,
+) -> Result<Option<Info>> {
+    let data = db
+        .client
+        .member_withdrawal()
+        .find_unique(member_withdrawal::id::equals(id))
+        .exec()
+        .await?
+        .ok_or(ServiceError::DataNotFound)?;
+    if WithdrawalStatus::from(data.status) != WithdrawalStatus::Approved
+        || data.last_error.is_empty()
+    {
+        return Ok(None);
+    }
+    if data.gateway != gateway.name() {
+        return Err(ServiceError::Payment(format!(
+            "withdrawal {} was paid out through {}",
+            data.order_no, data.gateway
+        )));
+    }
+    match gateway.query_payout(&data.order_no).await? {
+        PayoutState::NotPaid => {
+            close_refunded(
+                db,
+                id,
+                WithdrawalStatus::Approved,
+                reviewed_by,
+                review_remark,
+            )
+            .await
+        }
+        PayoutState::Paid(trade_no) => {
+            settle_paid(db, id, WithdrawalStatus::Approved, trade_no).await?;
+            Ok(None)
+        }
+        PayoutState::Processing => Ok(None),
+    }
+}
+
+/// settle the withdrawals left paying for `PAYING_TIMEOUT`, the result of
+/// their payout was lost; the gateway tells whether they were paid, the
+/// unpaid ones wait for the payout again. Returns how many were settled
+pub async fn recover(db: &Database, gateway: &dyn PaymentGateway) -> Result<i64> {
+    let stale = db
+        .client
+        .member_withdrawal()
+        .find_many(vec![
+            member_withdrawal::status::equals(WithdrawalStatus::Paying.into()),
+            member_withdrawal::gateway::equals(gateway.name().to_owned()),
+            member_withdrawal::updated_at::lt(now_time() - Duration::minutes(PAYING_TIMEOUT)),
+        ])
+        .exec()
+        .await?;
+    let mut settled = 0;
+    for data in stale {
+        let result = match gateway.query_payout(&data.order_no).await {
+            Ok(PayoutState::Paid(trade_no)) => {
+                settle_paid(db, data.id, WithdrawalStatus::Paying, trade_no).await?
+            }
+            Ok(PayoutState::NotPaid) => {
+                transition(
+                    db,
+                    data.id,
+                    &[WithdrawalStatus::Paying],
+                    vec![
+                        member_withdrawal::status::set(WithdrawalStatus::Approved.into()),
+                        member_withdrawal::last_error::set(
+                            "payout not found at the gateway".to_owned(),
+                        ),
+                    ],
+                )
+                .await?
+            }
+            Ok(PayoutState::Processing) => None,
+            Err(err) => {
+                tracing::warn!("withdrawal {} payout not queried: {:?}", data.order_no, err);
+                None
+            }
+        };
+        if result.is_some() {
+            settled += 1;
+        }
+    }
+    Ok(settled)
+}
+
+/// recover on every interval
+pub async fn run(db: &Database, gateway: &dyn PaymentGateway, interval: std::time::Duration) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        match recover(db, gateway).await {
+            Ok(0) => {}
+            Ok(count) => tracing::info!("{} member withdrawals recovered", count),
+            Err(err) => tracing::error!("member withdrawal recover failed: {:?}", err),
+        }
+    }
+}
+
+/// close the withdrawal in the status as rejected and refund its amount in
+/// the same transaction; `None` when it left the status
+async fn close_refunded(
+    db: &Database,
+    id: i32,
+    status: WithdrawalStatus,
+    reviewed_by: i32,
+    review_remark: &str,
+) -> Result<Option<Info>> {
+    let review_remark = review_remark.to_owned();
+    Ok(db
+        .client
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            let data = match transition_with(
+                &client,
+                id,
+                &[status],
+                vec![
+                    member_withdrawal::status::set(WithdrawalStatus::Rejected.into()),
+                    member_withdrawal::reviewed_by::set(reviewed_by),
+                    member_withdrawal::reviewed_at::set(Some(now_time())),
+                    member_withdrawal::review_remark::set(review_remark.clone()),
+                ],
+            )
+            .await?
+            {
+                Some(data) => data,
+                None => return Ok(None),
+            };
+            member_service::post_with(
+                &client,
+                data.member_id,
+                BillPm::Increment,
+                BillType::Withdraw,
+                Some(data.amount.clone()),
+                None,
+                &BillMeta {
+                    source: BillSource::Admin,
+                    operator_id: reviewed_by,
+                    order_id: data.order_no.clone(),
+                    remark: review_remark,
+                    idempotency_key: Some(format!("member_withdrawal:{}:refund", data.order_no)),
+                },
+            )
+            .await?;
+            Ok(Some(data))
+        })
+        .await?
+        .map(|x| x.into()))
+}
+
+/// record the payout the gateway made while the withdrawal was in the status
+async fn settle_paid(
+    db: &Database,
+    id: i32,
+    status: WithdrawalStatus,
+    trade_no: String,
+) -> Result<Option<member_withdrawal::Data>> {
+    transition(
+        db,
+        id,
+        &[status],
+        vec![
+            member_withdrawal::status::set(WithdrawalStatus::Paid.into()),
+            member_withdrawal::trade_no::set(trade_no),
+            member_withdrawal::last_error::set(String::new()),
+            member_withdrawal::paid_at::set(Some(now_time())),
+        ],
+    )
+    .await
+}
+
+/// move the withdrawal on when it is in one of the statuses, audited in the
+/// same transaction
+async fn transition(
+    db: &Database,
+    id: i32,
+    statuses: &[WithdrawalStatus],
+    params: Vec<member_withdrawal::SetParam>,
+) -> Result<Option<member_withdrawal::Data>> {
+    let statuses = statuses.to_vec();
+    db.client
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            transition_with(&client, id, &statuses, params).await
+        })
+        .await
+}
+
+/// `transition` within the caller`s transaction
+async fn transition_with(
+    client: &PrismaClient,
+    id: i32,
+    statuses: &[WithdrawalStatus],
+    params: Vec<member_withdrawal::SetParam>,
+) -> Result<Option<member_withdrawal::Data>> {
+    let statuses = statuses.iter().map(|x| (*x).into()).collect::<Vec<i32>>();
+    let before = client
+        .member_withdrawal()
+        .find_unique(member_withdrawal::id::equals(id))
+        .exec()
+        .await?
+        .ok_or(ServiceError::DataNotFound)?;
+    let claimed = client
+        .member_withdrawal()
+        .update_many(
+            vec![
+                member_withdrawal::id::equals(id),
+                member_withdrawal::status::in_vec(statuses),
+            ],
+            params,
+        )
+        .exec()
+        .await?;
+    if claimed == 0 {
+        return Ok(None);
+    }
+    let data = client
+        .member_withdrawal()
+        .find_unique(member_withdrawal::id::equals(id))
+        .exec()
+        .await?
+        .ok_or(ServiceError::DataNotFound)?;
+    system_audit_service::record(
+        client,
+        "member_withdrawal",
+        data.id,
+        AuditAction::Update,
+        Some(&before),
+        Some(&data),
+    )
+    .await?;
+    Ok(Some(data))
+}
+
+async fn update(
+    db: &Database,
+    before: &member_withdrawal::Data,
+    params: Vec<member_withdrawal::SetParam>,
+) -> Result<member_withdrawal::Data> {
+    db.client
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            let data = client
+                .member_withdrawal()
+                .update(member_withdrawal::id::equals(before.id), params)
+                .exec()
+                .await?;
+            system_audit_service::record(
+                &client,
+                "member_withdrawal",
+                data.id,
+                AuditAction::Update,
+                Some(before),
+                Some(&data),
+            )
+            .await?;
+            Ok(data)
+        })
+        .await
+}
+
+pub async fn info(db: &Database, id: i32) -> Result<Info> {
+    Ok(db
+        .client
+        .member_withdrawal()
+        .find_unique(member_withdrawal::id::equals(id))
+        .exec()
+        .await?
+        .ok_or(ServiceError::DataNotFound)?
+        .into())
+}
+
+pub async fn paginate(db: &Database, params: &SearchParams) -> Result<PaginateResult<Vec<Info>>> {
+    let (data, total) = db
+        .client
+        ._batch((
+            db.client
+                .member_withdrawal()
+                .find_many(params.to_params())
+                .skip(params.paginate.get_skip())
+                .take(params.paginate.get_limit())
+                .order_by(member_withdrawal::id::order(SortOrder::Desc)),
+            db.client.member_withdrawal().count(params.to_params()),
+        ))
+        .await?;
+    Ok(PaginateResult {
+        total,
+        data: data.into_iter().map(|x| x.into()).collect::<Vec<Info>>(),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize_repr, Deserialize_repr)]
+#[repr(i32)]
+pub enum WithdrawalStatus {
+    /// 1.待审核
+    Pending = 1,
+    /// 2.待打款
+    Approved = 2,
+    /// 3.打款中
+    Paying = 3,
+    /// 4.已打款
+    Paid = 4,
+    /// 5.已驳回
+    Rejected = 5,
+}
+
+impl From<i32> for WithdrawalStatus {
+    fn from(value: i32) -> Self {
+        match value {
+            1 => Self::Pending,
+            2 => Self::Approved,
+            3 => Self::Paying,
+            4 => Self::Paid,
+            5 => Self::Rejected,
+            _ => Self::Pending,
+        }
+    }
+}
+
+impl From<WithdrawalStatus> for i32 {
+    fn from(value: WithdrawalStatus) -> Self {
+        match value {
+            WithdrawalStatus::Pending => 1,
+            WithdrawalStatus::Approved => 2,
+            WithdrawalStatus::Paying => 3,
+            WithdrawalStatus::Paid => 4,
+            WithdrawalStatus::Rejected => 5,
+        }
+    }
+}
+
+pub struct SearchParams {
+    member_id: Option<i32>,
+    order_no: Option<String>,
+    status: Option<WithdrawalStatus>,
+    paginate: PaginateParams,
+}
+
+impl SearchParams {
+    fn to_params(&self) -> Vec<member_withdrawal::WhereParam> {
+        let mut params = vec![];
+        if let Some(member_id) = self.member_id {
+            params.push(member_withdrawal::member_id::equals(member_id));
+        }
+        if let Some(order_no) = &self.order_no {
+            params.push(member_withdrawal::order_no::contains(order_no.to_string()));
+        }
+        if let Some(status) = self.status {
+            params.push(member_withdrawal::status::equals(status.into()));
+        }
+        params
+    }
+
+    pub fn new(
+        member_id: Option<i32>,
+        order_no: Option<String>,
+        status: Option<WithdrawalStatus>,
+        paginate: PaginateParams,
+    ) -> Self {
+        Self {
+            member_id,
+            order_no,
+            status,
+            paginate,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Info {
+    id: i32,
+    order_no: String,
+    member_id: i32,
+    amount: BigDecimal,
+    account: String,
+    account_name: String,
+    status: WithdrawalStatus,
+    reviewed_by: i32,
+    reviewed_at: Option<String>,
+    review_remark: String,
+    gateway: String,
+    trade_no: String,
+    last_error: String,
+    paid_at: Option<String>,
+    created_at: String,
+}
+
+impl From<member_withdrawal::Data> for Info {
+    fn from(value: member_withdrawal::Data) -> Self {
+        Self {
+            id: value.id,
+            order_no: value.order_no,
+            member_id: value.member_id,
+            amount: value.amount,
+            account: value.account,
+            account_name: value.account_name,
+            status: value.status.into(),
+            reviewed_by: value.reviewed_by,
+            reviewed_at: value.reviewed_at.map(to_local_string),
+            review_remark: value.review_remark,
+            gateway: value.gateway,
+            trade_no: value.trade_no,
+            last_error: value.last_error,
+            paid_at: value.paid_at.map(to_local_string),
+            created_at: to_local_string(value.created_at),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prisma::member;
+
+    async fn balance(db: &Database, member_id: i32) -> BigDecimal {
+        db.client
+            .member()
+            .find_unique(member::id::equals(member_id))
+            .exec()
+            .await
+            .unwrap()
+            .unwrap()
+            .balance
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database"]
+    async fn rejected_refunds_once() {
+        let db = Database::new(crate::DatabaseConfig::default())
+            .await
+            .unwrap();
+        let code = format!("test{}", fastrand::u32(..));
+        let member_id = db
+            .client
+            .member()
+            .create(
+                code.clone(),
+                format!("{}@example.com", code),
+                vec![member::balance::set(BigDecimal::from(100))],
+            )
+            .exec()
+            .await
+            .unwrap()
+            .id;
+        let data = apply(&db, member_id, BigDecimal::from(30), "account", "name")
+            .await
+            .unwrap();
+        assert_eq!(balance(&db, member_id).await, BigDecimal::from(70));
+        assert!(reject(&db, data.id, 1, "").await.unwrap().is_some());
+        assert!(reject(&db, data.id, 1, "").await.unwrap().is_none());
+        assert_eq!(balance(&db, member_id).await, BigDecimal::from(100));
+        // nothing failed at the gateway, nothing is refunded again
+        let gateway = payment_service::MockGateway::new("secret", "");
+        assert!(refund(&db, &gateway, data.id, 1, "")
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(balance(&db, member_id).await, BigDecimal::from(100));
+
+        // a failed payout the gateway never made is refunded once
+        let data = apply(&db, member_id, BigDecimal::from(30), "account", "name")
+            .await
+            .unwrap();
+        db.client
+            .member_withdrawal()
+            .update(
+                member_withdrawal::id::equals(data.id),
+                vec![
+                    member_withdrawal::status::set(WithdrawalStatus::Approved.into()),
+                    member_withdrawal::gateway::set(gateway.name().to_owned()),
+                    member_withdrawal::last_error::set("timeout".to_owned()),
+                ],
+            )
+            .exec()
+            .await
+            .unwrap();
+        assert!(refund(&db, &gateway, data.id, 1, "")
+            .await
+            .unwrap()
+            .is_some());
+        assert!(refund(&db, &gateway, data.id, 1, "")
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(balance(&db, member_id).await, BigDecimal::from(100));
+    }
+}
@@ -0,0 +1,221 @@
+use crate::{Result, ServiceError};
+use prisma_client_rust::bigdecimal::BigDecimal;
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+use utils::datetime::now_time;
+
+/// recharge to be paid by the member
+#[derive(Debug, Clone)]
+pub struct PaymentOrder {
+    pub order_no: String,
+    pub amount: BigDecimal,
+    pub subject: String,
+}
+
+/// payment reported by a verified gateway callback
+#[derive(Debug, Clone)]
+pub struct PaymentNotice {
+    pub order_no: String,
+    pub trade_no: String,
+    pub amount: BigDecimal,
+}
+
+/// withdrawal to be paid out to the member`s account
+#[derive(Debug, Clone)]
+pub struct PayoutOrder {
+    pub order_no: String,
+    pub amount: BigDecimal,
+    pub account: String,
+    pub account_name: String,
+}
+
+/// what the gateway knows of a payout
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PayoutState {
+    /// paid, with the gateway trade no
+    Paid(String),
+    /// still being processed, ask again later
+    Processing,
+    /// never paid or failed for good, the amount may be refunded
+    NotPaid,
+}
+
+/// where recharges are paid and withdrawals paid out
+#[async_trait::async_trait]
+pub trait PaymentGateway: Send + Sync {
+    /// name in the callback path and on the orders
+    fn name(&self) -> &'static str;
+
+    /// start paying the order, returns the url the member pays at
+    async fn pay(&self, order: &PaymentOrder) -> Result<String>;
+
+    /// check the signature of the callback params and read the payment,
+    /// `ServiceError::Payment` for a forged or broken callback
+    fn verify(&self, params: &BTreeMap<String, String>) -> Result<PaymentNotice>;
+
+    /// pay the withdrawal out, returns the gateway trade no; paying the same
+    /// order again must not pay twice
+    async fn payout(&self, order: &PayoutOrder) -> Result<String>;
+
+    /// ask what became of the payout of the order no, for payouts whose
+    /// result was lost or failed
+    async fn query_payout(&self, order_no: &str) -> Result<PayoutState>;
+}
+
+/// gateway for local testing, every payment succeeds: the pay url is the
+/// signed callback itself, opening it marks the recharge paid. Payouts are
+/// remembered by the process only
+pub struct MockGateway {
+    secret: String,
+    notify_url: String,
+    /// order no => trade no of the payouts made
+    payouts: Mutex<HashMap<String, String>>,
+}
+
+impl MockGateway {
+    pub fn new(secret: &str, notify_url: &str) -> Self {
+        Self {
+            secret: secret.to_owned(),
+            notify_url: notify_url.to_owned(),
+            payouts: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl PaymentGateway for MockGateway {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    async fn pay(&self, order: &PaymentOrder) -> Result<String> {
+        let mut params = BTreeMap::new();
+        params.insert("order_no".to_owned(), order.order_no.clone());
+        params.insert("trade_no".to_owned(), format!("MOCK{}", order.order_no));
+        params.insert("amount".to_owned(), order.amount.to_string());
+        let sign = sign(&self.secret, &params);
+        params.insert("sign".to_owned(), sign);
+        Ok(format!(
+            "{}?{}",
+            self.notify_url,
+            params
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect::<Vec<String>>()
+                .join("&")
+        ))
+    }
+
+    fn verify(&self, params: &BTreeMap<String, String>) -> Result<PaymentNotice> {
+        verify_sign(&self.secret, params)?;
+        let get = |key: &str| {
+            params
+                .get(key)
+                .cloned()
+                .ok_or_else(|| ServiceError::Payment(format!("{} missing", key)))
+        };
+        Ok(PaymentNotice {
+            order_no: get("order_no")?,
+            trade_no: get("trade_no")?,
+            amount: BigDecimal::from_str(&get("amount")?)
+                .map_err(|x| ServiceError::Payment(x.to_string()))?,
+        })
+    }
+
+    async fn payout(&self, order: &PayoutOrder) -> Result<String> {
+        tracing::info!(
+            "mock payout {} of {} to {} ({})",
+            order.order_no,
+            order.amount,
+            order.account,
+            order.account_name
+        );
+        Ok(self
+            .payouts
+            .lock()
+            .unwrap()
+            .entry(order.order_no.clone())
+            .or_insert_with(|| format!("MOCK{}", order.order_no))
+            .clone())
+    }
+
+    async fn query_payout(&self, order_no: &str) -> Result<PayoutState> {
+        Ok(match self.payouts.lock().unwrap().get(order_no) {
+            Some(trade_no) => PayoutState::Paid(trade_no.clone()),
+            None => PayoutState::NotPaid,
+        })
+    }
+}
+
+/// `PAYMENT_GATEWAY` picks the gateway, only `mock` for now; there is no
+/// default, an unset or unknown gateway fails. The mock credits every opened
+/// pay url, so it must be asked for and signs with `PAYMENT_MOCK_SECRET`,
+/// which has no default either; it calls back `PAYMENT_MOCK_NOTIFY_URL`
+pub fn gateway_from_env() -> Result<Arc<dyn PaymentGateway>> {
+    let name = std::env::var("PAYMENT_GATEWAY")
+        .map(|x| x.trim().to_lowercase())
+        .unwrap_or_default();
+    match name.as_str() {
+        "mock" => {
+            let secret = std::env::var("PAYMENT_MOCK_SECRET")
+                .ok()
+                .filter(|x| !x.trim().is_empty())
+                .ok_or_else(|| {
+                    ServiceError::Payment("PAYMENT_MOCK_SECRET is not set".to_owned())
+                })?;
+            tracing::warn!("payment gateway is mock, every opened pay url is credited");
+            Ok(Arc::new(MockGateway::new(
+                &secret,
+                &std::env::var("PAYMENT_MOCK_NOTIFY_URL")
+                    .unwrap_or_else(|_| "http://127.0.0.1:3001/payment/notify/mock".to_owned()),
+            )))
+        }
+        "" => Err(ServiceError::Payment(
+            "PAYMENT_GATEWAY is not set".to_owned(),
+        )),
+        name => Err(ServiceError::Payment(format!(
+            "payment gateway {} is unknown",
+            name
+        ))),
+    }
+}
+
+/// hex HMAC-SHA256 of the params but `sign`, as `key=value` joined by `&`
+/// in key order
+pub fn sign(secret: &str, params: &BTreeMap<String, String>) -> String {
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret.as_bytes());
+    hex::encode(ring::hmac::sign(&key, sign_content(params).as_bytes()).as_ref())
+}
+
+/// check the `sign` param in constant time
+pub fn verify_sign(secret: &str, params: &BTreeMap<String, String>) -> Result<()> {
+    let sign = params
+        .get("sign")
+        .and_then(|x| hex::decode(x).ok())
+        .ok_or_else(|| ServiceError::Payment("sign missing".to_owned()))?;
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret.as_bytes());
+    ring::hmac::verify(&key, sign_content(params).as_bytes(), &sign)
+        .map_err(|_| ServiceError::Payment("sign mismatch".to_owned()))
+}
+
+fn sign_content(params: &BTreeMap<String, String>) -> String {
+    params
+        .iter()
+        .filter(|(key, _)| key.as_str() != "sign")
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<String>>()
+        .join("&")
+}
+
+/// order no of the prefix, the time to the second and 6 random digits
+pub fn generate_order_no(prefix: &str) -> String {
+    format!(
+        "{}{}{:06}",
+        prefix,
+        now_time().format("%Y%m%d%H%M%S"),
+        fastrand::u32(..1_000_000)
+    )
+}
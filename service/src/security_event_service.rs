@@ -0,0 +1,562 @@
+use crate::{Result, ServiceError};
+use prisma_client_rust::chrono::SecondsFormat;
+use serde::Serialize;
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpStream, UdpSocket},
+    sync::{mpsc, watch, Mutex},
+    task::JoinSet,
+};
+use utils::datetime::now_time;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Login,
+    LoginFailed,
+    Action,
+}
+
+impl EventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Login => "login",
+            Self::LoginFailed => "login_failed",
+            Self::Action => "action",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        [Self::Login, Self::LoginFailed, Self::Action]
+            .into_iter()
+            .find(|x| x.name() == value.trim())
+    }
+
+    /// syslog severity: warning, notice and informational
+    fn severity(&self) -> u8 {
+        match self {
+            Self::LoginFailed => 4,
+            Self::Login => 5,
+            Self::Action => 6,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SecurityEvent {
+    pub kind: EventKind,
+    pub user_id: i32,
+    /// username tried by a failed login
+    pub username: String,
+    pub ip_address: String,
+    pub user_agent: String,
+    pub method: String,
+    pub path: String,
+    pub status: i32,
+    /// menu names of an action, reason of a failed login
+    pub detail: String,
+    pub occurred_at: String,
+}
+
+impl SecurityEvent {
+    fn new(kind: EventKind, user_id: i32, ip_address: &str, user_agent: &str) -> Self {
+        Self {
+            kind,
+            user_id,
+            username: String::new(),
+            ip_address: ip_address.to_owned(),
+            user_agent: user_agent.to_owned(),
+            method: String::new(),
+            path: String::new(),
+            status: 0,
+            detail: String::new(),
+            occurred_at: now_time().to_rfc3339_opts(SecondsFormat::Millis, false),
+        }
+    }
+
+    pub fn login(user_id: i32, ip_address: &str, user_agent: &str) -> Self {
+        Self::new(EventKind::Login, user_id, ip_address, user_agent)
+    }
+
+    pub fn login_failed(username: &str, ip_address: &str, user_agent: &str, reason: &str) -> Self {
+        Self {
+            username: username.to_owned(),
+            detail: reason.to_owned(),
+            ..Self::new(EventKind::LoginFailed, 0, ip_address, user_agent)
+        }
+    }
+
+    pub fn action(
+        user_id: i32,
+        ip_address: &str,
+        user_agent: &str,
+        method: &str,
+        path: &str,
+        status: i32,
+        menu_names: &str,
+    ) -> Self {
+        Self {
+            method: method.to_owned(),
+            path: path.to_owned(),
+            status,
+            detail: menu_names.to_owned(),
+            ..Self::new(EventKind::Action, user_id, ip_address, user_agent)
+        }
+    }
+}
+
+/// where events are delivered
+#[async_trait::async_trait]
+pub trait EventSink: Send + Sync {
+    async fn send(&self, event: &SecurityEvent) -> Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyslogTransport {
+    Udp,
+    /// octet counting framing of RFC 6587
+    Tcp,
+}
+
+/// RFC 5424 syslog messages, with the authpriv facility
+pub struct SyslogSink {
+    transport: SyslogTransport,
+    address: String,
+    hostname: String,
+    app_name: String,
+    udp: Mutex<Option<UdpSocket>>,
+    tcp: Mutex<Option<TcpStream>>,
+}
+
+impl SyslogSink {
+    pub fn new(transport: SyslogTransport, address: &str, app_name: &str) -> Self {
+        Self {
+            transport,
+            address: address.to_owned(),
+            hostname: std::env::var("HOSTNAME").unwrap_or_else(|_| "-".to_owned()),
+            app_name: app_name.to_owned(),
+            udp: Mutex::new(None),
+            tcp: Mutex::new(None),
+        }
+    }
+
+    /// `<PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID [SD] MSG`
+    pub fn format(&self, event: &SecurityEvent) -> Result<String> {
+        let pri = AUTHPRIV * 8 + event.kind.severity();
+        let params = [
+            ("user_id", event.user_id.to_string()),
+            ("username", event.username.to_owned()),
+            ("ip", event.ip_address.to_owned()),
+            ("method", event.method.to_owned()),
+            ("path", event.path.to_owned()),
+            ("status", event.status.to_string()),
+        ]
+        .iter()
+        .map(|(name, value)| format!(" {}=\"{}\"", name, escape_param(value)))
+        .collect::<String>();
+        Ok(format!(
+            "<{}>1 {} {} {} {} {} [event@32473{}] {}",
+            pri,
+            event.occurred_at,
+            header_field(&self.hostname, 255),
+            header_field(&self.app_name, 48),
+            std::process::id(),
+            event.kind.name().to_uppercase(),
+            params,
+            serde_json::to_string(event)?
+        ))
+    }
+
+    async fn send_udp(&self, message: &str) -> std::io::Result<()> {
+        let mut socket = self.udp.lock().await;
+        if socket.is_none() {
+            *socket = Some(UdpSocket::bind("0.0.0.0:0").await?);
+        }
+        if let Some(socket) = socket.as_ref() {
+            socket.send_to(message.as_bytes(), &self.address).await?;
+        }
+        Ok(())
+    }
+
+    /// the connection is kept, and opened again once after it failed
+    async fn send_tcp(&self, message: &str) -> std::io::Result<()> {
+        let frame = format!("{} {}", message.len(), message);
+        let mut stream = self.tcp.lock().await;
+        if let Some(connected) = stream.as_mut() {
+            if connected.write_all(frame.as_bytes()).await.is_ok() {
+                return Ok(());
+            }
+        }
+        let mut connected = TcpStream::connect(&self.address).await?;
+        connected.write_all(frame.as_bytes()).await?;
+        *stream = Some(connected);
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl EventSink for SyslogSink {
+    async fn send(&self, event: &SecurityEvent) -> Result<()> {
+        let message = self.format(event)?;
+        match self.transport {
+            SyslogTransport::Udp => self.send_udp(&message).await?,
+            SyslogTransport::Tcp => self.send_tcp(&message).await?,
+        }
+        Ok(())
+    }
+}
+
+/// one JSON object per line, appended to the file
+pub struct JsonLinesSink {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl JsonLinesSink {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EventSink for JsonLinesSink {
+    async fn send(&self, event: &SecurityEvent) -> Result<()> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        let _lock = self.lock.lock().await;
+        // opened for every event so rotated files are picked up
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(&line).await?;
+        Ok(())
+    }
+}
+
+/// event POSTed as JSON to the url
+pub struct WebhookSink {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl WebhookSink {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_owned(),
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(5))
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EventSink for WebhookSink {
+    async fn send(&self, event: &SecurityEvent) -> Result<()> {
+        self.client
+            .post(&self.url)
+            .json(event)
+            .send()
+            .await
+            .and_then(|x| x.error_for_status())
+            .map_err(|x| ServiceError::Io(std::io::Error::other(x)))?;
+        Ok(())
+    }
+}
+
+/// sink with the events it receives
+pub struct SinkRoute {
+    pub events: Vec<EventKind>,
+    pub sink: Arc<dyn EventSink>,
+}
+
+#[derive(Default)]
+pub struct EventSinkConfig {
+    pub routes: Vec<SinkRoute>,
+    /// queued events of each sink before new ones are dropped
+    pub capacity: usize,
+}
+
+impl EventSinkConfig {
+    /// sinks of `EVENT_SINK_SYSLOG` (`udp://host:514` or `tcp://host:601`),
+    /// `EVENT_SINK_JSONL` (file path) and `EVENT_SINK_WEBHOOK` (url), each
+    /// receiving the events of `<NAME>_EVENTS` (`login,login_failed,action`, all by default)
+    pub fn from_env() -> Self {
+        let mut routes = vec![];
+        if let Ok(url) = std::env::var("EVENT_SINK_SYSLOG") {
+            let sink = match url.split_once("://") {
+                Some(("tcp", address)) => {
+                    Some(SyslogSink::new(SyslogTransport::Tcp, address, "admin"))
+                }
+                Some(("udp", address)) => {
+                    Some(SyslogSink::new(SyslogTransport::Udp, address, "admin"))
+                }
+                _ => {
+                    tracing::warn!("EVENT_SINK_SYSLOG needs udp:// or tcp://, got {}", url);
+                    None
+                }
+            };
+            if let Some(sink) = sink {
+                routes.push(SinkRoute {
+                    events: events_from_env("EVENT_SINK_SYSLOG_EVENTS"),
+                    sink: Arc::new(sink),
+                });
+            }
+        }
+        if let Ok(path) = std::env::var("EVENT_SINK_JSONL") {
+            routes.push(SinkRoute {
+                events: events_from_env("EVENT_SINK_JSONL_EVENTS"),
+                sink: Arc::new(JsonLinesSink::new(PathBuf::from(path))),
+            });
+        }
+        if let Ok(url) = std::env::var("EVENT_SINK_WEBHOOK") {
+            routes.push(SinkRoute {
+                events: events_from_env("EVENT_SINK_WEBHOOK_EVENTS"),
+                sink: Arc::new(WebhookSink::new(&url)),
+            });
+        }
+        Self {
+            routes,
+            capacity: 10_000,
+        }
+    }
+}
+
+/// sending side, kept in the app state
+#[derive(Clone)]
+pub struct EventDispatcher {
+    /// a queue per sink, with the events it receives
+    queues: Arc<Vec<(Vec<EventKind>, mpsc::Sender<SecurityEvent>)>>,
+    dropped: Arc<AtomicU64>,
+    shutdown: Arc<watch::Sender<bool>>,
+}
+
+/// delivering side, run it on a task with [`EventWorker::run`]
+pub struct EventWorker {
+    queues: Vec<(Arc<dyn EventSink>, mpsc::Receiver<SecurityEvent>)>,
+    shutdown: watch::Receiver<bool>,
+}
+
+impl EventDispatcher {
+    pub fn new(config: EventSinkConfig) -> (Self, EventWorker) {
+        let (shutdown, shutdown_receiver) = watch::channel(false);
+        let (mut senders, mut receivers) = (vec![], vec![]);
+        for route in config.routes {
+            let (sender, receiver) = mpsc::channel(config.capacity.max(1));
+            senders.push((route.events, sender));
+            receivers.push((route.sink, receiver));
+        }
+        (
+            Self {
+                queues: Arc::new(senders),
+                dropped: Arc::new(AtomicU64::new(0)),
+                shutdown: Arc::new(shutdown),
+            },
+            EventWorker {
+                queues: receivers,
+                shutdown: shutdown_receiver,
+            },
+        )
+    }
+
+    /// queue the event for every sink receiving it, dropping it for a sink
+    /// whose queue is full
+    pub fn emit(&self, event: SecurityEvent) {
+        for (events, sender) in self.queues.iter() {
+            if !events.contains(&event.kind) {
+                continue;
+            }
+            if sender.try_send(event.clone()).is_err() {
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                if dropped.is_power_of_two() {
+                    tracing::warn!("event queue is full, {} events dropped so far", dropped);
+                }
+            }
+        }
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// ask the worker to deliver what is queued and stop
+    pub fn close(&self) {
+        self.shutdown.send_replace(true);
+    }
+}
+
+impl EventWorker {
+    /// deliver events until [`EventDispatcher::close`] is called, every sink
+    /// from its own queue so a stalled sink never holds up the others
+    pub async fn run(self) {
+        let mut tasks = JoinSet::new();
+        for (sink, receiver) in self.queues {
+            tasks.spawn(deliver(sink, receiver, self.shutdown.clone()));
+        }
+        while tasks.join_next().await.is_some() {}
+    }
+}
+
+async fn deliver(
+    sink: Arc<dyn EventSink>,
+    mut receiver: mpsc::Receiver<SecurityEvent>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    while !*shutdown.borrow() {
+        tokio::select! {
+            event = receiver.recv() => match event {
+                Some(event) => send(sink.as_ref(), &event).await,
+                None => break,
+            },
+            _ = shutdown.changed() => {},
+        }
+    }
+    receiver.close();
+    while let Ok(event) = receiver.try_recv() {
+        send(sink.as_ref(), &event).await;
+    }
+}
+
+async fn send(sink: &dyn EventSink, event: &SecurityEvent) {
+    if let Err(err) = sink.send(event).await {
+        tracing::error!("{} event not delivered: {:?}", event.kind.name(), err);
+    }
+}
+
+/// facility 10, security and authorization messages
+const AUTHPRIV: u8 = 10;
+
+fn events_from_env(key: &str) -> Vec<EventKind> {
+    let events = std::env::var(key)
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(EventKind::parse)
+        .collect::<Vec<EventKind>>();
+    match events.is_empty() {
+        true => vec![EventKind::Login, EventKind::LoginFailed, EventKind::Action],
+        false => events,
+    }
+}
+
+/// header fields are printable ascii without spaces, `-` when empty
+fn header_field(value: &str, max_length: usize) -> String {
+    let value = value
+        .chars()
+        .filter(|x| x.is_ascii_graphic())
+        .take(max_length)
+        .collect::<String>();
+    match value.is_empty() {
+        true => "-".to_owned(),
+        false => value,
+    }
+}
+
+/// `"`, `\` and `]` are escaped in structured data values
+fn escape_param(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace(']', "\\]")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn syslog_udp_frame() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sink = SyslogSink::new(
+            SyslogTransport::Udp,
+            &server.local_addr().unwrap().to_string(),
+            "admin",
+        );
+        let event = SecurityEvent::login_failed("root", "10.0.0.1", "curl", "bad \"password\"]");
+        sink.send(&event).await.unwrap();
+
+        let mut buf = [0u8; 4096];
+        let (len, _) = server.recv_from(&mut buf).await.unwrap();
+        let frame = std::str::from_utf8(&buf[..len]).unwrap();
+        // authpriv.warning
+        let header = format!(
+            "<84>1 {} {} admin {} LOGIN_FAILED ",
+            event.occurred_at,
+            header_field(&sink.hostname, 255),
+            std::process::id()
+        );
+        assert!(frame.starts_with(&header), "{}", frame);
+        let rest = &frame[header.len()..];
+        let sd = concat!(
+            r#"[event@32473 user_id="0" username="root" ip="10.0.0.1""#,
+            r#" method="" path="" status="0"] "#
+        );
+        assert!(rest.starts_with(sd), "{}", rest);
+        let message: serde_json::Value = serde_json::from_str(&rest[sd.len()..]).unwrap();
+        assert_eq!(message["kind"], "login_failed");
+        assert_eq!(message["detail"], "bad \"password\"]");
+    }
+
+    /// never finishes a delivery
+    struct StalledSink;
+
+    #[async_trait::async_trait]
+    impl EventSink for StalledSink {
+        async fn send(&self, _: &SecurityEvent) -> Result<()> {
+            std::future::pending().await
+        }
+    }
+
+    struct CountingSink(mpsc::UnboundedSender<EventKind>);
+
+    #[async_trait::async_trait]
+    impl EventSink for CountingSink {
+        async fn send(&self, event: &SecurityEvent) -> Result<()> {
+            let _ = self.0.send(event.kind);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn stalled_sink_does_not_block_others() {
+        let (sender, mut received) = mpsc::unbounded_channel();
+        let all = vec![EventKind::Login, EventKind::LoginFailed, EventKind::Action];
+        let (events, worker) = EventDispatcher::new(EventSinkConfig {
+            routes: vec![
+                SinkRoute {
+                    events: all.clone(),
+                    sink: Arc::new(StalledSink),
+                },
+                SinkRoute {
+                    events: vec![EventKind::Login],
+                    sink: Arc::new(CountingSink(sender)),
+                },
+            ],
+            capacity: 10,
+        });
+        let worker = tokio::spawn(worker.run());
+        events.emit(SecurityEvent::login(1, "127.0.0.1", "test"));
+        events.emit(SecurityEvent::login_failed("x", "127.0.0.1", "test", ""));
+        events.emit(SecurityEvent::login(2, "127.0.0.1", "test"));
+        for _ in 0..2 {
+            let kind = tokio::time::timeout(Duration::from_secs(1), received.recv())
+                .await
+                .unwrap();
+            assert_eq!(kind, Some(EventKind::Login));
+        }
+        worker.abort();
+    }
+}
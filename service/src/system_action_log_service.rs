@@ -1,6 +1,8 @@
 use crate::{
     prisma::{system_action_log, SortOrder},
-    system_menu_service, system_user_service, Database, Result, ServiceError,
+    system_menu_service,
+    system_user_service::{self, DataScope},
+    Database, Result, ServiceError,
 };
 use prisma_client_rust::{or, prisma_models::parse_datetime};
 use serde::Serialize;
@@ -34,19 +36,28 @@ pub async fn info(db: &Database, id: i32) -> Result<Info> {
         .ok_or(ServiceError::DataNotFound)?
         .into())
 }
-pub async fn paginate(db: &Database, params: &SearchParams) -> Result<PaginateResult<Vec<Info>>> {
+pub async fn paginate(
+    db: &Database,
+    params: &SearchParams,
+    scope: &DataScope,
+) -> Result<PaginateResult<Vec<Info>>> {
+    let mut query_params = params.to_params();
+    let user_params = scope.to_params();
+    if !user_params.is_empty() {
+        query_params.push(system_action_log::user::is(user_params));
+    }
     let (data, total) = db
         .client
         ._batch((
             db.client
                 .system_action_log()
-                .find_many(params.to_params())
+                .find_many(query_params.clone())
                 .skip(params.paginate.get_skip())
                 .take(params.paginate.get_limit())
                 .with(system_action_log::user::fetch())
                 .with(system_action_log::menu::fetch())
                 .order_by(system_action_log::id::order(SortOrder::Desc)),
-            db.client.system_action_log().count(params.to_params()),
+            db.client.system_action_log().count(query_params),
         ))
         .await?;
     Ok(PaginateResult {
@@ -55,6 +66,59 @@ pub async fn paginate(db: &Database, params: &SearchParams) -> Result<PaginateRe
     })
 }
 
+/// columns which can be exported, keyed by their path in [`Info`]
+pub const EXPORT_COLUMNS: &[(&str, &str)] = &[
+    ("id", "ID"),
+    ("user_id", "管理员ID"),
+    ("user/username", "管理员账号"),
+    ("user/nickname", "管理员昵称"),
+    ("menu_id", "菜单ID"),
+    ("menu_names", "菜单名称"),
+    ("ip_address", "IP地址"),
+    ("ip_address_name", "IP地址名称"),
+    ("browser_agent", "浏览器"),
+    ("method", "请求方法"),
+    ("path", "请求路径"),
+    ("path_params", "路径参数"),
+    ("query", "查询参数"),
+    ("request_body", "请求内容"),
+    ("status", "响应状态码"),
+    ("error_message", "错误信息"),
+    ("duration", "耗时(毫秒)"),
+    ("created_at", "操作时间"),
+];
+
+/// next rows to export, newest first, older than the `before_id` cursor
+pub async fn export(
+    db: &Database,
+    params: &SearchParams,
+    scope: &DataScope,
+    before_id: Option<i32>,
+    take: i64,
+) -> Result<Vec<Info>> {
+    let mut query_params = params.to_params();
+    let user_params = scope.to_params();
+    if !user_params.is_empty() {
+        query_params.push(system_action_log::user::is(user_params));
+    }
+    if let Some(before_id) = before_id {
+        query_params.push(system_action_log::id::lt(before_id));
+    }
+    Ok(db
+        .client
+        .system_action_log()
+        .find_many(query_params)
+        .take(take)
+        .with(system_action_log::user::fetch())
+        .with(system_action_log::menu::fetch())
+        .order_by(system_action_log::id::order(SortOrder::Desc))
+        .exec()
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect::<Vec<Info>>())
+}
+
 pub struct SearchParams {
     user_id: Option<i32>,
     menu_id: Option<i32>,
@@ -76,6 +140,7 @@ impl SearchParams {
                 system_action_log::ip_address::contains(keyword.to_string()),
                 system_action_log::ip_address_name::contains(keyword.to_string()),
                 system_action_log::browser_agent::contains(keyword.to_string()),
+                system_action_log::path::contains(keyword.to_string()),
             ));
         }
         if let Some(date) = &self.date {
@@ -113,6 +178,14 @@ pub struct Info {
     ip_address: String,
     ip_address_name: String,
     browser_agent: String,
+    method: String,
+    path: String,
+    path_params: String,
+    query: String,
+    request_body: String,
+    status: i32,
+    error_message: String,
+    duration: i32,
     created_at: String,
 }
 
@@ -134,6 +207,14 @@ impl From<system_action_log::Data> for Info {
             ip_address: value.ip_address,
             ip_address_name: value.ip_address_name,
             browser_agent: value.browser_agent,
+            method: value.method,
+            path: value.path,
+            path_params: value.path_params,
+            query: value.query,
+            request_body: value.request_body,
+            status: value.status,
+            error_message: value.error_message,
+            duration: value.duration,
             created_at: to_local_string(value.created_at),
         }
     }
@@ -142,4 +223,113 @@ system_action_log::partial_unchecked!(CreateParams {
     menu_names
     ip_address_name
     browser_agent
+    method
+    path
+    path_params
+    query
+    request_body
+    status
+    error_message
+    duration
 });
+
+/// what the action log keeps of a request
+#[derive(Debug, Clone)]
+pub struct RecordConfig {
+    /// fields whose values are masked, matched case-insensitively
+    pub redact_fields: Vec<String>,
+    /// longest request body or error message stored, in bytes
+    pub max_length: usize,
+}
+
+impl Default for RecordConfig {
+    fn default() -> Self {
+        Self {
+            redact_fields: [
+                "password",
+                "old_password",
+                "confirm_password",
+                "salt",
+                "token",
+                "access_token",
+                "refresh_token",
+                "secret",
+                "captcha",
+            ]
+            .iter()
+            .map(|x| x.to_string())
+            .collect(),
+            max_length: 4096,
+        }
+    }
+}
+
+impl RecordConfig {
+    /// masked query string
+    pub fn redact_query(&self, query: &str) -> String {
+        query
+            .split('&')
+            .filter(|x| !x.is_empty())
+            .map(|pair| match pair.split_once('=') {
+                Some((key, _)) if self.is_redacted(key) => format!("{}={}", key, REDACTED),
+                _ => pair.to_owned(),
+            })
+            .collect::<Vec<String>>()
+            .join("&")
+    }
+
+    /// masked request body, json and url encoded forms are parsed, anything else is summarized
+    pub fn redact_body(&self, body: &[u8]) -> String {
+        if body.is_empty() {
+            return String::new();
+        }
+        if let Ok(mut value) = serde_json::from_slice::<serde_json::Value>(body) {
+            self.redact_json(&mut value);
+            return self.truncate(value.to_string());
+        }
+        match std::str::from_utf8(body) {
+            Ok(text) if text.contains('=') && !text.contains(char::is_whitespace) => {
+                self.truncate(self.redact_query(text))
+            }
+            _ => format!("<{} bytes>", body.len()),
+        }
+    }
+
+    pub fn truncate(&self, mut value: String) -> String {
+        if value.len() > self.max_length {
+            let mut index = self.max_length;
+            while !value.is_char_boundary(index) {
+                index -= 1;
+            }
+            value.truncate(index);
+        }
+        value
+    }
+
+    fn redact_json(&self, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(map) => {
+                for (key, item) in map.iter_mut() {
+                    match self.is_redacted(key) {
+                        true => *item = serde_json::Value::String(REDACTED.to_owned()),
+                        false => self.redact_json(item),
+                    }
+                }
+            }
+            serde_json::Value::Array(items) => {
+                for item in items.iter_mut() {
+                    self.redact_json(item);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn is_redacted(&self, key: &str) -> bool {
+        self.redact_fields
+            .iter()
+            .any(|x| x.eq_ignore_ascii_case(key))
+    }
+}
+
+const REDACTED: &str = "******";
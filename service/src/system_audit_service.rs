@@ -0,0 +1,287 @@
+use crate::{
+    prisma::{system_audit, PrismaClient, SortOrder},
+    Database, Result, ServiceError,
+};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use std::future::Future;
+use utils::{
+    datetime::to_local_string,
+    paginate::{PaginateParams, PaginateResult},
+};
+
+tokio::task_local! {
+    /// admin user or member doing the current request
+    static ACTOR: (ActorType, i32);
+}
+
+/// run the future with the admin user or member recorded as the actor of its
+/// changes
+pub async fn with_actor<F: Future>(actor_type: ActorType, user_id: i32, f: F) -> F::Output {
+    ACTOR.scope((actor_type, user_id), f).await
+}
+
+/// fields never written to the audit trail
+const REDACTED_FIELDS: [&str; 2] = ["password", "salt"];
+/// fields changing on every write
+const IGNORED_FIELDS: [&str; 2] = ["updated_at", "updated_time"];
+
+/// record the changed fields of an entity, skipping updates which changed
+/// nothing; pass the client of the transaction writing the entity, so a
+/// failed audit rolls the change back
+pub async fn record<T: Serialize>(
+    client: &PrismaClient,
+    entity: &str,
+    entity_id: i32,
+    action: AuditAction,
+    before: Option<&T>,
+    after: Option<&T>,
+) -> Result<()> {
+    let changes = diff(
+        before.map(serde_json::to_value).transpose()?,
+        after.map(serde_json::to_value).transpose()?,
+    );
+    if changes.is_empty() && action == AuditAction::Update {
+        return Ok(());
+    }
+    let (actor_type, user_id) = ACTOR
+        .try_with(|x| x.clone())
+        .unwrap_or((ActorType::System, 0));
+    client
+        .system_audit()
+        .create_unchecked(
+            entity.to_owned(),
+            entity_id,
+            action.into(),
+            vec![
+                system_audit::actor_type::set(actor_type.into()),
+                system_audit::user_id::set(user_id),
+                system_audit::changes::set(Value::Object(changes).to_string()),
+            ],
+        )
+        .exec()
+        .await?;
+    Ok(())
+}
+
+pub async fn info(db: &Database, id: i32) -> Result<Info> {
+    Ok(db
+        .client
+        .system_audit()
+        .find_unique(system_audit::id::equals(id))
+        .exec()
+        .await?
+        .ok_or(ServiceError::DataNotFound)?
+        .into())
+}
+
+pub async fn paginate(db: &Database, params: &SearchParams) -> Result<PaginateResult<Vec<Info>>> {
+    let (data, total) = db
+        .client
+        ._batch((
+            db.client
+                .system_audit()
+                .find_many(params.to_params())
+                .skip(params.paginate.get_skip())
+                .take(params.paginate.get_limit())
+                .order_by(system_audit::id::order(SortOrder::Desc)),
+            db.client.system_audit().count(params.to_params()),
+        ))
+        .await?;
+    Ok(PaginateResult {
+        total,
+        data: data.into_iter().map(|x| x.into()).collect::<Vec<Info>>(),
+    })
+}
+
+/// changed fields as `{"field": {"old": .., "new": ..}}`
+fn diff(before: Option<Value>, after: Option<Value>) -> Map<String, Value> {
+    let before = match before {
+        Some(Value::Object(map)) => map,
+        _ => Map::new(),
+    };
+    let after = match after {
+        Some(Value::Object(map)) => map,
+        _ => Map::new(),
+    };
+    let mut keys = before.keys().chain(after.keys()).collect::<Vec<&String>>();
+    keys.sort();
+    keys.dedup();
+    let mut changes = Map::new();
+    for key in keys {
+        if IGNORED_FIELDS.contains(&key.as_str()) {
+            continue;
+        }
+        let old = before.get(key).cloned().unwrap_or(Value::Null);
+        let new = after.get(key).cloned().unwrap_or(Value::Null);
+        if is_relation(&old) || is_relation(&new) || old == new {
+            continue;
+        }
+        let (old, new) = match REDACTED_FIELDS.contains(&key.as_str()) {
+            true => (redact(old), redact(new)),
+            false => (old, new),
+        };
+        let mut change = Map::new();
+        change.insert("old".to_owned(), old);
+        change.insert("new".to_owned(), new);
+        changes.insert(key.to_owned(), Value::Object(change));
+    }
+    changes
+}
+
+/// fetched relations are not part of the entity, lists of ids are
+fn is_relation(value: &Value) -> bool {
+    match value {
+        Value::Object(_) => true,
+        Value::Array(items) => items.iter().any(|x| x.is_object()),
+        _ => false,
+    }
+}
+
+fn redact(value: Value) -> Value {
+    match value {
+        Value::Null => Value::Null,
+        _ => Value::String("******".to_owned()),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize_repr, Deserialize_repr)]
+#[repr(i32)]
+pub enum AuditAction {
+    /// 1.新增
+    Create = 1,
+    /// 2.更新
+    Update = 2,
+    /// 3.删除
+    Delete = 3,
+}
+
+impl From<i32> for AuditAction {
+    fn from(value: i32) -> Self {
+        match value {
+            1 => Self::Create,
+            2 => Self::Update,
+            3 => Self::Delete,
+            _ => Self::Update,
+        }
+    }
+}
+
+impl From<AuditAction> for i32 {
+    fn from(value: AuditAction) -> Self {
+        match value {
+            AuditAction::Create => 1,
+            AuditAction::Update => 2,
+            AuditAction::Delete => 3,
+        }
+    }
+}
+
+/// who made a change
+#[derive(Debug, Clone, PartialEq, Eq, Serialize_repr, Deserialize_repr)]
+#[repr(i32)]
+pub enum ActorType {
+    /// 0.系统
+    System = 0,
+    /// 1.管理员
+    Admin = 1,
+    /// 2.会员
+    Member = 2,
+}
+
+impl From<i32> for ActorType {
+    fn from(value: i32) -> Self {
+        match value {
+            1 => Self::Admin,
+            2 => Self::Member,
+            _ => Self::System,
+        }
+    }
+}
+
+impl From<ActorType> for i32 {
+    fn from(value: ActorType) -> Self {
+        match value {
+            ActorType::System => 0,
+            ActorType::Admin => 1,
+            ActorType::Member => 2,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Info {
+    id: i32,
+    entity: String,
+    entity_id: i32,
+    action: AuditAction,
+    actor_type: ActorType,
+    user_id: i32,
+    changes: Value,
+    created_at: String,
+}
+
+impl From<system_audit::Data> for Info {
+    fn from(value: system_audit::Data) -> Self {
+        Self {
+            id: value.id,
+            entity: value.entity,
+            entity_id: value.entity_id,
+            action: value.action.into(),
+            actor_type: value.actor_type.into(),
+            user_id: value.user_id,
+            changes: serde_json::from_str(&value.changes).unwrap_or_default(),
+            created_at: to_local_string(value.created_at),
+        }
+    }
+}
+
+pub struct SearchParams {
+    entity: Option<String>,
+    entity_id: Option<i32>,
+    actor_type: Option<ActorType>,
+    user_id: Option<i32>,
+    action: Option<AuditAction>,
+    paginate: PaginateParams,
+}
+
+impl SearchParams {
+    fn to_params(&self) -> Vec<system_audit::WhereParam> {
+        let mut params = vec![];
+        if let Some(entity) = &self.entity {
+            params.push(system_audit::entity::equals(entity.to_owned()));
+        }
+        if let Some(entity_id) = self.entity_id {
+            params.push(system_audit::entity_id::equals(entity_id));
+        }
+        if let Some(actor_type) = &self.actor_type {
+            params.push(system_audit::actor_type::equals(actor_type.clone().into()));
+        }
+        if let Some(user_id) = self.user_id {
+            params.push(system_audit::user_id::equals(user_id));
+        }
+        if let Some(action) = &self.action {
+            params.push(system_audit::action::equals(action.clone().into()));
+        }
+        params
+    }
+
+    pub fn new(
+        entity: Option<String>,
+        entity_id: Option<i32>,
+        actor_type: Option<ActorType>,
+        user_id: Option<i32>,
+        action: Option<AuditAction>,
+        paginate: PaginateParams,
+    ) -> Self {
+        Self {
+            entity,
+            entity_id,
+            actor_type,
+            user_id,
+            action,
+            paginate,
+        }
+    }
+}
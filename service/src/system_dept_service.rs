@@ -1,5 +1,6 @@
 use crate::{
     prisma::{system_dept, SortOrder},
+    system_audit_service::{self, AuditAction},
     system_user_service, Database, Result, ServiceError,
 };
 use getset::Getters;
@@ -15,9 +16,24 @@ pub async fn create(db: &Database, name: &str, params: CreateParams) -> Result<I
         Ok(info) => Ok(info),
         Err(ServiceError::DataNotFound) => Ok(db
             .client
-            .system_dept()
-            .create_unchecked(name.to_owned(), params.to_params())
-            .exec()
+            ._transaction()
+            .run::<ServiceError, _, _, _>(|client| async move {
+                let data = client
+                    .system_dept()
+                    .create_unchecked(name.to_owned(), params.to_params())
+                    .exec()
+                    .await?;
+                system_audit_service::record(
+                    &client,
+                    "system_dept",
+                    data.id,
+                    AuditAction::Create,
+                    None,
+                    Some(&data),
+                )
+                .await?;
+                Ok(data)
+            })
             .await?
             .into()),
         Err(err) => Err(err),
@@ -27,40 +43,76 @@ pub async fn create(db: &Database, name: &str, params: CreateParams) -> Result<I
 pub async fn update(db: &Database, id: i32, params: UpdateParams) -> Result<Info> {
     Ok(db
         .client
-        .system_dept()
-        .update_unchecked(system_dept::id::equals(id), params.to_params())
-        .exec()
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            let before = client
+                .system_dept()
+                .find_unique(system_dept::id::equals(id))
+                .exec()
+                .await?;
+            let data = client
+                .system_dept()
+                .update_unchecked(system_dept::id::equals(id), params.to_params())
+                .exec()
+                .await?;
+            system_audit_service::record(
+                &client,
+                "system_dept",
+                data.id,
+                AuditAction::Update,
+                before.as_ref(),
+                Some(&data),
+            )
+            .await?;
+            Ok(data)
+        })
         .await?
         .into())
 }
 
 pub async fn delete(db: &Database, id: i32) -> Result<Info> {
-    let res = db
+    let (info, user_ids) = db
         .client
         ._transaction()
         .run::<ServiceError, _, _, _>(|client| async move {
             let client = std::sync::Arc::new(client);
-            let info = client
+            let before = client
+                .system_dept()
+                .find_unique(system_dept::id::equals(id))
+                .exec()
+                .await?;
+            let data = client
                 .system_dept()
                 .update(
                     system_dept::id::equals(id),
                     vec![system_dept::deleted_at::set(Some(now_time()))],
                 )
                 .exec()
-                .await?
-                .into();
+                .await?;
+            system_audit_service::record(
+                &client,
+                "system_dept",
+                data.id,
+                AuditAction::Delete,
+                before.as_ref(),
+                Some(&data),
+            )
+            .await?;
+            let info: Info = data.into();
             let user_ids = system_user_service::get_users_by_dept_id(db, id)
                 .await?
                 .into_iter()
                 .map(|x| *x.id())
                 .collect::<Vec<i32>>();
-            if !user_ids.is_empty() {
-                system_user_service::batch_set_dept(db, None, user_ids).await?;
-            }
-            Ok(info)
+            system_user_service::batch_set_dept(&client, None, user_ids.clone()).await?;
+            Ok((info, user_ids))
         })
         .await?;
-    Ok(res)
+    // moved users may lose the depts of their data scope
+    for user_id in user_ids {
+        db.permissions().forget_user(user_id);
+    }
+    Ok(info)
 }
 
 pub async fn get_dept_children_ids(db: &Database, parent_dept_id: i32) -> Result<Vec<i32>> {
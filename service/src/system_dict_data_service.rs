@@ -1,9 +1,11 @@
 use crate::{
+    cache_service::{CacheType, Driver},
     prisma::{system_dict_data, SortOrder},
+    system_audit_service::{self, AuditAction},
     system_dict_service, Database, Result, ServiceError,
 };
 use prisma_client_rust::or;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use utils::{
     datetime::{now_time, to_local_string},
     paginate::{PaginateParams, PaginateResult},
@@ -16,46 +18,119 @@ pub async fn create(
     value: i32,
     params: CreateParams,
 ) -> Result<Info> {
-    Ok(db
+    let data = db
         .client
-        .system_dict_data()
-        .create_unchecked(dict_id, label.to_owned(), value, params.to_params())
-        .exec()
-        .await?
-        .into())
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            let data = client
+                .system_dict_data()
+                .create_unchecked(dict_id, label.to_owned(), value, params.to_params())
+                .exec()
+                .await?;
+            system_audit_service::record(
+                &client,
+                "system_dict_data",
+                data.id,
+                AuditAction::Create,
+                None,
+                Some(&data),
+            )
+            .await?;
+            Ok(data)
+        })
+        .await?;
+    let info = data.into();
+    system_dict_service::forget_cache(db).await?;
+    Ok(info)
 }
 
 pub async fn update(db: &Database, id: i32, params: UpdateParams) -> Result<Info> {
-    Ok(db
-        .client
-        .system_dict_data()
-        .update_unchecked(system_dict_data::id::equals(id), params.to_params())
-        .exec()
-        .await?
-        .into())
+    set(db, id, AuditAction::Update, params.to_params()).await
 }
 pub async fn delete(db: &Database, id: i32) -> Result<Info> {
-    Ok(db
+    set(
+        db,
+        id,
+        AuditAction::Delete,
+        vec![system_dict_data::deleted_at::set(Some(now_time()))],
+    )
+    .await
+}
+
+/// update the dict data and audit it in one transaction
+async fn set(
+    db: &Database,
+    id: i32,
+    action: AuditAction,
+    params: Vec<system_dict_data::UncheckedSetParam>,
+) -> Result<Info> {
+    let data = db
         .client
-        .system_dict_data()
-        .update(
-            system_dict_data::id::equals(id),
-            vec![system_dict_data::deleted_at::set(Some(now_time()))],
-        )
-        .exec()
-        .await?
-        .into())
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            let before = client
+                .system_dict_data()
+                .find_unique(system_dict_data::id::equals(id))
+                .exec()
+                .await?;
+            let data = client
+                .system_dict_data()
+                .update_unchecked(system_dict_data::id::equals(id), params)
+                .exec()
+                .await?;
+            system_audit_service::record(
+                &client,
+                "system_dict_data",
+                data.id,
+                action,
+                before.as_ref(),
+                Some(&data),
+            )
+            .await?;
+            Ok(data)
+        })
+        .await?;
+    let info = data.into();
+    system_dict_service::forget_cache(db).await?;
+    Ok(info)
 }
 pub async fn batch_delete(db: &Database, ids: Vec<i32>) -> Result<i64> {
-    Ok(db
+    let count = db
         .client
-        .system_dict_data()
-        .update_many(
-            vec![system_dict_data::id::in_vec(ids)],
-            vec![system_dict_data::deleted_at::set(Some(now_time()))],
-        )
-        .exec()
-        .await?)
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            let befores = client
+                .system_dict_data()
+                .find_many(vec![system_dict_data::id::in_vec(ids.clone())])
+                .exec()
+                .await?;
+            let deleted_at = now_time();
+            let count = client
+                .system_dict_data()
+                .update_many(
+                    vec![system_dict_data::id::in_vec(ids)],
+                    vec![system_dict_data::deleted_at::set(Some(deleted_at))],
+                )
+                .exec()
+                .await?;
+            for before in befores {
+                let mut data = before.clone();
+                data.deleted_at = Some(deleted_at);
+                system_audit_service::record(
+                    &client,
+                    "system_dict_data",
+                    data.id,
+                    AuditAction::Delete,
+                    Some(&before),
+                    Some(&data),
+                )
+                .await?;
+            }
+            Ok(count)
+        })
+        .await?;
+    system_dict_service::forget_cache(db).await?;
+    Ok(count)
 }
 pub async fn info(db: &Database, id: i32) -> Result<Info> {
     Ok(db
@@ -67,6 +142,31 @@ pub async fn info(db: &Database, id: i32) -> Result<Info> {
         .ok_or(ServiceError::DataNotFound)?
         .into())
 }
+/// data of the dict by sort, cached until any dict or dict data changes
+pub async fn get_by_dict(db: &Database, dict_id: i32) -> Result<Vec<Info>> {
+    db.cache()
+        .remember_forever(
+            CacheType::SystemDict,
+            &format!("data:{}", dict_id),
+            None,
+            || async {
+                Ok(db
+                    .client
+                    .system_dict_data()
+                    .find_many(vec![
+                        system_dict_data::dict_id::equals(dict_id),
+                        system_dict_data::deleted_at::equals(None),
+                    ])
+                    .order_by(system_dict_data::sort::order(SortOrder::Asc))
+                    .exec()
+                    .await?
+                    .into_iter()
+                    .map(|x| x.into())
+                    .collect::<Vec<Info>>())
+            },
+        )
+        .await
+}
 pub async fn get_by_label(
     db: &Database,
     dict_id: i32,
@@ -143,7 +243,7 @@ impl SearchParams {
         }
     }
 }
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Info {
     id: i32,
     dict_id: i32,
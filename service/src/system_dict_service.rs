@@ -1,44 +1,92 @@
 use crate::{
+    cache_service::{CacheType, Driver},
     prisma::{system_dict, SortOrder},
+    system_audit_service::{self, AuditAction},
     system_dict_data_service, Database, Result, ServiceError,
 };
 use prisma_client_rust::or;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use utils::{
     datetime::{now_time, to_local_string},
     paginate::{PaginateParams, PaginateResult},
 };
 
 pub async fn create(db: &Database, name: &str, sign: &str, params: CreateParams) -> Result<Info> {
-    Ok(db
+    let data = db
         .client
-        .system_dict()
-        .create_unchecked(name.to_owned(), sign.to_owned(), params.to_params())
-        .exec()
-        .await?
-        .into())
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            let data = client
+                .system_dict()
+                .create_unchecked(name.to_owned(), sign.to_owned(), params.to_params())
+                .exec()
+                .await?;
+            system_audit_service::record(
+                &client,
+                "system_dict",
+                data.id,
+                AuditAction::Create,
+                None,
+                Some(&data),
+            )
+            .await?;
+            Ok(data)
+        })
+        .await?;
+    let info = data.into();
+    forget_cache(db).await?;
+    Ok(info)
 }
 
 pub async fn update(db: &Database, id: i32, params: UpdateParams) -> Result<Info> {
-    Ok(db
-        .client
-        .system_dict()
-        .update_unchecked(system_dict::id::equals(id), params.to_params())
-        .exec()
-        .await?
-        .into())
+    set(db, id, AuditAction::Update, params.to_params()).await
 }
 pub async fn delete(db: &Database, id: i32) -> Result<Info> {
-    Ok(db
+    set(
+        db,
+        id,
+        AuditAction::Delete,
+        vec![system_dict::deleted_at::set(Some(now_time()))],
+    )
+    .await
+}
+
+/// update the dict and audit it in one transaction
+async fn set(
+    db: &Database,
+    id: i32,
+    action: AuditAction,
+    params: Vec<system_dict::UncheckedSetParam>,
+) -> Result<Info> {
+    let data = db
         .client
-        .system_dict()
-        .update(
-            system_dict::id::equals(id),
-            vec![system_dict::deleted_at::set(Some(now_time()))],
-        )
-        .exec()
-        .await?
-        .into())
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            let before = client
+                .system_dict()
+                .find_unique(system_dict::id::equals(id))
+                .exec()
+                .await?;
+            let data = client
+                .system_dict()
+                .update_unchecked(system_dict::id::equals(id), params)
+                .exec()
+                .await?;
+            system_audit_service::record(
+                &client,
+                "system_dict",
+                data.id,
+                action,
+                before.as_ref(),
+                Some(&data),
+            )
+            .await?;
+            Ok(data)
+        })
+        .await?;
+    let info = data.into();
+    forget_cache(db).await?;
+    Ok(info)
 }
 pub async fn info(db: &Database, id: i32) -> Result<Info> {
     Ok(db
@@ -71,16 +119,25 @@ pub async fn get_by_sign(
         .map(|x| x.into()))
 }
 pub async fn all(db: &Database) -> Result<Vec<Info>> {
-    Ok(db
-        .client
-        .system_dict()
-        .find_many(vec![system_dict::deleted_at::equals(None)])
-        .order_by(system_dict::id::order(SortOrder::Asc))
-        .exec()
-        .await?
-        .into_iter()
-        .map(|x| x.into())
-        .collect::<Vec<Info>>())
+    db.cache()
+        .remember_forever(CacheType::SystemDict, "all", None, || async {
+            Ok(db
+                .client
+                .system_dict()
+                .find_many(vec![system_dict::deleted_at::equals(None)])
+                .order_by(system_dict::id::order(SortOrder::Asc))
+                .exec()
+                .await?
+                .into_iter()
+                .map(|x| x.into())
+                .collect::<Vec<Info>>())
+        })
+        .await
+}
+
+/// drop cached dict and dict data lookups
+pub async fn forget_cache(db: &Database) -> Result<i64> {
+    db.cache().flush(Some(CacheType::SystemDict)).await
 }
 
 pub async fn paginate(db: &Database, params: &SearchParams) -> Result<PaginateResult<Vec<Info>>> {
@@ -130,7 +187,7 @@ impl SearchParams {
         }
     }
 }
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Info {
     id: i32,
     name: String,
@@ -0,0 +1,355 @@
+use crate::{
+    prisma::{system_grant, system_grant_menu, system_menu, SortOrder},
+    system_audit_service::{self, AuditAction},
+    system_menu_service, Database, Result, ServiceError,
+};
+use prisma_client_rust::chrono::{DateTime, FixedOffset};
+use serde::Serialize;
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use utils::{
+    datetime::{now_time, to_local_string},
+    paginate::{PaginateParams, PaginateResult},
+};
+
+pub async fn create(
+    db: &Database,
+    granted_by: i32,
+    start_at: DateTime<FixedOffset>,
+    end_at: DateTime<FixedOffset>,
+    params: CreateParams,
+    menu_ids: Vec<i32>,
+) -> Result<system_grant::Data> {
+    let result = db
+        .client
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            let grant = client
+                .system_grant()
+                .create_unchecked(start_at, end_at, granted_by, params.to_params())
+                .exec()
+                .await?;
+            if !menu_ids.is_empty() {
+                client
+                    .system_grant_menu()
+                    .create_many(
+                        menu_ids
+                            .into_iter()
+                            .map(|x| system_grant_menu::create_unchecked(grant.id, x, vec![]))
+                            .collect::<Vec<system_grant_menu::CreateUnchecked>>(),
+                    )
+                    .exec()
+                    .await?;
+            }
+            system_audit_service::record(
+                &client,
+                "system_grant",
+                grant.id,
+                AuditAction::Create,
+                None,
+                Some(&grant),
+            )
+            .await?;
+            Ok(grant)
+        })
+        .await?;
+    db.permissions().forget_grants();
+    Ok(result)
+}
+
+/// revoke the grant before it expires, `None` when it was revoked or has expired
+pub async fn revoke(db: &Database, id: i32, revoked_by: i32) -> Result<Option<Info>> {
+    let data = db
+        .client
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            let before = client
+                .system_grant()
+                .find_unique(system_grant::id::equals(id))
+                .exec()
+                .await?
+                .ok_or(ServiceError::DataNotFound)?;
+            let now = now_time();
+            // only an active grant, another admin may have revoked it meanwhile
+            let revoked = client
+                .system_grant()
+                .update_many(
+                    vec![
+                        system_grant::id::equals(id),
+                        system_grant::revoked_at::equals(None),
+                        system_grant::end_at::gt(now),
+                    ],
+                    vec![
+                        system_grant::revoked_at::set(Some(now)),
+                        system_grant::revoked_by::set(revoked_by),
+                    ],
+                )
+                .exec()
+                .await?;
+            if revoked == 0 {
+                return Ok(None);
+            }
+            let data = client
+                .system_grant()
+                .find_unique(system_grant::id::equals(id))
+                .exec()
+                .await?
+                .ok_or(ServiceError::DataNotFound)?;
+            system_audit_service::record(
+                &client,
+                "system_grant",
+                data.id,
+                AuditAction::Update,
+                Some(&before),
+                Some(&data),
+            )
+            .await?;
+            Ok(Some(data))
+        })
+        .await?;
+    let data = match data {
+        Some(data) => data,
+        None => return Ok(None),
+    };
+    db.permissions().forget_grants();
+    Ok(Some(data.into()))
+}
+
+pub async fn info(db: &Database, id: i32) -> Result<Info> {
+    Ok(db
+        .client
+        .system_grant()
+        .find_first(vec![
+            system_grant::id::equals(id),
+            system_grant::deleted_at::equals(None),
+        ])
+        .with(system_grant::grant_menu::fetch(vec![]).with(system_grant_menu::menu::fetch()))
+        .exec()
+        .await?
+        .ok_or(ServiceError::DataNotFound)?
+        .into())
+}
+
+pub async fn paginate(db: &Database, params: &SearchParams) -> Result<PaginateResult<Vec<Info>>> {
+    let (data, total) = db
+        .client
+        ._batch((
+            db.client
+                .system_grant()
+                .find_many(params.to_params())
+                .skip(params.paginate.get_skip())
+                .take(params.paginate.get_limit())
+                .with(
+                    system_grant::grant_menu::fetch(vec![]).with(system_grant_menu::menu::fetch()),
+                )
+                .order_by(system_grant::id::order(SortOrder::Desc)),
+            db.client.system_grant().count(params.to_params()),
+        ))
+        .await?;
+    Ok(PaginateResult {
+        total,
+        data: data.into_iter().map(|x| x.into()).collect::<Vec<Info>>(),
+    })
+}
+
+/// grants which are not revoked and not yet expired, with their menus
+pub async fn get_unexpired(db: &Database) -> Result<Vec<Grant>> {
+    Ok(db
+        .client
+        .system_grant()
+        .find_many(vec![
+            system_grant::deleted_at::equals(None),
+            system_grant::revoked_at::equals(None),
+            system_grant::end_at::gt(now_time()),
+        ])
+        .with(
+            system_grant::grant_menu::fetch(vec![system_grant_menu::menu::is(vec![
+                system_menu::deleted_at::equals(None),
+            ])])
+            .with(system_grant_menu::menu::fetch()),
+        )
+        .exec()
+        .await?
+        .into_iter()
+        .map(|x| Grant {
+            user_id: x.user_id,
+            role_id: x.role_id,
+            start_at: x.start_at.timestamp(),
+            end_at: x.end_at.timestamp(),
+            menus: get_menus(&x),
+        })
+        .collect::<Vec<Grant>>())
+}
+
+fn get_menus(value: &system_grant::Data) -> Vec<system_menu_service::Info> {
+    match value.grant_menu() {
+        Ok(grant_menus) => grant_menus
+            .iter()
+            .filter_map(|x| x.menu().ok().map(|x| x.clone().into()))
+            .collect::<Vec<system_menu_service::Info>>(),
+        Err(_) => vec![],
+    }
+}
+
+/// unexpired grant, as kept by the permission index
+#[derive(Debug, Clone)]
+pub struct Grant {
+    pub user_id: i32,
+    pub role_id: i32,
+    pub start_at: i64,
+    pub end_at: i64,
+    pub menus: Vec<system_menu_service::Info>,
+}
+
+impl Grant {
+    pub fn is_active(&self, timestamp: i64) -> bool {
+        self.start_at <= timestamp && timestamp < self.end_at
+    }
+
+    /// granted to the user, directly or through one of the roles
+    pub fn is_granted(&self, user_id: i32, role_ids: &[i32]) -> bool {
+        (self.user_id > 0 && self.user_id == user_id)
+            || (self.role_id > 0 && role_ids.contains(&self.role_id))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize_repr, Deserialize_repr)]
+#[repr(i32)]
+pub enum GrantStatus {
+    /// 1.未生效
+    Pending = 1,
+    /// 2.生效中
+    Active = 2,
+    /// 3.已过期
+    Expired = 3,
+    /// 4.已撤销
+    Revoked = 4,
+}
+
+impl From<i32> for GrantStatus {
+    fn from(value: i32) -> Self {
+        match value {
+            1 => Self::Pending,
+            2 => Self::Active,
+            3 => Self::Expired,
+            4 => Self::Revoked,
+            _ => Self::Active,
+        }
+    }
+}
+
+impl From<GrantStatus> for i32 {
+    fn from(value: GrantStatus) -> Self {
+        match value {
+            GrantStatus::Pending => 1,
+            GrantStatus::Active => 2,
+            GrantStatus::Expired => 3,
+            GrantStatus::Revoked => 4,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Info {
+    id: i32,
+    user_id: i32,
+    role_id: i32,
+    menu_ids: Vec<i32>,
+    menu_titles: Vec<String>,
+    start_at: String,
+    end_at: String,
+    reason: String,
+    granted_by: i32,
+    revoked_at: Option<String>,
+    revoked_by: i32,
+    status: GrantStatus,
+    created_at: String,
+}
+
+impl From<system_grant::Data> for Info {
+    fn from(value: system_grant::Data) -> Self {
+        let menus = get_menus(&value);
+        let now = now_time();
+        let status = match value.revoked_at.is_some() {
+            true => GrantStatus::Revoked,
+            false if value.start_at > now => GrantStatus::Pending,
+            false if value.end_at <= now => GrantStatus::Expired,
+            false => GrantStatus::Active,
+        };
+        Self {
+            id: value.id,
+            user_id: value.user_id,
+            role_id: value.role_id,
+            menu_ids: menus.iter().map(|x| x.id).collect::<Vec<i32>>(),
+            menu_titles: menus.into_iter().map(|x| x.title).collect::<Vec<String>>(),
+            start_at: to_local_string(value.start_at),
+            end_at: to_local_string(value.end_at),
+            reason: value.reason,
+            granted_by: value.granted_by,
+            revoked_at: value.revoked_at.map(to_local_string),
+            revoked_by: value.revoked_by,
+            status,
+            created_at: to_local_string(value.created_at),
+        }
+    }
+}
+
+pub struct SearchParams {
+    user_id: Option<i32>,
+    role_id: Option<i32>,
+    status: Option<GrantStatus>,
+    paginate: PaginateParams,
+}
+
+impl SearchParams {
+    fn to_params(&self) -> Vec<system_grant::WhereParam> {
+        let mut params = vec![system_grant::deleted_at::equals(None)];
+        if let Some(user_id) = self.user_id {
+            params.push(system_grant::user_id::equals(user_id));
+        }
+        if let Some(role_id) = self.role_id {
+            params.push(system_grant::role_id::equals(role_id));
+        }
+        if let Some(status) = &self.status {
+            let now = now_time();
+            match status {
+                GrantStatus::Pending => {
+                    params.push(system_grant::revoked_at::equals(None));
+                    params.push(system_grant::start_at::gt(now));
+                }
+                GrantStatus::Active => {
+                    params.push(system_grant::revoked_at::equals(None));
+                    params.push(system_grant::start_at::lte(now));
+                    params.push(system_grant::end_at::gt(now));
+                }
+                GrantStatus::Expired => {
+                    params.push(system_grant::revoked_at::equals(None));
+                    params.push(system_grant::end_at::lte(now));
+                }
+                GrantStatus::Revoked => {
+                    params.push(system_grant::revoked_at::not(None));
+                }
+            }
+        }
+        params
+    }
+
+    pub fn new(
+        user_id: Option<i32>,
+        role_id: Option<i32>,
+        status: Option<GrantStatus>,
+        paginate: PaginateParams,
+    ) -> Self {
+        Self {
+            user_id,
+            role_id,
+            status,
+            paginate,
+        }
+    }
+}
+
+system_grant::partial_unchecked!(CreateParams {
+    user_id
+    role_id
+    reason
+});
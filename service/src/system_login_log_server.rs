@@ -1,7 +1,8 @@
 use crate::{
     generate_prisma::system_user,
     prisma::{system_login_log, SortOrder},
-    system_user_service, Database, Result, ServiceError,
+    system_user_service::{self, DataScope},
+    Database, Result, ServiceError,
 };
 use prisma_client_rust::{or, prisma_models::parse_datetime};
 use serde::Serialize;
@@ -35,18 +36,27 @@ pub async fn info(db: &Database, id: i32) -> Result<Info> {
         .ok_or(ServiceError::DataNotFound)?
         .into())
 }
-pub async fn paginate(db: &Database, params: &SearchParams) -> Result<PaginateResult<Vec<Info>>> {
+pub async fn paginate(
+    db: &Database,
+    params: &SearchParams,
+    scope: &DataScope,
+) -> Result<PaginateResult<Vec<Info>>> {
+    let mut query_params = params.to_params();
+    let user_params = scope.to_params();
+    if !user_params.is_empty() {
+        query_params.push(system_login_log::user::is(user_params));
+    }
     let (data, total) = db
         .client
         ._batch((
             db.client
                 .system_login_log()
-                .find_many(params.to_params())
+                .find_many(query_params.clone())
                 .skip(params.paginate.get_skip())
                 .take(params.paginate.get_limit())
                 .with(system_login_log::user::fetch())
                 .order_by(system_login_log::id::order(SortOrder::Desc)),
-            db.client.system_login_log().count(params.to_params()),
+            db.client.system_login_log().count(query_params),
         ))
         .await?;
     Ok(PaginateResult {
@@ -55,6 +65,48 @@ pub async fn paginate(db: &Database, params: &SearchParams) -> Result<PaginateRe
     })
 }
 
+/// columns which can be exported, keyed by their path in [`Info`]
+pub const EXPORT_COLUMNS: &[(&str, &str)] = &[
+    ("id", "ID"),
+    ("user_id", "管理员ID"),
+    ("user/username", "管理员账号"),
+    ("user/nickname", "管理员昵称"),
+    ("ip_address", "IP地址"),
+    ("ip_address_name", "IP地址名称"),
+    ("browser_agent", "浏览器"),
+    ("created_at", "登录时间"),
+];
+
+/// next rows to export, newest first, older than the `before_id` cursor
+pub async fn export(
+    db: &Database,
+    params: &SearchParams,
+    scope: &DataScope,
+    before_id: Option<i32>,
+    take: i64,
+) -> Result<Vec<Info>> {
+    let mut query_params = params.to_params();
+    let user_params = scope.to_params();
+    if !user_params.is_empty() {
+        query_params.push(system_login_log::user::is(user_params));
+    }
+    if let Some(before_id) = before_id {
+        query_params.push(system_login_log::id::lt(before_id));
+    }
+    Ok(db
+        .client
+        .system_login_log()
+        .find_many(query_params)
+        .take(take)
+        .with(system_login_log::user::fetch())
+        .order_by(system_login_log::id::order(SortOrder::Desc))
+        .exec()
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect::<Vec<Info>>())
+}
+
 pub struct SearchParams {
     user_id: Option<i32>,
     keyword: Option<String>,
@@ -0,0 +1,368 @@
+use crate::{
+    mailer_service::{Mail, Mailer},
+    prisma::{system_mail_outbox, SortOrder},
+    system_mail_template_service, Database, Result, ServiceError,
+};
+use prisma_client_rust::{chrono::Duration, or};
+use serde::Serialize;
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use utils::{
+    datetime::{now_time, to_local_string},
+    paginate::{PaginateParams, PaginateResult},
+};
+
+/// most mails sent on one dispatch
+const BATCH_SIZE: i64 = 50;
+/// seconds before the first retry, doubled on every attempt
+const RETRY_BASE: i64 = 30;
+/// longest wait between two attempts
+const RETRY_MAX: i64 = 60 * 60;
+/// mails left sending for the minutes are taken as interrupted
+const SENDING_TIMEOUT: i64 = 10;
+/// body shown for sensitive mails
+const REDACTED_BODY: &str = "[redacted]";
+
+/// render the template of the sign and queue the mail
+pub async fn queue<T: Serialize>(
+    db: &Database,
+    recipient: &str,
+    sign: &str,
+    variables: T,
+) -> Result<Info> {
+    create(db, recipient, sign, variables, false).await
+}
+
+/// queue a mail carrying a secret such as an email code, its body is never
+/// shown and is dropped once the mail is sent or has failed
+pub async fn queue_sensitive<T: Serialize>(
+    db: &Database,
+    recipient: &str,
+    sign: &str,
+    variables: T,
+) -> Result<Info> {
+    create(db, recipient, sign, variables, true).await
+}
+
+async fn create<T: Serialize>(
+    db: &Database,
+    recipient: &str,
+    sign: &str,
+    variables: T,
+    sensitive: bool,
+) -> Result<Info> {
+    let rendered = system_mail_template_service::render(db, sign, variables).await?;
+    Ok(db
+        .client
+        .system_mail_outbox()
+        .create_unchecked(
+            recipient.to_owned(),
+            rendered.subject,
+            rendered.body,
+            vec![
+                system_mail_outbox::template_sign::set(sign.to_owned()),
+                system_mail_outbox::is_html::set(rendered.is_html),
+                system_mail_outbox::sensitive::set(sensitive),
+            ],
+        )
+        .exec()
+        .await?
+        .into())
+}
+
+/// send the pending mails which are due, returns the number sent
+pub async fn dispatch(db: &Database, mailer: &Mailer) -> Result<i64> {
+    let now = now_time();
+    let data = db
+        .client
+        .system_mail_outbox()
+        .find_many(vec![
+            system_mail_outbox::status::equals(MailStatus::Pending.into()),
+            system_mail_outbox::next_attempt_at::lte(now),
+        ])
+        .order_by(system_mail_outbox::id::order(SortOrder::Asc))
+        .take(BATCH_SIZE)
+        .exec()
+        .await?;
+    let mut sent = 0;
+    for mail in data {
+        // claim the mail, another worker may have taken it
+        let claimed = db
+            .client
+            .system_mail_outbox()
+            .update_many(
+                vec![
+                    system_mail_outbox::id::equals(mail.id),
+                    system_mail_outbox::status::equals(MailStatus::Pending.into()),
+                ],
+                vec![
+                    system_mail_outbox::status::set(MailStatus::Sending.into()),
+                    system_mail_outbox::attempts::increment(1),
+                ],
+            )
+            .exec()
+            .await?;
+        if claimed == 0 {
+            continue;
+        }
+        let attempts = mail.attempts + 1;
+        let result = mailer
+            .send(&Mail {
+                to: mail.recipient.clone(),
+                subject: mail.subject.clone(),
+                body: mail.body.clone(),
+                is_html: mail.is_html,
+            })
+            .await;
+        let sent_now = result.is_ok();
+        let mut params = match result {
+            Ok(_) => {
+                sent += 1;
+                vec![
+                    system_mail_outbox::status::set(MailStatus::Sent.into()),
+                    system_mail_outbox::sent_at::set(Some(now_time())),
+                    system_mail_outbox::last_error::set(String::new()),
+                ]
+            }
+            Err(err) => {
+                let err = match err {
+                    ServiceError::Mail(err) => err,
+                    err => format!("{:?}", err),
+                };
+                tracing::warn!("mail {} to {} not sent: {}", mail.id, mail.recipient, err);
+                let status = match attempts >= mail.max_attempts {
+                    true => MailStatus::Failed,
+                    false => MailStatus::Pending,
+                };
+                vec![
+                    system_mail_outbox::status::set(status.into()),
+                    system_mail_outbox::next_attempt_at::set(now_time() + get_backoff(attempts)),
+                    system_mail_outbox::last_error::set(err),
+                ]
+            }
+        };
+        // a sent or failed secret is of no use any more
+        if mail.sensitive && (sent_now || attempts >= mail.max_attempts) {
+            params.push(system_mail_outbox::body::set(String::new()));
+        }
+        db.client
+            .system_mail_outbox()
+            .update(system_mail_outbox::id::equals(mail.id), params)
+            .exec()
+            .await?;
+    }
+    Ok(sent)
+}
+
+/// put mails left sending by a stopped worker back to pending
+pub async fn recover(db: &Database) -> Result<i64> {
+    Ok(db
+        .client
+        .system_mail_outbox()
+        .update_many(
+            vec![
+                system_mail_outbox::status::equals(MailStatus::Sending.into()),
+                system_mail_outbox::updated_at::lt(now_time() - Duration::minutes(SENDING_TIMEOUT)),
+            ],
+            vec![system_mail_outbox::status::set(MailStatus::Pending.into())],
+        )
+        .exec()
+        .await?)
+}
+
+/// dispatch on every interval
+pub async fn run(db: &Database, mailer: Mailer, interval: std::time::Duration) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        if let Err(err) = recover(db).await {
+            tracing::error!("mail outbox recover failed: {:?}", err);
+        }
+        if let Err(err) = dispatch(db, &mailer).await {
+            tracing::error!("mail outbox dispatch failed: {:?}", err);
+        }
+    }
+}
+
+/// queue a failed mail again, with all its attempts
+pub async fn retry(db: &Database, id: i32) -> Result<Info> {
+    Ok(db
+        .client
+        .system_mail_outbox()
+        .update(
+            system_mail_outbox::id::equals(id),
+            vec![
+                system_mail_outbox::status::set(MailStatus::Pending.into()),
+                system_mail_outbox::attempts::set(0),
+                system_mail_outbox::next_attempt_at::set(now_time()),
+            ],
+        )
+        .exec()
+        .await?
+        .into())
+}
+
+pub async fn info(db: &Database, id: i32) -> Result<Info> {
+    Ok(db
+        .client
+        .system_mail_outbox()
+        .find_unique(system_mail_outbox::id::equals(id))
+        .exec()
+        .await?
+        .ok_or(ServiceError::DataNotFound)?
+        .into())
+}
+
+pub async fn paginate(db: &Database, params: &SearchParams) -> Result<PaginateResult<Vec<Info>>> {
+    let (data, total) = db
+        .client
+        ._batch((
+            db.client
+                .system_mail_outbox()
+                .find_many(params.to_params())
+                .skip(params.paginate.get_skip())
+                .take(params.paginate.get_limit())
+                .order_by(system_mail_outbox::id::order(SortOrder::Desc)),
+            db.client.system_mail_outbox().count(params.to_params()),
+        ))
+        .await?;
+    Ok(PaginateResult {
+        total,
+        data: data.into_iter().map(|x| x.into()).collect::<Vec<Info>>(),
+    })
+}
+
+/// wait before the next attempt, `RETRY_BASE` doubled per attempt up to `RETRY_MAX`
+fn get_backoff(attempts: i32) -> Duration {
+    let shift = attempts.clamp(1, 16) as u32 - 1;
+    Duration::seconds((RETRY_BASE << shift).min(RETRY_MAX))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize_repr, Deserialize_repr)]
+#[repr(i32)]
+pub enum MailStatus {
+    /// 1.待发送
+    Pending = 1,
+    /// 2.发送中
+    Sending = 2,
+    /// 3.已发送
+    Sent = 3,
+    /// 4.发送失败
+    Failed = 4,
+}
+
+impl From<i32> for MailStatus {
+    fn from(value: i32) -> Self {
+        match value {
+            1 => Self::Pending,
+            2 => Self::Sending,
+            3 => Self::Sent,
+            4 => Self::Failed,
+            _ => Self::Pending,
+        }
+    }
+}
+
+impl From<MailStatus> for i32 {
+    fn from(value: MailStatus) -> Self {
+        match value {
+            MailStatus::Pending => 1,
+            MailStatus::Sending => 2,
+            MailStatus::Sent => 3,
+            MailStatus::Failed => 4,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Info {
+    id: i32,
+    template_sign: String,
+    recipient: String,
+    subject: String,
+    body: String,
+    is_html: bool,
+    sensitive: bool,
+    status: MailStatus,
+    attempts: i32,
+    max_attempts: i32,
+    next_attempt_at: String,
+    last_error: String,
+    sent_at: Option<String>,
+    created_at: String,
+}
+
+impl Info {
+    pub fn status(&self) -> MailStatus {
+        self.status
+    }
+
+    pub fn sensitive(&self) -> bool {
+        self.sensitive
+    }
+}
+
+impl From<system_mail_outbox::Data> for Info {
+    fn from(value: system_mail_outbox::Data) -> Self {
+        Self {
+            id: value.id,
+            template_sign: value.template_sign,
+            recipient: value.recipient,
+            subject: value.subject,
+            body: match value.sensitive {
+                true => REDACTED_BODY.to_owned(),
+                false => value.body,
+            },
+            is_html: value.is_html,
+            sensitive: value.sensitive,
+            status: value.status.into(),
+            attempts: value.attempts,
+            max_attempts: value.max_attempts,
+            next_attempt_at: to_local_string(value.next_attempt_at),
+            last_error: value.last_error,
+            sent_at: value.sent_at.map(to_local_string),
+            created_at: to_local_string(value.created_at),
+        }
+    }
+}
+
+pub struct SearchParams {
+    keyword: Option<String>,
+    template_sign: Option<String>,
+    status: Option<MailStatus>,
+    paginate: PaginateParams,
+}
+
+impl SearchParams {
+    fn to_params(&self) -> Vec<system_mail_outbox::WhereParam> {
+        let mut params = vec![];
+        if let Some(keyword) = &self.keyword {
+            params.push(or!(
+                system_mail_outbox::recipient::contains(keyword.to_string()),
+                system_mail_outbox::subject::contains(keyword.to_string()),
+            ));
+        }
+        if let Some(template_sign) = &self.template_sign {
+            params.push(system_mail_outbox::template_sign::equals(
+                template_sign.to_owned(),
+            ));
+        }
+        if let Some(status) = self.status {
+            params.push(system_mail_outbox::status::equals(status.into()));
+        }
+        params
+    }
+
+    pub fn new(
+        keyword: Option<String>,
+        template_sign: Option<String>,
+        status: Option<MailStatus>,
+        paginate: PaginateParams,
+    ) -> Self {
+        Self {
+            keyword,
+            template_sign,
+            status,
+            paginate,
+        }
+    }
+}
@@ -0,0 +1,332 @@
+use crate::{
+    prisma::{system_mail_template, SortOrder},
+    system_audit_service::{self, AuditAction},
+    Database, Result, ServiceError,
+};
+use minijinja::{AutoEscape, Environment, UndefinedBehavior};
+use prisma_client_rust::or;
+use serde::Serialize;
+use utils::{
+    datetime::{now_time, to_local_string},
+    paginate::{PaginateParams, PaginateResult},
+};
+
+/// templates the services send by sign, seeded on init: (sign, name, subject, body, variables)
+pub const DEFAULT_TEMPLATES: [(&str, &str, &str, &str, &str); 2] = [
+    (
+        "member_register_code",
+        "会员注册验证码",
+        "注册验证码",
+        "您的验证码是 {{ code }}，{{ minutes }} 分钟内有效。",
+        r#"{"code":"123456","minutes":10}"#,
+    ),
+    (
+        "member_login_code",
+        "会员登录验证码",
+        "登录验证码",
+        "您的验证码是 {{ code }}，{{ minutes }} 分钟内有效。",
+        r#"{"code":"123456","minutes":10}"#,
+    ),
+];
+
+pub async fn create(
+    db: &Database,
+    name: &str,
+    sign: &str,
+    subject: &str,
+    body: &str,
+    params: CreateParams,
+) -> Result<Info> {
+    Ok(db
+        .client
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            let data = client
+                .system_mail_template()
+                .create_unchecked(
+                    name.to_owned(),
+                    sign.to_owned(),
+                    subject.to_owned(),
+                    body.to_owned(),
+                    params.to_params(),
+                )
+                .exec()
+                .await?;
+            system_audit_service::record(
+                &client,
+                "system_mail_template",
+                data.id,
+                AuditAction::Create,
+                None,
+                Some(&data),
+            )
+            .await?;
+            Ok(data)
+        })
+        .await?
+        .into())
+}
+
+pub async fn update(db: &Database, id: i32, params: UpdateParams) -> Result<Info> {
+    set(db, id, AuditAction::Update, params.to_params()).await
+}
+
+pub async fn delete(db: &Database, id: i32) -> Result<Info> {
+    set(
+        db,
+        id,
+        AuditAction::Delete,
+        vec![system_mail_template::deleted_at::set(Some(now_time()))],
+    )
+    .await
+}
+
+/// update the template and audit it in one transaction
+async fn set(
+    db: &Database,
+    id: i32,
+    action: AuditAction,
+    params: Vec<system_mail_template::UncheckedSetParam>,
+) -> Result<Info> {
+    Ok(db
+        .client
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            let before = client
+                .system_mail_template()
+                .find_unique(system_mail_template::id::equals(id))
+                .exec()
+                .await?;
+            let data = client
+                .system_mail_template()
+                .update_unchecked(system_mail_template::id::equals(id), params)
+                .exec()
+                .await?;
+            system_audit_service::record(
+                &client,
+                "system_mail_template",
+                data.id,
+                action,
+                before.as_ref(),
+                Some(&data),
+            )
+            .await?;
+            Ok(data)
+        })
+        .await?
+        .into())
+}
+
+pub async fn info(db: &Database, id: i32) -> Result<Info> {
+    Ok(db
+        .client
+        .system_mail_template()
+        .find_first(vec![
+            system_mail_template::id::equals(id),
+            system_mail_template::deleted_at::equals(None),
+        ])
+        .exec()
+        .await?
+        .ok_or(ServiceError::DataNotFound)?
+        .into())
+}
+
+pub async fn get_by_sign(
+    db: &Database,
+    sign: &str,
+    filter_id: Option<i32>,
+) -> Result<Option<Info>> {
+    let mut params = vec![
+        system_mail_template::sign::equals(sign.to_owned()),
+        system_mail_template::deleted_at::equals(None),
+    ];
+    if let Some(id) = filter_id {
+        params.push(system_mail_template::id::not(id));
+    }
+    Ok(db
+        .client
+        .system_mail_template()
+        .find_first(params)
+        .exec()
+        .await?
+        .map(|x| x.into()))
+}
+
+pub async fn paginate(db: &Database, params: &SearchParams) -> Result<PaginateResult<Vec<Info>>> {
+    let (data, total) = db
+        .client
+        ._batch((
+            db.client
+                .system_mail_template()
+                .find_many(params.to_params())
+                .skip(params.paginate.get_skip())
+                .take(params.paginate.get_limit())
+                .order_by(system_mail_template::id::order(SortOrder::Desc)),
+            db.client.system_mail_template().count(params.to_params()),
+        ))
+        .await?;
+    Ok(PaginateResult {
+        total,
+        data: data.into_iter().map(|x| x.into()).collect::<Vec<Info>>(),
+    })
+}
+
+/// create the default templates which are missing
+pub async fn seed_defaults(db: &Database) -> Result<()> {
+    for (sign, name, subject, body, variables) in DEFAULT_TEMPLATES {
+        if get_by_sign(db, sign, None).await?.is_some() {
+            continue;
+        }
+        create(
+            db,
+            name,
+            sign,
+            subject,
+            body,
+            CreateParams {
+                is_html: None,
+                variables: Some(variables.to_owned()),
+                remark: None,
+                status: None,
+            },
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// render the enabled template of the sign with the variables
+pub async fn render<T: Serialize>(db: &Database, sign: &str, variables: T) -> Result<Rendered> {
+    let data = db
+        .client
+        .system_mail_template()
+        .find_first(vec![
+            system_mail_template::sign::equals(sign.to_owned()),
+            system_mail_template::status::equals(1),
+            system_mail_template::deleted_at::equals(None),
+        ])
+        .exec()
+        .await?
+        .ok_or(ServiceError::DataNotFound)?;
+    preview(&data.subject, &data.body, data.is_html, variables)
+}
+
+/// render a template source, undefined variables are errors and
+/// html bodies escape the variables
+pub fn preview<T: Serialize>(
+    subject: &str,
+    body: &str,
+    is_html: bool,
+    variables: T,
+) -> Result<Rendered> {
+    let mut env = Environment::new();
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    let subject = env.render_str(subject, &variables)?;
+    if is_html {
+        env.set_auto_escape_callback(|_| AutoEscape::Html);
+    }
+    Ok(Rendered {
+        subject: subject.trim().to_owned(),
+        body: env.render_str(body, &variables)?,
+        is_html,
+    })
+}
+
+/// check the template sources compile
+pub fn validate(subject: &str, body: &str) -> Result<()> {
+    let env = Environment::new();
+    env.template_from_str(subject)?;
+    env.template_from_str(body)?;
+    Ok(())
+}
+
+/// rendered mail content
+#[derive(Debug, Clone, Serialize)]
+pub struct Rendered {
+    pub subject: String,
+    pub body: String,
+    pub is_html: bool,
+}
+
+pub struct SearchParams {
+    keyword: Option<String>,
+    status: Option<i32>,
+    paginate: PaginateParams,
+}
+
+impl SearchParams {
+    fn to_params(&self) -> Vec<system_mail_template::WhereParam> {
+        let mut params = vec![system_mail_template::deleted_at::equals(None)];
+        if let Some(keyword) = &self.keyword {
+            params.push(or!(
+                system_mail_template::name::contains(keyword.to_string()),
+                system_mail_template::sign::contains(keyword.to_string()),
+                system_mail_template::subject::contains(keyword.to_string()),
+            ));
+        }
+        if let Some(status) = self.status {
+            params.push(system_mail_template::status::equals(status));
+        }
+        params
+    }
+
+    pub fn new(keyword: Option<String>, status: Option<i32>, paginate: PaginateParams) -> Self {
+        Self {
+            keyword,
+            status,
+            paginate,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Info {
+    id: i32,
+    name: String,
+    sign: String,
+    subject: String,
+    body: String,
+    is_html: bool,
+    /// sample values of the variables
+    variables: serde_json::Value,
+    remark: String,
+    status: i32,
+    created_at: String,
+    updated_at: String,
+}
+
+impl From<system_mail_template::Data> for Info {
+    fn from(value: system_mail_template::Data) -> Self {
+        Self {
+            id: value.id,
+            name: value.name,
+            sign: value.sign,
+            subject: value.subject,
+            body: value.body,
+            is_html: value.is_html,
+            variables: serde_json::from_str(&value.variables).unwrap_or_default(),
+            remark: value.remark,
+            status: value.status,
+            created_at: to_local_string(value.created_at),
+            updated_at: to_local_string(value.updated_at),
+        }
+    }
+}
+
+system_mail_template::partial_unchecked!(CreateParams {
+    is_html
+    variables
+    remark
+    status
+});
+
+system_mail_template::partial_unchecked!(UpdateParams {
+    name
+    sign
+    subject
+    body
+    is_html
+    variables
+    remark
+    status
+});
@@ -1,7 +1,9 @@
 use crate::{
+    cache_service::{self, CacheType, Driver},
     prisma::{system_menu, SortOrder},
-    system_role_menu_service, system_role_service, system_user_service, Database, Result,
-    ServiceError,
+    system_audit_service::{self, AuditAction},
+    system_permission_service, system_role_menu_service, system_role_service, system_user_service,
+    Database, Result, ServiceError,
 };
 use getset::Getters;
 use serde::{Deserialize, Serialize};
@@ -11,37 +13,87 @@ use utils::{
     tree::{get_tree_start_parent_id, vec_to_tree_into, Tree, TreeInfo},
 };
 
+/// role menus cache seconds
+const ROLE_MENUS_CACHE_SECONDS: i64 = 10 * 60;
+
 pub async fn create(db: &Database, title: &str, params: CreateParams) -> Result<Info> {
-    Ok(db
+    let data = db
         .client
-        .system_menu()
-        .create_unchecked(title.to_owned(), params.to_params())
-        .exec()
-        .await?
-        .into())
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            let data = client
+                .system_menu()
+                .create_unchecked(title.to_owned(), params.to_params())
+                .exec()
+                .await?;
+            system_audit_service::record(
+                &client,
+                "system_menu",
+                data.id,
+                AuditAction::Create,
+                None,
+                Some(&data),
+            )
+            .await?;
+            Ok(data)
+        })
+        .await?;
+    let info = data.into();
+    db.permissions().forget_menus();
+    Ok(info)
 }
 
 pub async fn update(db: &Database, id: i32, params: UpdateParams) -> Result<Info> {
-    Ok(db
-        .client
-        .system_menu()
-        .update_unchecked(system_menu::id::equals(id), params.to_params())
-        .exec()
-        .await?
-        .into())
+    set(db, id, AuditAction::Update, params.to_params()).await
 }
 
 pub async fn delete(db: &Database, id: i32) -> Result<Info> {
-    Ok(db
+    set(
+        db,
+        id,
+        AuditAction::Delete,
+        vec![system_menu::deleted_at::set(Some(now_time()))],
+    )
+    .await
+}
+
+/// update the menu and audit it in one transaction
+async fn set(
+    db: &Database,
+    id: i32,
+    action: AuditAction,
+    params: Vec<system_menu::UncheckedSetParam>,
+) -> Result<Info> {
+    let data = db
         .client
-        .system_menu()
-        .update(
-            system_menu::id::equals(id),
-            vec![system_menu::deleted_at::set(Some(now_time()))],
-        )
-        .exec()
-        .await?
-        .into())
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            let before = client
+                .system_menu()
+                .find_unique(system_menu::id::equals(id))
+                .exec()
+                .await?;
+            let data = client
+                .system_menu()
+                .update_unchecked(system_menu::id::equals(id), params)
+                .exec()
+                .await?;
+            system_audit_service::record(
+                &client,
+                "system_menu",
+                data.id,
+                action,
+                before.as_ref(),
+                Some(&data),
+            )
+            .await?;
+            Ok(data)
+        })
+        .await?;
+    let info = data.into();
+    forget_role_menus(db).await?;
+    db.permissions().forget_menus();
+    Ok(info)
 }
 
 pub async fn info(db: &Database, id: i32) -> Result<Info> {
@@ -107,32 +159,140 @@ pub async fn get_menu_by_role(
     role: Option<system_role_service::Info>,
 ) -> Result<Vec<Info>> {
     Ok(match role {
-        Some(role) => system_role_menu_service::get_role_menus(db, role.id()).await?,
+        Some(role) => {
+            // keyed by the shared version, a change on any instance is seen here
+            let version = cache_service::shared_version(db, CacheType::SystemRoleMenus).await?;
+            db.cache()
+                .remember(
+                    CacheType::SystemRoleMenus,
+                    &format!("{}:{}", version, role.id()),
+                    Some(ROLE_MENUS_CACHE_SECONDS),
+                    None,
+                    || system_role_menu_service::get_effective_role_menus(db, role.id()),
+                )
+                .await?
+        }
         None => vec![],
     })
 }
 
+/// merged menus of all the roles, without duplicates
+pub async fn get_menu_by_roles(
+    db: &Database,
+    roles: Vec<system_role_service::Info>,
+) -> Result<Vec<Info>> {
+    let mut menus: Vec<Info> = vec![];
+    for role in roles {
+        for menu in get_menu_by_role(db, Some(role)).await? {
+            if !menus.iter().any(|x| x.id.eq(&menu.id)) {
+                menus.push(menu);
+            }
+        }
+    }
+    menus.sort_by(|a, b| a.sort.cmp(&b.sort).then(a.id.cmp(&b.id)));
+    Ok(menus)
+}
+
+/// drop cached role menus on every instance, menus are inherited so a change
+/// to one role reaches the others
+pub async fn forget_role_menus(db: &Database) -> Result<()> {
+    cache_service::bump_shared_version(db, CacheType::SystemRoleMenus).await?;
+    db.cache().flush(Some(CacheType::SystemRoleMenus)).await?;
+    Ok(())
+}
+
 pub async fn get_menu_id_by_api_request(
     db: &Database,
     method: &str,
     path: &str,
 ) -> Result<Option<(i32, String)>> {
-    let info: Option<Info> = db
+    Ok(system_permission_service::get_api_menu(db, method, path)
+        .await?
+        .map(|x| (x.menu_id, x.menu_names)))
+}
+
+/// diff registered api routes with api permission menus
+pub async fn diff_api_routes(db: &Database, routes: &[ApiRoute]) -> Result<ApiRouteDiff> {
+    let menus = filter_menu_types(Some(vec![MenuType::Api]), get_menus(db).await?);
+    let rules = menus
+        .iter()
+        .map(|x| (x.is_deny == 1, system_permission_service::ApiRule::from(x)))
+        .collect::<Vec<_>>();
+    let missing = routes
+        .iter()
+        .filter(|r| {
+            !rules
+                .iter()
+                .any(|(is_deny, rule)| !is_deny && rule.is_match(&r.method, &r.path))
+        })
+        .cloned()
+        .collect::<Vec<ApiRoute>>();
+    let orphaned = menus
+        .into_iter()
+        .zip(rules.iter())
+        .filter(|(_, (_, rule))| !routes.iter().any(|r| rule.is_match(&r.method, &r.path)))
+        .map(|(menu, _)| menu)
+        .collect::<Vec<Info>>();
+    Ok(ApiRouteDiff { missing, orphaned })
+}
+
+/// create api permission menus for routes, placed under the menu owning the same resource
+pub async fn create_api_menus(db: &Database, routes: Vec<ApiRoute>) -> Result<Vec<Info>> {
+    let menus = get_menus(db).await?;
+    let data = routes
+        .iter()
+        .map(|x| {
+            (
+                x.title.to_owned(),
+                vec![
+                    system_menu::parent_id::set(api_parent_id(&menus, x)),
+                    system_menu::r#type::set(MenuType::Api.into()),
+                    system_menu::api_url::set(x.path.to_owned()),
+                    system_menu::api_method::set(x.method.to_uppercase()),
+                ],
+            )
+        })
+        .collect::<Vec<_>>();
+    let created = db
         .client
-        .system_menu()
-        .find_first(vec![
-            system_menu::api_method::equals(method.to_owned()),
-            system_menu::api_url::equals(path.to_owned()),
-        ])
-        .exec()
+        ._batch(
+            data.into_iter()
+                .map(|(title, params)| db.client.system_menu().create_unchecked(title, params))
+                .collect::<Vec<_>>(),
+        )
         .await?
-        .map(|x| x.into());
-    if let Some(x) = info {
-        let mut parent_names = get_parent_names(db, x.parent_id).await?;
-        parent_names.push(x.title);
-        return Ok(Some((x.id, parent_names.join("/"))));
-    }
-    Ok(None)
+        .into_iter()
+        .map(|x| x.into())
+        .collect::<Vec<Info>>();
+    db.permissions().forget_menus();
+    Ok(created)
+}
+
+/// an existing api menu of the same resource wins, then a page menu whose path ends with the resource
+fn api_parent_id(menus: &[Info], route: &ApiRoute) -> i32 {
+    let resource = route.resource();
+    menus
+        .iter()
+        .find(|x| {
+            x.r#type == MenuType::Api
+                && ApiRoute::path_resource(&x.api_url) == resource
+                && x.parent_id > 0
+        })
+        .map(|x| x.parent_id)
+        .or_else(|| {
+            menus
+                .iter()
+                .find(|x| {
+                    x.r#type == MenuType::Menu
+                        && x.router_path
+                            .rsplit('/')
+                            .next()
+                            .map(|s| s.to_lowercase().replace('_', ""))
+                            .is_some_and(|s| !s.is_empty() && s == resource)
+                })
+                .map(|x| x.id)
+        })
+        .unwrap_or(0)
 }
 
 async fn get_user_menus(
@@ -140,9 +300,17 @@ async fn get_user_menus(
     user_id: i32,
     query_params: &SearchParams,
 ) -> Result<Vec<Info>> {
-    get_menus_by_user_id(db, user_id)
-        .await
-        .map(|x| filter_menu_by_search(query_params, x))
+    let mut menus = get_menus_by_user_id(db, user_id).await?;
+    let granted_menus = system_permission_service::get_granted_menus(db, user_id).await?;
+    if !granted_menus.is_empty() {
+        for menu in granted_menus {
+            if !menus.iter().any(|x| x.id.eq(&menu.id)) {
+                menus.push(menu);
+            }
+        }
+        menus.sort_by(|a, b| a.sort.cmp(&b.sort).then(a.id.cmp(&b.id)));
+    }
+    Ok(filter_menu_by_search(query_params, menus))
 }
 
 fn filter_menu_by_search(query_params: &SearchParams, x: Vec<Info>) -> Vec<Info> {
@@ -198,23 +366,7 @@ async fn get_menus_by_user_id(db: &Database, user_id: i32) -> Result<Vec<Info>>
     if user_permission.username().eq(&db.config.admin_username) {
         return get_menus(db).await;
     }
-    get_menu_by_role(db, user_permission.role().clone()).await
-}
-
-#[async_recursion::async_recursion]
-async fn get_parent_names(db: &Database, menu_id: i32) -> Result<Vec<String>> {
-    let mut parent_names = vec![];
-    let menu = db
-        .client
-        .system_menu()
-        .find_unique(system_menu::id::equals(menu_id))
-        .exec()
-        .await?;
-    if let Some(x) = menu {
-        parent_names = get_parent_names(db, x.parent_id).await?;
-        parent_names.push(x.title);
-    }
-    Ok(parent_names)
+    get_menu_by_roles(db, user_permission.roles().clone()).await
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize_repr, Deserialize_repr)]
@@ -261,6 +413,36 @@ impl From<MenuType> for i32 {
     }
 }
 
+/// api route registered by the admin routers
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ApiRoute {
+    pub method: String,
+    pub path: String,
+    pub title: String,
+}
+
+impl ApiRoute {
+    fn resource(&self) -> String {
+        Self::path_resource(&self.path)
+    }
+
+    fn path_resource(path: &str) -> String {
+        path.trim_start_matches('/')
+            .split('/')
+            .next()
+            .unwrap_or_default()
+            .to_lowercase()
+            .replace('_', "")
+    }
+}
+
+/// routes without api menu, and api menus without route
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiRouteDiff {
+    pub missing: Vec<ApiRoute>,
+    pub orphaned: Vec<Info>,
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct UserMenu {
     /// 菜单ID
@@ -412,6 +594,9 @@ pub struct Info {
     pub api_url: String,
     /// 接口请求方法
     pub api_method: String,
+    /// 是否禁止
+    #[serde(default)]
+    pub is_deny: i32,
     /// 是否隐藏
     pub is_hide: i32,
     /// 是否开启keep_alive
@@ -441,6 +626,7 @@ impl From<system_menu::Data> for Info {
             btn_auth: value.btn_auth,
             api_url: value.api_url,
             api_method: value.api_method,
+            is_deny: value.is_deny,
             is_hide: value.is_hide,
             is_keep_alive: value.is_keep_alive,
             is_affix: value.is_affix,
@@ -488,6 +674,7 @@ system_menu::partial_unchecked!(CreateParams {
     btn_auth
     api_url
     api_method
+    is_deny
     is_hide
     is_keep_alive
     is_affix
@@ -509,6 +696,7 @@ impl From<Menu> for CreateParams {
             btn_auth: Some(value.info.btn_auth),
             api_url: Some(value.info.api_url),
             api_method: Some(value.info.api_method),
+            is_deny: Some(value.info.is_deny),
             is_hide: Some(value.info.is_hide),
             is_keep_alive: Some(value.info.is_keep_alive),
             is_affix: Some(value.info.is_affix),
@@ -531,6 +719,7 @@ system_menu::partial_unchecked!(UpdateParams {
     btn_auth
     api_url
     api_method
+    is_deny
     is_hide
     is_keep_alive
     is_affix
@@ -0,0 +1,1018 @@
+use crate::{
+    system_dept_service,
+    system_grant_service::{self, Grant},
+    system_menu_service,
+    system_role_service::{self, DataScopeType},
+    system_user_service::{self, DataScope},
+    Database, Result,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+
+/// api permission menu, with its full title path
+#[derive(Debug, Clone)]
+pub struct ApiMenu {
+    pub menu_id: i32,
+    pub menu_names: String,
+}
+
+#[derive(Debug, Clone)]
+struct UserRoles {
+    is_admin: bool,
+    role_ids: Vec<i32>,
+}
+
+/// api permission rule of a menu, a method set and a path pattern.
+/// `api_method` is a comma separated list or `*`, and in `api_url`
+/// a `*` segment matches one segment while `**` matches any remaining ones.
+#[derive(Debug, Clone)]
+pub struct ApiRule {
+    /// None matches every method
+    methods: Option<HashSet<String>>,
+    segments: Vec<String>,
+}
+
+impl ApiRule {
+    pub fn new(method: &str, url: &str) -> Self {
+        let methods = method
+            .split(',')
+            .map(|x| x.trim().to_uppercase())
+            .filter(|x| !x.is_empty())
+            .collect::<HashSet<String>>();
+        Self {
+            methods: match methods.contains("*") {
+                true => None,
+                false => Some(methods),
+            },
+            segments: split_path(url),
+        }
+    }
+
+    pub fn is_match(&self, method: &str, path: &str) -> bool {
+        let method_matched = match &self.methods {
+            Some(methods) => methods.contains(&method.to_uppercase()),
+            None => true,
+        };
+        method_matched && match_segments(&self.segments, &split_path(path))
+    }
+
+    /// "METHOD path" when the rule holds a single method and no wildcard
+    fn exact_key(&self) -> Option<String> {
+        match &self.methods {
+            Some(methods) if methods.len() == 1 && !self.has_wildcard() => methods
+                .iter()
+                .next()
+                .map(|method| api_key(method, &format!("/{}", self.segments.join("/")))),
+            _ => None,
+        }
+    }
+
+    fn has_wildcard(&self) -> bool {
+        self.segments.iter().any(|x| x == "*" || x == "**")
+    }
+
+    /// literal segments, the more the rule is specific
+    fn specificity(&self) -> usize {
+        self.segments
+            .iter()
+            .filter(|x| *x != "*" && *x != "**")
+            .count()
+    }
+}
+
+impl From<&system_menu_service::Info> for ApiRule {
+    fn from(value: &system_menu_service::Info) -> Self {
+        Self::new(&value.api_method, &value.api_url)
+    }
+}
+
+/// compiled api rules of a role
+#[derive(Debug, Default)]
+struct RoleRules {
+    /// exact allowed "METHOD path"
+    allows: HashSet<String>,
+    allow_rules: Vec<ApiRule>,
+    deny_rules: Vec<ApiRule>,
+}
+
+/// compiled api menus, used to name the requested api
+#[derive(Debug, Default)]
+struct ApiMenus {
+    exact: HashMap<String, ApiMenu>,
+    rules: Vec<(ApiRule, ApiMenu)>,
+}
+
+/// In memory permission index, built on first use and dropped whenever
+/// the menus, roles or user roles it was compiled from change.
+#[derive(Debug, Default)]
+pub struct PermissionIndex {
+    apis: RwLock<Option<Arc<ApiMenus>>>,
+    roles: RwLock<HashMap<i32, Arc<RoleRules>>>,
+    users: RwLock<HashMap<i32, Arc<UserRoles>>>,
+    /// unexpired grants with their compiled rules, checked against the time of the request
+    grants: RwLock<Option<Arc<Vec<(Grant, Arc<RoleRules>)>>>>,
+    /// bumped by every forget, an entry read before a forget is not cached
+    generation: AtomicU64,
+}
+
+impl PermissionIndex {
+    /// menus changed, every compiled api, role and grant is stale
+    pub fn forget_menus(&self) {
+        let mut apis = self.apis.write().unwrap();
+        let mut roles = self.roles.write().unwrap();
+        let mut grants = self.grants.write().unwrap();
+        self.bump();
+        *apis = None;
+        roles.clear();
+        *grants = None;
+    }
+
+    /// roles inherit menus from their parents, so a role change drops every role
+    pub fn forget_roles(&self) {
+        let mut roles = self.roles.write().unwrap();
+        self.bump();
+        roles.clear();
+    }
+
+    pub fn forget_user(&self, user_id: i32) {
+        let mut users = self.users.write().unwrap();
+        self.bump();
+        users.remove(&user_id);
+    }
+
+    pub fn forget_grants(&self) {
+        let mut grants = self.grants.write().unwrap();
+        self.bump();
+        *grants = None;
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// bumped while holding the write lock of what is forgotten, so a read
+    /// checking the generation under the same lock never caches stale data
+    fn bump(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// whether the user may request the api, a deny rule of any role wins
+pub async fn check_user_permission(
+    db: &Database,
+    user_id: i32,
+    method: &str,
+    path: &str,
+) -> Result<bool> {
+    let user = get_user_roles(db, user_id).await?;
+    if user.is_admin {
+        return Ok(true);
+    }
+    let mut roles = Vec::with_capacity(user.role_ids.len());
+    for role_id in user.role_ids.iter() {
+        roles.push(get_role_rules(db, *role_id).await?);
+    }
+    let timestamp = utils::datetime::now_timestamp(None);
+    for (grant, rules) in get_grants(db).await?.iter() {
+        if grant.is_active(timestamp) && grant.is_granted(user_id, &user.role_ids) {
+            roles.push(rules.clone());
+        }
+    }
+    Ok(rules_allow(&roles, method, path))
+}
+
+/// a deny rule of any role wins over every allow, however specific
+fn rules_allow(roles: &[Arc<RoleRules>], method: &str, path: &str) -> bool {
+    if roles
+        .iter()
+        .any(|x| x.deny_rules.iter().any(|r| r.is_match(method, path)))
+    {
+        return false;
+    }
+    let key = api_key(method, path);
+    roles
+        .iter()
+        .any(|x| x.allows.contains(&key) || x.allow_rules.iter().any(|r| r.is_match(method, path)))
+}
+
+/// what an operator tried to hand out beyond its own permissions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Escalation {
+    /// the role holds menus the operator does not
+    Role(i32),
+    /// the dept is outside the operator`s data scope
+    Dept(Option<i32>),
+    /// the role sees data beyond the operator`s data scope
+    Scope,
+    /// the user is the super admin, or holds more than the operator
+    User(i32),
+}
+
+/// roles may only be assigned when their effective menus are a subset of the
+/// operator`s and the data they show a user of `dept_id` is within the
+/// operator`s data scope; `dept_id` is the dept of the user given the roles,
+/// `None` when they are not given to a user
+pub async fn check_assign_roles(
+    db: &Database,
+    operator_id: i32,
+    dept_id: Option<i32>,
+    role_ids: &[i32],
+) -> Result<Option<Escalation>> {
+    if role_ids.is_empty() || get_user_roles(db, operator_id).await?.is_admin {
+        return Ok(None);
+    }
+    let operator_menu_ids = get_operator_menu_ids(db, operator_id).await?;
+    let scope = system_user_service::get_data_scope(db, operator_id).await?;
+    for role_id in role_ids {
+        let role = system_role_service::info(db, *role_id).await?;
+        if role_exceeds(
+            role.sign(),
+            role.effective_menu_ids(),
+            &operator_menu_ids,
+            &db.config.admin_role_sign,
+        ) {
+            return Ok(Some(Escalation::Role(*role_id)));
+        }
+        let dept_ids = get_scope_dept_ids(db, role.data_scope(), role.dept_ids(), dept_id).await?;
+        if scope_exceeds(&scope, role.data_scope(), &dept_ids) {
+            return Ok(Some(Escalation::Role(*role_id)));
+        }
+    }
+    Ok(None)
+}
+
+/// a role created or changed by the operator may not see beyond the operator`s
+/// data scope; its custom depts are checked here, the depts relative to the
+/// user given the role once it is assigned
+pub async fn check_role_scope(
+    db: &Database,
+    operator_id: i32,
+    data_scope: &DataScopeType,
+    dept_ids: &[i32],
+) -> Result<Option<Escalation>> {
+    let scope = system_user_service::get_data_scope(db, operator_id).await?;
+    let dept_ids = get_scope_dept_ids(db, data_scope, dept_ids, None).await?;
+    Ok(match scope_exceeds(&scope, data_scope, &dept_ids) {
+        true => Some(Escalation::Scope),
+        false => None,
+    })
+}
+
+/// depts may only be assigned inside the operator`s data scope, no dept
+/// assigns nothing
+pub async fn check_assign_dept(
+    db: &Database,
+    operator_id: i32,
+    dept_id: Option<i32>,
+) -> Result<Option<Escalation>> {
+    let scope = system_user_service::get_data_scope(db, operator_id).await?;
+    Ok(match dept_in_scope(&scope, dept_id) {
+        true => None,
+        false => Some(Escalation::Dept(dept_id)),
+    })
+}
+
+/// users may only be changed by the super admin, themselves, or an operator
+/// who sees them within its data scope and holds all of their roles` menus
+pub async fn check_manage_user(
+    db: &Database,
+    operator_id: i32,
+    user_id: i32,
+) -> Result<Option<Escalation>> {
+    let target = system_user_service::get_current_user_info(db, user_id).await?;
+    let operator = get_user_roles(db, operator_id).await?;
+    if operator.is_admin || operator_id == user_id {
+        return Ok(None);
+    }
+    if target.username().eq(&db.config.admin_username)
+        || !system_user_service::get_data_scope(db, operator_id)
+            .await?
+            .contains(user_id, *target.dept_id())
+        || check_assign_roles(db, operator_id, *target.dept_id(), target.role_ids())
+            .await?
+            .is_some()
+    {
+        return Ok(Some(Escalation::User(user_id)));
+    }
+    Ok(None)
+}
+
+/// the admin role, or a role holding a menu the operator does not
+fn role_exceeds(
+    sign: &str,
+    menu_ids: &[i32],
+    operator_menu_ids: &HashSet<i32>,
+    admin_role_sign: &str,
+) -> bool {
+    sign.eq(admin_role_sign) || !menu_ids.iter().all(|x| operator_menu_ids.contains(x))
+}
+
+fn dept_in_scope(scope: &DataScope, dept_id: Option<i32>) -> bool {
+    match (scope, dept_id) {
+        (_, None) | (DataScope::All, _) => true,
+        (DataScope::Depts(_, dept_ids), Some(dept_id)) => dept_ids.contains(&dept_id),
+        (DataScope::User(_), Some(_)) => false,
+    }
+}
+
+/// a role scope of every row, or of depts the operator does not see
+fn scope_exceeds(scope: &DataScope, data_scope: &DataScopeType, dept_ids: &[i32]) -> bool {
+    match (scope, data_scope) {
+        (DataScope::All, _) | (_, DataScopeType::OnlySelf) => false,
+        (_, DataScopeType::All) => true,
+        _ => !dept_ids.iter().all(|x| dept_in_scope(scope, Some(*x))),
+    }
+}
+
+/// depts a role scope shows a user of `dept_id`, the way `get_data_scope` reads them
+async fn get_scope_dept_ids(
+    db: &Database,
+    data_scope: &DataScopeType,
+    custom_dept_ids: &[i32],
+    dept_id: Option<i32>,
+) -> Result<Vec<i32>> {
+    Ok(match (data_scope, dept_id) {
+        (DataScopeType::Custom, _) => custom_dept_ids.to_vec(),
+        (DataScopeType::Dept, Some(dept_id)) => vec![dept_id],
+        (DataScopeType::DeptAndChildren, Some(dept_id)) => {
+            system_dept_service::get_dept_children_ids(db, dept_id).await?
+        }
+        _ => vec![],
+    })
+}
+
+async fn get_operator_menu_ids(db: &Database, operator_id: i32) -> Result<HashSet<i32>> {
+    let operator = system_user_service::get_current_user_info(db, operator_id).await?;
+    Ok(
+        system_menu_service::get_menu_by_roles(db, operator.roles().clone())
+            .await?
+            .into_iter()
+            .map(|x| x.id)
+            .collect::<HashSet<i32>>(),
+    )
+}
+
+/// menus temporarily granted to the user, to the user itself or one of its roles
+pub async fn get_granted_menus(
+    db: &Database,
+    user_id: i32,
+) -> Result<Vec<system_menu_service::Info>> {
+    let user = get_user_roles(db, user_id).await?;
+    let timestamp = utils::datetime::now_timestamp(None);
+    let mut menus: Vec<system_menu_service::Info> = vec![];
+    for (grant, _) in get_grants(db).await?.iter() {
+        if !grant.is_active(timestamp) || !grant.is_granted(user_id, &user.role_ids) {
+            continue;
+        }
+        for menu in grant.menus.iter() {
+            if !menus.iter().any(|x| x.id.eq(&menu.id)) {
+                menus.push(menu.clone());
+            }
+        }
+    }
+    Ok(menus)
+}
+
+/// api menu matched by the request, the exact one first, then the most specific rule
+pub async fn get_api_menu(db: &Database, method: &str, path: &str) -> Result<Option<ApiMenu>> {
+    let apis = get_apis(db).await?;
+    if let Some(menu) = apis.exact.get(&api_key(method, path)) {
+        return Ok(Some(menu.clone()));
+    }
+    Ok(apis
+        .rules
+        .iter()
+        .filter(|(rule, _)| rule.is_match(method, path))
+        .max_by_key(|(rule, _)| rule.specificity())
+        .map(|(_, menu)| menu.clone()))
+}
+
+fn api_key(method: &str, path: &str) -> String {
+    format!("{} {}", method.to_uppercase(), path)
+}
+
+fn split_path(path: &str) -> Vec<String> {
+    path.split('/')
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_owned())
+        .collect()
+}
+
+fn match_segments(rule: &[String], path: &[String]) -> bool {
+    match rule.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=path.len()).any(|i| match_segments(rest, &path[i..]))
+        }
+        Some((first, rest)) => match path.split_first() {
+            Some((segment, path_rest)) => {
+                (first == "*" || first == segment) && match_segments(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+async fn get_user_roles(db: &Database, user_id: i32) -> Result<Arc<UserRoles>> {
+    let cached = db
+        .permissions()
+        .users
+        .read()
+        .unwrap()
+        .get(&user_id)
+        .cloned();
+    if let Some(user) = cached {
+        return Ok(user);
+    }
+    let generation = db.permissions().generation();
+    let info = system_user_service::get_current_user_info(db, user_id).await?;
+    let user = Arc::new(UserRoles {
+        is_admin: info.username().eq(&db.config.admin_username),
+        role_ids: info.roles().iter().map(|x| *x.id()).collect::<Vec<i32>>(),
+    });
+    let mut users = db.permissions().users.write().unwrap();
+    if db.permissions().generation() == generation {
+        users.insert(user_id, user.clone());
+    }
+    Ok(user)
+}
+
+async fn get_role_rules(db: &Database, role_id: i32) -> Result<Arc<RoleRules>> {
+    let cached = db
+        .permissions()
+        .roles
+        .read()
+        .unwrap()
+        .get(&role_id)
+        .cloned();
+    if let Some(rules) = cached {
+        return Ok(rules);
+    }
+    let generation = db.permissions().generation();
+    let rules = Arc::new(compile_rules(
+        &crate::system_role_menu_service::get_effective_role_menus(db, &role_id).await?,
+    ));
+    let mut roles = db.permissions().roles.write().unwrap();
+    if db.permissions().generation() == generation {
+        roles.insert(role_id, rules.clone());
+    }
+    Ok(rules)
+}
+
+async fn get_grants(db: &Database) -> Result<Arc<Vec<(Grant, Arc<RoleRules>)>>> {
+    let cached = db.permissions().grants.read().unwrap().clone();
+    if let Some(grants) = cached {
+        return Ok(grants);
+    }
+    let generation = db.permissions().generation();
+    let grants = Arc::new(
+        system_grant_service::get_unexpired(db)
+            .await?
+            .into_iter()
+            .map(|x| {
+                let rules = Arc::new(compile_rules(&x.menus));
+                (x, rules)
+            })
+            .collect::<Vec<(Grant, Arc<RoleRules>)>>(),
+    );
+    let mut cached = db.permissions().grants.write().unwrap();
+    if db.permissions().generation() == generation {
+        *cached = Some(grants.clone());
+    }
+    Ok(grants)
+}
+
+fn compile_rules(menus: &[system_menu_service::Info]) -> RoleRules {
+    let mut rules = RoleRules::default();
+    for menu in menus
+        .iter()
+        .filter(|x| x.r#type.eq(&system_menu_service::MenuType::Api))
+    {
+        let rule = ApiRule::from(menu);
+        if menu.is_deny == 1 {
+            rules.deny_rules.push(rule);
+            continue;
+        }
+        match rule.exact_key() {
+            Some(key) => {
+                rules.allows.insert(key);
+            }
+            None => rules.allow_rules.push(rule),
+        }
+    }
+    rules
+}
+
+async fn get_apis(db: &Database) -> Result<Arc<ApiMenus>> {
+    let cached = db.permissions().apis.read().unwrap().clone();
+    if let Some(apis) = cached {
+        return Ok(apis);
+    }
+    let generation = db.permissions().generation();
+    let menus = system_menu_service::get_menus(db).await?;
+    let titles = menus
+        .iter()
+        .map(|x| (x.id, (x.parent_id, x.title.clone())))
+        .collect::<HashMap<i32, (i32, String)>>();
+    let mut apis = ApiMenus::default();
+    for x in menus
+        .iter()
+        .filter(|x| x.r#type.eq(&system_menu_service::MenuType::Api) && x.is_deny != 1)
+    {
+        let mut names = vec![x.title.clone()];
+        let mut parent_id = x.parent_id;
+        while let Some((next_parent_id, title)) = titles.get(&parent_id) {
+            // guard against a parent_id cycle
+            if names.len() > titles.len() {
+                break;
+            }
+            names.push(title.clone());
+            parent_id = *next_parent_id;
+        }
+        names.reverse();
+        let menu = ApiMenu {
+            menu_id: x.id,
+            menu_names: names.join("/"),
+        };
+        let rule = ApiRule::from(x);
+        match rule.exact_key() {
+            Some(key) => {
+                apis.exact.insert(key, menu);
+            }
+            None => apis.rules.push((rule, menu)),
+        }
+    }
+    let apis = Arc::new(apis);
+    let mut cached = db.permissions().apis.write().unwrap();
+    if db.permissions().generation() == generation {
+        *cached = Some(apis.clone());
+    }
+    Ok(apis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forget_bumps_generation() {
+        let index = PermissionIndex::default();
+        let generation = index.generation();
+        index.forget_user(1);
+        index.forget_roles();
+        index.forget_grants();
+        index.forget_menus();
+        assert_eq!(index.generation(), generation + 4);
+    }
+
+    #[test]
+    fn role_escalation() {
+        let operator_menu_ids = HashSet::from([1, 2, 3]);
+        assert!(!role_exceeds(
+            "editor",
+            &[1, 2],
+            &operator_menu_ids,
+            "admin"
+        ));
+        assert!(!role_exceeds("empty", &[], &operator_menu_ids, "admin"));
+        // a menu the operator does not hold
+        assert!(role_exceeds("editor", &[1, 4], &operator_menu_ids, "admin"));
+        // the admin role holds everything, whatever its menus
+        assert!(role_exceeds("admin", &[1], &operator_menu_ids, "admin"));
+    }
+
+    #[test]
+    fn dept_out_of_scope() {
+        let depts = DataScope::Depts(1, vec![10, 11]);
+        assert!(dept_in_scope(&depts, Some(10)));
+        assert!(!dept_in_scope(&depts, Some(12)));
+        assert!(dept_in_scope(&DataScope::All, Some(12)));
+        assert!(!dept_in_scope(&DataScope::User(1), Some(10)));
+        // no dept is within every scope
+        assert!(dept_in_scope(&depts, None));
+        assert!(dept_in_scope(&DataScope::User(1), None));
+    }
+
+    #[test]
+    fn user_above_operator() {
+        let depts = DataScope::Depts(1, vec![10]);
+        // the operator itself, and users of its depts
+        assert!(depts.contains(1, None));
+        assert!(depts.contains(2, Some(10)));
+        // users of other depts, or of none
+        assert!(!depts.contains(3, Some(11)));
+        assert!(!depts.contains(3, None));
+        assert!(!DataScope::User(1).contains(2, Some(10)));
+    }
+
+    fn segments(path: &str) -> Vec<String> {
+        split_path(path)
+    }
+
+    #[test]
+    fn wildcard_segments() {
+        // `*` is exactly one segment
+        assert!(match_segments(&segments("/user/*"), &segments("/user/1")));
+        assert!(!match_segments(&segments("/user/*"), &segments("/user")));
+        assert!(!match_segments(
+            &segments("/user/*"),
+            &segments("/user/1/roles")
+        ));
+        // `**` at the end is any number of segments, none included
+        assert!(match_segments(&segments("/user/**"), &segments("/user")));
+        assert!(match_segments(
+            &segments("/user/**"),
+            &segments("/user/1/roles")
+        ));
+        assert!(!match_segments(&segments("/user/**"), &segments("/role/1")));
+        // `**` in the middle still needs what follows it
+        assert!(match_segments(
+            &segments("/a/**/edit"),
+            &segments("/a/edit")
+        ));
+        assert!(match_segments(
+            &segments("/a/**/edit"),
+            &segments("/a/1/2/edit")
+        ));
+        assert!(!match_segments(
+            &segments("/a/**/edit"),
+            &segments("/a/1/2")
+        ));
+        assert!(match_segments(
+            &segments("/a/**/*/edit"),
+            &segments("/a/1/edit")
+        ));
+        assert!(!match_segments(
+            &segments("/a/**/*/edit"),
+            &segments("/a/edit")
+        ));
+    }
+
+    #[test]
+    fn trailing_slashes() {
+        assert!(ApiRule::new("GET", "/user/").is_match("GET", "/user"));
+        assert!(ApiRule::new("GET", "/user").is_match("GET", "/user/"));
+        assert!(ApiRule::new("GET", "/user/*/").is_match("GET", "/user/1/"));
+        assert_eq!(
+            ApiRule::new("GET", "/user/").exact_key(),
+            Some(api_key("GET", "/user"))
+        );
+    }
+
+    #[test]
+    fn method_sets() {
+        let rule = ApiRule::new("get, post", "/user");
+        assert!(rule.is_match("GET", "/user"));
+        assert!(rule.is_match("post", "/user"));
+        assert!(!rule.is_match("DELETE", "/user"));
+        let any = ApiRule::new("*", "/user");
+        assert!(any.is_match("DELETE", "/user"));
+        assert!(any.is_match("patch", "/user"));
+        assert!(ApiRule::new("GET,*", "/user").is_match("PUT", "/user"));
+        assert!(!ApiRule::new("", "/user").is_match("GET", "/user"));
+    }
+
+    #[test]
+    fn exact_keys() {
+        assert_eq!(
+            ApiRule::new("get", "/user/1").exact_key(),
+            Some("GET /user/1".to_owned())
+        );
+        // wildcards, several methods or any method are matched rule by rule
+        assert_eq!(ApiRule::new("GET", "/user/*").exact_key(), None);
+        assert_eq!(ApiRule::new("GET", "/user/**").exact_key(), None);
+        assert_eq!(ApiRule::new("GET,POST", "/user").exact_key(), None);
+        assert_eq!(ApiRule::new("*", "/user").exact_key(), None);
+    }
+
+    #[test]
+    fn most_specific_rule() {
+        let rules = [
+            ApiRule::new("*", "/**"),
+            ApiRule::new("GET", "/user/**"),
+            ApiRule::new("GET", "/user/*/roles"),
+        ];
+        let matched = rules
+            .iter()
+            .filter(|x| x.is_match("GET", "/user/1/roles"))
+            .max_by_key(|x| x.specificity())
+            .unwrap();
+        assert_eq!(matched.specificity(), 2);
+        assert_eq!(ApiRule::new("*", "/**").specificity(), 0);
+    }
+
+    #[test]
+    fn deny_wins() {
+        let allow = |method: &str, url: &str| {
+            let rule = ApiRule::new(method, url);
+            let mut rules = RoleRules::default();
+            match rule.exact_key() {
+                Some(key) => {
+                    rules.allows.insert(key);
+                }
+                None => rules.allow_rules.push(rule),
+            }
+            Arc::new(rules)
+        };
+        let deny = |method: &str, url: &str| {
+            Arc::new(RoleRules {
+                deny_rules: vec![ApiRule::new(method, url)],
+                ..Default::default()
+            })
+        };
+        let roles = vec![allow("DELETE", "/user/1"), allow("GET", "/user/**")];
+        assert!(rules_allow(&roles, "DELETE", "/user/1"));
+        assert!(rules_allow(&roles, "GET", "/user/2"));
+        // a broad deny of another role beats the exact allow
+        let mut denied = roles.clone();
+        denied.push(deny("*", "/user/**"));
+        assert!(!rules_allow(&denied, "DELETE", "/user/1"));
+        assert!(!rules_allow(&denied, "GET", "/user/2"));
+        let mut denied = roles;
+        denied.push(deny("delete", "/user/*"));
+        assert!(!rules_allow(&denied, "DELETE", "/user/1"));
+        assert!(rules_allow(&denied, "GET", "/user/1"));
+    }
+
+    #[test]
+    fn role_scope_escalation() {
+        let depts = DataScope::Depts(1, vec![10, 11]);
+        assert!(!scope_exceeds(&depts, &DataScopeType::OnlySelf, &[]));
+        assert!(!scope_exceeds(&depts, &DataScopeType::Custom, &[10, 11]));
+        assert!(!scope_exceeds(&depts, &DataScopeType::Dept, &[10]));
+        // every row, or a dept the operator does not see
+        assert!(scope_exceeds(&depts, &DataScopeType::All, &[]));
+        assert!(scope_exceeds(&depts, &DataScopeType::Custom, &[10, 12]));
+        assert!(scope_exceeds(
+            &depts,
+            &DataScopeType::DeptAndChildren,
+            &[11, 12]
+        ));
+        assert!(scope_exceeds(
+            &DataScope::User(1),
+            &DataScopeType::Dept,
+            &[10]
+        ));
+        assert!(!scope_exceeds(&DataScope::All, &DataScopeType::All, &[]));
+    }
+
+    /// rows of one escalation test, named apart from other runs
+    struct Seed {
+        db: Database,
+        prefix: String,
+    }
+
+    impl Seed {
+        async fn new() -> Self {
+            Self {
+                db: Database::new(crate::DatabaseConfig::default())
+                    .await
+                    .unwrap(),
+                prefix: format!("test{}", fastrand::u32(..)),
+            }
+        }
+
+        async fn dept(&self, parent_id: i32) -> i32 {
+            self.db
+                .client
+                .system_dept()
+                .create(
+                    self.prefix.clone(),
+                    vec![crate::prisma::system_dept::parent_id::set(parent_id)],
+                )
+                .exec()
+                .await
+                .unwrap()
+                .id
+        }
+
+        async fn menu(&self) -> i32 {
+            self.db
+                .client
+                .system_menu()
+                .create(self.prefix.clone(), vec![])
+                .exec()
+                .await
+                .unwrap()
+                .id
+        }
+
+        async fn role(&self, data_scope: DataScopeType, menu_ids: &[i32], dept_ids: &[i32]) -> i32 {
+            let role = self
+                .db
+                .client
+                .system_role()
+                .create(
+                    self.prefix.clone(),
+                    format!("{}-{}", self.prefix, fastrand::u32(..)),
+                    vec![crate::prisma::system_role::data_scope::set(
+                        data_scope.into(),
+                    )],
+                )
+                .exec()
+                .await
+                .unwrap();
+            for menu_id in menu_ids {
+                self.db
+                    .client
+                    .system_role_menu()
+                    .create_unchecked(role.id, *menu_id, vec![])
+                    .exec()
+                    .await
+                    .unwrap();
+            }
+            for dept_id in dept_ids {
+                self.db
+                    .client
+                    .system_role_dept()
+                    .create_unchecked(role.id, *dept_id, vec![])
+                    .exec()
+                    .await
+                    .unwrap();
+            }
+            role.id
+        }
+
+        async fn user(&self, dept_id: Option<i32>, role_ids: &[i32]) -> i32 {
+            let user = self
+                .db
+                .client
+                .system_user()
+                .create_unchecked(
+                    format!("{}-{}", self.prefix, fastrand::u32(..)),
+                    vec![crate::prisma::system_user::dept_id::set(dept_id)],
+                )
+                .exec()
+                .await
+                .unwrap();
+            for role_id in role_ids {
+                self.db
+                    .client
+                    .system_user_role()
+                    .create_unchecked(user.id, *role_id, vec![])
+                    .exec()
+                    .await
+                    .unwrap();
+            }
+            user.id
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database"]
+    async fn escalation_paths() {
+        let seed = Seed::new().await;
+        let db = &seed.db;
+        // the operator sees its own dept only, through two menus
+        let dept = seed.dept(0).await;
+        let child_dept = seed.dept(dept).await;
+        let other_dept = seed.dept(0).await;
+        let (menu, other_menu, foreign_menu) =
+            (seed.menu().await, seed.menu().await, seed.menu().await);
+        let operator_role = seed
+            .role(DataScopeType::Dept, &[menu, other_menu], &[])
+            .await;
+        let operator = seed.user(Some(dept), &[operator_role]).await;
+
+        // menus within the operator`s, no wider data
+        let narrow = seed.role(DataScopeType::OnlySelf, &[menu], &[]).await;
+        assert_eq!(
+            check_assign_roles(db, operator, Some(dept), &[narrow])
+                .await
+                .unwrap(),
+            None
+        );
+        let own_dept = seed.role(DataScopeType::Custom, &[menu], &[dept]).await;
+        assert_eq!(
+            check_assign_roles(db, operator, Some(dept), &[own_dept])
+                .await
+                .unwrap(),
+            None
+        );
+
+        // a menu the operator does not hold
+        let wide_menus = seed
+            .role(DataScopeType::OnlySelf, &[menu, foreign_menu], &[])
+            .await;
+        assert_eq!(
+            check_assign_roles(db, operator, Some(dept), &[wide_menus])
+                .await
+                .unwrap(),
+            Some(Escalation::Role(wide_menus))
+        );
+        // own menus but every row, or depts out of the operator`s scope
+        let all_rows = seed.role(DataScopeType::All, &[menu], &[]).await;
+        assert_eq!(
+            check_assign_roles(db, operator, Some(dept), &[all_rows])
+                .await
+                .unwrap(),
+            Some(Escalation::Role(all_rows))
+        );
+        let foreign_depts = seed
+            .role(DataScopeType::Custom, &[menu], &[other_dept])
+            .await;
+        assert_eq!(
+            check_assign_roles(db, operator, None, &[foreign_depts])
+                .await
+                .unwrap(),
+            Some(Escalation::Role(foreign_depts))
+        );
+        // the children of the user`s dept are beyond the operator`s own dept
+        let children = seed
+            .role(DataScopeType::DeptAndChildren, &[menu], &[])
+            .await;
+        assert_eq!(
+            check_assign_roles(db, operator, None, &[children])
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            check_assign_roles(db, operator, Some(dept), &[children])
+                .await
+                .unwrap(),
+            Some(Escalation::Role(children))
+        );
+        assert!(
+            get_scope_dept_ids(db, &DataScopeType::DeptAndChildren, &[], Some(dept))
+                .await
+                .unwrap()
+                .contains(&child_dept)
+        );
+
+        // creating or changing such roles
+        assert_eq!(
+            check_role_scope(db, operator, &DataScopeType::All, &[])
+                .await
+                .unwrap(),
+            Some(Escalation::Scope)
+        );
+        assert_eq!(
+            check_role_scope(db, operator, &DataScopeType::Custom, &[dept, other_dept])
+                .await
+                .unwrap(),
+            Some(Escalation::Scope)
+        );
+        assert_eq!(
+            check_role_scope(db, operator, &DataScopeType::Custom, &[dept])
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            check_role_scope(db, operator, &DataScopeType::Dept, &[])
+                .await
+                .unwrap(),
+            None
+        );
+
+        // depts given to users
+        assert_eq!(
+            check_assign_dept(db, operator, Some(dept)).await.unwrap(),
+            None
+        );
+        assert_eq!(check_assign_dept(db, operator, None).await.unwrap(), None);
+        assert_eq!(
+            check_assign_dept(db, operator, Some(other_dept))
+                .await
+                .unwrap(),
+            Some(Escalation::Dept(Some(other_dept)))
+        );
+
+        // the operator manages itself, yet can not give itself a wider role
+        assert_eq!(
+            check_manage_user(db, operator, operator).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            check_assign_roles(db, operator, Some(dept), &[operator_role, all_rows])
+                .await
+                .unwrap(),
+            Some(Escalation::Role(all_rows))
+        );
+        // users of its dept holding no more than the operator
+        let peer = seed.user(Some(dept), &[narrow]).await;
+        assert_eq!(check_manage_user(db, operator, peer).await.unwrap(), None);
+        // users of another dept, or holding more than the operator
+        let outsider = seed.user(Some(other_dept), &[narrow]).await;
+        assert_eq!(
+            check_manage_user(db, operator, outsider).await.unwrap(),
+            Some(Escalation::User(outsider))
+        );
+        let senior = seed.user(Some(dept), &[wide_menus]).await;
+        assert_eq!(
+            check_manage_user(db, operator, senior).await.unwrap(),
+            Some(Escalation::User(senior))
+        );
+        let seeing_all = seed.user(Some(dept), &[all_rows]).await;
+        assert_eq!(
+            check_manage_user(db, operator, seeing_all).await.unwrap(),
+            Some(Escalation::User(seeing_all))
+        );
+    }
+}
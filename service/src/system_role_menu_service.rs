@@ -1,6 +1,6 @@
 use crate::{
     prisma::{system_menu, system_role_menu},
-    system_menu_service, Database, Result,
+    system_menu_service, system_role_service, Database, Result,
 };
 
 pub async fn get_role_menus(
@@ -25,6 +25,22 @@ pub async fn get_role_menus(
         .collect::<Vec<system_menu_service::Info>>())
 }
 
+/// menus of the role merged with the ones inherited from its parent roles
+pub async fn get_effective_role_menus(
+    db: &Database,
+    role_id: &i32,
+) -> Result<Vec<system_menu_service::Info>> {
+    let mut menus = get_role_menus(db, role_id).await?;
+    for parent_id in system_role_service::get_ancestor_ids(db, *role_id).await? {
+        for menu in get_role_menus(db, &parent_id).await? {
+            if !menus.iter().any(|x| x.id.eq(&menu.id)) {
+                menus.push(menu);
+            }
+        }
+    }
+    Ok(menus)
+}
+
 pub async fn delete_by_role_id(db: &Database, role_id: i32) -> Result<i64> {
     Ok(db
         .client
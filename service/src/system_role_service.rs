@@ -1,10 +1,13 @@
 use crate::{
-    prisma::{system_role, system_role_menu, SortOrder},
-    system_menu_service, system_role_menu_service, DataPower, Database, Result, ServiceError,
+    prisma::{system_role, system_role_dept, system_role_menu, PrismaClient, SortOrder},
+    system_audit_service::{self, AuditAction},
+    system_menu_service, system_role_menu_service, system_user_role_service, DataPower, Database,
+    Result, ServiceError,
 };
 use getset::Getters;
 use prisma_client_rust::or;
 use serde::{Deserialize, Serialize};
+use serde_repr::{Deserialize_repr, Serialize_repr};
 use utils::{
     datetime::{now_time, to_local_string},
     paginate::{PaginateParams, PaginateResult},
@@ -16,6 +19,7 @@ pub async fn create(
     sign: &str,
     params: CreateParams,
     menus: Vec<system_menu_service::Info>,
+    dept_ids: Vec<i32>,
 ) -> Result<system_role::Data> {
     // todo wait PCR 0.7
     // link: https://github.com/Brendonovich/prisma-client-rust/issues/44
@@ -42,6 +46,16 @@ pub async fn create(
                         .await?;
                 }
             }
+            set_role_depts(&client, role.id, dept_ids).await?;
+            system_audit_service::record(
+                &client,
+                "system_role",
+                role.id,
+                AuditAction::Create,
+                None,
+                Some(&role),
+            )
+            .await?;
 
             Ok(role)
         })
@@ -54,11 +68,17 @@ pub async fn update(
     id: i32,
     params: UpdateParams,
     menus: Vec<system_menu_service::Info>,
+    dept_ids: Option<Vec<i32>>,
 ) -> Result<system_role::Data> {
     let result = db
         .client
         ._transaction()
         .run::<ServiceError, _, _, _>(|client| async move {
+            let before = client
+                .system_role()
+                .find_unique(system_role::id::equals(id))
+                .exec()
+                .await?;
             let role = client
                 .system_role()
                 .update_unchecked(system_role::id::equals(id), params.to_params())
@@ -109,10 +129,25 @@ pub async fn update(
             } else if !current_menus.is_empty() {
                 system_role_menu_service::delete_by_role_id(db, id).await?;
             }
+            // depts left out keep the role`s current ones
+            if let Some(dept_ids) = dept_ids {
+                set_role_depts(&client, role.id, dept_ids).await?;
+            }
+            system_audit_service::record(
+                &client,
+                "system_role",
+                role.id,
+                AuditAction::Update,
+                before.as_ref(),
+                Some(&role),
+            )
+            .await?;
 
             Ok(role)
         })
         .await?;
+    system_menu_service::forget_role_menus(db).await?;
+    db.permissions().forget_roles();
     Ok(result)
 }
 
@@ -121,6 +156,11 @@ pub async fn delete(db: &Database, id: i32) -> Result<system_role::Data> {
         .client
         ._transaction()
         .run::<ServiceError, _, _, _>(|client| async move {
+            let before = client
+                .system_role()
+                .find_unique(system_role::id::equals(id))
+                .exec()
+                .await?;
             let info = client
                 .system_role()
                 .update(
@@ -130,9 +170,112 @@ pub async fn delete(db: &Database, id: i32) -> Result<system_role::Data> {
                 .exec()
                 .await?;
             system_role_menu_service::delete_by_role_id(db, id).await?;
+            system_user_role_service::delete_by_role_id(db, id).await?;
+            client
+                .system_role()
+                .update_many(
+                    vec![system_role::parent_id::equals(id)],
+                    vec![system_role::parent_id::set(0)],
+                )
+                .exec()
+                .await?;
+            client
+                .system_role_dept()
+                .delete_many(vec![system_role_dept::role_id::equals(id)])
+                .exec()
+                .await?;
+            system_audit_service::record(
+                &client,
+                "system_role",
+                info.id,
+                AuditAction::Delete,
+                before.as_ref(),
+                Some(&info),
+            )
+            .await?;
             Ok(info)
         })
         .await?;
+    system_menu_service::forget_role_menus(db).await?;
+    db.permissions().forget_roles();
+    Ok(result)
+}
+
+/// copy the role with its menus and custom depts
+pub async fn clone_role(
+    db: &Database,
+    id: i32,
+    name: &str,
+    sign: &str,
+) -> Result<system_role::Data> {
+    let source = db
+        .client
+        .system_role()
+        .find_first(vec![
+            system_role::id::equals(id),
+            system_role::deleted_at::equals(None),
+        ])
+        .with(system_role::role_menu::fetch(vec![
+            system_role_menu::deleted_at::equals(None),
+        ]))
+        .with(system_role::role_dept::fetch(vec![]))
+        .exec()
+        .await?
+        .ok_or(ServiceError::DataNotFound)?;
+    let menu_ids = source
+        .role_menu()?
+        .iter()
+        .map(|x| x.menu_id)
+        .collect::<Vec<i32>>();
+    let dept_ids = source
+        .role_dept()?
+        .iter()
+        .map(|x| x.dept_id)
+        .collect::<Vec<i32>>();
+    let result = db
+        .client
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            let role = client
+                .system_role()
+                .create_unchecked(
+                    name.to_owned(),
+                    sign.to_owned(),
+                    vec![
+                        system_role::parent_id::set(source.parent_id),
+                        system_role::sort::set(source.sort),
+                        system_role::describe::set(source.describe),
+                        system_role::status::set(source.status),
+                        system_role::data_scope::set(source.data_scope),
+                    ],
+                )
+                .exec()
+                .await?;
+            if !menu_ids.is_empty() {
+                client
+                    .system_role_menu()
+                    .create_many(
+                        menu_ids
+                            .into_iter()
+                            .map(|x| system_role_menu::create_unchecked(role.id, x, vec![]))
+                            .collect::<Vec<system_role_menu::CreateUnchecked>>(),
+                    )
+                    .exec()
+                    .await?;
+            }
+            set_role_depts(&client, role.id, dept_ids).await?;
+            system_audit_service::record(
+                &client,
+                "system_role",
+                role.id,
+                AuditAction::Create,
+                None,
+                Some(&role),
+            )
+            .await?;
+            Ok(role)
+        })
+        .await?;
     Ok(result)
 }
 
@@ -192,11 +335,18 @@ pub async fn info(db: &Database, id: i32) -> Result<Info> {
             system_role::role_menu::fetch(vec![system_role_menu::deleted_at::equals(None)])
                 .with(system_role_menu::menu::fetch()),
         )
+        .with(system_role::role_dept::fetch(vec![]))
         .exec()
         .await?
         .ok_or(ServiceError::DataNotFound)?;
     let mut role: Info = data.clone().into();
-    role.menu_ids = system_menu_service::get_menu_by_role(db, Some(data.into()))
+    role.menu_ids = data
+        .role_menu()?
+        .iter()
+        .filter(|x| matches!(x.menu(), Ok(menu) if menu.deleted_at.is_none()))
+        .map(|x| x.menu_id)
+        .collect::<Vec<i32>>();
+    role.effective_menu_ids = system_menu_service::get_menu_by_role(db, Some(data.into()))
         .await?
         .into_iter()
         .map(|x| x.id)
@@ -204,6 +354,76 @@ pub async fn info(db: &Database, id: i32) -> Result<Info> {
     Ok(role)
 }
 
+/// parent role ids, nearest first
+pub async fn get_ancestor_ids(db: &Database, role_id: i32) -> Result<Vec<i32>> {
+    let mut ids: Vec<i32> = vec![];
+    let mut current_id = role_id;
+    loop {
+        let parent_id = match db
+            .client
+            .system_role()
+            .find_first(vec![
+                system_role::id::equals(current_id),
+                system_role::deleted_at::equals(None),
+            ])
+            .exec()
+            .await?
+        {
+            Some(role) => role.parent_id,
+            None => break,
+        };
+        // stop on a parent_id cycle
+        if parent_id == 0 || parent_id == role_id || ids.contains(&parent_id) {
+            break;
+        }
+        ids.push(parent_id);
+        current_id = parent_id;
+    }
+    Ok(ids)
+}
+
+/// whether setting parent_id as the role`s parent would make a cycle
+pub async fn is_parent_cycle(db: &Database, id: i32, parent_id: i32) -> Result<bool> {
+    if parent_id == id {
+        return Ok(true);
+    }
+    Ok(get_ancestor_ids(db, parent_id).await?.contains(&id))
+}
+
+/// custom data scope depts of role
+pub async fn get_dept_ids(db: &Database, role_id: i32) -> Result<Vec<i32>> {
+    Ok(db
+        .client
+        .system_role_dept()
+        .find_many(vec![system_role_dept::role_id::equals(role_id)])
+        .exec()
+        .await?
+        .into_iter()
+        .map(|x| x.dept_id)
+        .collect::<Vec<i32>>())
+}
+
+async fn set_role_depts(client: &PrismaClient, role_id: i32, dept_ids: Vec<i32>) -> Result<()> {
+    client
+        .system_role_dept()
+        .delete_many(vec![system_role_dept::role_id::equals(role_id)])
+        .exec()
+        .await?;
+    if !dept_ids.is_empty() {
+        client
+            .system_role_dept()
+            .create_many(
+                dept_ids
+                    .into_iter()
+                    .map(|x| system_role_dept::create_unchecked(role_id, x, vec![]))
+                    .collect::<Vec<system_role_dept::CreateUnchecked>>(),
+            )
+            .exec()
+            .await?;
+    }
+    Ok(())
+}
+
 pub async fn get_by_sign(
     db: &Database,
     sign: &str,
@@ -253,40 +473,101 @@ impl SearchParams {
 pub struct Info {
     #[getset(get = "pub")]
     id: i32,
+    parent_id: i32,
     name: String,
     #[getset(get = "pub")]
     sign: String,
     describe: String,
     status: i32,
     sort: i32,
+    #[getset(get = "pub")]
+    data_scope: DataScopeType,
+    #[getset(get = "pub")]
+    dept_ids: Vec<i32>,
     created_at: String,
+    /// directly assigned menus
     menu_ids: Vec<i32>,
+    /// assigned and inherited menus
+    #[getset(get = "pub")]
+    effective_menu_ids: Vec<i32>,
 }
 impl From<system_role::Data> for Info {
     fn from(value: system_role::Data) -> Self {
+        let dept_ids = match value.role_dept() {
+            Ok(role_depts) => role_depts.iter().map(|x| x.dept_id).collect::<Vec<i32>>(),
+            Err(_) => vec![],
+        };
         Self {
             id: value.id,
+            parent_id: value.parent_id,
             name: value.name,
             sign: value.sign,
             describe: value.describe,
             status: value.status,
             sort: value.sort,
+            data_scope: value.data_scope.into(),
+            dept_ids,
             created_at: to_local_string(value.created_at),
             menu_ids: vec![],
+            effective_menu_ids: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize_repr, Deserialize_repr)]
+#[repr(i32)]
+pub enum DataScopeType {
+    /// 1.全部
+    All = 1,
+    /// 2.本部门
+    Dept = 2,
+    /// 3.本部门及以下
+    DeptAndChildren = 3,
+    /// 4.自定义部门
+    Custom = 4,
+    /// 5.仅本人
+    OnlySelf = 5,
+}
+
+impl From<i32> for DataScopeType {
+    fn from(value: i32) -> Self {
+        match value {
+            1 => Self::All,
+            2 => Self::Dept,
+            3 => Self::DeptAndChildren,
+            4 => Self::Custom,
+            5 => Self::OnlySelf,
+            _ => Self::OnlySelf,
+        }
+    }
+}
+
+impl From<DataScopeType> for i32 {
+    fn from(value: DataScopeType) -> Self {
+        match value {
+            DataScopeType::All => 1,
+            DataScopeType::Dept => 2,
+            DataScopeType::DeptAndChildren => 3,
+            DataScopeType::Custom => 4,
+            DataScopeType::OnlySelf => 5,
         }
     }
 }
 
 system_role::partial_unchecked!(CreateParams {
+    parent_id
     sort
     describe
     status
+    data_scope
 });
 
 system_role::partial_unchecked!(UpdateParams {
+    parent_id
     name
     sign
     sort
     describe
     status
+    data_scope
 });
@@ -0,0 +1,122 @@
+use crate::{
+    prisma::{system_role, system_user, system_user_role, PrismaClient},
+    system_audit_service::{self, AuditAction},
+    system_role_service, Database, Result,
+};
+use serde_json::json;
+use std::collections::HashSet;
+
+pub async fn get_user_roles(db: &Database, user_id: i32) -> Result<Vec<system_role_service::Info>> {
+    Ok(db
+        .client
+        .system_user_role()
+        .find_many(vec![
+            system_user_role::user_id::equals(user_id),
+            system_user_role::role::is(vec![system_role::deleted_at::equals(None)]),
+        ])
+        .with(system_user_role::role::fetch())
+        .exec()
+        .await?
+        .into_iter()
+        .filter_map(|x| x.role().ok().map(|x| x.clone().into()))
+        .collect::<Vec<system_role_service::Info>>())
+}
+
+/// replace the user`s roles within the caller`s transaction, audited; the
+/// caller forgets the user`s cached permissions after the commit. A role given
+/// twice is kept once, the first role stays the legacy `role_id`
+pub(crate) async fn replace_user_roles(
+    client: &PrismaClient,
+    user_id: i32,
+    mut role_ids: Vec<i32>,
+) -> Result<i64> {
+    let mut seen = HashSet::new();
+    role_ids.retain(|x| seen.insert(*x));
+    let before = client
+        .system_user_role()
+        .find_many(vec![
+            system_user_role::user_id::equals(user_id),
+            system_user_role::role::is(vec![system_role::deleted_at::equals(None)]),
+        ])
+        .exec()
+        .await?
+        .into_iter()
+        .map(|x| x.role_id)
+        .collect::<Vec<i32>>();
+    let after = json!({ "role_ids": role_ids.clone() });
+    client
+        .system_user_role()
+        .delete_many(vec![system_user_role::user_id::equals(user_id)])
+        .exec()
+        .await?;
+    client
+        .system_user()
+        .update(
+            system_user::id::equals(user_id),
+            vec![system_user::role_id::set(role_ids.first().copied())],
+        )
+        .exec()
+        .await?;
+    let result = match role_ids.is_empty() {
+        true => 0,
+        false => {
+            client
+                .system_user_role()
+                .create_many(
+                    role_ids
+                        .into_iter()
+                        .map(|x| system_user_role::create_unchecked(user_id, x, vec![]))
+                        .collect::<Vec<system_user_role::CreateUnchecked>>(),
+                )
+                .exec()
+                .await?
+        }
+    };
+    system_audit_service::record(
+        client,
+        "system_user",
+        user_id,
+        AuditAction::Update,
+        Some(&json!({ "role_ids": before })),
+        Some(&after),
+    )
+    .await?;
+    Ok(result)
+}
+
+pub async fn delete_by_role_id(db: &Database, role_id: i32) -> Result<i64> {
+    Ok(db
+        .client
+        .system_user_role()
+        .delete_many(vec![system_user_role::role_id::equals(role_id)])
+        .exec()
+        .await?)
+}
+
+/// copy the legacy single `role_id` of users into the user role table
+pub async fn migrate_role_id(db: &Database) -> Result<i64> {
+    let user_roles = db
+        .client
+        .system_user()
+        .find_many(vec![
+            system_user::role_id::not(None),
+            system_user::user_role::none(vec![]),
+        ])
+        .exec()
+        .await?
+        .into_iter()
+        .filter_map(|x| {
+            x.role_id
+                .map(|role_id| system_user_role::create_unchecked(x.id, role_id, vec![]))
+        })
+        .collect::<Vec<system_user_role::CreateUnchecked>>();
+    if user_roles.is_empty() {
+        return Ok(0);
+    }
+    Ok(db
+        .client
+        .system_user_role()
+        .create_many(user_roles)
+        .exec()
+        .await?)
+}
@@ -2,10 +2,11 @@ use crate::{
     prisma::{
         system_dept, system_role,
         system_user::{self, UncheckedSetParam},
-        SortOrder,
+        system_user_role, PrismaClient, SortOrder,
     },
-    system_dept_service, system_menu_service, system_role_service, DataPower, Database, Result,
-    ServiceError,
+    system_audit_service::{self, AuditAction},
+    system_dept_service, system_permission_service, system_role_service, system_user_role_service,
+    DataPower, Database, Result, ServiceError,
 };
 use getset::Getters;
 use prisma_client_rust::or;
@@ -41,6 +42,7 @@ pub async fn get_current_user_info(db: &Database, id: i32) -> Result<Info> {
         .system_user()
         .find_first(vec![system_user::id::equals(id)])
         .with(system_user::role::fetch())
+        .with(system_user::user_role::fetch(vec![]).with(system_user_role::role::fetch()))
         .with(system_user::dept::fetch())
         .exec()
         .await?
@@ -54,26 +56,36 @@ pub async fn check_user_permission(
     method: &str,
     path: &str,
 ) -> Result<bool> {
-    let user = db
-        .client
-        .system_user()
-        .find_first(vec![system_user::id::equals(user_id)])
-        .with(system_user::role::fetch())
-        .exec()
-        .await?
-        .ok_or(ServiceError::DataNotFound)?;
+    system_permission_service::check_user_permission(db, user_id, method, path).await
+}
+
+/// resolve which rows the user may see, according to the role`s data scope
+pub async fn get_data_scope(db: &Database, user_id: i32) -> Result<DataScope> {
+    let user = get_current_user_info(db, user_id).await?;
     if user.username.eq(&db.config.admin_username) {
-        return Ok(true);
+        return Ok(DataScope::All);
     }
-    let role = user.role().map(|x| x.cloned()).unwrap_or_default();
-    let auths = system_menu_service::filter_menu_types(
-        Some(vec![system_menu_service::MenuType::Api]),
-        system_menu_service::get_menu_by_role(db, role.clone().map(|x| x.into())).await?,
-    )
-    .into_iter()
-    .filter(|x| x.api_method.to_uppercase().eq(method) && x.api_url.eq(path))
-    .count();
-    Ok(auths > 0)
+    // the widest scope among the user`s roles wins
+    let mut dept_ids = vec![];
+    for role in user.roles {
+        match (role.data_scope(), user.dept_id) {
+            (system_role_service::DataScopeType::All, _) => return Ok(DataScope::All),
+            (system_role_service::DataScopeType::Dept, Some(dept_id)) => dept_ids.push(dept_id),
+            (system_role_service::DataScopeType::DeptAndChildren, Some(dept_id)) => {
+                dept_ids.extend(system_dept_service::get_dept_children_ids(db, dept_id).await?)
+            }
+            (system_role_service::DataScopeType::Custom, _) => {
+                dept_ids.extend(system_role_service::get_dept_ids(db, *role.id()).await?)
+            }
+            _ => {}
+        }
+    }
+    if dept_ids.is_empty() {
+        return Ok(DataScope::User(user_id));
+    }
+    dept_ids.sort();
+    dept_ids.dedup();
+    Ok(DataScope::Depts(user_id, dept_ids))
 }
 
 pub async fn get_users_by_dept_id(db: &Database, dept_id: i32) -> Result<Vec<Info>> {
@@ -91,56 +103,142 @@ pub async fn get_users_by_dept_id(db: &Database, dept_id: i32) -> Result<Vec<Inf
         .collect::<Vec<Info>>())
 }
 
+/// move the users to the dept, auditing every user; pass the client of the
+/// transaction the move belongs to
 pub async fn batch_set_dept(
-    db: &Database,
+    client: &PrismaClient,
     dept_id: Option<i32>,
     user_ids: Vec<i32>,
 ) -> Result<i64> {
-    Ok(db
-        .client
-        .system_user()
-        .update_many(
-            vec![system_user::id::in_vec(user_ids)],
-            vec![match dept_id {
-                Some(dept_id) => system_user::dept::connect(system_dept::id::equals(dept_id)),
-                None => system_user::dept::disconnect(),
-            }],
+    let mut count = 0;
+    for id in user_ids {
+        let before = client
+            .system_user()
+            .find_unique(system_user::id::equals(id))
+            .exec()
+            .await?;
+        let data = client
+            .system_user()
+            .update(
+                system_user::id::equals(id),
+                vec![match dept_id {
+                    Some(dept_id) => system_user::dept::connect(system_dept::id::equals(dept_id)),
+                    None => system_user::dept::disconnect(),
+                }],
+            )
+            .exec()
+            .await?;
+        system_audit_service::record(
+            client,
+            "system_user",
+            data.id,
+            AuditAction::Update,
+            before.as_ref(),
+            Some(&data),
         )
-        .exec()
-        .await?)
+        .await?;
+        count += 1;
+    }
+    Ok(count)
 }
 
-pub async fn create(db: &Database, username: &str, params: CreateParams) -> Result<Info> {
-    Ok(db
+/// create the user with its roles in one transaction, `None` gives no roles
+pub async fn create(
+    db: &Database,
+    username: &str,
+    params: CreateParams,
+    role_ids: Option<Vec<i32>>,
+) -> Result<Info> {
+    let data = db
         .client
-        .system_user()
-        .create_unchecked(username.to_owned(), params.to_params())
-        .exec()
-        .await?
-        .into())
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            let data = client
+                .system_user()
+                .create_unchecked(username.to_owned(), params.to_params())
+                .exec()
+                .await?;
+            system_audit_service::record(
+                &client,
+                "system_user",
+                data.id,
+                AuditAction::Create,
+                None,
+                Some(&data),
+            )
+            .await?;
+            if let Some(role_ids) = role_ids {
+                system_user_role_service::replace_user_roles(&client, data.id, role_ids).await?;
+            }
+            Ok(data)
+        })
+        .await?;
+    db.permissions().forget_user(data.id);
+    Ok(data.into())
 }
 
-pub async fn update(db: &Database, id: i32, params: Vec<UncheckedSetParam>) -> Result<Info> {
-    Ok(db
-        .client
-        .system_user()
-        .update_unchecked(system_user::id::equals(id), params)
-        .exec()
-        .await?
-        .into())
+/// update the user and replace its roles in one transaction, `None` keeps
+/// the roles unchanged
+pub async fn update(
+    db: &Database,
+    id: i32,
+    params: Vec<UncheckedSetParam>,
+    role_ids: Option<Vec<i32>>,
+) -> Result<Info> {
+    set(db, id, AuditAction::Update, params, role_ids).await
 }
 
 pub async fn delete(db: &Database, id: i32) -> Result<Info> {
-    Ok(db
+    set(
+        db,
+        id,
+        AuditAction::Delete,
+        vec![system_user::deleted_at::set(Some(now_time()))],
+        None,
+    )
+    .await
+}
+
+/// update the user and audit it in one transaction
+async fn set(
+    db: &Database,
+    id: i32,
+    action: AuditAction,
+    params: Vec<UncheckedSetParam>,
+    role_ids: Option<Vec<i32>>,
+) -> Result<Info> {
+    let data = db
         .client
-        .system_user()
-        .update(
-            system_user::id::equals(id),
-            vec![system_user::deleted_at::set(Some(now_time()))],
-        )
-        .exec()
-        .await?
-        .into())
+        ._transaction()
+        .run::<ServiceError, _, _, _>(|client| async move {
+            let before = client
+                .system_user()
+                .find_unique(system_user::id::equals(id))
+                .exec()
+                .await?;
+            let data = client
+                .system_user()
+                .update_unchecked(system_user::id::equals(id), params)
+                .exec()
+                .await?;
+            system_audit_service::record(
+                &client,
+                "system_user",
+                data.id,
+                action,
+                before.as_ref(),
+                Some(&data),
+            )
+            .await?;
+            if let Some(role_ids) = role_ids {
+                system_user_role_service::replace_user_roles(&client, id, role_ids).await?;
+            }
+            Ok(data)
+        })
+        .await?;
+    let info = data.into();
+    db.permissions().forget_user(id);
+    Ok(info)
 }
 
 pub async fn info(db: &Database, id: i32) -> Result<Info> {
@@ -150,6 +248,7 @@ pub async fn info(db: &Database, id: i32) -> Result<Info> {
             system_user::id::equals(id),
             system_user::deleted_at::equals(None),
         ])
+        .with(system_user::user_role::fetch(vec![]).with(system_user_role::role::fetch()))
         .exec()
         .await?
         .map(|x| x.into())
@@ -159,8 +258,10 @@ pub async fn info(db: &Database, id: i32) -> Result<Info> {
 pub async fn paginate(
     db: &Database,
     params: SearchParams,
+    scope: &DataScope,
 ) -> Result<PaginateResult<Vec<DataPower<Info>>>> {
     let mut query_params = params.to_params();
+    query_params.extend(scope.to_params());
     if let Some(dept_id) = params.dept_id {
         query_params.push(system_user::dept_id::in_vec(
             system_dept_service::get_dept_children_ids(db, dept_id).await?,
@@ -173,6 +274,7 @@ pub async fn paginate(
                 .system_user()
                 .find_many(query_params.clone())
                 .with(system_user::role::fetch())
+                .with(system_user::user_role::fetch(vec![]).with(system_user_role::role::fetch()))
                 .with(system_user::dept::fetch())
                 .skip(params.paginate.get_skip())
                 .take(params.paginate.get_limit())
@@ -259,7 +361,9 @@ impl SearchParams {
             params.push(system_user::status::equals(status));
         }
         if let Some(role_id) = self.role_id {
-            params.push(system_user::role_id::equals(Some(role_id)));
+            params.push(system_user::user_role::some(vec![
+                system_user_role::role_id::equals(role_id),
+            ]));
         }
         params
     }
@@ -289,6 +393,7 @@ pub struct Info {
     username: String,
     nickname: String,
     role_id: Option<i32>,
+    #[getset(get = "pub")]
     dept_id: Option<i32>,
     phone: String,
     email: String,
@@ -309,6 +414,10 @@ pub struct Info {
     dept: Option<system_dept_service::Info>,
     #[getset(get = "pub")]
     role: Option<system_role_service::Info>,
+    #[getset(get = "pub")]
+    role_ids: Vec<i32>,
+    #[getset(get = "pub")]
+    roles: Vec<system_role_service::Info>,
 }
 
 impl From<system_user::Data> for Info {
@@ -321,6 +430,15 @@ impl From<system_user::Data> for Info {
             Ok(role) => role.map(|x| x.clone().into()),
             Err(_) => None,
         };
+        let roles = match value.user_role() {
+            Ok(user_roles) => user_roles
+                .iter()
+                .filter_map(|x| x.role().ok())
+                .filter(|x| x.deleted_at.is_none())
+                .map(|x| x.clone().into())
+                .collect::<Vec<system_role_service::Info>>(),
+            Err(_) => vec![],
+        };
         Self {
             id: value.id,
             username: value.username,
@@ -340,9 +458,51 @@ impl From<system_user::Data> for Info {
             created_at: to_local_string(value.created_at),
             dept,
             role,
+            role_ids: roles
+                .iter()
+                .map(|x: &system_role_service::Info| *x.id())
+                .collect::<Vec<i32>>(),
+            roles,
+        }
+    }
+}
+/// rows visible to a user
+#[derive(Debug, Clone)]
+pub enum DataScope {
+    /// everything
+    All,
+    /// rows of the users in these depts, and the user`s own rows
+    Depts(i32, Vec<i32>),
+    /// only the user`s own rows
+    User(i32),
+}
+
+impl DataScope {
+    /// conditions on `system_user`, nest them with a `user::is` relation filter
+    /// to scope tables owned by admin users
+    pub fn to_params(&self) -> Vec<system_user::WhereParam> {
+        match self {
+            Self::All => vec![],
+            Self::Depts(user_id, dept_ids) => vec![or!(
+                system_user::id::equals(*user_id),
+                system_user::dept_id::in_vec(dept_ids.clone())
+            )],
+            Self::User(user_id) => vec![system_user::id::equals(*user_id)],
+        }
+    }
+
+    /// whether the user is visible within the scope
+    pub fn contains(&self, user_id: i32, dept_id: Option<i32>) -> bool {
+        match self {
+            Self::All => true,
+            Self::Depts(id, dept_ids) => {
+                id.eq(&user_id) || dept_id.is_some_and(|x| dept_ids.contains(&x))
+            }
+            Self::User(id) => id.eq(&user_id),
         }
     }
 }
+
 #[derive(Debug, Serialize)]
 pub struct Permission {
     pub user: Info,
@@ -28,6 +28,19 @@ pub fn parse_string(datetime: String) -> chrono::DateTime<chrono::FixedOffset> {
         .unwrap_or_else(|_x| now_time())
 }
 
+/// parse local `%Y-%m-%d` string as the start of the day
+pub fn parse_date(date: &str) -> Option<chrono::DateTime<chrono::FixedOffset>> {
+    parse_datetime(&format!("{} 00:00:00", date))
+}
+
+/// parse local `%Y-%m-%d %H:%M:%S` string
+pub fn parse_datetime(datetime: &str) -> Option<chrono::DateTime<chrono::FixedOffset>> {
+    chrono::NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M:%S")
+        .ok()
+        .and_then(|x| x.and_local_timezone(chrono::Local).single())
+        .map(|x| x.fixed_offset())
+}
+
 pub fn offset_from_timestamp(timestamp: i64) -> chrono::DateTime<chrono::FixedOffset> {
     let utc_time = chrono::DateTime::<chrono::Utc>::from_timestamp(timestamp, 0).unwrap();
     utc_time.fixed_offset()
@@ -0,0 +1,150 @@
+use rust_xlsxwriter::Workbook;
+pub use rust_xlsxwriter::XlsxError;
+use serde::Serialize;
+use serde_json::Value;
+
+/// exported column, the key is a `/` separated path into the serialized row
+#[derive(Debug, Clone)]
+pub struct Column {
+    pub key: &'static str,
+    pub title: &'static str,
+}
+
+/// rows turned into CSV lines or a XLSX workbook
+#[derive(Debug, Clone)]
+pub struct Exporter {
+    columns: Vec<Column>,
+}
+
+impl Exporter {
+    /// the selected keys, comma separated, in their order; unknown keys are
+    /// ignored and no valid key selects every column
+    pub fn new(columns: &[(&'static str, &'static str)], selected: Option<&str>) -> Self {
+        let all = columns
+            .iter()
+            .map(|(key, title)| Column {
+                key: *key,
+                title: *title,
+            })
+            .collect::<Vec<Column>>();
+        let selected = selected
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|x| all.iter().find(|c| c.key == x.trim()).cloned())
+            .collect::<Vec<Column>>();
+        Self {
+            columns: match selected.is_empty() {
+                true => all,
+                false => selected,
+            },
+        }
+    }
+
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    /// header line, with a BOM so spreadsheet apps read it as UTF-8
+    pub fn csv_header(&self) -> String {
+        let titles = self
+            .columns
+            .iter()
+            .map(|x| x.title.to_owned())
+            .collect::<Vec<String>>();
+        format!("\u{feff}{}", csv_line(&titles))
+    }
+
+    pub fn csv_rows(&self, rows: &[Value]) -> String {
+        rows.iter()
+            .map(|row| csv_line(&self.cells(row)))
+            .collect::<String>()
+    }
+
+    pub fn xlsx(&self, rows: &[Value]) -> Result<Vec<u8>, XlsxError> {
+        let mut workbook = Workbook::new();
+        let sheet = workbook.add_worksheet();
+        for (col, column) in self.columns.iter().enumerate() {
+            sheet.write_string(0, col as u16, column.title)?;
+        }
+        for (index, row) in rows.iter().enumerate() {
+            for (col, cell) in self.cells(row).iter().enumerate() {
+                sheet.write_string(index as u32 + 1, col as u16, cell)?;
+            }
+        }
+        workbook.save_to_buffer()
+    }
+
+    fn cells(&self, row: &Value) -> Vec<String> {
+        self.columns
+            .iter()
+            .map(|x| match row.pointer(&format!("/{}", x.key)) {
+                None | Some(Value::Null) => String::new(),
+                Some(Value::String(value)) => value.to_owned(),
+                Some(value) => value.to_string(),
+            })
+            .collect::<Vec<String>>()
+    }
+}
+
+/// serialize the rows for the exporter
+pub fn to_values<T: Serialize>(rows: &[T]) -> serde_json::Result<Vec<Value>> {
+    rows.iter().map(serde_json::to_value).collect()
+}
+
+fn csv_line(cells: &[String]) -> String {
+    let line = cells
+        .iter()
+        .map(|x| csv_cell(x))
+        .collect::<Vec<String>>()
+        .join(",");
+    format!("{}\r\n", line)
+}
+
+/// quote the cell when needed, and keep spreadsheet apps from reading it as a formula
+fn csv_cell(value: &str) -> String {
+    let value = match value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        true => format!("'{}", value),
+        false => value.to_owned(),
+    };
+    match value.contains([',', '"', '\r', '\n']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_cell_guards_formulas() {
+        assert_eq!(csv_cell("=SUM(A1)"), "'=SUM(A1)");
+        assert_eq!(csv_cell("+1"), "'+1");
+        assert_eq!(csv_cell("-1"), "'-1");
+        assert_eq!(csv_cell("@cmd"), "'@cmd");
+        assert_eq!(csv_cell("\t=1"), "'\t=1");
+        assert_eq!(csv_cell("\r=1"), "\"'\r=1\"");
+        assert_eq!(csv_cell("a=1"), "a=1");
+    }
+
+    #[test]
+    fn csv_cell_quotes() {
+        assert_eq!(csv_cell("plain"), "plain");
+        assert_eq!(csv_cell(""), "");
+        assert_eq!(csv_cell("a,b"), "\"a,b\"");
+        assert_eq!(csv_cell("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_cell("a\nb"), "\"a\nb\"");
+        assert_eq!(csv_cell("=a,b"), "\"'=a,b\"");
+    }
+
+    #[test]
+    fn csv_rows_by_selected_columns() {
+        let exporter = Exporter::new(
+            &[("id", "ID"), ("user/name", "Name")],
+            Some("user/name, id"),
+        );
+        let rows = vec![serde_json::json!({"id": 1, "user": {"name": "a,b"}})];
+        assert_eq!(exporter.csv_header(), "\u{feff}Name,ID\r\n");
+        assert_eq!(exporter.csv_rows(&rows), "\"a,b\",1\r\n");
+    }
+}
@@ -1,5 +1,7 @@
 #[cfg(feature = "datetime")]
 pub mod datetime;
+#[cfg(feature = "export")]
+pub mod export;
 #[cfg(feature = "extract")]
 pub mod extracts;
 #[cfg(feature = "logger")]
@@ -8,6 +8,10 @@ pub struct PaginateParams {
 }
 
 impl PaginateParams {
+    pub fn new(page: i64, limit: i64) -> Self {
+        Self { page, limit }
+    }
+
     pub fn get_skip(&self) -> i64 {
         match self.page > 0 {
             true => (self.page - 1) * self.limit,